@@ -4,6 +4,7 @@ use canister_sdk::candid::{CandidType, Principal};
 use serde::Deserialize;
 
 use crate::error::TxError;
+use crate::state::permissions::TokenAwareCanisters;
 
 pub static DEFAULT_SUBACCOUNT: Subaccount = [0u8; 32];
 
@@ -109,10 +110,11 @@ impl CheckedAccount<WithRecipient> {
         let caller = canister_sdk::ic_kit::ic::caller();
         let from = AccountInternal::new(caller, from_subaccount);
         if recipient == from {
-            Err(TxError::SelfTransfer)
-        } else {
-            Ok(Self(from, WithRecipient { recipient }))
+            return Err(TxError::SelfTransfer);
         }
+
+        TokenAwareCanisters::check_recipient(&recipient)?;
+        Ok(Self(from, WithRecipient { recipient }))
     }
     pub fn recipient(&self) -> AccountInternal {
         self.1.recipient
@@ -1,4 +1,7 @@
-use candid::Principal;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use candid::{CandidType, Deserialize, Principal};
 #[cfg(feature = "auction")]
 use canister_sdk::ic_auction::{
     api::Auction,
@@ -6,31 +9,72 @@ use canister_sdk::ic_auction::{
     state::{AuctionInfo, AuctionState},
 };
 use canister_sdk::ic_canister::{
-    generate_exports, generate_idl, query, update, Canister, Idl, PreUpdate,
+    generate_exports, generate_idl, query, update, AsyncReturn, Canister, Idl, PreUpdate,
 };
 use canister_sdk::ic_helpers::tokens::Tokens128;
 use canister_sdk::ic_kit::ic;
 pub use inspect::AcceptReason;
 
 use self::is20_transactions::{
-    batch_transfer, burn_as_owner, burn_own_tokens, is20_transfer, mint_as_owner, mint_test_token,
+    batch_burn, batch_mint, batch_transfer, batch_transfer_best_effort, burn_as_owner,
+    burn_own_tokens, cancel_timelocked, claim_timelocked, commit_transfer, create_vesting,
+    decrease_allowance, icrc2_approve, icrc2_transfer_from, import_balances_chunk,
+    increase_allowance, is20_transfer, mint_as_minter, mint_as_owner, mint_test_token,
+    move_auction_pool, multi_source_transfer, notify_transfer, prepare_transfer, reclaim_dormant,
+    redeem, refund_escrow, release_escrow, release_vested, transfer_conditional,
+    transfer_timelocked,
 };
 #[cfg(feature = "claim")]
-use self::is20_transactions::{claim, get_claim_subaccount};
+use self::is20_transactions::{
+    claim, get_claim_subaccount, reclaim_expired_claims, transfer_to_account_id,
+};
 use crate::account::{Account, AccountInternal, CheckedAccount, Subaccount};
 use crate::canister::icrc1_transfer::icrc1_transfer;
+use crate::canister::is20_archive::{ArchiveCanister, ArchiveOptions};
 use crate::error::{TransferError, TxError};
 use crate::principal::{CheckedPrincipal, Owner};
+use crate::state::allowances::Allowances;
+use crate::state::analytics::{AnalyticsLedger, AnalyticsRecord};
+#[cfg(feature = "auction")]
+use crate::state::auction_dust::AuctionDust;
+use crate::state::balance_tree::with_balance_tree;
 use crate::state::balances::{Balances, StableBalances};
-use crate::state::config::{StandardRecord, Timestamp, TokenConfig, TokenInfo, Value};
+use crate::state::batch_guard::BatchGuard;
+use crate::state::blocks::{GetBlocksArgs, GetBlocksResult};
+use crate::state::certification::CertifiedBalance;
+use crate::state::config::{
+    AuctionBeneficiary, DynamicFeeParams, FeeMode, FeeRatio, StandardRecord, Timestamp,
+    TokenConfig, TokenInfo, Value, MAX_TX_WINDOW_NANOS,
+};
+use crate::state::escrow::{Condition, Escrow, Escrows};
+use crate::state::failures::{FailedTx, FailedTxLog};
+use crate::state::fee_history::{FeeChange, FeeHistory};
+use crate::state::fee_oracle::{FeeOracle, OracleCanister, OraclePrice};
+use crate::state::fee_quotes::{FeeQuote, FeeQuotes};
+use crate::state::fingerprint::StateFingerprint;
+use crate::state::governance::{PendingOwnerAction, PendingOwnerActions};
+use crate::state::layout::{MIN_COMPATIBLE_LAYOUT_VERSION, STABLE_LAYOUT_VERSION};
 use crate::state::ledger::{
-    BatchTransferArgs, LedgerData, PaginatedResult, TransferArgs, TxReceipt,
+    ApproveArgs, BalanceDelta, BatchTransferArgs, Cursor, LedgerData, Memo,
+    MultiSourceTransferArgs, NotifyReceipt, PaginatedResult, TransferArgs, TransferFromArgs,
+    TxReceipt,
+};
+use crate::state::migration::MigrationState;
+use crate::state::permissions::{
+    FrozenAccounts, Minters, OutgoingDisabledAccounts, TokenAwareCanisters,
 };
+use crate::state::prepared_transfers::PreparedTransfer;
+use crate::state::statistics::{
+    BalanceMapStats, LedgerCounters, MetricPoint, OperationCounts, TokenStatistics,
+};
+use crate::state::timelocks::{Timelock, Timelocks};
+use crate::state::vesting::{VestingSchedule, Vestings};
 use crate::tx_record::{TxId, TxRecord};
 
 mod inspect;
 
 pub mod icrc1_transfer;
+pub mod is20_archive;
 
 #[cfg(feature = "auction")]
 pub mod is20_auction;
@@ -38,16 +82,125 @@ pub mod is20_transactions;
 
 pub(crate) const MAX_TRANSACTION_REQUEST: usize = 2000;
 pub(crate) const MAX_ACCOUNT_TRANSACTION_REQUEST: usize = 1000;
+pub(crate) const MAX_SNAPSHOT_ACCOUNTS: usize = 100;
+pub(crate) const MAX_SNAPSHOT_ACCOUNT_TRANSACTIONS: usize = 100;
+pub(crate) const MAX_FEE_CURVE_POINTS: usize = 1000;
+pub(crate) const MAX_METRIC_POINTS: usize = 1000;
+pub(crate) const MAX_BALANCE_QUERY_LEN: usize = 1000;
+pub(crate) const MAX_TOP_HOLDERS: usize = 100;
+pub(crate) const MAX_AUCTION_HISTORY_REQUEST: usize = 100;
 // 1 day in seconds.
 pub const DEFAULT_AUCTION_PERIOD_SECONDS: Timestamp = 60 * 60 * 24;
 
+#[derive(Debug, Clone, CandidType, Deserialize)]
 pub enum CanisterUpdate {
     Name(String),
     Symbol(String),
+    Logo(String),
     Fee(Tokens128),
-    FeeTo(Principal),
+    FeeTo(Account),
     Owner(Principal),
+    PendingOwner(Option<Principal>),
     MinCycles(u64),
+    MaxQueryPageSize(Option<usize>),
+    AuctionPoolTrigger(Option<Tokens128>),
+    GovernanceDelay(Option<u64>),
+    FailedTxLogEnabled(bool),
+    StorageHighWaterMark(Option<u64>),
+    CirculationExcludedAccounts(Vec<Account>),
+    AuctionDustSinking(bool),
+    FeeMode(FeeMode),
+    DormancyPeriod(Option<u64>),
+    AuctionBeneficiary(AuctionBeneficiary),
+    FeeOracle(Option<FeeOracle>),
+    Transferable(bool),
+    DynamicFee(Option<DynamicFeeParams>),
+    AnalyticsEnabled(bool),
+    MaxConcurrentBatches(Option<u32>),
+    BlockCanisterRecipients(bool),
+    PreparedTransferTtl(Option<u64>),
+    FeeQuoteTtl(Option<u64>),
+    TxWindow(Option<u64>),
+    PermittedDrift(Option<u64>),
+    ClaimExpiry(Option<u64>),
+    Royalty(Option<(Account, u16)>),
+    BurnRate(Option<u16>),
+    AuctionFeeRatio(Option<FeeRatio>),
+    MinAuctionBid(Option<u64>),
+    Paused(bool),
+    RejectZeroTransfers(bool),
+    FrozenAccount(Principal, bool),
+    Minter(Principal, bool),
+    TokenAwareCanister(Principal),
+    AuctionAccount(Account),
+    ArchiveOptions(Option<ArchiveOptions>),
+}
+
+impl CanisterUpdate {
+    /// Apply the update to the token configuration.
+    pub fn apply(self) {
+        use CanisterUpdate::*;
+        let mut stats = TokenConfig::get_stable();
+        match self {
+            Name(name) => stats.name = name,
+            Symbol(symbol) => stats.symbol = symbol,
+            Logo(logo) => stats.logo = Some(logo),
+            Fee(fee) => {
+                FeeHistory::record(stats.fee, fee);
+                stats.fee = fee
+            }
+            FeeTo(fee_to) => {
+                stats.fee_to = fee_to.owner;
+                stats.fee_to_subaccount = fee_to.subaccount;
+            }
+            Owner(owner) => {
+                stats.owner = owner;
+                stats.pending_owner = None;
+            }
+            PendingOwner(owner) => stats.pending_owner = owner,
+            MinCycles(min_cycles) => stats.min_cycles = min_cycles,
+            MaxQueryPageSize(size) => stats.max_query_page_size = size,
+            AuctionPoolTrigger(trigger) => stats.auction_pool_trigger = trigger,
+            GovernanceDelay(delay) => stats.governance_delay_nanos = delay,
+            FailedTxLogEnabled(enabled) => stats.failed_tx_log_enabled = Some(enabled),
+            StorageHighWaterMark(mark) => stats.storage_high_water_mark = mark,
+            CirculationExcludedAccounts(accounts) => {
+                stats.circulation_excluded_accounts = Some(accounts)
+            }
+            AuctionDustSinking(enabled) => stats.auction_dust_sinking = Some(enabled),
+            CanisterUpdate::FeeMode(mode) => stats.fee_mode = Some(mode),
+            DormancyPeriod(period) => stats.dormancy_period_nanos = period,
+            CanisterUpdate::AuctionBeneficiary(beneficiary) => {
+                stats.auction_beneficiary = Some(beneficiary)
+            }
+            CanisterUpdate::FeeOracle(oracle) => stats.fee_oracle = oracle,
+            Transferable(transferable) => stats.transferable = Some(transferable),
+            DynamicFee(params) => stats.dynamic_fee = params,
+            AnalyticsEnabled(enabled) => stats.analytics_enabled = Some(enabled),
+            MaxConcurrentBatches(limit) => stats.max_concurrent_batches = limit,
+            BlockCanisterRecipients(enabled) => stats.block_canister_recipients = Some(enabled),
+            PreparedTransferTtl(ttl) => stats.prepared_transfer_ttl_nanos = ttl,
+            FeeQuoteTtl(ttl) => stats.fee_quote_ttl_nanos = ttl,
+            TxWindow(window) => stats.tx_window_nanos = window,
+            PermittedDrift(drift) => stats.permitted_drift_nanos = drift,
+            ClaimExpiry(expiry) => stats.claim_expiry_nanos = expiry,
+            Royalty(royalty) => stats.royalty = royalty,
+            BurnRate(burn_rate) => stats.burn_rate = burn_rate,
+            AuctionFeeRatio(ratio) => stats.auction_fee_ratio = ratio,
+            MinAuctionBid(min_bid) => stats.min_auction_bid = min_bid,
+            Paused(paused) => stats.paused = Some(paused),
+            RejectZeroTransfers(reject) => stats.reject_zero_transfers = Some(reject),
+            FrozenAccount(principal, frozen) => FrozenAccounts::set(principal, frozen),
+            Minter(principal, minter) => Minters::set(principal, minter),
+            TokenAwareCanister(canister) => TokenAwareCanisters::register(canister),
+            AuctionAccount(account) => {
+                move_auction_pool(account.into());
+                stats.auction_account = Some(account);
+            }
+            CanisterUpdate::ArchiveOptions(options) => stats.archive_options = options,
+        }
+        TokenConfig::set_stable(stats)
+    }
 }
 
 #[cfg(not(feature = "auction"))]
@@ -77,11 +230,64 @@ pub trait TokenCanisterAPI: Canister + Sized + AuctionCanister {
         StableBalances.total_supply()
     }
 
+    /// Total supply without the balances of the accounts excluded from circulation.
+    #[query(trait = true)]
+    fn circulating_supply(&self) -> Tokens128 {
+        let excluded = TokenConfig::get_stable()
+            .circulation_excluded_accounts
+            .unwrap_or_default()
+            .into_iter()
+            .map(AccountInternal::from)
+            .collect::<std::collections::HashSet<_>>();
+
+        excluded
+            .iter()
+            .fold(StableBalances.total_supply(), |supply, account| {
+                supply.saturating_sub(StableBalances.balance_of(account))
+            })
+    }
+
+    #[query(trait = true)]
+    fn circulation_excluded_accounts(&self) -> Vec<Account> {
+        TokenConfig::get_stable()
+            .circulation_excluded_accounts
+            .unwrap_or_default()
+    }
+
+    /// Sets the treasury and other accounts which balances are not counted in the circulating
+    /// supply.
+    #[update(trait = true)]
+    fn set_circulation_excluded_accounts(
+        &self,
+        accounts: Vec<Account>,
+    ) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(
+            caller,
+            CanisterUpdate::CirculationExcludedAccounts(accounts),
+        ))
+    }
+
     #[query(trait = true)]
     fn owner(&self) -> Principal {
         TokenConfig::get_stable().owner
     }
 
+    /// Principals controlling the canister, as recorded in the token configuration: the upgrade
+    /// authority and the owner.
+    #[query(trait = true)]
+    fn controllers(&self) -> Vec<Principal> {
+        TokenConfig::get_stable().controllers()
+    }
+
+    /// Principal allowed to upgrade the canister. For the tokens deployed by the factory this is
+    /// the factory canister, which is controlled by the factory controller.
+    #[query(trait = true)]
+    fn upgrade_authority(&self) -> Principal {
+        TokenConfig::get_stable().upgrade_authority()
+    }
+
     #[query(trait = true)]
     fn get_token_info(&self) -> TokenInfo {
         let TokenConfig {
@@ -99,689 +305,5141 @@ pub trait TokenCanisterAPI: Canister + Sized + AuctionCanister {
         }
     }
 
-    #[update(trait = true)]
-    fn set_name(&self, name: String) -> Result<(), TxError> {
-        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
-        self.update_stats(caller, CanisterUpdate::Name(name));
-        Ok(())
+    /// Returns the summary of the token age and activity.
+    #[query(trait = true)]
+    fn token_statistics(&self) -> TokenStatistics {
+        let deploy_time = TokenConfig::get_stable().deploy_time;
+        let counters = LedgerCounters::get_stable();
+        TokenStatistics {
+            deploy_time,
+            age_days: TokenStatistics::age_days(deploy_time, ic::time()),
+            total_transactions: LedgerData::len(),
+            total_volume: counters.total_volume,
+            unique_holders: StableBalances.get_holders().len() as u64,
+            total_fees: counters.total_fees,
+            last_activity: counters.last_activity,
+        }
+    }
+
+    /// Returns the number of the recorded transactions of each kind.
+    #[query(trait = true)]
+    fn operation_counts(&self) -> OperationCounts {
+        LedgerCounters::get_stable()
+            .operation_counts
+            .unwrap_or_default()
+    }
+
+    /// Returns the distribution of the balances between the accounts. The query iterates over all
+    /// the balances, so it's intended for occasional use by the operators.
+    #[query(trait = true)]
+    fn balance_map_stats(&self) -> BalanceMapStats {
+        BalanceMapStats::compute(&StableBalances, auction_account())
+    }
+
+    /// Returns the total supply, the number of holders, the cumulative volume and the number of
+    /// transactions of each kind at the end of each of `num_points` intervals of
+    /// `interval_seconds`, the latest first. The values are
+    /// reconstructed from the ledger history, so if the oldest transactions are removed from the
+    /// ledger, only the points covered by the remaining ones are returned. At most
+    /// `MAX_METRIC_POINTS` points are returned.
+    #[query(trait = true)]
+    fn metrics_timeseries(&self, interval_seconds: u64, num_points: usize) -> Vec<MetricPoint> {
+        LedgerData::metrics_timeseries(
+            &StableBalances,
+            interval_seconds.saturating_mul(1_000_000_000),
+            num_points.min(MAX_METRIC_POINTS),
+        )
+    }
+
+    /// Returns a compact hash-based summary of the token state. Canisters with the same balances
+    /// and transaction history return equal fingerprints.
+    #[query(trait = true)]
+    fn state_fingerprint(&self) -> StateFingerprint {
+        StateFingerprint::compute()
+    }
+
+    /// Version of the stable memory layout used by the canister code.
+    #[query(trait = true)]
+    fn stable_layout_version(&self) -> u32 {
+        STABLE_LAYOUT_VERSION
+    }
+
+    /// The oldest stable memory layout version the canister code can be upgraded from.
+    #[query(trait = true)]
+    fn min_compatible_version(&self) -> u32 {
+        MIN_COMPATIBLE_LAYOUT_VERSION
     }
 
     #[update(trait = true)]
-    fn set_symbol(&self, symbol: String) -> Result<(), TxError> {
+    fn set_name(&self, name: String) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
         let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
-        self.update_stats(caller, CanisterUpdate::Symbol(symbol));
-        Ok(())
+        Ok(self.update_stats(caller, CanisterUpdate::Name(name)))
     }
 
     #[update(trait = true)]
-    fn set_fee(&self, fee: Tokens128) -> Result<(), TxError> {
+    fn set_symbol(&self, symbol: String) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
         let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
-        self.update_stats(caller, CanisterUpdate::Fee(fee));
-        Ok(())
+        Ok(self.update_stats(caller, CanisterUpdate::Symbol(symbol)))
     }
 
     #[update(trait = true)]
-    fn set_fee_to(&self, fee_to: Principal) -> Result<(), TxError> {
+    fn set_logo(&self, logo: String) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
         let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
-        self.update_stats(caller, CanisterUpdate::FeeTo(fee_to));
-        Ok(())
+        Ok(self.update_stats(caller, CanisterUpdate::Logo(logo)))
     }
 
     #[update(trait = true)]
-    fn set_owner(&self, owner: Principal) -> Result<(), TxError> {
+    fn set_fee(&self, fee: Tokens128) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
         let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
-        self.update_stats(caller, CanisterUpdate::Owner(owner));
-        Ok(())
+        Ok(self.update_stats(caller, CanisterUpdate::Fee(fee)))
     }
 
-    /********************** BALANCES INFO ***********************/
+    /// Returns up to `count` most recent fee changes, the newest first. A change is recorded when
+    /// it is applied, so a delayed `set_fee` call appears here only after it's executed.
+    #[query(trait = true)]
+    fn fee_change_history(&self, count: u32) -> Vec<FeeChange> {
+        FeeHistory::recent(count as usize)
+    }
 
-    /// This method retreieves holders of `Account` and their amounts.
     #[query(trait = true)]
-    fn get_holders(&self, start: usize, limit: usize) -> Vec<(Account, Tokens128)> {
-        StableBalances
-            .list_balances(start, limit)
-            .into_iter()
-            .map(|(acc, amount)| (acc.into(), amount))
-            .collect()
+    fn dynamic_fee(&self) -> Option<DynamicFeeParams> {
+        TokenConfig::get_stable().dynamic_fee
+    }
+
+    /// Sets the congestion pricing parameters of the flat fee. If set to `None`, the `fee` value
+    /// is charged regardless of the ledger length.
+    #[update(trait = true)]
+    fn set_dynamic_fee(&self, params: Option<DynamicFeeParams>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::DynamicFee(params)))
     }
 
-    /// Returns the list of the caller's subaccounts with balances. If the caller account does not exist, will
-    /// return an empty list.
-    ///
-    /// It is intentional that the method does not accept the principal to list the subaccounts
-    /// for, because in some cases the token holder want to keep some of his subaccounts a secret.
-    /// So only own subaccounts can be listed safely.
     #[query(trait = true)]
-    fn list_subaccounts(&self) -> std::collections::HashMap<Subaccount, Tokens128> {
-        StableBalances.get_subaccounts(ic::caller())
+    fn fee_mode(&self) -> FeeMode {
+        TokenConfig::get_stable().fee_mode.unwrap_or(FeeMode::Flat)
     }
 
-    /********************** CLAIMS ***********************/
+    #[update(trait = true)]
+    fn set_fee_mode(&self, mode: FeeMode) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::FeeMode(mode)))
+    }
 
-    #[cfg(feature = "claim")]
+    /// Returns the fee charged for a transfer of the `amount` of tokens.
     #[query(trait = true)]
-    fn get_claimable_amount(&self, holder: Principal, subaccount: Option<Subaccount>) -> Tokens128 {
-        StableBalances::get_claimable_amount(holder, subaccount)
+    fn effective_fee(&self, amount: Tokens128) -> Tokens128 {
+        TokenConfig::get_stable().effective_fee(amount)
+    }
+
+    /// Sets the account receiving the royalty on transfers and the royalty rate in basis points of
+    /// the transferred amount. The royalty is deducted from the amount received by the recipient.
+    /// If `None`, no royalty is charged.
+    #[update(trait = true)]
+    fn set_royalty(&self, royalty: Option<(Account, u16)>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::Royalty(royalty)))
     }
 
-    #[cfg(feature = "claim")]
     #[query(trait = true)]
-    fn get_claim_subaccount(
-        &self,
-        claimer: Principal,
-        claimer_subaccount: Option<Subaccount>,
-    ) -> Subaccount {
-        get_claim_subaccount(claimer, claimer_subaccount)
+    fn royalty(&self) -> Option<(Account, u16)> {
+        TokenConfig::get_stable().royalty
     }
 
-    #[cfg(feature = "claim")]
+    /// Sets the part of every transfer to be burned, in basis points of the transferred amount.
+    /// If `None`, nothing is burned.
     #[update(trait = true)]
-    fn claim(&self, holder: Principal, subaccount: Option<Subaccount>) -> TxReceipt {
-        claim(holder, subaccount)
+    fn set_burn_rate(&self, burn_rate: Option<u16>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::BurnRate(burn_rate)))
     }
 
-    /********************** TRANSACTION HISTORY ***********************/
+    #[query(trait = true)]
+    fn burn_rate(&self) -> Option<u16> {
+        TokenConfig::get_stable().burn_rate
+    }
+
+    /// Sets the part of the fees going to the auction, overriding the ratio computed by the cycle
+    /// auction. If `None`, the computed ratio is used again.
+    #[update(trait = true)]
+    fn set_auction_fee_ratio(&self, ratio: Option<FeeRatio>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        if ratio.map_or(false, |ratio| !ratio.is_valid()) {
+            return Err(TxError::InvalidFeeRatio);
+        }
+
+        Ok(self.update_stats(caller, CanisterUpdate::AuctionFeeRatio(ratio)))
+    }
 
     #[query(trait = true)]
-    fn history_size(&self) -> u64 {
-        LedgerData::len()
+    fn auction_fee_ratio(&self) -> Option<FeeRatio> {
+        TokenConfig::get_stable().auction_fee_ratio
     }
 
+    /// Returns the effective fee for each of the `amounts`. At most `MAX_FEE_CURVE_POINTS`
+    /// amounts are processed.
     #[query(trait = true)]
-    fn get_transaction(&self, id: TxId) -> TxRecord {
-        LedgerData::get(id).unwrap_or_else(|| {
-            canister_sdk::ic_kit::ic::trap(&format!("Transaction {} does not exist", id))
-        })
+    fn fee_curve(&self, amounts: Vec<Tokens128>) -> Vec<Tokens128> {
+        let stats = TokenConfig::get_stable();
+        amounts
+            .into_iter()
+            .take(MAX_FEE_CURVE_POINTS)
+            .map(|amount| stats.effective_fee(amount))
+            .collect()
     }
 
-    /// Returns a list of transactions in paginated form. The `who` is optional, if given, only transactions of the `who` are
-    /// returned. `count` is the number of transactions to return, `transaction_id` is the transaction index which is used as
-    /// the offset of the first transaction to return, any
-    ///
-    /// It returns `PaginatedResult` a struct, which contains `result` which is a list of transactions `Vec<TxRecord>` that meet the requirements of the query,
-    /// and `next_id` which is the index of the next transaction to return.
     #[query(trait = true)]
-    fn get_transactions(
-        &self,
-        who: Option<Principal>,
-        count: usize,
-        transaction_id: Option<TxId>,
-    ) -> PaginatedResult {
-        let count = who
-            .map_or(MAX_TRANSACTION_REQUEST, |_| MAX_ACCOUNT_TRANSACTION_REQUEST)
-            .min(count);
+    fn fee_oracle(&self) -> Option<FeeOracle> {
+        TokenConfig::get_stable().fee_oracle
+    }
 
-        LedgerData::get_transactions(who, count, transaction_id)
+    /// Sets the oracle used to cap the effective fee by a fiat amount. `None` disables the cap.
+    #[update(trait = true)]
+    fn set_fee_oracle(&self, oracle: Option<FeeOracle>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::FeeOracle(oracle)))
     }
 
-    /// Returns the total number of transactions related to the user `who`.
     #[query(trait = true)]
-    fn get_user_transaction_count(&self, who: Principal) -> usize {
-        LedgerData::get_len_user_history(who)
+    fn fee_oracle_price(&self) -> OraclePrice {
+        OraclePrice::get_stable()
     }
 
-    /********************** IS20 TRANSACTIONS ***********************/
+    /// Returns the fiat value of one whole token used to cap the fee. The price is requested from
+    /// the fee oracle once the cached one is older than `price_ttl_nanos`. If the oracle fails, the
+    /// cached price is used until it becomes stale, after which the fee is not capped.
+    #[update(trait = true)]
+    fn refresh_fee_oracle_price(&self) -> AsyncReturn<Result<u128, TxError>> {
+        Box::pin(async move {
+            check_maintenance()?;
+            let oracle = TokenConfig::get_stable()
+                .fee_oracle
+                .ok_or(TxError::FeeOracleNotSet)?;
+            OraclePrice::refresh(&OracleCanister(oracle.oracle), oracle.price_ttl_nanos).await
+        })
+    }
 
-    #[cfg_attr(feature = "transfer", update(trait = true))]
-    fn transfer(&self, transfer: TransferArgs) -> Result<u128, TxError> {
-        let account = CheckedAccount::with_recipient(transfer.to.into(), transfer.from_subaccount)?;
-        is20_transfer(account, &transfer, self.fee_ratio())
+    /// Sets the account receiving the owner part of the fees.
+    #[update(trait = true)]
+    fn set_fee_to(&self, fee_to: Account) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::FeeTo(fee_to)))
     }
 
-    /// Takes a list of transfers, each of which is a pair of `to` and `value` fields, it returns a `TxReceipt` which contains
-    /// a vec of transaction index or an error message. The list of transfers is processed in the order they are given. if the `fee`
-    /// is set, the `fee` amount is applied to each transfer.
-    /// The balance of the caller is reduced by sum of `value + fee` amount for each transfer. If the total sum of `value + fee` for all transfers,
-    /// is less than the `balance` of the caller, the transaction will fail with `TxError::InsufficientBalance` error.
-    #[cfg_attr(feature = "transfer", update(trait = true))]
-    fn batch_transfer(
-        &self,
-        from_subaccount: Option<Subaccount>,
-        transfers: Vec<BatchTransferArgs>,
-    ) -> Result<Vec<TxId>, TxError> {
-        for x in &transfers {
-            let recipient = x.receiver;
-            CheckedAccount::with_recipient(recipient.into(), from_subaccount)?;
-        }
-        batch_transfer(from_subaccount, transfers, self.fee_ratio())
+    #[update(trait = true)]
+    fn set_owner(&self, owner: Principal) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::Owner(owner)))
     }
 
-    #[cfg_attr(feature = "mint_burn", update(trait = true))]
-    fn mint(
-        &self,
-        to: Principal,
-        to_subaccount: Option<Subaccount>,
-        amount: Tokens128,
-    ) -> TxReceipt {
-        if self.is_test_token() {
-            let test_user = CheckedPrincipal::test_user(&TokenConfig::get_stable())?;
-            mint_test_token(test_user, to, to_subaccount, amount)
-        } else {
-            let owner = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
-            mint_as_owner(owner, to, to_subaccount, amount)
-        }
+    /// Proposes the `new_owner` to take over the ownership of the canister. The ownership is
+    /// transferred only when the proposed owner calls `accept_ownership`, so it cannot be handed
+    /// to a principal unable to use it.
+    #[update(trait = true)]
+    fn propose_new_owner(&self, new_owner: Principal) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::PendingOwner(Some(new_owner))))
     }
 
-    /// Burn `amount` of tokens from `from` principal.
-    /// If `from` is None, then caller's tokens will be burned.
-    /// If `from` is Some(_) but method called not by owner, `TxError::Unauthorized` will be returned.
-    /// If owner calls this method and `from` is Some(who), then who's tokens will be burned.
-    #[cfg_attr(feature = "mint_burn", update(trait = true))]
-    fn burn(
-        &self,
-        from: Option<Principal>,
-        from_subaccount: Option<Subaccount>,
-        amount: Tokens128,
-    ) -> TxReceipt {
-        match from {
-            None => burn_own_tokens(from_subaccount, amount),
-            Some(from) if from == canister_sdk::ic_kit::ic::caller() => {
-                burn_own_tokens(from_subaccount, amount)
-            }
-            Some(from) => {
-                let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
-                burn_as_owner(caller, from, from_subaccount, amount)
-            }
+    /// Makes the caller the owner of the canister, if it was proposed by `propose_new_owner`. The
+    /// auction controller is changed to the new owner as well.
+    #[update(trait = true)]
+    fn accept_ownership(&self) -> Result<(), TxError> {
+        check_maintenance()?;
+        let caller = ic::caller();
+        let mut stats = TokenConfig::get_stable();
+        if stats.pending_owner != Some(caller) {
+            return Err(TxError::Unauthorized);
         }
-    }
 
-    /********************** ICRC-1 METHODS ***********************/
+        stats.owner = caller;
+        stats.pending_owner = None;
+        TokenConfig::set_stable(stats);
 
-    #[query(trait = true)]
-    fn icrc1_balance_of(&self, account: Account) -> Tokens128 {
-        StableBalances.balance_of(&account.into())
-    }
+        #[cfg(feature = "auction")]
+        {
+            self.auction_state().borrow_mut().controller = caller;
+        }
 
-    #[cfg_attr(feature = "transfer", update(trait = true))]
-    fn icrc1_transfer(&self, transfer: TransferArgs) -> Result<u128, TransferError> {
-        let account = CheckedAccount::with_recipient(transfer.to.into(), transfer.from_subaccount)?;
+        Ok(())
+    }
 
-        Ok(icrc1_transfer(account, &transfer, self.fee_ratio())?)
+    /// Withdraws the proposal made by `propose_new_owner`.
+    #[update(trait = true)]
+    fn cancel_ownership_transfer(&self) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::PendingOwner(None)))
     }
 
     #[query(trait = true)]
-    fn icrc1_name(&self) -> String {
-        TokenConfig::get_stable().name
+    fn pending_owner(&self) -> Option<Principal> {
+        TokenConfig::get_stable().pending_owner
     }
 
     #[query(trait = true)]
-    fn icrc1_symbol(&self) -> String {
-        TokenConfig::get_stable().symbol
+    fn is_transferable(&self) -> bool {
+        TokenConfig::get_stable().is_transferable()
     }
 
-    #[query(trait = true)]
-    fn icrc1_decimals(&self) -> u8 {
-        TokenConfig::get_stable().decimals
+    /// Enables or disables transfers of the token. When disabled, the tokens can still be minted
+    /// and burned, which makes the token non-transferable (soulbound).
+    #[update(trait = true)]
+    fn set_transferable(&self, transferable: bool) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::Transferable(transferable)))
     }
 
-    /// Returns the default transfer fee.
     #[query(trait = true)]
-    fn icrc1_fee(&self) -> Tokens128 {
-        TokenConfig::get_stable().fee
+    fn is_paused(&self) -> bool {
+        TokenConfig::get_stable().is_paused()
     }
-    #[query(trait = true)]
-    fn icrc1_metadata(&self) -> Vec<(String, Value)> {
-        TokenConfig::get_stable().icrc1_metadata()
+
+    /// Pauses or resumes all the transfers of the token. While paused, the transfers are rejected
+    /// with `TxError::TokenPaused`, but the owner can still mint and burn tokens.
+    #[update(trait = true)]
+    fn set_paused(&self, paused: bool) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::Paused(paused)))
     }
 
     #[query(trait = true)]
-    fn icrc1_supported_standards(&self) -> Vec<StandardRecord> {
-        TokenConfig::get_stable().supported_standards()
+    fn rejects_zero_transfers(&self) -> bool {
+        TokenConfig::get_stable().is_zero_transfers_rejection_enabled()
     }
 
-    #[query(trait = true)]
-    fn icrc1_minting_account(&self) -> Option<Account> {
-        Some(TokenConfig::get_stable().owner.into())
+    /// If enabled, `transfer` and `batch_transfer` reject zero amounts with
+    /// `TxError::AmountTooSmall` before validating the transfer.
+    #[update(trait = true)]
+    fn set_reject_zero_transfers(&self, reject: bool) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::RejectZeroTransfers(reject)))
     }
 
-    /********************** INTERNAL METHODS ***********************/
+    /// Sets the maximum number of records returned by one page of the paginated queries. `None`
+    /// restores the default limits. Values above `MAX_QUERY_PAGE_SIZE` are capped to it.
+    #[update(trait = true)]
+    fn set_max_query_page_size(&self, size: Option<usize>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::MaxQueryPageSize(size)))
+    }
 
-    // Important: This function *must* be defined to be the
-    // last one in the trait because it depends on the order
-    // of expansion of update/query(trait = true) methods.
-    fn get_idl() -> Idl {
-        generate_idl!()
+    /// Sets the stable memory size (in bytes) starting from which the operations adding new
+    /// balance entries are rejected with `TxError::StorageFull`. Transfers between existing
+    /// accounts are still allowed.
+    #[update(trait = true)]
+    fn set_storage_high_water_mark(&self, mark: Option<u64>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::StorageHighWaterMark(mark)))
     }
 
-    fn update_stats(&self, _caller: CheckedPrincipal<Owner>, update: CanisterUpdate) {
-        use CanisterUpdate::*;
-        let mut stats = TokenConfig::get_stable();
-        match update {
-            Name(name) => stats.name = name,
-            Symbol(symbol) => stats.symbol = symbol,
-            Fee(fee) => stats.fee = fee,
-            FeeTo(fee_to) => stats.fee_to = fee_to,
-            Owner(owner) => stats.owner = owner,
-            MinCycles(min_cycles) => stats.min_cycles = min_cycles,
+    /********************** GOVERNANCE ***********************/
+
+    /// Sets the delay after which queued owner actions can be executed. While the delay is set,
+    /// all the configuration updates by the owner (including changing the delay itself) are queued
+    /// instead of being applied immediately. The methods making the updates return the id of the
+    /// queued action, or `None` if the update was applied.
+    #[update(trait = true)]
+    fn set_governance_delay(&self, delay_nanos: Option<u64>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::GovernanceDelay(delay_nanos)))
+    }
+
+    #[query(trait = true)]
+    fn pending_owner_actions(&self) -> Vec<PendingOwnerAction> {
+        PendingOwnerActions::list()
+    }
+
+    /// Applies the queued owner action, if its governance delay has passed.
+    #[update(trait = true)]
+    fn execute_owner_action(&self, id: u64) -> Result<(), TxError> {
+        check_maintenance()?;
+        CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        self.apply_update(PendingOwnerActions::take_executable(id)?);
+        Ok(())
+    }
+
+    /// Removes the queued owner action without applying it.
+    #[update(trait = true)]
+    fn cancel_owner_action(&self, id: u64) -> Result<(), TxError> {
+        check_maintenance()?;
+        CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        PendingOwnerActions::cancel(id)
+    }
+
+    /********************** BALANCES INFO ***********************/
+
+    /// This method retreieves holders of `Account` and their amounts. The holders are ordered by
+    /// the account, and the page starts after the `start` account, so the last account of a page
+    /// can be used as `start` of the next one.
+    #[query(trait = true)]
+    fn get_holders(&self, start: Option<Account>, limit: usize) -> Vec<(Account, Tokens128)> {
+        let limit = TokenConfig::get_stable()
+            .query_page_size(usize::MAX)
+            .min(limit);
+        let start = start.map(AccountInternal::from);
+        with_balance_tree(|tree| tree.holders_after(start.as_ref(), limit))
+            .into_iter()
+            .map(|(acc, amount)| (acc.into(), amount))
+            .collect()
+    }
+
+    /// Returns up to `n` accounts with the highest balances in descending order. Accounts with
+    /// equal balances are ordered as in `get_holders`. At most `MAX_TOP_HOLDERS` accounts are
+    /// returned, and the auction account is not listed.
+    #[query(trait = true)]
+    fn top_holders(&self, n: usize) -> Vec<(Account, Tokens128)> {
+        let n = n.min(MAX_TOP_HOLDERS);
+        let auction_account = auction_account();
+
+        // Min-heap of the best `n` holders seen so far, so that the worst of them is popped.
+        let mut top = BinaryHeap::with_capacity(n + 1);
+        for (acc, amount) in StableBalances.list_balances(0, usize::MAX) {
+            if acc == auction_account || amount.is_zero() {
+                continue;
+            }
+
+            let (owner, subaccount) = account_key(&acc);
+            top.push(Reverse((amount, Reverse((owner.to_vec(), *subaccount)))));
+            if top.len() > n {
+                top.pop();
+            }
+        }
+
+        top.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((amount, Reverse((owner, subaccount))))| {
+                let owner = Principal::from_slice(&owner);
+                (
+                    Account::from(AccountInternal::new(owner, Some(subaccount))),
+                    amount,
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the number of accounts with non-zero balances.
+    #[query(trait = true)]
+    fn holders_count(&self) -> u64 {
+        with_balance_tree(|tree| tree.nonzero_account_count()) as u64
+    }
+
+    /// Returns up to `count` holders with balances in the `[min, max]` range, skipping the first
+    /// `start` of them. The auction account is not listed.
+    #[query(trait = true)]
+    fn accounts_in_range(
+        &self,
+        min: Tokens128,
+        max: Tokens128,
+        start: usize,
+        count: usize,
+    ) -> Vec<(Account, Tokens128)> {
+        let count = TokenConfig::get_stable()
+            .query_page_size(usize::MAX)
+            .min(count);
+        StableBalances
+            .list_balances(0, usize::MAX)
+            .into_iter()
+            .filter(|(acc, amount)| *acc != auction_account() && (min..=max).contains(amount))
+            .skip(start)
+            .take(count)
+            .map(|(acc, amount)| (acc.into(), amount))
+            .collect()
+    }
+
+    /// Returns the balance of each of the `accounts`, zero for unknown accounts. At most
+    /// `MAX_BALANCE_QUERY_LEN` accounts are processed.
+    #[query(trait = true)]
+    fn balances_of(&self, accounts: Vec<Account>) -> Vec<Tokens128> {
+        accounts
+            .into_iter()
+            .take(MAX_BALANCE_QUERY_LEN)
+            .map(|account| StableBalances.balance_of(&account.into()))
+            .collect()
+    }
+
+    /// Returns the list of the caller's subaccounts with balances. If the caller account does not exist, will
+    /// return an empty list.
+    ///
+    /// It is intentional that the method does not accept the principal to list the subaccounts
+    /// for, because in some cases the token holder want to keep some of his subaccounts a secret.
+    /// So only own subaccounts can be listed safely.
+    #[query(trait = true)]
+    fn list_subaccounts(&self) -> std::collections::HashMap<Subaccount, Tokens128> {
+        StableBalances.get_subaccounts(ic::caller())
+    }
+
+    /********************** ACCOUNT SETTINGS ***********************/
+
+    /// Makes the caller's subaccount receive-only. Outgoing transfers from the subaccount are
+    /// rejected with `TxError::OutgoingDisabled` until the flag is unset, while incoming transfers
+    /// are still accepted.
+    #[update(trait = true)]
+    fn set_outgoing_disabled(
+        &self,
+        subaccount: Option<Subaccount>,
+        disabled: bool,
+    ) -> Result<(), TxError> {
+        check_maintenance()?;
+        let account = AccountInternal::new(ic::caller(), subaccount);
+        OutgoingDisabledAccounts::set(account, disabled);
+        Ok(())
+    }
+
+    #[query(trait = true)]
+    fn is_outgoing_disabled(&self, account: Account) -> bool {
+        OutgoingDisabledAccounts::contains(&account.into())
+    }
+
+    /// Enables or disables rejecting transfers to canisters which are not registered with
+    /// `register_token_aware_canister`. This protects users from locking their tokens in canisters
+    /// that can't handle them.
+    #[update(trait = true)]
+    fn set_block_canister_recipients(&self, enabled: bool) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::BlockCanisterRecipients(enabled)))
+    }
+
+    /// Allows transfers to the `canister` when blocking of canister recipients is enabled.
+    #[update(trait = true)]
+    fn register_token_aware_canister(&self, canister: Principal) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::TokenAwareCanister(canister)))
+    }
+
+    #[query(trait = true)]
+    fn token_aware_canisters(&self) -> Vec<Principal> {
+        TokenAwareCanisters::list()
+    }
+
+    /// Rejects all the transfers from and to the accounts of the `principal` with
+    /// `TxError::AccountFrozen`. The owner can still burn the balances of a frozen principal.
+    #[update(trait = true)]
+    fn freeze_account(&self, principal: Principal) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::FrozenAccount(principal, true)))
+    }
+
+    #[update(trait = true)]
+    fn unfreeze_account(&self, principal: Principal) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::FrozenAccount(principal, false)))
+    }
+
+    #[query(trait = true)]
+    fn is_frozen(&self, principal: Principal) -> bool {
+        FrozenAccounts::contains(&principal)
+    }
+
+    /// Allows the `principal` to mint tokens with `mint_as_minter`. Minters have no other owner
+    /// rights.
+    #[update(trait = true)]
+    fn add_minter(&self, principal: Principal) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::Minter(principal, true)))
+    }
+
+    #[update(trait = true)]
+    fn remove_minter(&self, principal: Principal) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::Minter(principal, false)))
+    }
+
+    #[query(trait = true)]
+    fn is_minter(&self, principal: Principal) -> bool {
+        Minters::contains(&principal)
+    }
+
+    /********************** CLAIMS ***********************/
+
+    #[cfg(feature = "claim")]
+    #[query(trait = true)]
+    fn get_claimable_amount(&self, holder: Principal, subaccount: Option<Subaccount>) -> Tokens128 {
+        StableBalances::get_claimable_amount(holder, subaccount)
+    }
+
+    #[cfg(feature = "claim")]
+    #[query(trait = true)]
+    fn get_claim_subaccount(
+        &self,
+        claimer: Principal,
+        claimer_subaccount: Option<Subaccount>,
+    ) -> Subaccount {
+        get_claim_subaccount(claimer, claimer_subaccount)
+    }
+
+    #[cfg(feature = "claim")]
+    #[update(trait = true)]
+    fn claim(&self, holder: Principal, subaccount: Option<Subaccount>) -> TxReceipt {
+        check_maintenance()?;
+        claim(holder, subaccount)
+    }
+
+    /// Transfers `amount` of the caller's tokens to the account identifier with the `to` address.
+    /// The owner of the account identifier can claim the tokens with `claim`, giving the caller as
+    /// the holder. The normal transfer fee is charged.
+    #[cfg(feature = "claim")]
+    #[update(trait = true)]
+    fn transfer_to_account_id(
+        &self,
+        from_subaccount: Option<Subaccount>,
+        to: Subaccount,
+        amount: Tokens128,
+        fee: Option<Tokens128>,
+    ) -> TxReceipt {
+        check_maintenance()?;
+        self.with_pool_trigger(transfer_to_account_id(
+            from_subaccount,
+            to,
+            amount,
+            fee,
+            self.fee_ratio(),
+        ))
+    }
+
+    /// Sets the period (in nanoseconds) after which the unclaimed claims expire. Expired claims
+    /// cannot be claimed anymore and are swept back to their holders by `reclaim_expired_claims`.
+    /// If `None`, the claims never expire.
+    #[cfg(feature = "claim")]
+    #[update(trait = true)]
+    fn set_claim_expiry(&self, expiry_nanos: Option<u64>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::ClaimExpiry(expiry_nanos)))
+    }
+
+    /// Moves the balances of the expired claims back to the default accounts of their holders.
+    /// Returns the total amount swept back.
+    #[cfg(feature = "claim")]
+    #[update(trait = true)]
+    fn reclaim_expired_claims(&self) -> Result<Tokens128, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        reclaim_expired_claims(caller)
+    }
+
+    /********************** CYCLE AUCTION ***********************/
+
+    /// Returns the accumulated fees amount at which the auction is run before the end of the
+    /// auction period.
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn auction_pool_trigger(&self) -> Option<Tokens128> {
+        TokenConfig::get_stable().auction_pool_trigger
+    }
+
+    /// Sets the accumulated fees amount at which the auction is run before the end of the auction
+    /// period. `None` disables early auctions.
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn set_auction_pool_trigger(&self, trigger: Option<Tokens128>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::AuctionPoolTrigger(trigger)))
+    }
+
+    /// Sets the minimum amount of cycles accepted as an auction bid. If `None`, the
+    /// `MIN_BIDDING_AMOUNT` of the auction is used.
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn set_min_auction_bid(&self, min_bid: Option<u64>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::MinAuctionBid(min_bid)))
+    }
+
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn min_auction_bid(&self) -> u64 {
+        is20_auction::min_auction_bid()
+    }
+
+    /// Returns the account holding the tokens accumulated in the auction pool.
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn auction_account(&self) -> Account {
+        auction_account().into()
+    }
+
+    /// Returns the reward the `bidder` would receive from the auction pool if the auction was run
+    /// now.
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn pending_reward_for(&self, bidder: Principal) -> Tokens128 {
+        is20_auction::pending_reward_for(&self.auction_state().borrow(), bidder)
+    }
+
+    /// Returns the results of the past auctions, the latest first, starting from the auction with
+    /// id `start` or from the latest one if `start` is `None`. At most
+    /// `MAX_AUCTION_HISTORY_REQUEST` auctions are returned.
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn get_auction_history(&self, start: Option<u64>, limit: usize) -> Vec<AuctionInfo> {
+        is20_auction::auction_history(
+            &self.auction_state().borrow(),
+            start,
+            limit.min(MAX_AUCTION_HISTORY_REQUEST),
+        )
+    }
+
+    /// Moves the auction pool to the `new_account` and makes it the auction account, so that all
+    /// subsequent auction fees accrue to it. The new account must be an empty subaccount of the
+    /// management canister, other than the escrow and vesting ones.
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn rotate_auction_account(&self, new_account: Account) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        if AccountInternal::from(new_account) == auction_account() {
+            return Ok(None);
+        }
+
+        is20_auction::check_auction_account(new_account.into())?;
+        Ok(self.update_stats(caller, CanisterUpdate::AuctionAccount(new_account)))
+    }
+
+    /// Returns the receiver of the tokens accumulated in the auction pool.
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn auction_beneficiary(&self) -> AuctionBeneficiary {
+        TokenConfig::get_stable()
+            .auction_beneficiary
+            .unwrap_or_default()
+    }
+
+    /// Sets the receiver of the tokens accumulated in the auction pool: the bidders (default), a
+    /// specific account, or nobody (the pool is burned).
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn set_auction_beneficiary(
+        &self,
+        beneficiary: AuctionBeneficiary,
+    ) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::AuctionBeneficiary(beneficiary)))
+    }
+
+    /// Returns the accumulated fractions of the auction fees, in `1 / DUST_UNITS_PER_TOKEN`
+    /// token units, which are not moved into the auction pool yet.
+    #[cfg(feature = "auction")]
+    #[query(trait = true)]
+    fn pending_auction_dust(&self) -> u64 {
+        AuctionDust::pending()
+    }
+
+    /// Enables or disables accumulating the fractions of the auction fees lost to rounding.
+    #[cfg(feature = "auction")]
+    #[update(trait = true)]
+    fn set_auction_dust_sinking(&self, enabled: bool) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::AuctionDustSinking(enabled)))
+    }
+
+    /********************** TRANSACTION HISTORY ***********************/
+
+    #[query(trait = true)]
+    fn history_size(&self) -> u64 {
+        LedgerData::len()
+    }
+
+    /// Returns the transaction with the given id. The archived transactions are requested from the
+    /// archive canister, so this is an update call. Returns `TxError::TransactionDoesNotExist` if
+    /// the id is out of range or the transaction is removed from the history without archiving.
+    #[update(trait = true)]
+    fn get_transaction(&self, id: TxId) -> AsyncReturn<Result<TxRecord, TxError>> {
+        Box::pin(
+            async move { is20_archive::get_transaction(ArchiveCanister::get().as_ref(), id).await },
+        )
+    }
+
+    /// Returns the `(owner_fee, auction_fee)` split of the fee charged by the transaction, as it
+    /// was applied at the time of the transaction.
+    #[query(trait = true)]
+    fn transaction_fee_split(&self, tx_id: TxId) -> Result<(Tokens128, Tokens128), TxError> {
+        LedgerData::get(tx_id)
+            .map(|record| record.fee_split())
+            .ok_or(TxError::TransactionDoesNotExist)
+    }
+
+    /// Returns all the transactions of the batch transfer. The `batch_id` is the id of the first
+    /// transaction of the batch, as returned by `batch_transfer`.
+    #[query(trait = true)]
+    fn get_batch(&self, batch_id: TxId) -> Vec<TxRecord> {
+        LedgerData::get_batch(batch_id)
+    }
+
+    /// Returns a list of transactions in paginated form. The `who` is optional, if given, only transactions of the `who` are
+    /// returned. `count` is the number of transactions to return, `transaction_id` is the transaction index which is used as
+    /// the offset of the first transaction to return, any
+    ///
+    /// It returns `PaginatedResult` a struct, which contains `result` which is a list of transactions `Vec<TxRecord>` that meet the requirements of the query,
+    /// and `next_id` which is the index of the next transaction to return.
+    ///
+    /// Once the stored transactions are exhausted, the page is continued with the transactions of
+    /// the archive canister, so this is an update call.
+    #[update(trait = true)]
+    fn get_transactions(
+        &self,
+        who: Option<Principal>,
+        count: usize,
+        transaction_id: Option<TxId>,
+    ) -> AsyncReturn<PaginatedResult> {
+        let default_count =
+            who.map_or(MAX_TRANSACTION_REQUEST, |_| MAX_ACCOUNT_TRANSACTION_REQUEST);
+        let count = TokenConfig::get_stable()
+            .query_page_size(default_count)
+            .min(count);
+
+        Box::pin(async move {
+            let archive = ArchiveCanister::get();
+            is20_archive::get_transactions(archive.as_ref(), who, count, transaction_id).await
+        })
+    }
+
+    #[query(trait = true)]
+    fn archive_options(&self) -> Option<ArchiveOptions> {
+        TokenConfig::get_stable().archive_options
+    }
+
+    /// Sets the archive canister and when the transactions are moved to it. `None` disables the
+    /// archiving, in which case the oldest transactions are dropped once the history is full.
+    #[update(trait = true)]
+    fn set_archive_options(&self, options: Option<ArchiveOptions>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::ArchiveOptions(options)))
+    }
+
+    /// Id of the oldest transaction stored in the canister. The older ones are in the archive or
+    /// removed from the history.
+    #[query(trait = true)]
+    fn first_local_index(&self) -> TxId {
+        LedgerData::first_local_index()
+    }
+
+    /// Moves the oldest transactions to the archive canister, if more than the `trigger_threshold`
+    /// of them are stored. Returns the number of the archived transactions.
+    #[update(trait = true)]
+    fn archive_transactions(&self) -> AsyncReturn<Result<u64, TxError>> {
+        Box::pin(async move {
+            check_maintenance()?;
+            let stats = TokenConfig::get_stable();
+            CheckedPrincipal::owner(&stats)?;
+            let options = stats.archive_options.ok_or(TxError::ArchiveNotSet)?;
+            is20_archive::archive_transactions(&ArchiveCanister(options.archive), options).await
+        })
+    }
+
+    /// Returns up to `count` transactions recorded after the `cursor`, the oldest first, and the
+    /// cursor for the next page. Pass `None` to start from the oldest stored transaction. The
+    /// returned cursor is `None` when the end of the history is reached.
+    ///
+    /// Unlike offset pagination, the cursor stays valid when old records are removed from the
+    /// history. If the records following the cursor were removed, `TxError::CursorPruned` is
+    /// returned with the id of the first available transaction.
+    #[query(trait = true)]
+    fn get_transactions_cursor(
+        &self,
+        cursor: Option<Cursor>,
+        count: u32,
+    ) -> Result<(Vec<TxRecord>, Option<Cursor>), TxError> {
+        let count = TokenConfig::get_stable()
+            .query_page_size(MAX_TRANSACTION_REQUEST)
+            .min(count as usize);
+
+        LedgerData::transactions_after(cursor, count)
+    }
+
+    /// Returns the total number of transactions related to the user `who`.
+    #[query(trait = true)]
+    fn get_user_transaction_count(&self, who: Principal) -> usize {
+        LedgerData::get_len_user_history(who)
+    }
+
+    /// Returns true if the `account` has ever sent or received tokens. Unlike a non-zero balance,
+    /// this also holds for accounts that were active but have been emptied since then.
+    #[query(trait = true)]
+    fn has_transacted(&self, account: Account) -> bool {
+        LedgerData::has_transacted(account.into())
+    }
+
+    /// Returns the net change of the `account` balance made by the transactions with ids greater
+    /// than `since_tx`. Combined with a balance value cached at `since_tx`, it allows a wallet to
+    /// update the balance without re-fetching it.
+    ///
+    /// Fees received by the `fee_to` account are not recorded in the transactions, so they are not
+    /// included in the result. Returns `TxError::HistoryPruned` if some of the transactions after
+    /// `since_tx` are already removed from the history.
+    #[query(trait = true)]
+    fn balance_delta_since(
+        &self,
+        account: Account,
+        since_tx: TxId,
+    ) -> Result<BalanceDelta, TxError> {
+        LedgerData::balance_delta_since(account.into(), since_tx)
+    }
+
+    /// Returns the ids of the transactions involving the `account` with ids in the range
+    /// `from_id..=to_id`, in ascending order. Returning only ids allows clients to fetch just the
+    /// records they don't have yet.
+    #[query(trait = true)]
+    fn account_tx_ids(&self, account: Account, from_id: TxId, to_id: TxId) -> Vec<TxId> {
+        let limit = TokenConfig::get_stable().query_page_size(MAX_ACCOUNT_TRANSACTION_REQUEST);
+        LedgerData::account_tx_ids(account.into(), from_id, to_id, limit)
+    }
+
+    /// Returns up to `limit` transactions in which the `account` is the sender, the recipient or
+    /// the caller, the newest first. If `start` is given, only transactions with ids not greater
+    /// than `start` are returned.
+    #[query(trait = true)]
+    fn get_account_transactions(
+        &self,
+        account: Account,
+        start: Option<TxId>,
+        limit: usize,
+    ) -> Vec<TxRecord> {
+        let limit = TokenConfig::get_stable()
+            .query_page_size(MAX_TRANSACTION_REQUEST)
+            .min(limit);
+        LedgerData::get_account_transactions(account.into(), start, limit)
+    }
+
+    /// Returns up to `count` transactions tagged with the `tag`, starting from the `start`-th of
+    /// them in the order they were recorded.
+    #[query(trait = true)]
+    fn transactions_by_tag(&self, tag: String, start: usize, count: usize) -> Vec<TxRecord> {
+        let count = TokenConfig::get_stable()
+            .query_page_size(MAX_TRANSACTION_REQUEST)
+            .min(count);
+        LedgerData::transactions_by_tag(&tag, start, count)
+    }
+
+    /// Returns the balance and up to `tx_count` most recent transactions (the newest first) for
+    /// each of the `accounts`. At most `MAX_SNAPSHOT_ACCOUNTS` accounts and
+    /// `MAX_SNAPSHOT_ACCOUNT_TRANSACTIONS` transactions per account are returned.
+    #[query(trait = true)]
+    fn accounts_snapshot(
+        &self,
+        accounts: Vec<Account>,
+        tx_count: u32,
+    ) -> Vec<(Account, Tokens128, Vec<TxRecord>)> {
+        let tx_count = (tx_count as usize).min(MAX_SNAPSHOT_ACCOUNT_TRANSACTIONS);
+        accounts
+            .into_iter()
+            .take(MAX_SNAPSHOT_ACCOUNTS)
+            .map(|account| {
+                let balance = StableBalances.balance_of(&account.into());
+                let transactions = LedgerData::account_transactions(account.into(), tx_count);
+                (account, balance, transactions)
+            })
+            .collect()
+    }
+
+    /// Returns up to `count` most recent failed transfer attempts with their errors, the newest
+    /// first. Failed attempts are stored only while the failed transactions log is enabled.
+    #[query(trait = true)]
+    fn recent_failures(&self, count: u32) -> Vec<FailedTx> {
+        FailedTxLog::recent(count as usize)
+    }
+
+    /// Turns the maintenance mode on or off. In the maintenance mode the queries work as usual,
+    /// but all the other update methods are rejected. The mode is switched immediately, even if
+    /// the governance delay is set, so the owner can always turn it off.
+    #[update(trait = true)]
+    fn set_maintenance_mode(&self, enabled: bool) -> Result<(), TxError> {
+        let mut stats = TokenConfig::get_stable();
+        CheckedPrincipal::owner(&stats)?;
+        stats.maintenance = Some(enabled);
+        TokenConfig::set_stable(stats);
+        Ok(())
+    }
+
+    #[query(trait = true)]
+    fn is_under_maintenance(&self) -> bool {
+        TokenConfig::get_stable().is_under_maintenance()
+    }
+
+    #[update(trait = true)]
+    fn set_failed_tx_log_enabled(&self, enabled: bool) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::FailedTxLogEnabled(enabled)))
+    }
+
+    /// Returns up to `count` analytics records of the transactions with ids starting from
+    /// `start`. The records are stored only while the analytics ledger is enabled.
+    #[query(trait = true)]
+    fn get_analytics(&self, start: TxId, count: u32) -> Vec<AnalyticsRecord> {
+        let count = TokenConfig::get_stable()
+            .query_page_size(MAX_TRANSACTION_REQUEST)
+            .min(count as usize);
+        AnalyticsLedger::list(start, count)
+    }
+
+    #[update(trait = true)]
+    fn set_analytics_enabled(&self, enabled: bool) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::AnalyticsEnabled(enabled)))
+    }
+
+    /********************** IS20 TRANSACTIONS ***********************/
+
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn transfer(&self, transfer: TransferArgs) -> Result<u128, TxError> {
+        check_maintenance()?;
+        self.with_pool_trigger(
+            CheckedAccount::with_recipient(transfer.to.into(), transfer.from_subaccount)
+                .and_then(|account| is20_transfer(account, &transfer, self.fee_ratio())),
+        )
+        .map_err(|err| log_failure(&transfer, err))
+    }
+
+    /// Transfers the tokens and notifies the recipient canister by calling its `notify_method`
+    /// with the transaction id and the received amount. The transfer is committed even if the
+    /// notification fails, in which case the rejection is returned in `notify_error`.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn transfer_notify(
+        &self,
+        transfer: TransferArgs,
+        notify_method: String,
+    ) -> AsyncReturn<Result<NotifyReceipt, TxError>> {
+        // The transfer is committed before the notification is sent.
+        let tx_id = check_maintenance().and_then(|_| {
+            CheckedAccount::with_recipient(transfer.to.into(), transfer.from_subaccount)
+                .and_then(|account| is20_transfer(account, &transfer, self.fee_ratio()))
+                .map_err(|err| log_failure(&transfer, err))
+        });
+        let tx_id = self.with_pool_trigger(tx_id);
+        Box::pin(async move { Ok(notify_transfer(tx_id?, &transfer, notify_method).await) })
+    }
+
+    /// Validates the transfer without executing it and returns a token to commit it with. The
+    /// commit charges exactly the returned fee, if done before the token expires.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn prepare_transfer(&self, transfer: TransferArgs) -> Result<PreparedTransfer, TxError> {
+        check_maintenance()?;
+        CheckedAccount::with_recipient(transfer.to.into(), transfer.from_subaccount)
+            .and_then(|account| prepare_transfer(account, &transfer))
+            .map_err(|err| log_failure(&transfer, err))
+    }
+
+    /// Executes the transfer prepared by `prepare_transfer`. Each token can be committed only
+    /// once.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn commit_transfer(&self, token: u64) -> Result<u128, TxError> {
+        check_maintenance()?;
+        self.with_pool_trigger(commit_transfer(token, self.fee_ratio()))
+    }
+
+    /// Quotes the fee for a transfer of the `amount` by the caller. Until the quote expires, a
+    /// transfer of the same amount with `max_fee` not lower than the quoted fee is charged at most
+    /// the quoted fee, even if the fee is raised in the meantime. Only the latest quote of the
+    /// caller is kept.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn quote_fee(&self, amount: Tokens128) -> FeeQuote {
+        let stats = TokenConfig::get_stable();
+        FeeQuotes::quote(
+            amount,
+            stats.effective_fee(amount),
+            stats.fee_quote_ttl_nanos(),
+        )
+    }
+
+    /// Sets the period (in nanoseconds) during which a fee quote is honored. If `None`, the
+    /// default period is used.
+    #[update(trait = true)]
+    fn set_fee_quote_ttl(&self, ttl_nanos: Option<u64>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::FeeQuoteTtl(ttl_nanos)))
+    }
+
+    /// Sets the period (in nanoseconds) during which a prepared transfer can be committed. If
+    /// `None`, the default period is used. The period is limited by the deduplication window.
+    #[update(trait = true)]
+    fn set_prepared_transfer_ttl(&self, ttl_nanos: Option<u64>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::PreparedTransferTtl(ttl_nanos)))
+    }
+
+    /// Sets the period (in nanoseconds) during which transactions with `created_at_time` are
+    /// accepted and deduplicated. If `None`, `TX_WINDOW` is used. The period cannot exceed
+    /// `MAX_TX_WINDOW_NANOS`.
+    #[update(trait = true)]
+    fn set_tx_window(&self, window_nanos: Option<u64>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        check_tx_window(window_nanos)?;
+        Ok(self.update_stats(caller, CanisterUpdate::TxWindow(window_nanos)))
+    }
+
+    /// Sets the allowed clock difference (in nanoseconds) for the transactions created in the
+    /// future. If `None`, `PERMITTED_DRIFT` is used. The drift cannot exceed
+    /// `MAX_TX_WINDOW_NANOS`.
+    #[update(trait = true)]
+    fn set_permitted_drift(&self, drift_nanos: Option<u64>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        check_tx_window(drift_nanos)?;
+        Ok(self.update_stats(caller, CanisterUpdate::PermittedDrift(drift_nanos)))
+    }
+
+    /// Takes a list of transfers, each of which is a pair of `to` and `value` fields, it returns a `TxReceipt` which contains
+    /// a vec of transaction index or an error message. The list of transfers is processed in the order they are given. if the `fee`
+    /// is set, the `fee` amount is applied to each transfer.
+    /// The balance of the caller is reduced by sum of `value + fee` amount for each transfer. If the total sum of `value + fee` for all transfers,
+    /// is less than the `balance` of the caller, the transaction will fail with `TxError::InsufficientBalance` error.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn batch_transfer(
+        &self,
+        from_subaccount: Option<Subaccount>,
+        transfers: Vec<BatchTransferArgs>,
+    ) -> Result<Vec<TxId>, TxError> {
+        check_maintenance()?;
+        for x in &transfers {
+            let recipient = x.receiver;
+            CheckedAccount::with_recipient(recipient.into(), from_subaccount)?;
+        }
+        self.with_pool_trigger(batch_transfer(from_subaccount, transfers, self.fee_ratio()))
+    }
+
+    /// Same as `batch_transfer`, but each transfer is applied independently: a failed transfer is
+    /// skipped, while the others are still applied and recorded. Returns the result of each
+    /// transfer in the order they are given.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn batch_transfer_best_effort(
+        &self,
+        from_subaccount: Option<Subaccount>,
+        transfers: Vec<BatchTransferArgs>,
+    ) -> Result<Vec<Result<TxId, TxError>>, TxError> {
+        check_maintenance()?;
+        self.with_pool_trigger(batch_transfer_best_effort(
+            from_subaccount,
+            transfers,
+            self.fee_ratio(),
+        ))
+    }
+
+    /// Sets the maximum number of batch operations one caller can have in flight. If `None`, the
+    /// default limit is used.
+    #[update(trait = true)]
+    fn set_max_concurrent_batches(&self, limit: Option<u32>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::MaxConcurrentBatches(limit)))
+    }
+
+    /// Number of the batch operations of the `caller` currently in flight.
+    #[query(trait = true)]
+    fn in_flight_batches(&self, caller: Principal) -> u32 {
+        BatchGuard::in_flight(caller)
+    }
+
+    /// Transfers the tokens drawn from several subaccounts of the caller to `to`. Each of the
+    /// `sources` is a pair of the caller's subaccount and the amount to debit from it. The
+    /// recipient gets the sum of the amounts minus a single transfer fee. The fee, the deadline,
+    /// the tags and `created_at_time` are checked as for `transfer`, with each source
+    /// deduplicated as a separate transfer.
+    ///
+    /// Either all of the sources are debited or none of them. Returns ids of the transactions
+    /// recorded for each source.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn multi_source_transfer(&self, args: MultiSourceTransferArgs) -> Result<Vec<TxId>, TxError> {
+        check_maintenance()?;
+        self.with_pool_trigger(multi_source_transfer(&args, self.fee_ratio()))
+    }
+
+    /// Mints tokens to each of the `mints` targets, given as `(owner, subaccount, amount)`. If any
+    /// of the mints fails, none of them is applied.
+    ///
+    /// Returns ids of the mint transactions in the order of the `mints`.
+    #[cfg_attr(feature = "mint_burn", update(trait = true))]
+    fn batch_mint(
+        &self,
+        mints: Vec<(Principal, Option<Subaccount>, Tokens128)>,
+    ) -> Result<Vec<TxId>, TxError> {
+        check_maintenance()?;
+        let owner = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        batch_mint(owner, &mints)
+    }
+
+    /// Mints `amount` of tokens to the `to` account. The `memo` is stored in the mint transaction.
+    #[cfg_attr(feature = "mint_burn", update(trait = true))]
+    fn mint(
+        &self,
+        to: Principal,
+        to_subaccount: Option<Subaccount>,
+        amount: Tokens128,
+        memo: Option<Memo>,
+    ) -> TxReceipt {
+        check_maintenance()?;
+        if self.is_test_token() {
+            let test_user = CheckedPrincipal::test_user(&TokenConfig::get_stable())?;
+            mint_test_token(test_user, to, to_subaccount, amount, memo)
+        } else {
+            let owner = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+            mint_as_owner(owner, to, to_subaccount, amount, memo)
+        }
+    }
+
+    /// Mints `amount` of tokens to the `to` account. Can be called only by the minters added with
+    /// `add_minter`.
+    #[cfg_attr(feature = "mint_burn", update(trait = true))]
+    fn mint_as_minter(
+        &self,
+        to: Principal,
+        to_subaccount: Option<Subaccount>,
+        amount: Tokens128,
+        memo: Option<Memo>,
+    ) -> TxReceipt {
+        check_maintenance()?;
+        let minter = CheckedPrincipal::minter()?;
+        mint_as_minter(minter, to, to_subaccount, amount, memo)
+    }
+
+    /// Burn `amount` of tokens from `from` principal.
+    /// If `from` is None, then caller's tokens will be burned.
+    /// If `from` is Some(_) but method called not by owner, `TxError::Unauthorized` will be returned.
+    /// If owner calls this method and `from` is Some(who), then who's tokens will be burned.
+    /// The `memo` is stored in the burn transaction.
+    #[cfg_attr(feature = "mint_burn", update(trait = true))]
+    fn burn(
+        &self,
+        from: Option<Principal>,
+        from_subaccount: Option<Subaccount>,
+        amount: Tokens128,
+        memo: Option<Memo>,
+    ) -> TxReceipt {
+        check_maintenance()?;
+        match from {
+            None => burn_own_tokens(from_subaccount, amount, memo),
+            Some(from) if from == canister_sdk::ic_kit::ic::caller() => {
+                burn_own_tokens(from_subaccount, amount, memo)
+            }
+            Some(from) => {
+                let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+                burn_as_owner(caller, from, from_subaccount, amount, memo)
+            }
+        }
+    }
+
+    /// Burns the tokens from each of the `burns` accounts. If any of the accounts has insufficient
+    /// funds, none of the burns is applied.
+    ///
+    /// Returns ids of the burn transactions in the order of the `burns`.
+    #[cfg_attr(feature = "mint_burn", update(trait = true))]
+    fn batch_burn(&self, burns: Vec<(Account, Tokens128)>) -> Result<Vec<TxId>, TxError> {
+        check_maintenance()?;
+        let owner = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        batch_burn(owner, &burns)
+    }
+
+    /// Burns `amount` of the caller's tokens and records a `Redemption` transaction carrying the
+    /// `redemption_ref`, so an off-chain processor can match the burn with the payout.
+    #[cfg_attr(feature = "mint_burn", update(trait = true))]
+    fn redeem(&self, amount: Tokens128, redemption_ref: Vec<u8>) -> TxReceipt {
+        check_maintenance()?;
+        redeem(amount, redemption_ref)
+    }
+
+    /********************** ESCROW ***********************/
+
+    /// Locks `amount` of the caller's tokens until they are released to `to` by the caller or by
+    /// the arbiter of the `condition`. Once the condition times out, the tokens can only be
+    /// refunded to the caller. The transfer fee is charged when the tokens are locked.
+    ///
+    /// Returns id of the escrow.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn transfer_conditional(
+        &self,
+        to: Account,
+        amount: Tokens128,
+        condition: Condition,
+    ) -> Result<u64, TxError> {
+        check_maintenance()?;
+        let caller = CheckedAccount::with_recipient(to.into(), None)?;
+        self.with_pool_trigger(transfer_conditional(
+            caller,
+            amount,
+            condition,
+            self.fee_ratio(),
+        ))
+    }
+
+    /// Transfers the escrowed tokens to the recipient. Can be called by the sender or the arbiter
+    /// before the escrow expires.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn release_escrow(&self, id: u64) -> TxReceipt {
+        check_maintenance()?;
+        release_escrow(id)
+    }
+
+    /// Returns the escrowed tokens to the sender. Can be called by anyone after the escrow
+    /// expires.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn refund_escrow(&self, id: u64) -> TxReceipt {
+        check_maintenance()?;
+        refund_escrow(id)
+    }
+
+    #[query(trait = true)]
+    fn get_escrow(&self, id: u64) -> Option<Escrow> {
+        Escrows::get(id).ok()
+    }
+
+    /// Locks the transferred tokens until `release_at`, after which the recipient can claim them
+    /// with `claim_timelocked`. Until then the caller can cancel the transfer. The transfer fee and
+    /// the tip are charged when the tokens are locked.
+    ///
+    /// Returns id of the timelock.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn transfer_timelocked(
+        &self,
+        transfer: TransferArgs,
+        release_at: Timestamp,
+    ) -> Result<u64, TxError> {
+        check_maintenance()?;
+        let caller = CheckedAccount::with_recipient(transfer.to.into(), transfer.from_subaccount)?;
+        self.with_pool_trigger(transfer_timelocked(
+            caller,
+            &transfer,
+            release_at,
+            self.fee_ratio(),
+        ))
+    }
+
+    /// Transfers the tokens of all the released timelocks sent to the caller.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn claim_timelocked(&self) -> Result<Vec<TxId>, TxError> {
+        check_maintenance()?;
+        claim_timelocked()
+    }
+
+    /// Returns the timelocked tokens to the caller. Can be called only by the sender before the
+    /// tokens are released.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn cancel_timelocked(&self, id: u64) -> TxReceipt {
+        check_maintenance()?;
+        cancel_timelocked(id)
+    }
+
+    /// Returns the timelocked transfers sent to the `recipient`, which are not claimed yet.
+    #[query(trait = true)]
+    fn timelocks_of(&self, recipient: Principal) -> Vec<Timelock> {
+        Timelocks::for_recipient(recipient)
+    }
+
+    /********************** VESTING ***********************/
+
+    /// Mints `total` tokens locked in a vesting schedule for the `beneficiary`. Nothing can be
+    /// released before `start + cliff_nanos`, after that the tokens vest linearly until
+    /// `start + duration_nanos`. If `start` is not given, the schedule starts now.
+    ///
+    /// Returns id of the schedule.
+    #[cfg_attr(feature = "mint_burn", update(trait = true))]
+    fn create_vesting(
+        &self,
+        beneficiary: Account,
+        total: Tokens128,
+        start: Option<Timestamp>,
+        cliff_nanos: u64,
+        duration_nanos: u64,
+    ) -> Result<u64, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        create_vesting(
+            caller,
+            beneficiary,
+            total,
+            start.unwrap_or_else(ic::time),
+            cliff_nanos,
+            duration_nanos,
+        )
+    }
+
+    /// Transfers the tokens vested so far to the beneficiary. Can be called only by the
+    /// beneficiary of the schedule.
+    #[update(trait = true)]
+    fn release_vested(&self, id: u64) -> TxReceipt {
+        check_maintenance()?;
+        release_vested(id)
+    }
+
+    #[query(trait = true)]
+    fn get_vesting(&self, id: u64) -> Option<VestingSchedule> {
+        Vestings::get(id).ok()
+    }
+
+    /// Returns the vesting schedules of the `beneficiary` which are not fully released yet.
+    #[query(trait = true)]
+    fn vesting_schedules_of(&self, beneficiary: Principal) -> Vec<VestingSchedule> {
+        Vestings::for_beneficiary(beneficiary)
+    }
+
+    /********************** BALANCES MIGRATION ***********************/
+
+    /// Credits balances migrated from another token canister. The method is intended to be
+    /// called repeatedly with chunks of the source balances until the migration is complete. If
+    /// `chunk_index` is given, importing the same chunk for the second time returns
+    /// `TxError::ChunkAlreadyImported`. A chunk which would take the total supply above the
+    /// maximum supply is rejected with `TxError::MaxSupplyExceeded`.
+    ///
+    /// Returns the total amount of tokens imported so far.
+    #[update(trait = true)]
+    fn import_balances_chunk(
+        &self,
+        entries: Vec<(Account, Tokens128)>,
+        chunk_index: Option<u64>,
+    ) -> Result<Tokens128, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        import_balances_chunk(caller, entries, chunk_index)
+    }
+
+    /// Total amount of tokens imported with `import_balances_chunk`.
+    #[query(trait = true)]
+    fn migration_imported_total(&self) -> Tokens128 {
+        MigrationState::get_stable().imported_total
+    }
+
+    /********************** DORMANT BALANCES ***********************/
+
+    #[query(trait = true)]
+    fn dormancy_period(&self) -> Option<u64> {
+        TokenConfig::get_stable().dormancy_period_nanos
+    }
+
+    /// Sets the period of inactivity (in nanoseconds) after which the owner can reclaim the
+    /// balance of an account with `reclaim_dormant`. `None` disables reclaiming.
+    ///
+    /// Reclaiming takes tokens from their holders without their consent, so the period must be
+    /// announced to the holders in advance and be long enough for it to be reasonable to consider
+    /// the balance abandoned (years rather than months).
+    #[update(trait = true)]
+    fn set_dormancy_period(&self, period_nanos: Option<u64>) -> Result<Option<u64>, TxError> {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        Ok(self.update_stats(caller, CanisterUpdate::DormancyPeriod(period_nanos)))
+    }
+
+    /// Moves the whole balance of the `account` to the owner's default account, if the account
+    /// had no transactions for longer than the dormancy period. Accounts without transactions in
+    /// the history (e.g. after an upgrade) are not considered dormant. The reclaim is recorded in
+    /// the history as a `Reclaim` operation.
+    ///
+    /// Returns `TxError::DormancyReclaimDisabled` if the dormancy period is not set,
+    /// `TxError::AccountNotDormant` if the account was active during the period or has no
+    /// recorded activity, `TxError::ReservedAccount` for the accounts holding the auction, escrow
+    /// and vesting tokens, and `TxError::AccountFrozen` for the frozen accounts.
+    #[update(trait = true)]
+    fn reclaim_dormant(&self, account: Account) -> TxReceipt {
+        check_maintenance()?;
+        let caller = CheckedPrincipal::owner(&TokenConfig::get_stable())?;
+        reclaim_dormant(caller, account.into())
+    }
+
+    /********************** ICRC-1 METHODS ***********************/
+
+    #[query(trait = true)]
+    fn icrc1_balance_of(&self, account: Account) -> Tokens128 {
+        StableBalances.balance_of(&account.into())
+    }
+
+    /// Returns the balance of the `account` with the certificate of the canister state and the
+    /// merkle path from the balance to the certified balances root. The balance can be trusted if
+    /// `CertifiedBalance::certified_data` is equal to the data in the certificate.
+    #[query(trait = true)]
+    fn icrc1_balance_of_certified(&self, account: Account) -> CertifiedBalance {
+        CertifiedBalance::get(account.into())
+    }
+
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn icrc1_transfer(&self, transfer: TransferArgs) -> Result<u128, TransferError> {
+        check_maintenance()?;
+        let account = CheckedAccount::with_recipient(transfer.to.into(), transfer.from_subaccount)
+            .map_err(|err| log_failure(&transfer, err))?;
+
+        Ok(self
+            .with_pool_trigger(icrc1_transfer(account, &transfer, self.fee_ratio()))
+            .map_err(|err| log_failure(&transfer, err))?)
+    }
+
+    #[query(trait = true)]
+    fn icrc1_name(&self) -> String {
+        TokenConfig::get_stable().name
+    }
+
+    #[query(trait = true)]
+    fn icrc1_symbol(&self) -> String {
+        TokenConfig::get_stable().symbol
+    }
+
+    #[query(trait = true)]
+    fn icrc1_decimals(&self) -> u8 {
+        TokenConfig::get_stable().decimals
+    }
+
+    /// Returns the default transfer fee. If the dynamic fee is set, the fee for the current ledger
+    /// length is returned. If the fee is charged in basis points of the amount, the minimum fee is
+    /// returned, and the fee of a specific amount is given by `effective_fee`.
+    #[query(trait = true)]
+    fn icrc1_fee(&self) -> Tokens128 {
+        TokenConfig::get_stable().icrc1_fee()
+    }
+    #[query(trait = true)]
+    fn icrc1_metadata(&self) -> Vec<(String, Value)> {
+        TokenConfig::get_stable().icrc1_metadata()
+    }
+
+    #[query(trait = true)]
+    fn icrc1_supported_standards(&self) -> Vec<StandardRecord> {
+        TokenConfig::get_stable().supported_standards()
+    }
+
+    #[query(trait = true)]
+    fn icrc1_minting_account(&self) -> Option<Account> {
+        Some(TokenConfig::get_stable().owner.into())
+    }
+
+    /********************** ICRC-2 METHODS ***********************/
+
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn icrc2_approve(&self, args: ApproveArgs) -> TxReceipt {
+        check_maintenance()?;
+        self.with_pool_trigger(icrc2_approve(&args, self.fee_ratio()))
+    }
+
+    #[query(trait = true)]
+    fn icrc2_allowance(&self, owner: Account, spender: Account) -> Tokens128 {
+        Allowances::get(owner.into(), spender.into())
+    }
+
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn icrc2_transfer_from(&self, args: TransferFromArgs) -> TxReceipt {
+        check_maintenance()?;
+        self.with_pool_trigger(icrc2_transfer_from(&args, self.fee_ratio()))
+    }
+
+    /// Increases the allowance of the `spender` over the caller's `from_subaccount` by `delta`.
+    /// Prefer this to `icrc2_approve` when changing an existing allowance, as the spender cannot
+    /// front-run the change to spend both the old and the new allowance.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn increase_allowance(
+        &self,
+        from_subaccount: Option<Subaccount>,
+        spender: Account,
+        delta: Tokens128,
+    ) -> TxReceipt {
+        check_maintenance()?;
+        self.with_pool_trigger(increase_allowance(
+            from_subaccount,
+            spender,
+            delta,
+            self.fee_ratio(),
+        ))
+    }
+
+    /// Decreases the allowance of the `spender` over the caller's `from_subaccount` by `delta`,
+    /// saturating at zero.
+    #[cfg_attr(feature = "transfer", update(trait = true))]
+    fn decrease_allowance(
+        &self,
+        from_subaccount: Option<Subaccount>,
+        spender: Account,
+        delta: Tokens128,
+    ) -> TxReceipt {
+        check_maintenance()?;
+        self.with_pool_trigger(decrease_allowance(
+            from_subaccount,
+            spender,
+            delta,
+            self.fee_ratio(),
+        ))
+    }
+
+    /********************** ICRC-3 METHODS ***********************/
+
+    /// Returns the transactions in the requested ranges as ICRC-3 blocks. Each block contains the
+    /// hash of the previous one. Only the blocks stored in the canister are returned, at most
+    /// `MAX_TRANSACTION_REQUEST` of them in total.
+    #[query(trait = true)]
+    fn icrc3_get_blocks(&self, args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+        let mut blocks = vec![];
+        for range in args {
+            let limit = MAX_TRANSACTION_REQUEST - blocks.len();
+            blocks.extend(LedgerData::get_blocks(range.start(), range.length(), limit));
+        }
+
+        GetBlocksResult {
+            log_length: LedgerData::len().into(),
+            blocks,
+        }
+    }
+
+    /********************** INTERNAL METHODS ***********************/
+
+    // Important: This function *must* be defined to be the
+    // last one in the trait because it depends on the order
+    // of expansion of update/query(trait = true) methods.
+    fn get_idl() -> Idl {
+        generate_idl!()
+    }
+
+    /// Applies the owner update to the canister configuration. If the governance delay is set, the
+    /// update is queued instead and can be executed with `execute_owner_action` after the delay.
+    ///
+    /// Returns id of the queued action, or `None` if the update was applied.
+    fn update_stats(
+        &self,
+        _caller: CheckedPrincipal<Owner>,
+        update: CanisterUpdate,
+    ) -> Option<u64> {
+        match TokenConfig::get_stable().governance_delay_nanos {
+            Some(delay) => Some(PendingOwnerActions::queue(update, delay)),
+            None => {
+                self.apply_update(update);
+                None
+            }
+        }
+    }
+
+    /// Applies the `update` to the token configuration. The auction controller follows the owner
+    /// of the canister.
+    fn apply_update(&self, update: CanisterUpdate) {
+        #[cfg(feature = "auction")]
+        if let CanisterUpdate::Owner(owner) = update {
+            self.auction_state().borrow_mut().controller = owner;
+        }
+
+        update.apply();
+    }
+
+    /// Runs the auction before the end of the auction period if the fees charged by the successful
+    /// transaction have filled the pool up to the `auction_pool_trigger`. The transaction stays
+    /// committed whatever the result of the auction is.
+    fn with_pool_trigger<T, E>(&self, result: Result<T, E>) -> Result<T, E> {
+        #[cfg(feature = "auction")]
+        if result.is_ok() {
+            let _ = is20_auction::run_auction_on_pool_trigger(self);
+        }
+
+        result
+    }
+
+    fn fee_ratio(&self) -> f64 {
+        #[cfg(feature = "auction")]
+        return TokenConfig::get_stable().effective_fee_ratio(self.bidding_info().fee_ratio);
+
+        #[cfg(not(feature = "auction"))]
+        0.0
+    }
+}
+
+generate_exports!(TokenCanisterAPI, TokenCanisterExports);
+
+#[cfg(feature = "auction")]
+use canister_sdk::ic_metrics::Interval;
+#[cfg(feature = "auction")]
+use canister_sdk::ic_storage::IcStorage;
+
+#[cfg(feature = "auction")]
+impl Auction for TokenCanisterExports {
+    fn auction_state(&self) -> std::rc::Rc<std::cell::RefCell<AuctionState>> {
+        AuctionState::get()
+    }
+
+    fn disburse_rewards(&self) -> Result<AuctionInfo, AuctionError> {
+        is20_auction::disburse_rewards(&self.auction_state().borrow())
+    }
+
+    fn set_auction_period(&self, interval: Interval) -> Result<(), AuctionError> {
+        is20_auction::set_auction_period(&self.auction_state(), interval)
+    }
+
+    fn bid_cycles(&self, bidder: Principal) -> Result<u64, AuctionError> {
+        is20_auction::bid_cycles(&self.auction_state(), bidder)
+    }
+}
+
+/// Returns `TxError::UnderMaintenance` if the canister is in the maintenance mode.
+fn check_maintenance() -> Result<(), TxError> {
+    if TokenConfig::get_stable().is_under_maintenance() {
+        return Err(TxError::UnderMaintenance);
+    }
+
+    Ok(())
+}
+
+/// Returns `TxError::TxWindowTooLong` if the transaction window `period_nanos` is above
+/// `MAX_TX_WINDOW_NANOS`.
+fn check_tx_window(period_nanos: Option<u64>) -> Result<(), TxError> {
+    if period_nanos.map_or(false, |period| period > MAX_TX_WINDOW_NANOS) {
+        return Err(TxError::TxWindowTooLong {
+            max_nanos: MAX_TX_WINDOW_NANOS,
+        });
+    }
+
+    Ok(())
+}
+
+/// Key defining the order of the accounts in the paginated holders list.
+fn account_key(account: &AccountInternal) -> (&[u8], &Subaccount) {
+    (account.owner.as_slice(), &account.subaccount)
+}
+
+fn log_failure(transfer: &TransferArgs, err: TxError) -> TxError {
+    FailedTxLog::record(transfer.from_subaccount, transfer.to, transfer.amount, &err);
+    err
+}
+
+pub fn auction_account() -> AccountInternal {
+    match TokenConfig::get_stable().auction_account {
+        Some(account) => account.into(),
+        // There are no sub accounts for the auction principal
+        None => AccountInternal::new(Principal::management_canister(), None),
+    }
+}
+
+const ESCROW_SUBACCOUNT: Subaccount = [1; 32];
+
+pub fn escrow_account() -> AccountInternal {
+    // Escrowed tokens are held by the auction principal on a dedicated subaccount.
+    AccountInternal::new(Principal::management_canister(), Some(ESCROW_SUBACCOUNT))
+}
+
+const VESTING_SUBACCOUNT: Subaccount = [2; 32];
+
+pub fn vesting_account() -> AccountInternal {
+    // Tokens locked by the vesting schedules are held by the auction principal as well.
+    AccountInternal::new(Principal::management_canister(), Some(VESTING_SUBACCOUNT))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use canister_sdk::ic_canister::canister_call;
+    use canister_sdk::ic_kit::inject::get_context;
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john, xtc};
+    use canister_sdk::ic_kit::{Method, MockContext};
+    #[cfg(feature = "claim")]
+    use canister_sdk::ledger::{AccountIdentifier, Subaccount as SubaccountIdentifier};
+
+    use crate::mock::TokenCanisterMock;
+    use crate::state::fee_oracle::{OracleCall, PriceOracle};
+    use crate::state::ledger::{
+        Operation, MAX_REDEMPTION_REF_LENGTH, MAX_TAG_LENGTH, MAX_TRANSFER_TAGS,
+    };
+    use crate::{account::DEFAULT_SUBACCOUNT, state::config::Metadata};
+
+    use super::*;
+
+    // Method for generating random Subaccount.
+    #[cfg(feature = "claim")]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn gen_subaccount() -> Subaccount {
+        use rand::{thread_rng, Rng};
+
+        let mut subaccount = [0u8; 32];
+        thread_rng().fill(&mut subaccount);
+        subaccount
+    }
+
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn test_context() -> (&'static MockContext, TokenCanisterMock) {
+        let context = MockContext::new().with_caller(john()).inject();
+
+        let principal = Principal::from_text("mfufu-x6j4c-gomzb-geilq").unwrap();
+        let canister = TokenCanisterMock::from_principal(principal);
+
+        // Refresh canister's state.
+        TokenConfig::set_stable(TokenConfig::default());
+        StableBalances.clear();
+        LedgerData::clear();
+
+        // Due to this update, init() code will get actual
+        // principal of the canister from ic::id().
+        context.update_id(canister.principal());
+
+        canister.init(
+            Metadata {
+                name: "".to_string(),
+                symbol: "".to_string(),
+                decimals: 8,
+
+                owner: john(),
+                fee: Tokens128::from(0),
+                fee_to: john(),
+                is_test_token: None,
+                max_supply: None,
+            },
+            Tokens128::from(1000),
+        );
+
+        // This is to make tests that don't rely on auction state
+        // pass, because since we are running auction state on each
+        // endpoint call, it affects `BiddingInfo.fee_ratio` that is
+        // used for charging fees in `approve` endpoint.
+        let mut stats = TokenConfig::get_stable();
+        stats.min_cycles = 0;
+        TokenConfig::set_stable(stats);
+
+        canister.mint(alice(), None, 1000.into(), None).unwrap();
+        context.update_caller(alice());
+
+        (context, canister)
+    }
+
+    fn test_canister() -> TokenCanisterMock {
+        let context = MockContext::new().with_caller(alice()).inject();
+
+        let principal = Principal::from_text("mfufu-x6j4c-gomzb-geilq").unwrap();
+        let canister = TokenCanisterMock::from_principal(principal);
+        context.update_id(canister.principal());
+
+        // Refresh canister's state.
+        TokenConfig::set_stable(TokenConfig::default());
+        StableBalances.clear();
+        LedgerData::clear();
+
+        canister.init(
+            Metadata {
+                name: "".to_string(),
+                symbol: "".to_string(),
+                decimals: 8,
+                owner: alice(),
+                fee: Tokens128::from(0),
+                fee_to: alice(),
+                is_test_token: None,
+                max_supply: None,
+            },
+            Tokens128::from(1000),
+        );
+
+        let mut stats = TokenConfig::get_stable();
+        stats.min_cycles = 0;
+        TokenConfig::set_stable(stats);
+
+        canister
+    }
+
+    #[test]
+    fn transfer_to_same_account() {
+        let canister = test_canister();
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: alice().into(),
+            amount: 100.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        let res = canister.icrc1_transfer(transfer);
+        assert_eq!(
+            res,
+            Err(TransferError::GenericError {
+                error_code: 500,
+                message: "self transfer".into()
+            })
+        )
+    }
+
+    #[test]
+    fn transfer_to_same_default_subaccount() {
+        let canister = test_canister();
+        let transfer = TransferArgs {
+            from_subaccount: Some(crate::account::DEFAULT_SUBACCOUNT),
+            to: alice().into(),
+            amount: 100.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        let res = canister.icrc1_transfer(transfer);
+        assert_eq!(
+            res,
+            Err(TransferError::GenericError {
+                error_code: 500,
+                message: "self transfer".into()
+            })
+        );
+
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: Account::new(alice(), Some(DEFAULT_SUBACCOUNT)),
+            amount: 100.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        let res = canister.icrc1_transfer(transfer);
+        assert_eq!(
+            res,
+            Err(TransferError::GenericError {
+                error_code: 500,
+                message: "self transfer".into()
+            })
+        );
+    }
+
+    #[cfg(feature = "claim")]
+    #[test]
+    fn test_claim() {
+        let bob_sub = gen_subaccount();
+        let alice_sub = gen_subaccount();
+
+        let alice_aid =
+            AccountIdentifier::new(alice().into(), Some(SubaccountIdentifier(alice_sub)));
+        let bob_aid = AccountIdentifier::new(bob().into(), Some(SubaccountIdentifier(bob_sub)));
+
+        let (ctx, canister) = test_context();
+        ctx.update_caller(john());
+
+        assert!(canister
+            .mint(
+                canister.owner(),
+                Some(alice_aid.to_address()),
+                Tokens128::from(1000),
+                None
+            )
+            .is_ok());
+        assert!(canister
+            .mint(
+                canister.owner(),
+                Some(bob_aid.to_address()),
+                Tokens128::from(2000),
+                None
+            )
+            .is_ok());
+
+        ctx.update_caller(alice());
+        assert_eq!(
+            canister.get_claimable_amount(canister.owner(), Some(alice_sub)),
+            Tokens128::from(1000)
+        );
+
+        let balance_before = canister.icrc1_balance_of(alice().into());
+        canister.claim(canister.owner(), Some(alice_sub)).unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(alice().into()),
+            (Tokens128::from(1000) + balance_before).unwrap()
+        );
+        assert_eq!(
+            canister.get_claimable_amount(canister.owner(), Some(alice_sub)),
+            0.into()
+        );
+
+        ctx.update_caller(bob());
+        assert_eq!(
+            canister.get_claimable_amount(canister.owner(), Some(bob_sub)),
+            Tokens128::from(2000)
+        );
+    }
+
+    #[cfg(feature = "claim")]
+    #[test]
+    fn test_transfer_to_account_id() {
+        let bob_sub = gen_subaccount();
+        let bob_aid = AccountIdentifier::new(bob().into(), Some(SubaccountIdentifier(bob_sub)));
+
+        let (ctx, canister) = test_context();
+        let mut stats = TokenConfig::get_stable();
+        stats.fee = Tokens128::from(10);
+        TokenConfig::set_stable(stats);
+
+        assert_eq!(
+            canister.transfer_to_account_id(None, bob_aid.to_address(), 100.into(), Some(5.into())),
+            Err(TxError::BadFee {
+                expected_fee: 10.into()
+            })
+        );
+
+        let id = canister
+            .transfer_to_account_id(None, bob_aid.to_address(), 100.into(), None)
+            .unwrap();
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 890.into());
+        let (owner_fee, auction_fee) = canister.transaction_fee_split(id as u64).unwrap();
+        assert_eq!((owner_fee + auction_fee).unwrap(), 10.into());
+
+        // The claim is held under the principal of the sender.
+        ctx.update_caller(bob());
+        assert_eq!(
+            canister.get_claimable_amount(alice(), Some(bob_sub)),
+            100.into()
+        );
+        assert_eq!(
+            canister.claim(john(), Some(bob_sub)),
+            Err(TxError::NothingToClaim)
+        );
+
+        canister.claim(alice(), Some(bob_sub)).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 100.into());
+        assert_eq!(
+            canister.get_claimable_amount(alice(), Some(bob_sub)),
+            0.into()
+        );
+    }
+
+    #[cfg(feature = "claim")]
+    #[test]
+    fn claim_pays_no_royalty() {
+        let bob_sub = gen_subaccount();
+        let bob_aid = AccountIdentifier::new(bob().into(), Some(SubaccountIdentifier(bob_sub)));
+
+        let (ctx, canister) = test_context();
+        ctx.update_caller(john());
+        canister.set_royalty(Some((xtc().into(), 500))).unwrap();
+
+        ctx.update_caller(alice());
+        canister
+            .transfer_to_account_id(None, bob_aid.to_address(), 400.into(), None)
+            .unwrap();
+        assert_eq!(canister.icrc1_balance_of(xtc().into()), 20.into());
+        assert_eq!(
+            canister.get_claimable_amount(alice(), Some(bob_sub)),
+            380.into()
+        );
+
+        // The royalty is only charged on the deposit to the claim account.
+        ctx.update_caller(bob());
+        canister.claim(alice(), Some(bob_sub)).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 380.into());
+        assert_eq!(canister.icrc1_balance_of(xtc().into()), 20.into());
+    }
+
+    #[cfg(feature = "claim")]
+    #[test]
+    fn claim_burns_nothing() {
+        let bob_sub = gen_subaccount();
+        let bob_aid = AccountIdentifier::new(bob().into(), Some(SubaccountIdentifier(bob_sub)));
+
+        let (ctx, canister) = test_context();
+        ctx.update_caller(john());
+        canister.set_burn_rate(Some(1000)).unwrap();
+
+        ctx.update_caller(alice());
+        canister
+            .transfer_to_account_id(None, bob_aid.to_address(), 400.into(), None)
+            .unwrap();
+        assert_eq!(canister.icrc1_total_supply(), 1960.into());
+
+        // The burn is only taken from the deposit to the claim account.
+        ctx.update_caller(bob());
+        canister.claim(alice(), Some(bob_sub)).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 360.into());
+        assert_eq!(canister.icrc1_total_supply(), 1960.into());
+    }
+
+    #[cfg(feature = "claim")]
+    #[test]
+    fn reclaim_expired_claims_storage_full() {
+        use crate::state::storage::set_mock_stable_memory_pages;
+
+        let alice_sub = gen_subaccount();
+        let alice_aid =
+            AccountIdentifier::new(alice().into(), Some(SubaccountIdentifier(alice_sub)));
+
+        // The owner without a default account mints to the claim, so the holder's default account
+        // would be a new entry.
+        let (ctx, canister) = test_context();
+        ctx.update_caller(john());
+        canister.set_owner(xtc()).unwrap();
+        ctx.update_caller(xtc());
+        canister.set_claim_expiry(Some(1_000)).unwrap();
+        canister
+            .mint(xtc(), Some(alice_aid.to_address()), 300.into(), None)
+            .unwrap();
+        get_context().add_time(1_000);
+
+        // The claim is kept while the storage is full.
+        set_mock_stable_memory_pages(100);
+        canister
+            .set_storage_high_water_mark(Some(100 * 64 * 1024))
+            .unwrap();
+        assert_eq!(canister.reclaim_expired_claims(), Err(TxError::StorageFull));
+        assert_eq!(canister.icrc1_balance_of(xtc().into()), 0.into());
+
+        set_mock_stable_memory_pages(99);
+        assert_eq!(canister.reclaim_expired_claims(), Ok(300.into()));
+        assert_eq!(canister.icrc1_balance_of(xtc().into()), 300.into());
+    }
+
+    #[cfg(feature = "claim")]
+    #[test]
+    fn claim_expiry() {
+        let alice_sub = gen_subaccount();
+        let bob_sub = gen_subaccount();
+        let alice_aid =
+            AccountIdentifier::new(alice().into(), Some(SubaccountIdentifier(alice_sub)));
+        let bob_aid = AccountIdentifier::new(bob().into(), Some(SubaccountIdentifier(bob_sub)));
+
+        let (ctx, canister) = test_context();
+        ctx.update_caller(john());
+        canister.set_claim_expiry(Some(1_000)).unwrap();
+        canister
+            .mint(john(), Some(alice_aid.to_address()), 300.into(), None)
+            .unwrap();
+        canister
+            .mint(john(), Some(bob_aid.to_address()), 200.into(), None)
+            .unwrap();
+
+        // Claims made in time are not affected by the expiry.
+        ctx.update_caller(bob());
+        get_context().add_time(500);
+        canister.claim(john(), Some(bob_sub)).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 200.into());
+
+        ctx.update_caller(alice());
+        get_context().add_time(500);
+        assert_eq!(
+            canister.claim(john(), Some(alice_sub)),
+            Err(TxError::ClaimExpired)
+        );
+        assert_eq!(
+            canister.reclaim_expired_claims(),
+            Err(TxError::Unauthorized)
+        );
+
+        ctx.update_caller(john());
+        assert_eq!(canister.reclaim_expired_claims(), Ok(300.into()));
+        assert_eq!(canister.icrc1_balance_of(john().into()), 1300.into());
+        assert_eq!(canister.reclaim_expired_claims(), Ok(0.into()));
+
+        ctx.update_caller(alice());
+        assert_eq!(
+            canister.get_claimable_amount(john(), Some(alice_sub)),
+            0.into()
+        );
+    }
+
+    // **** APIs tests ****
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn set_name() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        canister_call!(
+            canister.set_name("War and Piece".to_string()),
+            Result<Option<u64>, TxError>
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.name, "War and Piece".to_string());
+
+        ctx.update_id(bob());
+        let res = canister_call!(
+            canister.set_name("Crime and Punishment".to_string()),
+            Result<Option<u64>, TxError>
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(res, Err(TxError::Unauthorized));
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.name, "War and Piece".to_string());
+        let name = canister_call!(canister.icrc1_name(), String).await.unwrap();
+        assert_eq!(name, "War and Piece".to_string());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn set_symbol() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        canister_call!(
+            canister.set_symbol("MAX".to_string()),
+            Result<Option<u64>, TxError>
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.symbol, "MAX".to_string());
+
+        ctx.update_id(bob());
+        let res = canister_call!(
+            canister.set_symbol("BOB".to_string()),
+            Result<Option<u64>, TxError>
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(res, Err(TxError::Unauthorized));
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.symbol, "MAX".to_string());
+        let symbol = canister_call!(canister.icrc1_symbol(), String)
+            .await
+            .unwrap();
+        assert_eq!(symbol, "MAX".to_string());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn set_fee() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        canister_call!(
+            canister.set_fee(100500.into()),
+            Result<Option<u64>, TxError>
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.fee, 100500.into());
+
+        ctx.update_id(bob());
+        let res = canister_call!(canister.set_fee(0.into()), Result<Option<u64>, TxError>)
+            .await
+            .unwrap();
+
+        assert_eq!(res, Err(TxError::Unauthorized));
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.fee, 100500.into());
+        let fee = canister_call!(canister.icrc1_fee(), Tokens128)
+            .await
+            .unwrap();
+        assert_eq!(fee, 100500.into());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn set_fee_to() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        canister_call!(
+            canister.set_fee_to(Account::new(alice(), None)),
+            Result<Option<u64>, TxError>
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.fee_to, alice());
+
+        ctx.update_id(bob());
+        let res = canister_call!(
+            canister.set_fee_to(Account::new(bob(), None)),
+            Result<Option<u64>, TxError>
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(res, Err(TxError::Unauthorized));
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.fee_to, alice());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn set_owner() {
+        let (ctx, canister) = test_context();
+        ctx.update_id(john());
+        canister_call!(canister.set_owner(alice()), Result<Option<u64>, TxError>)
+            .await
+            .unwrap()
+            .unwrap();
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.owner, alice());
+
+        ctx.update_id(bob());
+        let res = canister_call!(canister.set_owner(bob()), Result<Option<u64>, TxError>)
+            .await
+            .unwrap();
+
+        assert_eq!(res, Err(TxError::Unauthorized));
+        let info = canister_call!(canister.get_token_info(), TokenInfo)
+            .await
+            .unwrap();
+
+        assert_eq!(info.metadata.owner, alice());
+        let owner = canister_call!(canister.owner(), Principal).await.unwrap();
+        assert_eq!(owner, alice());
+
+        let minting_account = canister_call!(canister.icrc1_minting_account(), Principal)
+            .await
+            .unwrap();
+        assert_eq!(minting_account, Some(alice().into()));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn list_subaccounts() {
+        let canister = test_canister();
+        let subaccount: Subaccount = [1; 32];
+        canister
+            .transfer(TransferArgs {
+                from_subaccount: None,
+                to: Account::new(alice(), Some(subaccount)),
+                amount: 100.into(),
+                fee: None,
+                max_fee: None,
+                memo: None,
+                created_at_time: None,
+                valid_until: None,
+                tags: None,
+                tip: None,
+            })
+            .unwrap();
+
+        get_context().update_id(alice());
+        let list = canister_call!(canister.list_subaccounts(), std::collections::HashMap<Subaccount, Tokens128>).await.unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[&DEFAULT_SUBACCOUNT], 900.into());
+        assert_eq!(list[&subaccount], 100.into());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn max_query_page_size() {
+        let canister = test_canister();
+        for amount in 1..=5 {
+            canister
+                .transfer(TransferArgs {
+                    from_subaccount: None,
+                    to: Account::new(bob(), Some([amount; 32])),
+                    amount: (amount as u128).into(),
+                    fee: None,
+                    max_fee: None,
+                    memo: None,
+                    created_at_time: None,
+                    valid_until: None,
+                    tags: None,
+                    tip: None,
+                })
+                .unwrap();
+        }
+
+        assert_eq!(
+            canister.get_transactions(None, 10, None).await.result.len(),
+            6
+        );
+        assert_eq!(canister.get_holders(None, 10).len(), 6);
+
+        canister.set_max_query_page_size(Some(3)).unwrap();
+        assert_eq!(
+            canister.get_transactions(None, 10, None).await.result.len(),
+            3
+        );
+        assert_eq!(
+            canister
+                .get_transactions(Some(bob()), 10, None)
+                .await
+                .result
+                .len(),
+            3
+        );
+        assert_eq!(
+            canister.get_transactions(None, 2, None).await.result.len(),
+            2
+        );
+        assert_eq!(canister.get_holders(None, 10).len(), 3);
+
+        canister.set_max_query_page_size(None).unwrap();
+        assert_eq!(
+            canister.get_transactions(None, 10, None).await.result.len(),
+            6
+        );
+
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.set_max_query_page_size(Some(1)),
+            Err(TxError::Unauthorized)
+        );
+    }
+
+    #[test]
+    fn has_transacted() {
+        let canister = test_canister();
+        assert!(!canister.has_transacted(bob().into()));
+        assert!(!canister.has_transacted(xtc().into()));
+
+        canister
+            .transfer(TransferArgs {
+                from_subaccount: None,
+                to: bob().into(),
+                amount: 100.into(),
+                fee: None,
+                max_fee: None,
+                memo: None,
+                created_at_time: None,
+                valid_until: None,
+                tags: None,
+                tip: None,
+            })
+            .unwrap();
+        assert!(canister.has_transacted(bob().into()));
+        assert!(!canister.has_transacted(Account::new(bob(), Some([1; 32]))));
+
+        get_context().update_caller(bob());
+        canister.burn(None, None, 100.into(), None).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 0.into());
+        assert!(canister.has_transacted(bob().into()));
+        assert!(!canister.has_transacted(xtc().into()));
+
+        // The mark survives the removal of the transactions from the history.
+        LedgerData::remove_oldest(LedgerData::len() as usize);
+        assert!(canister.has_transacted(bob().into()));
+        assert!(!canister.has_transacted(xtc().into()));
+    }
+
+    #[test]
+    fn certified_balance() {
+        use crate::state::balance_tree::{with_balance_tree, BalanceTree};
+        use crate::state::certification::certified_data;
+
+        let canister = test_canister();
+        let certified = certified_data();
+
+        let balance = canister.icrc1_balance_of_certified(alice().into());
+        assert_eq!(balance.balance, 1000.into());
+        assert_eq!(balance.total_supply, 1000.into());
+        assert_eq!(balance.certified_data(alice().into()), certified);
+
+        // Queries don't change the certified data.
+        assert_eq!(canister.icrc1_balance_of_certified(alice().into()), balance);
+        assert_eq!(certified_data(), certified);
+
+        canister
+            .transfer(TransferArgs {
+                from_subaccount: None,
+                to: bob().into(),
+                amount: 100.into(),
+                fee: None,
+                max_fee: None,
+                memo: None,
+                created_at_time: None,
+                valid_until: None,
+                tags: None,
+                tip: None,
+            })
+            .unwrap();
+        assert_ne!(certified_data(), certified);
+
+        // The incrementally updated tree matches the tree built from the current balances.
+        let certified = certified_data();
+        let rebuilt = BalanceTree::from_balances(StableBalances.list_balances(0, usize::MAX));
+        assert_eq!(canister.icrc1_total_supply(), rebuilt.total_supply());
+        assert_eq!(
+            with_balance_tree(|tree| tree.root_hash()),
+            rebuilt.root_hash()
+        );
+        assert_eq!(
+            canister.state_fingerprint().balances_merkle_root,
+            rebuilt.root_hash()
+        );
+
+        for (account, amount) in [(alice(), 900), (bob(), 100)] {
+            let balance = canister.icrc1_balance_of_certified(account.into());
+            assert_eq!(balance.balance, amount.into());
+            assert_eq!(balance.certified_data(account.into()), certified);
+            // A forged balance doesn't match the certified data.
+            let forged = CertifiedBalance {
+                balance: (amount + 1).into(),
+                ..balance
+            };
+            assert_ne!(forged.certified_data(account.into()), certified);
+        }
+
+        let missing = canister.icrc1_balance_of_certified(john().into());
+        assert_eq!(missing.balance, 0.into());
+        assert!(missing.witness.is_empty());
+    }
+
+    #[test]
+    fn state_fingerprint() {
+        let canister = test_canister();
+
+        // Rebuilds the state from scratch with the same history except for the last transfer
+        // amount.
+        let replay = |last_amount: u128| {
+            TokenConfig::set_stable(TokenConfig::default());
+            StableBalances.clear();
+            LedgerData::clear();
+            canister.init(
+                Metadata {
+                    name: "".to_string(),
+                    symbol: "".to_string(),
+                    decimals: 8,
+                    owner: alice(),
+                    fee: Tokens128::from(0),
+                    fee_to: alice(),
+                    is_test_token: None,
+                    max_supply: None,
+                },
+                Tokens128::from(1000),
+            );
+
+            for (to, amount) in [(bob(), 100), (xtc(), last_amount)] {
+                canister
+                    .transfer(TransferArgs {
+                        from_subaccount: None,
+                        to: to.into(),
+                        amount: amount.into(),
+                        fee: None,
+                        max_fee: None,
+                        memo: None,
+                        created_at_time: None,
+                        valid_until: None,
+                        tags: None,
+                        tip: None,
+                    })
+                    .unwrap();
+            }
+
+            canister.state_fingerprint()
+        };
+
+        let fingerprint = replay(10);
+        assert_eq!(fingerprint.supply, 1000.into());
+        assert_eq!(fingerprint.holder_count, 3);
+        assert_eq!(fingerprint.ledger_len, 3);
+        assert!(fingerprint.ledger_tip_hash.is_some());
+        assert_eq!(replay(10), fingerprint);
+
+        let divergent = replay(11);
+        assert_eq!(divergent.supply, fingerprint.supply);
+        assert_eq!(divergent.ledger_len, fingerprint.ledger_len);
+        assert_ne!(divergent.ledger_tip_hash, fingerprint.ledger_tip_hash);
+        assert_ne!(
+            divergent.balances_merkle_root,
+            fingerprint.balances_merkle_root
+        );
+    }
+
+    #[test]
+    fn governance_delay() {
+        let canister = test_canister();
+        assert_eq!(canister.set_governance_delay(Some(1_000)), Ok(None));
+        assert!(canister.pending_owner_actions().is_empty());
+
+        let fee_id = canister.set_fee(100.into()).unwrap();
+        assert_eq!(canister.icrc1_fee(), 0.into());
+
+        // Permission changes are delayed as well.
+        let freeze_id = canister.freeze_account(bob()).unwrap();
+        assert!(!canister.is_frozen(bob()));
+
+        let pending = canister.pending_owner_actions();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(fee_id, Some(pending[0].id));
+        assert_eq!(freeze_id, Some(pending[1].id));
+        assert!(matches!(
+            pending[0].update,
+            CanisterUpdate::Fee(fee) if fee == 100.into()
+        ));
+        assert_eq!(
+            canister.execute_owner_action(pending[0].id),
+            Err(TxError::OwnerActionTimelocked {
+                executable_at: pending[0].executable_at
+            })
+        );
+
+        get_context().add_time(1_000);
+        canister.execute_owner_action(pending[0].id).unwrap();
+        assert_eq!(canister.icrc1_fee(), 100.into());
+        canister.execute_owner_action(pending[1].id).unwrap();
+        assert!(canister.is_frozen(bob()));
+        assert!(canister.pending_owner_actions().is_empty());
+        assert_eq!(
+            canister.execute_owner_action(pending[0].id),
+            Err(TxError::OwnerActionNotFound)
+        );
+    }
+
+    #[test]
+    fn fee_change_history() {
+        let canister = test_canister();
+        assert!(canister.fee_change_history(10).is_empty());
+
+        canister.set_fee(10.into()).unwrap();
+        get_context().add_time(100);
+        canister.set_fee(20.into()).unwrap();
+
+        canister.set_governance_delay(Some(1_000)).unwrap();
+        canister.set_fee(30.into()).unwrap();
+        assert_eq!(canister.fee_change_history(10).len(), 2);
+
+        get_context().add_time(1_000);
+        let id = canister.pending_owner_actions()[0].id;
+        canister.execute_owner_action(id).unwrap();
+
+        let history = canister.fee_change_history(10);
+        assert_eq!(history.len(), 3);
+        assert_eq!(
+            history[0],
+            FeeChange {
+                timestamp: history[1].timestamp + 1_000,
+                old_fee: 20.into(),
+                new_fee: 30.into(),
+                changed_by: alice(),
+            }
+        );
+        assert_eq!(history[1].old_fee, 10.into());
+        assert_eq!(history[1].new_fee, 20.into());
+        assert_eq!(history[1].timestamp, history[2].timestamp + 100);
+        assert_eq!(history[2].old_fee, 0.into());
+        assert_eq!(history[2].new_fee, 10.into());
+
+        assert_eq!(canister.fee_change_history(1), vec![history[0].clone()]);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn dynamic_fee() {
+        let canister = test_canister();
+        let params = DynamicFeeParams {
+            base_fee: 10.into(),
+            ledger_step: 2,
+            fee_step: 5.into(),
+            max_fee: 20.into(),
+        };
+        canister.set_dynamic_fee(Some(params)).unwrap();
+        assert_eq!(canister.dynamic_fee(), Some(params));
+
+        // The ledger contains the initial mint only.
+        assert_eq!(canister.icrc1_fee(), 10.into());
+
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: bob().into(),
+            amount: 10.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+        let id = canister.transfer(transfer.clone()).unwrap() as TxId;
+        assert_eq!(canister.get_transaction(id).await.unwrap().fee, 10.into());
+        assert_eq!(canister.icrc1_fee(), 15.into());
+        assert!(canister
+            .icrc1_metadata()
+            .contains(&("icrc1:fee".to_string(), Value::Nat(15.into()))));
+
+        canister.transfer(transfer.clone()).unwrap();
+        let id = canister.transfer(transfer.clone()).unwrap() as TxId;
+        assert_eq!(canister.get_transaction(id).await.unwrap().fee, 15.into());
+        assert_eq!(canister.icrc1_fee(), 20.into());
+
+        for _ in 0..4 {
+            canister.transfer(transfer.clone()).unwrap();
+        }
+        assert_eq!(canister.icrc1_fee(), 20.into());
+
+        canister.set_dynamic_fee(None).unwrap();
+        assert_eq!(canister.icrc1_fee(), 0.into());
+    }
+
+    #[test]
+    fn cancel_owner_action() {
+        let canister = test_canister();
+        canister.set_governance_delay(Some(1_000)).unwrap();
+        canister.set_name("Delayed".to_string()).unwrap();
+        let id = canister.pending_owner_actions()[0].id;
+
+        get_context().update_caller(bob());
+        assert_eq!(canister.cancel_owner_action(id), Err(TxError::Unauthorized));
+        assert_eq!(
+            canister.execute_owner_action(id),
+            Err(TxError::Unauthorized)
+        );
+
+        get_context().update_caller(alice());
+        canister.cancel_owner_action(id).unwrap();
+        assert!(canister.pending_owner_actions().is_empty());
+
+        get_context().add_time(1_000);
+        assert_eq!(
+            canister.execute_owner_action(id),
+            Err(TxError::OwnerActionNotFound)
+        );
+        assert_eq!(canister.icrc1_name(), "".to_string());
+    }
+
+    #[test]
+    fn import_balances_chunks() {
+        let canister = test_canister();
+        let bob_account = Account::new(bob(), None);
+        let john_account = Account::new(john(), None);
+
+        let imported = canister
+            .import_balances_chunk(
+                vec![(bob_account, 100.into()), (john_account, 50.into())],
+                Some(0),
+            )
+            .unwrap();
+        assert_eq!(imported, 150.into());
+
+        let imported = canister
+            .import_balances_chunk(
+                vec![(bob_account, 30.into()), (alice().into(), 20.into())],
+                Some(1),
+            )
+            .unwrap();
+        assert_eq!(imported, 200.into());
+
+        assert_eq!(canister.icrc1_balance_of(bob_account), 130.into());
+        assert_eq!(canister.icrc1_balance_of(john_account), 50.into());
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 1020.into());
+        assert_eq!(canister.icrc1_total_supply(), 1200.into());
+        assert_eq!(canister.migration_imported_total(), 200.into());
+
+        assert_eq!(
+            canister.import_balances_chunk(vec![(bob_account, 30.into())], Some(1)),
+            Err(TxError::ChunkAlreadyImported { chunk_index: 1 })
+        );
+        assert_eq!(canister.icrc1_balance_of(bob_account), 130.into());
+
+        let mut stats = TokenConfig::get_stable();
+        stats.max_supply = Some(1250.into());
+        TokenConfig::set_stable(stats);
+        assert_eq!(
+            canister.import_balances_chunk(vec![(bob_account, 51.into())], Some(2)),
+            Err(TxError::MaxSupplyExceeded {
+                max_supply: 1250.into()
+            })
+        );
+        assert_eq!(canister.icrc1_total_supply(), 1200.into());
+
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.import_balances_chunk(vec![(bob_account, 30.into())], None),
+            Err(TxError::Unauthorized)
+        );
+        assert_eq!(canister.migration_imported_total(), 200.into());
+    }
+
+    #[test]
+    fn analytics_ledger() {
+        let canister = test_canister();
+        let transfer = |amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: bob().into(),
+            amount: amount.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: Some(vec!["payroll".to_string()]),
+            tip: None,
+        };
+        canister.transfer(transfer(10)).unwrap();
+        assert!(canister.get_analytics(0, 10).is_empty());
+
+        canister.set_analytics_enabled(true).unwrap();
+        let first = canister.transfer(transfer(20)).unwrap() as TxId;
+        let second = canister.transfer(transfer(30)).unwrap() as TxId;
+        canister.burn(None, None, 5.into(), None).unwrap();
+
+        let records = canister.get_analytics(0, 10);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].tx_id, first);
+        assert_eq!(records[0].amount, 20.into());
+        assert_eq!(records[0].tags, vec!["payroll".to_string()]);
+        assert_eq!(records[0].fiat_value, None);
+        assert_eq!(records[1].tx_id, second);
+        assert_eq!(records[2].operation, Operation::Burn);
+        assert_eq!(canister.get_analytics(second, 10).len(), 2);
+        assert_eq!(canister.history_size(), second + 2);
+
+        canister.set_analytics_enabled(false).unwrap();
+        canister.transfer(transfer(40)).unwrap();
+        assert_eq!(canister.get_analytics(0, 10).len(), 3);
+    }
+
+    #[test]
+    fn recent_failures() {
+        let canister = test_canister();
+        canister.set_fee(10.into()).unwrap();
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: bob().into(),
+            amount: 100.into(),
+            fee: Some(1.into()),
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        assert!(canister.transfer(transfer.clone()).is_err());
+        assert!(canister.recent_failures(10).is_empty());
+
+        canister.set_failed_tx_log_enabled(true).unwrap();
+        assert_eq!(
+            canister.transfer(transfer.clone()),
+            Err(TxError::BadFee {
+                expected_fee: 10.into()
+            })
+        );
+        assert!(canister
+            .transfer(TransferArgs {
+                fee: None,
+                ..transfer.with_amount(2000.into())
+            })
+            .is_err());
+        canister
+            .transfer(TransferArgs {
+                fee: None,
+                ..transfer.clone()
+            })
+            .unwrap();
+
+        let failures = canister.recent_failures(10);
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].amount, 2000.into());
+        assert_eq!(
+            failures[0].error,
+            TxError::InsufficientFunds {
+                balance: 1000.into()
+            }
+        );
+        assert_eq!(failures[1].caller, alice());
+        assert_eq!(failures[1].to, bob().into());
+        assert_eq!(
+            failures[1].error,
+            TxError::BadFee {
+                expected_fee: 10.into()
+            }
+        );
+        assert_eq!(canister.recent_failures(1), failures[..1]);
+    }
+
+    #[test]
+    fn storage_high_water_mark() {
+        use crate::state::storage::set_mock_stable_memory_pages;
+
+        let canister = test_canister();
+        let transfer = |to: Principal, amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: to.into(),
+            amount: amount.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+        canister.transfer(transfer(bob(), 100)).unwrap();
+
+        set_mock_stable_memory_pages(100);
+        canister
+            .set_storage_high_water_mark(Some(100 * 64 * 1024))
+            .unwrap();
+
+        assert_eq!(
+            canister.transfer(transfer(john(), 100)),
+            Err(TxError::StorageFull)
+        );
+        assert_eq!(
+            canister.mint(john(), None, 100.into(), None),
+            Err(TxError::StorageFull)
+        );
+        assert_eq!(canister.icrc1_balance_of(john().into()), 0.into());
+
+        canister.transfer(transfer(bob(), 100)).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 200.into());
+
+        // The zero fee staged for the fee account without a balance doesn't add an entry.
+        set_mock_stable_memory_pages(99);
+        canister.set_fee_to(xtc().into()).unwrap();
+        set_mock_stable_memory_pages(100);
+        canister.transfer(transfer(bob(), 100)).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 300.into());
+        assert_eq!(StableBalances.get(&AccountInternal::new(xtc(), None)), None);
+
+        set_mock_stable_memory_pages(99);
+        canister.transfer(transfer(john(), 100)).unwrap();
+        assert_eq!(canister.icrc1_balance_of(john().into()), 100.into());
+    }
+
+    #[test]
+    fn reclaim_dormant_storage_full() {
+        use crate::state::storage::set_mock_stable_memory_pages;
+
+        let canister = test_canister();
+        canister
+            .transfer(TransferArgs {
+                from_subaccount: None,
+                to: bob().into(),
+                amount: 100.into(),
+                fee: None,
+                max_fee: None,
+                memo: None,
+                created_at_time: None,
+                valid_until: None,
+                tags: None,
+                tip: None,
+            })
+            .unwrap();
+        canister.set_dormancy_period(Some(1_000)).unwrap();
+        canister.set_owner(john()).unwrap();
+        get_context().add_time(2_000);
+
+        // The default account of the new owner would be a new entry.
+        get_context().update_caller(john());
+        set_mock_stable_memory_pages(100);
+        canister
+            .set_storage_high_water_mark(Some(100 * 64 * 1024))
+            .unwrap();
+        assert_eq!(
+            canister.reclaim_dormant(bob().into()),
+            Err(TxError::StorageFull)
+        );
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 100.into());
+
+        set_mock_stable_memory_pages(99);
+        canister.reclaim_dormant(bob().into()).unwrap();
+        assert_eq!(canister.icrc1_balance_of(john().into()), 100.into());
+    }
+
+    #[test]
+    fn circulating_supply() {
+        let canister = test_canister();
+        let treasury = Account::new(xtc(), Some([1; 32]));
+        canister
+            .set_circulation_excluded_accounts(vec![alice().into(), treasury])
+            .unwrap();
+        assert_eq!(canister.icrc1_total_supply(), 1000.into());
+        assert_eq!(canister.circulating_supply(), 0.into());
+
+        canister
+            .mint(treasury.owner, treasury.subaccount, 500.into(), None)
+            .unwrap();
+        assert_eq!(canister.icrc1_total_supply(), 1500.into());
+        assert_eq!(canister.circulating_supply(), 0.into());
+
+        canister.mint(bob(), None, 300.into(), None).unwrap();
+        assert_eq!(canister.icrc1_total_supply(), 1800.into());
+        assert_eq!(canister.circulating_supply(), 300.into());
+
+        canister.mint(xtc(), None, 200.into(), None).unwrap();
+        assert_eq!(canister.circulating_supply(), 500.into());
+    }
+
+    #[test]
+    fn accounts_snapshot() {
+        let canister = test_canister();
+        let transfer = |to: Principal, amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: to.into(),
+            amount: amount.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        let to_bob = canister.transfer(transfer(bob(), 100)).unwrap() as TxId;
+        let to_john = canister.transfer(transfer(john(), 200)).unwrap() as TxId;
+        get_context().update_caller(bob());
+        let bob_to_john = canister.transfer(transfer(john(), 50)).unwrap() as TxId;
+
+        let snapshot =
+            canister.accounts_snapshot(vec![bob().into(), john().into(), xtc().into()], 10);
+        assert_eq!(snapshot.len(), 3);
+
+        let ids = |txs: &Vec<TxRecord>| txs.iter().map(|tx| tx.index).collect::<Vec<_>>();
+        assert_eq!(snapshot[0].0, bob().into());
+        assert_eq!(snapshot[0].1, 50.into());
+        assert_eq!(ids(&snapshot[0].2), vec![bob_to_john, to_bob]);
+        assert_eq!(snapshot[1].0, john().into());
+        assert_eq!(snapshot[1].1, 250.into());
+        assert_eq!(ids(&snapshot[1].2), vec![bob_to_john, to_john]);
+        assert_eq!(snapshot[2].1, 0.into());
+        assert!(snapshot[2].2.is_empty());
+
+        let snapshot = canister.accounts_snapshot(vec![john().into()], 1);
+        assert_eq!(ids(&snapshot[0].2), vec![bob_to_john]);
+    }
+
+    #[test]
+    fn balance_map_stats() {
+        let canister = test_canister();
+        canister.mint(bob(), None, 10.into(), None).unwrap();
+        canister.mint(john(), None, 100.into(), None).unwrap();
+        canister.mint(xtc(), None, 5000.into(), None).unwrap();
+        StableBalances.insert(auction_account(), 7.into());
+
+        // Sorted balances are 7, 10, 100, 1000, 5000.
+        assert_eq!(
+            canister.balance_map_stats(),
+            BalanceMapStats {
+                total_accounts: 5,
+                auction_account_balance: 7.into(),
+                largest_balance: 5000.into(),
+                smallest_nonzero_balance: Some(7.into()),
+                median_estimate: 64.into(),
+            }
+        );
+    }
+
+    #[test]
+    fn metrics_timeseries() {
+        let canister = test_canister();
+        let second = 1_000_000_000;
+        let start = ic::time();
+        get_context().add_time(10 * second);
+        canister
+            .transfer(TransferArgs {
+                from_subaccount: None,
+                to: bob().into(),
+                amount: 100.into(),
+                fee: None,
+                max_fee: None,
+                memo: None,
+                created_at_time: None,
+                valid_until: None,
+                tags: None,
+                tip: None,
+            })
+            .unwrap();
+        get_context().add_time(10 * second);
+        canister.mint(bob(), None, 50.into(), None).unwrap();
+        get_context().add_time(5 * second);
+
+        let counts = |transfers: u64, mints: u64| OperationCounts {
+            transfers,
+            mints,
+            ..OperationCounts::default()
+        };
+        let point = |seconds: u64,
+                     supply: u128,
+                     holders: u64,
+                     volume: u128,
+                     operation_counts: OperationCounts| {
+            MetricPoint {
+                timestamp: start + seconds * second,
+                total_supply: supply.into(),
+                holders,
+                cumulative_volume: volume.into(),
+                operation_counts,
+            }
+        };
+        let expected = vec![
+            point(25, 1050, 2, 1150, counts(1, 2)),
+            point(15, 1000, 2, 1100, counts(1, 1)),
+            point(5, 1000, 1, 1000, counts(0, 1)),
+            MetricPoint {
+                timestamp: start - 5 * second,
+                total_supply: 0.into(),
+                holders: 0,
+                cumulative_volume: 0.into(),
+                operation_counts: counts(0, 0),
+            },
+        ];
+        assert_eq!(canister.metrics_timeseries(10, 4), expected);
+
+        // The points before the oldest stored transaction can't be reconstructed.
+        LedgerData::remove_oldest(1);
+        assert_eq!(canister.metrics_timeseries(10, 4), expected[..2]);
+    }
+
+    #[test]
+    fn token_statistics() {
+        let canister = test_canister();
+        let mut stats = TokenConfig::get_stable();
+        stats.fee = 10.into();
+        TokenConfig::set_stable(stats);
+        let transfer = |to: Principal, amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: to.into(),
+            amount: amount.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        get_context().add_time(3 * 24 * 60 * 60 * 1_000_000_000);
+        canister.transfer(transfer(bob(), 100)).unwrap();
+        canister.mint(john(), None, 50.into(), None).unwrap();
+        get_context().update_caller(bob());
+        canister.transfer(transfer(xtc(), 40)).unwrap();
+
+        let statistics = canister.token_statistics();
+        assert_eq!(statistics.age_days, 3);
+        assert_eq!(statistics.total_transactions, canister.history_size());
+        assert_eq!(statistics.total_transactions, 4);
+        // Initial mint of 1000, two transfers and a mint.
+        assert_eq!(statistics.total_volume, 1190.into());
+        assert_eq!(statistics.total_fees, 20.into());
+        assert_eq!(
+            statistics.unique_holders,
+            canister.get_token_info().holderNumber as u64
+        );
+        assert_eq!(statistics.last_activity, Some(ic::time()));
+    }
+
+    #[test]
+    fn outgoing_disabled() {
+        let canister = test_canister();
+        let transfer = |to: Principal, amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: to.into(),
+            amount: amount.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+        canister.transfer(transfer(bob(), 100)).unwrap();
+
+        get_context().update_caller(bob());
+        canister.set_outgoing_disabled(None, true).unwrap();
+        assert!(canister.is_outgoing_disabled(bob().into()));
+        assert_eq!(
+            canister.transfer(transfer(alice(), 10)),
+            Err(TxError::OutgoingDisabled)
+        );
+
+        get_context().update_caller(alice());
+        canister.transfer(transfer(bob(), 100)).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 200.into());
+
+        get_context().update_caller(bob());
+        canister.set_outgoing_disabled(None, false).unwrap();
+        assert!(!canister.is_outgoing_disabled(bob().into()));
+        canister.transfer(transfer(alice(), 10)).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 190.into());
+    }
+
+    #[test]
+    fn block_canister_recipients() {
+        let canister = test_canister();
+        let user = Principal::self_authenticating([1, 2, 3]);
+        let token_unaware = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 1, 1]);
+        let token_aware = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 2, 1, 1]);
+        let transfer = |to: Principal| TransferArgs {
+            from_subaccount: None,
+            to: to.into(),
+            amount: 10.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        // Disabled by default.
+        canister.transfer(transfer(token_unaware)).unwrap();
+
+        canister.set_block_canister_recipients(true).unwrap();
+        canister.register_token_aware_canister(token_aware).unwrap();
+        assert_eq!(canister.token_aware_canisters(), vec![token_aware]);
+
+        canister.transfer(transfer(user)).unwrap();
+        assert_eq!(
+            canister.transfer(transfer(token_unaware)),
+            Err(TxError::RecipientNotTokenAware)
+        );
+        canister.transfer(transfer(token_aware)).unwrap();
+        assert_eq!(canister.icrc1_balance_of(token_unaware.into()), 10.into());
+        assert_eq!(canister.icrc1_balance_of(token_aware.into()), 10.into());
+
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.register_token_aware_canister(token_unaware),
+            Err(TxError::Unauthorized)
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn fee_curve() {
+        let canister = test_canister();
+        canister.set_fee(5.into()).unwrap();
+        assert_eq!(
+            canister.fee_curve(vec![1.into(), 1000.into()]),
+            vec![5.into(), 5.into()]
+        );
+
+        canister.set_fee_mode(FeeMode::BasisPoints(30)).unwrap();
+        assert_eq!(canister.icrc1_fee(), 0.into());
+        let fees = canister.fee_curve(vec![
+            100.into(),
+            1_000.into(),
+            10_000.into(),
+            100_000.into(),
+        ]);
+        assert_eq!(fees, vec![0.into(), 3.into(), 30.into(), 300.into()]);
+        assert!(fees.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let amounts = vec![Tokens128::from(1); MAX_FEE_CURVE_POINTS + 1];
+        assert_eq!(canister.fee_curve(amounts).len(), MAX_FEE_CURVE_POINTS);
+
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: bob().into(),
+            amount: 500.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+        let id = canister.transfer(transfer).unwrap() as TxId;
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 500.into());
+        assert_eq!(canister.get_transaction(id).await.unwrap().fee, 1.into());
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn transactions_by_tag() {
+        let canister = test_canister();
+        let transfer = |to: Principal, amount: u128, tags: &[&str]| TransferArgs {
+            from_subaccount: None,
+            to: to.into(),
+            amount: amount.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+            tip: None,
+        };
+
+        let salary = canister
+            .transfer(transfer(bob(), 100, &["salary", "march"]))
+            .unwrap() as TxId;
+        let rent = canister
+            .transfer(transfer(john(), 50, &["rent", "march"]))
+            .unwrap() as TxId;
+        canister.transfer(transfer(xtc(), 10, &[])).unwrap();
+
+        let ids = |txs: Vec<TxRecord>| txs.into_iter().map(|tx| tx.index).collect::<Vec<_>>();
+        assert_eq!(
+            ids(canister.transactions_by_tag("march".into(), 0, 10)),
+            vec![salary, rent]
+        );
+        assert_eq!(
+            ids(canister.transactions_by_tag("march".into(), 1, 10)),
+            vec![rent]
+        );
+        assert_eq!(
+            ids(canister.transactions_by_tag("salary".into(), 0, 10)),
+            vec![salary]
+        );
+        assert!(canister
+            .transactions_by_tag("unknown".into(), 0, 10)
+            .is_empty());
+        assert_eq!(
+            canister.get_transaction(rent).await.unwrap().tags,
+            Some(vec!["rent".to_string(), "march".to_string()])
+        );
+
+        // Removed transactions are dropped from the tag index.
+        LedgerData::remove_oldest(salary as usize + 1);
+        assert!(canister
+            .transactions_by_tag("salary".into(), 0, 10)
+            .is_empty());
+        assert_eq!(
+            ids(canister.transactions_by_tag("march".into(), 0, 10)),
+            vec![rent]
+        );
+
+        let too_many_tags = ["tag"; MAX_TRANSFER_TAGS + 1];
+        assert_eq!(
+            canister.transfer(transfer(bob(), 10, &too_many_tags)),
+            Err(TxError::InvalidTags)
+        );
+        let long_tag = "a".repeat(MAX_TAG_LENGTH + 1);
+        assert_eq!(
+            canister.transfer(transfer(bob(), 10, &[long_tag.as_str()])),
+            Err(TxError::InvalidTags)
+        );
+    }
+
+    #[test]
+    fn get_transactions_cursor() {
+        let canister = test_canister();
+        for _ in 0..6 {
+            canister.mint(bob(), None, 10.into(), None).unwrap();
+        }
+
+        let (page, cursor) = canister.get_transactions_cursor(None, 3).unwrap();
+        assert_eq!(
+            page.iter().map(|tx| tx.index).collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+        let (page, cursor) = canister.get_transactions_cursor(cursor, 3).unwrap();
+        assert_eq!(
+            page.iter().map(|tx| tx.index).collect::<Vec<_>>(),
+            [3, 4, 5]
+        );
+        let (page, end) = canister.get_transactions_cursor(cursor, 3).unwrap();
+        assert_eq!(page.iter().map(|tx| tx.index).collect::<Vec<_>>(), [6]);
+        assert_eq!(end, None);
+
+        // The cursor stays valid after the records before it are removed.
+        canister.mint(bob(), None, 10.into(), None).unwrap();
+        LedgerData::remove_oldest(5);
+        let (page, _) = canister.get_transactions_cursor(cursor, 3).unwrap();
+        assert_eq!(page.iter().map(|tx| tx.index).collect::<Vec<_>>(), [6, 7]);
+
+        let (_, stale_cursor) = canister.get_transactions_cursor(None, 1).unwrap();
+        LedgerData::remove_oldest(2);
+        assert_eq!(
+            canister
+                .get_transactions_cursor(stale_cursor, 3)
+                .unwrap_err(),
+            TxError::CursorPruned { first_available: 7 }
+        );
+        let (page, end) = canister.get_transactions_cursor(None, 3).unwrap();
+        assert_eq!(page.iter().map(|tx| tx.index).collect::<Vec<_>>(), [7]);
+        assert_eq!(end, None);
+
+        LedgerData::clear();
+        assert_eq!(
+            canister.get_transactions_cursor(cursor, 3).unwrap_err(),
+            TxError::InvalidCursor
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn multi_source_transfer() {
+        let canister = test_canister();
+        let subaccount = Some([1; 32]);
+        canister
+            .mint(alice(), subaccount, 300.into(), None)
+            .unwrap();
+        canister.set_fee(10.into()).unwrap();
+        canister.set_fee_to(john().into()).unwrap();
+        let args = |sources: Vec<(Option<Subaccount>, Tokens128)>| MultiSourceTransferArgs {
+            sources,
+            to: bob().into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+        };
+
+        let ids = canister
+            .multi_source_transfer(args(vec![(None, 200.into()), (subaccount, 100.into())]))
+            .unwrap();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 800.into());
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), subaccount)),
+            200.into()
+        );
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 290.into());
+        assert_eq!(
+            canister.get_transaction(ids[0]).await.unwrap().fee,
+            10.into()
+        );
+        assert_eq!(
+            canister.get_transaction(ids[1]).await.unwrap().fee,
+            0.into()
+        );
+
+        assert_eq!(
+            canister
+                .multi_source_transfer(args(vec![(None, 100.into()), (subaccount, 500.into())])),
+            Err(TxError::InsufficientFunds {
+                balance: 200.into()
+            })
+        );
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 800.into());
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), subaccount)),
+            200.into()
+        );
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 290.into());
+
+        // The transfer is validated as a single one.
+        let sources = vec![(None, 100.into()), (subaccount, 50.into())];
+        assert_eq!(
+            canister.multi_source_transfer(MultiSourceTransferArgs {
+                max_fee: Some(5.into()),
+                ..args(sources.clone())
+            }),
+            Err(TxError::BadFee {
+                expected_fee: 10.into()
+            })
+        );
+        assert_eq!(
+            canister.multi_source_transfer(MultiSourceTransferArgs {
+                valid_until: Some(ic::time() - 1),
+                ..args(sources.clone())
+            }),
+            Err(TxError::Expired {
+                valid_until: ic::time() - 1
+            })
+        );
+        assert_eq!(
+            canister.multi_source_transfer(MultiSourceTransferArgs {
+                tags: Some(vec!["a".repeat(MAX_TAG_LENGTH + 1)]),
+                ..args(sources.clone())
+            }),
+            Err(TxError::InvalidTags)
+        );
+        canister.set_reject_zero_transfers(true).unwrap();
+        assert_eq!(
+            canister.multi_source_transfer(args(vec![(None, 100.into()), (subaccount, 0.into())])),
+            Err(TxError::AmountTooSmall)
+        );
+
+        let with_time = MultiSourceTransferArgs {
+            memo: Some([1; 32]),
+            created_at_time: Some(ic::time()),
+            ..args(sources)
+        };
+        let ids = canister.multi_source_transfer(with_time.clone()).unwrap();
+        assert_eq!(
+            canister.get_transaction(ids[1]).await.unwrap().memo,
+            with_time.memo
+        );
+        assert_eq!(
+            canister.multi_source_transfer(with_time),
+            Err(TxError::Duplicate {
+                duplicate_of: ids[0]
+            })
+        );
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 430.into());
+    }
+
+    #[test]
+    fn escrow_release() {
+        let canister = test_canister();
+        let condition = Condition {
+            arbiter: Some(john()),
+            timeout_nanos: 1_000,
+        };
+
+        let id = canister
+            .transfer_conditional(bob().into(), 100.into(), condition)
+            .unwrap();
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 900.into());
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 0.into());
+        let escrow = canister.get_escrow(id).unwrap();
+        assert_eq!(escrow.to, bob().into());
+        assert_eq!(escrow.amount, 100.into());
+
+        get_context().update_caller(bob());
+        assert_eq!(canister.release_escrow(id), Err(TxError::Unauthorized));
+
+        get_context().update_caller(john());
+        canister.release_escrow(id).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 100.into());
+        assert_eq!(canister.get_escrow(id), None);
+        assert_eq!(canister.release_escrow(id), Err(TxError::EscrowNotFound));
+
+        get_context().update_caller(alice());
+        let id = canister
+            .transfer_conditional(bob().into(), 50.into(), condition)
+            .unwrap();
+        canister.release_escrow(id).unwrap();
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 850.into());
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 150.into());
+    }
+
+    #[test]
+    fn escrow_refund_on_timeout() {
+        let canister = test_canister();
+        let condition = Condition {
+            arbiter: None,
+            timeout_nanos: 1_000,
+        };
+
+        let id = canister
+            .transfer_conditional(bob().into(), 100.into(), condition)
+            .unwrap();
+        let expires_at = canister.get_escrow(id).unwrap().expires_at;
+        assert_eq!(
+            canister.refund_escrow(id),
+            Err(TxError::EscrowNotExpired { expires_at })
+        );
+
+        get_context().add_time(1_000);
+        assert_eq!(
+            canister.release_escrow(id),
+            Err(TxError::EscrowExpired { expires_at })
+        );
+
+        get_context().update_caller(bob());
+        canister.refund_escrow(id).unwrap();
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 1000.into());
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 0.into());
+        assert_eq!(canister.get_escrow(id), None);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn timelocked_transfer() {
+        let canister = test_canister();
+        canister.set_fee(10.into()).unwrap();
+        canister.set_fee_to(john().into()).unwrap();
+
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: bob().into(),
+            amount: 100.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+        let release_at = ic::time() + 1_000;
+        let id = canister
+            .transfer_timelocked(transfer.clone(), release_at)
+            .unwrap();
+        // The fee is charged at lock time.
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 890.into());
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 0.into());
+        assert_eq!(canister.timelocks_of(bob())[0].id, id);
+
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.claim_timelocked(),
+            Err(TxError::TimelockNotReleased { release_at })
+        );
+        assert_eq!(canister.cancel_timelocked(id), Err(TxError::Unauthorized));
+
+        get_context().add_time(1_000);
+        assert_eq!(canister.claim_timelocked().unwrap().len(), 1);
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 100.into());
+        assert!(canister.timelocks_of(bob()).is_empty());
+        assert_eq!(canister.claim_timelocked(), Err(TxError::TimelockNotFound));
+
+        // The released tokens cannot be recovered by the sender.
+        get_context().update_caller(alice());
+        let release_at = ic::time();
+        let id = canister
+            .transfer_timelocked(transfer.clone(), release_at)
+            .unwrap();
+        assert_eq!(
+            canister.cancel_timelocked(id),
+            Err(TxError::TimelockReleased { release_at })
+        );
+
+        // The sender cancels the transfer before the release.
+        let id = canister
+            .transfer_timelocked(transfer.clone(), ic::time() + 1_000)
+            .unwrap();
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 670.into());
+        canister.cancel_timelocked(id).unwrap();
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 770.into());
+        assert_eq!(
+            canister.cancel_timelocked(id),
+            Err(TxError::TimelockNotFound)
+        );
+
+        get_context().update_caller(bob());
+        canister.claim_timelocked().unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 200.into());
+        assert_eq!(canister.claim_timelocked(), Err(TxError::TimelockNotFound));
+
+        // The memo is recorded and the same transfer is deduplicated.
+        get_context().update_caller(alice());
+        let transfer = TransferArgs {
+            memo: Some([1; 32]),
+            created_at_time: Some(ic::time()),
+            ..transfer
+        };
+        let release_at = ic::time() + 1_000;
+        canister
+            .transfer_timelocked(transfer.clone(), release_at)
+            .unwrap();
+        let tx_id = canister.history_size() - 1;
+        assert_eq!(
+            canister.get_transaction(tx_id).await.unwrap().memo,
+            transfer.memo
+        );
+        assert_eq!(
+            canister.transfer_timelocked(transfer, release_at),
+            Err(TxError::Duplicate {
+                duplicate_of: tx_id
+            })
+        );
+    }
+
+    #[test]
+    fn vesting() {
+        let canister = test_canister();
+        let second = 1_000_000_000;
+
+        assert_eq!(
+            canister.create_vesting(bob().into(), 1000.into(), None, 200 * second, 100 * second),
+            Err(TxError::InvalidVestingSchedule)
+        );
+
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.create_vesting(bob().into(), 1000.into(), None, 100 * second, 1000 * second),
+            Err(TxError::Unauthorized)
+        );
+
+        get_context().update_caller(alice());
+        let id = canister
+            .create_vesting(bob().into(), 1000.into(), None, 100 * second, 1000 * second)
+            .unwrap();
+        assert_eq!(canister.icrc1_total_supply(), 2000.into());
+        assert_eq!(
+            canister.icrc1_balance_of(vesting_account().into()),
+            1000.into()
+        );
+        assert_eq!(canister.vesting_schedules_of(bob()).len(), 1);
+
+        // Only the beneficiary can release the tokens.
+        get_context().add_time(99 * second);
+        assert_eq!(canister.release_vested(id), Err(TxError::Unauthorized));
+
+        // Nothing is vested before the cliff.
+        get_context().update_caller(bob());
+        assert_eq!(canister.release_vested(id), Err(TxError::NothingToRelease));
+
+        // At the cliff the tokens vested since the start are released.
+        get_context().add_time(second);
+        canister.release_vested(id).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 100.into());
+        assert_eq!(canister.release_vested(id), Err(TxError::NothingToRelease));
+
+        get_context().add_time(450 * second);
+        canister.release_vested(id).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 550.into());
+        assert_eq!(canister.get_vesting(id).unwrap().released, 550.into());
+
+        // After the end of the duration the rest is released and the schedule is removed.
+        get_context().add_time(1000 * second);
+        canister.release_vested(id).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 1000.into());
+        assert_eq!(
+            canister.icrc1_balance_of(vesting_account().into()),
+            0.into()
+        );
+        assert_eq!(canister.get_vesting(id), None);
+        assert_eq!(canister.release_vested(id), Err(TxError::VestingNotFound));
+    }
+
+    #[test]
+    fn royalty_accumulates() {
+        let canister = test_canister();
+        canister.set_royalty(Some((xtc().into(), 500))).unwrap();
+        let transfer = |to: Principal, amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: to.into(),
+            amount: amount.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        canister.transfer(transfer(bob(), 400)).unwrap();
+        assert_eq!(canister.icrc1_balance_of(xtc().into()), 20.into());
+
+        get_context().update_caller(bob());
+        canister.icrc1_transfer(transfer(john(), 100)).unwrap();
+        canister.icrc1_transfer(transfer(john(), 100)).unwrap();
+        assert_eq!(canister.icrc1_balance_of(xtc().into()), 30.into());
+
+        let batch_transfer = |amount: u128| BatchTransferArgs {
+            receiver: john().into(),
+            amount: amount.into(),
+            fee_override: None,
+        };
+        canister
+            .batch_transfer(None, vec![batch_transfer(20), batch_transfer(40)])
+            .unwrap();
+        assert_eq!(canister.icrc1_balance_of(xtc().into()), 33.into());
+
+        canister.icrc2_approve(approve_args(alice(), 100)).unwrap();
+        get_context().update_caller(alice());
+        canister
+            .icrc2_transfer_from(transfer_from_args(bob(), john(), 60))
+            .unwrap();
+        assert_eq!(canister.icrc1_balance_of(xtc().into()), 36.into());
+
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 600.into());
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 60.into());
+        assert_eq!(canister.icrc1_balance_of(john().into()), 304.into());
+        assert_eq!(canister.icrc1_total_supply(), 1000.into());
+        assert_eq!(StableBalances.total_supply(), 1000.into());
+    }
+
+    fn approve_args(spender: Principal, amount: u128) -> ApproveArgs {
+        ApproveArgs {
+            from_subaccount: None,
+            spender: spender.into(),
+            amount: amount.into(),
+            fee: None,
+            expires_at: None,
         }
-        TokenConfig::set_stable(stats)
     }
 
-    fn fee_ratio(&self) -> f64 {
-        #[cfg(feature = "auction")]
-        return self.bidding_info().fee_ratio;
+    fn transfer_from_args(from: Principal, to: Principal, amount: u128) -> TransferFromArgs {
+        TransferFromArgs {
+            spender_subaccount: None,
+            from: from.into(),
+            to: to.into(),
+            amount: amount.into(),
+            fee: None,
+        }
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn icrc2_transfer_from() {
+        let canister = test_canister();
+        let id = canister.icrc2_approve(approve_args(bob(), 300)).unwrap() as TxId;
+        assert_eq!(
+            canister.get_transaction(id).await.unwrap().operation,
+            Operation::Approve
+        );
+        assert_eq!(
+            canister.icrc2_allowance(alice().into(), bob().into()),
+            300.into()
+        );
+
+        get_context().update_caller(bob());
+        let id = canister
+            .icrc2_transfer_from(transfer_from_args(alice(), john(), 200))
+            .unwrap() as TxId;
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 800.into());
+        assert_eq!(canister.icrc1_balance_of(john().into()), 200.into());
+        assert_eq!(
+            canister.icrc2_allowance(alice().into(), bob().into()),
+            100.into()
+        );
+
+        let record = canister.get_transaction(id).await.unwrap();
+        assert_eq!(record.operation, Operation::TransferFrom);
+        assert_eq!(record.caller, bob());
+        assert_eq!(record.from, alice().into());
+        assert_eq!(record.to, john().into());
+
+        assert_eq!(
+            canister.icrc2_transfer_from(transfer_from_args(alice(), john(), 200)),
+            Err(TxError::InsufficientAllowance {
+                allowance: 100.into()
+            })
+        );
+
+        get_context().update_caller(john());
+        assert_eq!(
+            canister.icrc2_transfer_from(transfer_from_args(alice(), john(), 50)),
+            Err(TxError::InsufficientAllowance {
+                allowance: 0.into()
+            })
+        );
+    }
+
+    #[test]
+    fn icrc2_fee_is_paid_by_spender() {
+        let canister = test_canister();
+        canister.mint(bob(), None, 50.into(), None).unwrap();
+        canister.set_fee(10.into()).unwrap();
+        canister.set_fee_to(john().into()).unwrap();
+
+        canister.icrc2_approve(approve_args(bob(), 300)).unwrap();
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 990.into());
+
+        get_context().update_caller(bob());
+        canister
+            .icrc2_transfer_from(transfer_from_args(alice(), xtc(), 100))
+            .unwrap();
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 890.into());
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 40.into());
+        assert_eq!(canister.icrc1_balance_of(xtc().into()), 100.into());
+        assert_eq!(canister.icrc1_balance_of(john().into()), 20.into());
+        assert_eq!(
+            canister.icrc2_allowance(alice().into(), bob().into()),
+            200.into()
+        );
+    }
+
+    #[test]
+    fn icrc2_approve_charges_effective_fee() {
+        let canister = test_canister();
+        canister.set_fee(10.into()).unwrap();
+        canister.set_fee_mode(FeeMode::BasisPoints(30)).unwrap();
+
+        // The approval moves no tokens, so no fee is charged in the basis points mode.
+        let args = ApproveArgs {
+            fee: Some(10.into()),
+            ..approve_args(bob(), 300)
+        };
+        assert_eq!(
+            canister.icrc2_approve(args),
+            Err(TxError::BadFee {
+                expected_fee: 0.into()
+            })
+        );
+        canister.icrc2_approve(approve_args(bob(), 300)).unwrap();
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 1000.into());
+    }
+
+    #[test]
+    fn icrc2_transfer_from_frozen_spender() {
+        let canister = test_canister();
+        canister.icrc2_approve(approve_args(bob(), 300)).unwrap();
+        canister.freeze_account(bob()).unwrap();
+
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.icrc2_transfer_from(transfer_from_args(alice(), john(), 100)),
+            Err(TxError::AccountFrozen)
+        );
+        assert_eq!(
+            canister.icrc2_allowance(alice().into(), bob().into()),
+            300.into()
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn increase_decrease_allowance() {
+        let canister = test_canister();
+        let allowance = || canister.icrc2_allowance(alice().into(), bob().into());
+
+        let id = canister
+            .increase_allowance(None, bob().into(), 300.into())
+            .unwrap() as TxId;
+        let record = canister.get_transaction(id).await.unwrap();
+        assert_eq!(record.operation, Operation::Approve);
+        assert_eq!(record.amount, 300.into());
+        assert_eq!(allowance(), 300.into());
+
+        canister
+            .increase_allowance(None, bob().into(), 200.into())
+            .unwrap();
+        assert_eq!(allowance(), 500.into());
+
+        let id = canister
+            .decrease_allowance(None, bob().into(), 150.into())
+            .unwrap() as TxId;
+        assert_eq!(
+            canister.get_transaction(id).await.unwrap().amount,
+            350.into()
+        );
+        assert_eq!(allowance(), 350.into());
+
+        // The spender uses a part of the allowance before the decrease is applied.
+        get_context().update_caller(bob());
+        canister
+            .icrc2_transfer_from(transfer_from_args(alice(), john(), 100))
+            .unwrap();
+
+        get_context().update_caller(alice());
+        canister
+            .decrease_allowance(None, bob().into(), 200.into())
+            .unwrap();
+        assert_eq!(allowance(), 50.into());
+
+        let id = canister
+            .decrease_allowance(None, bob().into(), 1000.into())
+            .unwrap() as TxId;
+        assert_eq!(canister.get_transaction(id).await.unwrap().amount, 0.into());
+        assert_eq!(allowance(), 0.into());
+
+        canister
+            .increase_allowance(None, bob().into(), u128::MAX.into())
+            .unwrap();
+        assert_eq!(
+            canister.increase_allowance(None, bob().into(), 1.into()),
+            Err(TxError::AmountOverflow)
+        );
+        assert_eq!(allowance(), u128::MAX.into());
+
+        assert_eq!(
+            canister.increase_allowance(None, alice().into(), 1.into()),
+            Err(TxError::SelfTransfer)
+        );
+
+        // The allowance over a subaccount is independent of the default account.
+        let subaccount = Some([1; 32]);
+        let owner = Account::new(alice(), subaccount);
+        canister
+            .increase_allowance(subaccount, bob().into(), 70.into())
+            .unwrap();
+        canister
+            .decrease_allowance(subaccount, bob().into(), 20.into())
+            .unwrap();
+        assert_eq!(canister.icrc2_allowance(owner, bob().into()), 50.into());
+        assert_eq!(allowance(), u128::MAX.into());
+    }
+
+    #[test]
+    fn icrc2_zero_approval_clears_allowance() {
+        let canister = test_canister();
+        canister.icrc2_approve(approve_args(bob(), 300)).unwrap();
+        canister.icrc2_approve(approve_args(bob(), 0)).unwrap();
+        assert_eq!(
+            canister.icrc2_allowance(alice().into(), bob().into()),
+            0.into()
+        );
+
+        assert_eq!(
+            canister.icrc2_approve(approve_args(alice(), 100)),
+            Err(TxError::SelfTransfer)
+        );
+    }
+
+    #[test]
+    fn icrc2_approval_expires() {
+        let canister = test_canister();
+        let expires_at = ic::time() + 1_000_000_000;
+        canister
+            .icrc2_approve(ApproveArgs {
+                expires_at: Some(expires_at),
+                ..approve_args(bob(), 300)
+            })
+            .unwrap();
+
+        get_context().update_caller(bob());
+        canister
+            .icrc2_transfer_from(transfer_from_args(alice(), john(), 100))
+            .unwrap();
+        assert_eq!(
+            canister.icrc2_allowance(alice().into(), bob().into()),
+            200.into()
+        );
+
+        get_context().add_time(1_000_000_000);
+        assert_eq!(
+            canister.icrc2_allowance(alice().into(), bob().into()),
+            0.into()
+        );
+        assert_eq!(
+            canister.icrc2_transfer_from(transfer_from_args(alice(), john(), 100)),
+            Err(TxError::InsufficientAllowance {
+                allowance: 0.into()
+            })
+        );
+
+        get_context().update_caller(alice());
+        assert_eq!(
+            canister.icrc2_approve(ApproveArgs {
+                expires_at: Some(expires_at),
+                ..approve_args(bob(), 300)
+            }),
+            Err(TxError::ApprovalExpired { expires_at })
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn redeem() {
+        let canister = test_canister();
+        let supply = canister.icrc1_total_supply();
+
+        let id = canister.redeem(300.into(), b"payout-42".to_vec()).unwrap() as TxId;
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 700.into());
+        assert_eq!(
+            canister.icrc1_total_supply(),
+            (supply - 300.into()).unwrap()
+        );
+
+        let record = canister.get_transaction(id).await.unwrap();
+        assert_eq!(record.operation, Operation::Redemption);
+        assert_eq!(record.from, alice().into());
+        assert_eq!(record.amount, 300.into());
+        assert_eq!(record.redemption_ref, Some(b"payout-42".to_vec()));
+
+        assert_eq!(
+            canister.redeem(0.into(), b"payout-43".to_vec()),
+            Err(TxError::AmountTooSmall)
+        );
+        assert_eq!(
+            canister.redeem(100.into(), vec![]),
+            Err(TxError::InvalidRedemptionRef)
+        );
+        assert_eq!(
+            canister.redeem(100.into(), vec![0; MAX_REDEMPTION_REF_LENGTH + 1]),
+            Err(TxError::InvalidRedemptionRef)
+        );
+        assert_eq!(
+            canister.redeem(1000.into(), b"payout-44".to_vec()),
+            Err(TxError::InsufficientFunds {
+                balance: 700.into()
+            })
+        );
+        assert_eq!(canister.history_size(), id + 1);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn reclaim_dormant() {
+        const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+        let canister = test_canister();
+        let transfer = |to: Principal, amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: to.into(),
+            amount: amount.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+        canister.transfer(transfer(bob(), 100)).unwrap();
+        canister.transfer(transfer(john(), 200)).unwrap();
+
+        get_context().add_time(300 * DAY);
+        assert_eq!(
+            canister.reclaim_dormant(bob().into()),
+            Err(TxError::DormancyReclaimDisabled)
+        );
+
+        canister.set_dormancy_period(Some(365 * DAY)).unwrap();
+        get_context().add_time(100 * DAY);
+        canister.transfer(transfer(john(), 10)).unwrap();
+
+        let id = canister.reclaim_dormant(bob().into()).unwrap() as TxId;
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 0.into());
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 790.into());
+        let record = canister.get_transaction(id).await.unwrap();
+        assert_eq!(record.operation, Operation::Reclaim);
+        assert_eq!(record.amount, 100.into());
+
+        assert!(matches!(
+            canister.reclaim_dormant(john().into()),
+            Err(TxError::AccountNotDormant { .. })
+        ));
+        assert_eq!(canister.icrc1_balance_of(john().into()), 210.into());
+
+        get_context().add_time(400 * DAY);
+        canister.freeze_account(john()).unwrap();
+        assert_eq!(
+            canister.reclaim_dormant(john().into()),
+            Err(TxError::AccountFrozen)
+        );
+        canister.unfreeze_account(john()).unwrap();
+
+        for account in [auction_account(), escrow_account(), vesting_account()] {
+            assert_eq!(
+                canister.reclaim_dormant(account.into()),
+                Err(TxError::ReservedAccount)
+            );
+        }
+
+        // The history is lost on upgrade, so the activity of the account is unknown.
+        LedgerData::clear();
+        assert!(matches!(
+            canister.reclaim_dormant(john().into()),
+            Err(TxError::AccountNotDormant { .. })
+        ));
+        assert_eq!(canister.icrc1_balance_of(john().into()), 210.into());
+
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.reclaim_dormant(john().into()),
+            Err(TxError::Unauthorized)
+        );
+    }
+
+    /// Fee oracle returning the set price, or failing if there is none.
+    #[derive(Default)]
+    struct MockOracle {
+        price: Cell<Option<u128>>,
+        calls: Cell<usize>,
+    }
+
+    impl PriceOracle for MockOracle {
+        fn get_price(&self) -> OracleCall<u128> {
+            self.calls.set(self.calls.get() + 1);
+            let price = self.price.get().ok_or(TxError::FeeOracleCallFailed {
+                message: "no price".to_string(),
+            });
+            Box::pin(async move { price })
+        }
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn fee_oracle_cap() {
+        let canister = test_canister();
+        canister.set_fee(1_000.into()).unwrap();
+        assert_eq!(
+            canister.refresh_fee_oracle_price().await,
+            Err(TxError::FeeOracleNotSet)
+        );
+
+        // Decimals of the test token are 8, so the fee is capped by
+        // `max_fee_fiat * 10^8 / price` tokens.
+        canister
+            .set_fee_oracle(Some(FeeOracle {
+                oracle: xtc(),
+                max_fee_fiat: 1,
+                price_ttl_nanos: 1_000,
+            }))
+            .unwrap();
+        assert_eq!(canister.effective_fee(1.into()), 1_000.into());
+
+        let oracle = MockOracle::default();
+        oracle.price.set(Some(1_000_000));
+        assert_eq!(OraclePrice::refresh(&oracle, 1_000).await, Ok(1_000_000));
+        assert_eq!(canister.effective_fee(1.into()), 100.into());
+        assert_eq!(canister.icrc1_fee(), 100.into());
+
+        // The cached price is used until it becomes stale.
+        oracle.price.set(Some(100_000));
+        get_context().add_time(1_000);
+        assert_eq!(OraclePrice::refresh(&oracle, 1_000).await, Ok(1_000_000));
+        assert_eq!(oracle.calls.get(), 1);
+
+        get_context().add_time(1);
+        assert_eq!(OraclePrice::refresh(&oracle, 1_000).await, Ok(100_000));
+        assert_eq!(oracle.calls.get(), 2);
+        assert_eq!(canister.effective_fee(1.into()), 1_000.into());
+
+        oracle.price.set(Some(10_000_000));
+        get_context().add_time(1_001);
+        assert_eq!(OraclePrice::refresh(&oracle, 1_000).await, Ok(10_000_000));
+        assert_eq!(canister.effective_fee(1.into()), 10.into());
+
+        // The failed request keeps the cached price, and the fee is not capped once it is stale.
+        oracle.price.set(None);
+        get_context().add_time(1_001);
+        assert!(matches!(
+            OraclePrice::refresh(&oracle, 1_000).await,
+            Err(TxError::FeeOracleCallFailed { .. })
+        ));
+        assert_eq!(canister.fee_oracle_price().price, Some(10_000_000));
+        assert_eq!(canister.effective_fee(1.into()), 1_000.into());
+    }
+
+    #[test]
+    fn account_tx_ids() {
+        let canister = test_canister();
+        let transfer = |to: Principal, amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: to.into(),
+            amount: amount.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        let first = canister.transfer(transfer(bob(), 100)).unwrap() as TxId;
+        canister.transfer(transfer(john(), 100)).unwrap();
+        get_context().update_caller(bob());
+        let third = canister.transfer(transfer(john(), 10)).unwrap() as TxId;
+        let fourth = canister.transfer(transfer(xtc(), 10)).unwrap() as TxId;
+
+        assert_eq!(
+            canister.account_tx_ids(bob().into(), 0, fourth),
+            vec![first, third, fourth]
+        );
+        assert_eq!(
+            canister.account_tx_ids(bob().into(), first + 1, third),
+            vec![third]
+        );
+        assert!(canister
+            .account_tx_ids(john().into(), third + 1, fourth)
+            .is_empty());
+        assert!(canister
+            .account_tx_ids(bob().into(), fourth, first)
+            .is_empty());
+
+        // Removed transactions are dropped from the account index.
+        LedgerData::remove_oldest(first as usize + 1);
+        assert_eq!(
+            canister.account_tx_ids(bob().into(), 0, fourth),
+            vec![third, fourth]
+        );
+    }
+
+    #[test]
+    fn get_account_transactions() {
+        let canister = test_canister();
+        let ids = |records: Vec<TxRecord>| records.iter().map(|tx| tx.index).collect::<Vec<_>>();
+
+        let bob_mint = canister.mint(bob(), None, 200.into(), None).unwrap() as TxId;
+        let john_mint = canister.mint(john(), None, 300.into(), None).unwrap() as TxId;
+
+        get_context().update_caller(bob());
+        let bob_transfer = canister
+            .transfer(TransferArgs {
+                from_subaccount: None,
+                to: xtc().into(),
+                amount: 50.into(),
+                fee: None,
+                max_fee: None,
+                memo: None,
+                created_at_time: None,
+                valid_until: None,
+                tags: None,
+                tip: None,
+            })
+            .unwrap() as TxId;
+
+        get_context().update_caller(john());
+        let john_burn = canister.burn(None, None, 20.into(), None).unwrap() as TxId;
+
+        assert_eq!(
+            ids(canister.get_account_transactions(bob().into(), None, 10)),
+            vec![bob_transfer, bob_mint]
+        );
+        assert_eq!(
+            ids(canister.get_account_transactions(john().into(), None, 10)),
+            vec![john_burn, john_mint]
+        );
+        assert_eq!(
+            ids(canister.get_account_transactions(xtc().into(), None, 10)),
+            vec![bob_transfer]
+        );
+
+        assert_eq!(
+            ids(canister.get_account_transactions(bob().into(), None, 1)),
+            vec![bob_transfer]
+        );
+        assert_eq!(
+            ids(canister.get_account_transactions(bob().into(), Some(john_mint), 10)),
+            vec![bob_mint]
+        );
+        assert!(canister
+            .get_account_transactions(bob().into(), Some(0), 10)
+            .is_empty());
+    }
+
+    #[test]
+    fn non_transferable() {
+        let canister = test_canister();
+        canister.set_transferable(false).unwrap();
+        assert!(!canister.is_transferable());
+
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: bob().into(),
+            amount: 100.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+        assert_eq!(
+            canister.transfer(transfer.clone()),
+            Err(TxError::NonTransferable)
+        );
+        assert_eq!(
+            canister.icrc1_transfer(transfer.clone()),
+            Err(TransferError::GenericError {
+                error_code: 500,
+                message: "token is not transferable".to_string(),
+            })
+        );
+        assert_eq!(
+            canister.batch_transfer(
+                None,
+                vec![BatchTransferArgs {
+                    receiver: bob().into(),
+                    amount: 100.into(),
+                    fee_override: None,
+                }]
+            ),
+            Err(TxError::NonTransferable)
+        );
+
+        canister.mint(bob(), None, 100.into(), None).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 100.into());
+        get_context().update_caller(bob());
+        canister.burn(None, None, 40.into(), None).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 60.into());
 
-        #[cfg(not(feature = "auction"))]
-        0.0
+        get_context().update_caller(alice());
+        canister.set_transferable(true).unwrap();
+        canister.transfer(transfer).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 160.into());
     }
-}
 
-generate_exports!(TokenCanisterAPI, TokenCanisterExports);
+    #[test]
+    fn frozen_accounts() {
+        let canister = test_canister();
+        canister.mint(bob(), None, 100.into(), None).unwrap();
 
-#[cfg(feature = "auction")]
-use canister_sdk::ic_storage::IcStorage;
+        get_context().update_caller(bob());
+        assert_eq!(canister.freeze_account(bob()), Err(TxError::Unauthorized));
 
-#[cfg(feature = "auction")]
-impl Auction for TokenCanisterExports {
-    fn auction_state(&self) -> std::rc::Rc<std::cell::RefCell<AuctionState>> {
-        AuctionState::get()
-    }
+        get_context().update_caller(alice());
+        canister.freeze_account(bob()).unwrap();
+        assert!(canister.is_frozen(bob()));
+        assert!(!canister.is_frozen(alice()));
 
-    fn disburse_rewards(&self) -> Result<AuctionInfo, AuctionError> {
-        is20_auction::disburse_rewards(&self.auction_state().borrow())
+        let transfer = |to: Principal| TransferArgs {
+            from_subaccount: None,
+            to: to.into(),
+            amount: 10.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        // Frozen recipient.
+        assert_eq!(
+            canister.transfer(transfer(bob())),
+            Err(TxError::AccountFrozen)
+        );
+
+        // Frozen sender.
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.transfer(transfer(john())),
+            Err(TxError::AccountFrozen)
+        );
+        assert_eq!(
+            canister.batch_transfer(
+                None,
+                vec![BatchTransferArgs {
+                    receiver: john().into(),
+                    amount: 10.into(),
+                    fee_override: None,
+                }]
+            ),
+            Err(TxError::AccountFrozen)
+        );
+
+        // The owner seizes the frozen balance.
+        get_context().update_caller(alice());
+        canister.burn(Some(bob()), None, 100.into(), None).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 0.into());
+
+        canister.unfreeze_account(bob()).unwrap();
+        assert!(!canister.is_frozen(bob()));
+        canister.transfer(transfer(bob())).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 10.into());
     }
-}
 
-pub fn auction_account() -> AccountInternal {
-    // There are no sub accounts for the auction principal
-    AccountInternal::new(Principal::management_canister(), None)
-}
+    #[test]
+    fn minters() {
+        let canister = test_canister();
 
-#[cfg(test)]
-mod tests {
-    use canister_sdk::ic_canister::canister_call;
-    use canister_sdk::ic_kit::inject::get_context;
-    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
-    use canister_sdk::ic_kit::MockContext;
-    #[cfg(feature = "claim")]
-    use canister_sdk::ledger::{AccountIdentifier, Subaccount as SubaccountIdentifier};
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.mint_as_minter(bob(), None, 100.into(), None),
+            Err(TxError::Unauthorized)
+        );
+        assert_eq!(canister.add_minter(bob()), Err(TxError::Unauthorized));
 
-    use crate::mock::TokenCanisterMock;
-    use crate::{account::DEFAULT_SUBACCOUNT, state::config::Metadata};
+        get_context().update_caller(alice());
+        canister.add_minter(bob()).unwrap();
+        assert!(canister.is_minter(bob()));
+        assert!(!canister.is_minter(alice()));
 
-    use super::*;
+        get_context().update_caller(bob());
+        canister
+            .mint_as_minter(john(), None, 100.into(), None)
+            .unwrap();
+        assert_eq!(canister.icrc1_balance_of(john().into()), 100.into());
+        assert_eq!(canister.icrc1_total_supply(), 1100.into());
 
-    // Method for generating random Subaccount.
-    #[cfg(feature = "claim")]
-    #[cfg_attr(coverage_nightly, no_coverage)]
-    fn gen_subaccount() -> Subaccount {
-        use rand::{thread_rng, Rng};
+        // Minters have no other owner rights.
+        assert_eq!(canister.set_fee(10.into()), Err(TxError::Unauthorized));
+        assert_eq!(canister.remove_minter(bob()), Err(TxError::Unauthorized));
 
-        let mut subaccount = [0u8; 32];
-        thread_rng().fill(&mut subaccount);
-        subaccount
+        get_context().update_caller(alice());
+        canister.remove_minter(bob()).unwrap();
+        assert!(!canister.is_minter(bob()));
+
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.mint_as_minter(john(), None, 100.into(), None),
+            Err(TxError::Unauthorized)
+        );
+        assert_eq!(canister.icrc1_balance_of(john().into()), 100.into());
     }
 
+    #[tokio::test]
     #[cfg_attr(coverage_nightly, no_coverage)]
-    fn test_context() -> (&'static MockContext, TokenCanisterMock) {
-        let context = MockContext::new().with_caller(john()).inject();
+    async fn transfer_notify() {
+        let canister = test_canister();
+        let transfer = |amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: bob().into(),
+            amount: amount.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
 
-        let principal = Principal::from_text("mfufu-x6j4c-gomzb-geilq").unwrap();
-        let canister = TokenCanisterMock::from_principal(principal);
+        // The recipient records the notification by checking its arguments.
+        get_context().use_handler(
+            Method::new()
+                .name("on_transfer")
+                .expect_arguments((1u128, Tokens128::from(100)))
+                .response::<()>(()),
+        );
 
-        // Refresh canister's state.
-        TokenConfig::set_stable(TokenConfig::default());
-        StableBalances.clear();
-        LedgerData::clear();
+        let receipt = canister
+            .transfer_notify(transfer(100), "on_transfer".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            receipt,
+            NotifyReceipt {
+                tx_id: 1,
+                notify_error: None,
+            }
+        );
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 100.into());
 
-        // Due to this update, init() code will get actual
-        // principal of the canister from ic::id().
-        context.update_id(canister.principal());
+        // A failed transfer doesn't notify the recipient.
+        assert_eq!(
+            canister
+                .transfer_notify(transfer(2000), "on_transfer".to_string())
+                .await,
+            Err(TxError::InsufficientFunds {
+                balance: 900.into()
+            })
+        );
+        assert_eq!(canister.history_size(), 2);
+    }
 
-        canister.init(
-            Metadata {
-                name: "".to_string(),
-                symbol: "".to_string(),
-                decimals: 8,
+    #[test]
+    fn two_step_ownership_transfer() {
+        let canister = test_canister();
 
-                owner: john(),
-                fee: Tokens128::from(0),
-                fee_to: john(),
-                is_test_token: None,
-            },
-            Tokens128::from(1000),
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.propose_new_owner(bob()),
+            Err(TxError::Unauthorized)
         );
+        assert_eq!(canister.accept_ownership(), Err(TxError::Unauthorized));
 
-        // This is to make tests that don't rely on auction state
-        // pass, because since we are running auction state on each
-        // endpoint call, it affects `BiddingInfo.fee_ratio` that is
-        // used for charging fees in `approve` endpoint.
-        let mut stats = TokenConfig::get_stable();
-        stats.min_cycles = 0;
-        TokenConfig::set_stable(stats);
-
-        canister.mint(alice(), None, 1000.into()).unwrap();
-        context.update_caller(alice());
+        // Propose and cancel.
+        get_context().update_caller(alice());
+        canister.propose_new_owner(bob()).unwrap();
+        assert_eq!(canister.pending_owner(), Some(bob()));
+        canister.cancel_ownership_transfer().unwrap();
+        assert_eq!(canister.pending_owner(), None);
 
-        (context, canister)
-    }
+        get_context().update_caller(bob());
+        assert_eq!(canister.accept_ownership(), Err(TxError::Unauthorized));
+        assert_eq!(canister.owner(), alice());
 
-    fn test_canister() -> TokenCanisterMock {
-        let context = MockContext::new().with_caller(alice()).inject();
+        // Propose and accept.
+        get_context().update_caller(alice());
+        canister.propose_new_owner(bob()).unwrap();
 
-        let principal = Principal::from_text("mfufu-x6j4c-gomzb-geilq").unwrap();
-        let canister = TokenCanisterMock::from_principal(principal);
-        context.update_id(canister.principal());
+        get_context().update_caller(john());
+        assert_eq!(canister.accept_ownership(), Err(TxError::Unauthorized));
+        assert_eq!(canister.owner(), alice());
 
-        // Refresh canister's state.
-        TokenConfig::set_stable(TokenConfig::default());
-        StableBalances.clear();
-        LedgerData::clear();
+        get_context().update_caller(bob());
+        canister.accept_ownership().unwrap();
+        assert_eq!(canister.owner(), bob());
+        assert_eq!(canister.pending_owner(), None);
+        #[cfg(feature = "auction")]
+        assert_eq!(canister.auction_state().borrow().controller, bob());
 
-        canister.init(
-            Metadata {
-                name: "".to_string(),
-                symbol: "".to_string(),
-                decimals: 8,
-                owner: alice(),
-                fee: Tokens128::from(0),
-                fee_to: alice(),
-                is_test_token: None,
-            },
-            Tokens128::from(1000),
+        get_context().update_caller(alice());
+        assert_eq!(
+            canister.propose_new_owner(alice()),
+            Err(TxError::Unauthorized)
         );
+    }
 
-        let mut stats = TokenConfig::get_stable();
-        stats.min_cycles = 0;
-        TokenConfig::set_stable(stats);
+    #[test]
+    fn set_owner_overrides_ownership_transfer() {
+        let canister = test_canister();
+        canister.propose_new_owner(bob()).unwrap();
+        canister.set_owner(john()).unwrap();
+        assert_eq!(canister.owner(), john());
+        assert_eq!(canister.pending_owner(), None);
+        #[cfg(feature = "auction")]
+        assert_eq!(canister.auction_state().borrow().controller, john());
 
-        canister
+        get_context().update_caller(bob());
+        assert_eq!(canister.accept_ownership(), Err(TxError::Unauthorized));
+
+        // The queued owner update changes the auction controller when it is executed.
+        get_context().update_caller(john());
+        canister.set_governance_delay(Some(1_000)).unwrap();
+        let id = canister.set_owner(alice()).unwrap().unwrap();
+        get_context().add_time(1_000);
+        canister.execute_owner_action(id).unwrap();
+        assert_eq!(canister.owner(), alice());
+        #[cfg(feature = "auction")]
+        assert_eq!(canister.auction_state().borrow().controller, alice());
     }
 
     #[test]
-    fn transfer_to_same_account() {
+    fn paused_transfers() {
         let canister = test_canister();
+        canister.icrc2_approve(approve_args(bob(), 300)).unwrap();
+
+        get_context().update_caller(bob());
+        assert_eq!(canister.set_paused(true), Err(TxError::Unauthorized));
+
+        get_context().update_caller(alice());
+        canister.set_paused(true).unwrap();
+        assert!(canister.is_paused());
+
         let transfer = TransferArgs {
             from_subaccount: None,
-            to: alice().into(),
+            to: bob().into(),
             amount: 100.into(),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
+        assert_eq!(
+            canister.transfer(transfer.clone()),
+            Err(TxError::TokenPaused)
+        );
+        assert_eq!(
+            canister.icrc1_transfer(transfer.clone()),
+            Err(TransferError::TemporarilyUnavailable)
+        );
+        assert_eq!(
+            canister.batch_transfer(
+                None,
+                vec![BatchTransferArgs {
+                    receiver: bob().into(),
+                    amount: 100.into(),
+                    fee_override: None,
+                }]
+            ),
+            Err(TxError::TokenPaused)
+        );
 
-        let res = canister.icrc1_transfer(transfer);
+        get_context().update_caller(bob());
         assert_eq!(
-            res,
-            Err(TransferError::GenericError {
-                error_code: 500,
-                message: "self transfer".into()
-            })
-        )
+            canister.icrc2_transfer_from(transfer_from_args(alice(), john(), 200)),
+            Err(TxError::TokenPaused)
+        );
+
+        // The owner can still mint and burn.
+        get_context().update_caller(alice());
+        canister.mint(bob(), None, 100.into(), None).unwrap();
+        canister.burn(Some(bob()), None, 40.into(), None).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 60.into());
+
+        canister.set_paused(false).unwrap();
+        canister.transfer(transfer).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 160.into());
+
+        get_context().update_caller(bob());
+        canister
+            .icrc2_transfer_from(transfer_from_args(alice(), john(), 200))
+            .unwrap();
+        assert_eq!(canister.icrc1_balance_of(john().into()), 200.into());
     }
 
     #[test]
-    fn transfer_to_same_default_subaccount() {
+    fn balance_delta_since() {
+        use crate::state::ledger::DeltaDirection;
+
         let canister = test_canister();
-        let transfer = TransferArgs {
-            from_subaccount: Some(crate::account::DEFAULT_SUBACCOUNT),
-            to: alice().into(),
-            amount: 100.into(),
+        let mut stats = TokenConfig::get_stable();
+        stats.fee = 10.into();
+        stats.fee_to = john();
+        TokenConfig::set_stable(stats);
+
+        let transfer = |to: Principal, amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: to.into(),
+            amount: amount.into(),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
-        let res = canister.icrc1_transfer(transfer);
+        let first_id = canister.transfer(transfer(bob(), 300)).unwrap() as TxId;
+        get_context().update_caller(bob());
+        canister.transfer(transfer(alice(), 50)).unwrap();
+        canister.transfer(transfer(xtc(), 100)).unwrap();
+
         assert_eq!(
-            res,
-            Err(TransferError::GenericError {
-                error_code: 500,
-                message: "self transfer".into()
+            canister.balance_delta_since(bob().into(), 0),
+            Ok(BalanceDelta {
+                direction: DeltaDirection::Incoming,
+                amount: 130.into(),
             })
         );
-
-        let transfer = TransferArgs {
-            from_subaccount: None,
-            to: Account::new(alice(), Some(DEFAULT_SUBACCOUNT)),
-            amount: 100.into(),
-            fee: None,
-            memo: None,
-            created_at_time: None,
-        };
-
-        let res = canister.icrc1_transfer(transfer);
         assert_eq!(
-            res,
-            Err(TransferError::GenericError {
-                error_code: 500,
-                message: "self transfer".into()
+            canister.balance_delta_since(bob().into(), first_id),
+            Ok(BalanceDelta {
+                direction: DeltaDirection::Outgoing,
+                amount: 170.into(),
+            })
+        );
+        assert_eq!(
+            canister.balance_delta_since(alice().into(), 0),
+            Ok(BalanceDelta {
+                direction: DeltaDirection::Outgoing,
+                amount: 260.into(),
+            })
+        );
+        assert_eq!(
+            canister.balance_delta_since(xtc().into(), LedgerData::len()),
+            Ok(BalanceDelta {
+                direction: DeltaDirection::Incoming,
+                amount: 0.into(),
             })
         );
-    }
-
-    #[cfg(feature = "claim")]
-    #[test]
-    fn test_claim() {
-        let bob_sub = gen_subaccount();
-        let alice_sub = gen_subaccount();
-
-        let alice_aid =
-            AccountIdentifier::new(alice().into(), Some(SubaccountIdentifier(alice_sub)));
-        let bob_aid = AccountIdentifier::new(bob().into(), Some(SubaccountIdentifier(bob_sub)));
-
-        let (ctx, canister) = test_context();
-        ctx.update_caller(john());
-
-        assert!(canister
-            .mint(
-                canister.owner(),
-                Some(alice_aid.to_address()),
-                Tokens128::from(1000)
-            )
-            .is_ok());
-        assert!(canister
-            .mint(
-                canister.owner(),
-                Some(bob_aid.to_address()),
-                Tokens128::from(2000)
-            )
-            .is_ok());
 
-        ctx.update_caller(alice());
+        // The delta since a removed transaction is incomplete.
+        LedgerData::remove_oldest(2);
         assert_eq!(
-            canister.get_claimable_amount(canister.owner(), Some(alice_sub)),
-            Tokens128::from(1000)
+            canister.balance_delta_since(bob().into(), 0),
+            Err(TxError::HistoryPruned { first_available: 2 })
+        );
+        assert_eq!(
+            canister.balance_delta_since(bob().into(), first_id),
+            Ok(BalanceDelta {
+                direction: DeltaDirection::Outgoing,
+                amount: 170.into(),
+            })
         );
+    }
 
-        let balance_before = canister.icrc1_balance_of(alice().into());
-        canister.claim(canister.owner(), Some(alice_sub)).unwrap();
-        assert_eq!(
-            canister.icrc1_balance_of(alice().into()),
-            (Tokens128::from(1000) + balance_before).unwrap()
+    #[test]
+    fn upgrade_authority_of_factory_deployed_token() {
+        let canister = test_canister();
+        assert_eq!(canister.upgrade_authority(), alice());
+        assert_eq!(canister.controllers(), vec![alice()]);
+
+        // The factory installs the token, so it is the caller of `init`.
+        let factory = xtc();
+        get_context().update_caller(factory);
+        canister.init(
+            Metadata {
+                name: "".to_string(),
+                symbol: "".to_string(),
+                decimals: 8,
+                owner: alice(),
+                fee: Tokens128::from(0),
+                fee_to: alice(),
+                is_test_token: None,
+                max_supply: None,
+            },
+            Tokens128::from(1000),
         );
+
+        assert_eq!(canister.owner(), alice());
+        assert_eq!(canister.upgrade_authority(), factory);
+        assert_eq!(canister.controllers(), vec![factory, alice()]);
+    }
+
+    #[test]
+    fn icrc1_metadata() {
+        let canister = test_canister();
+        assert!(!canister
+            .icrc1_metadata()
+            .iter()
+            .any(|(key, _)| key == "icrc1:logo"));
+
+        get_context().update_caller(bob());
         assert_eq!(
-            canister.get_claimable_amount(canister.owner(), Some(alice_sub)),
-            0.into()
+            canister.set_logo("data:image/png;base64,AA==".to_string()),
+            Err(TxError::Unauthorized)
         );
 
-        ctx.update_caller(bob());
+        get_context().update_caller(alice());
+        canister
+            .set_logo("data:image/png;base64,AA==".to_string())
+            .unwrap();
+
+        let metadata = canister.icrc1_metadata();
+        let get = |key: &str| {
+            metadata
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| value.clone())
+        };
+        assert_eq!(get("icrc1:name"), Some(Value::Text("".to_string())));
+        assert_eq!(get("icrc1:symbol"), Some(Value::Text("".to_string())));
+        assert_eq!(get("icrc1:decimals"), Some(Value::Nat(8.into())));
+        assert_eq!(get("icrc1:fee"), Some(Value::Nat(0.into())));
         assert_eq!(
-            canister.get_claimable_amount(canister.owner(), Some(bob_sub)),
-            Tokens128::from(2000)
+            get("icrc1:logo"),
+            Some(Value::Text("data:image/png;base64,AA==".to_string()))
         );
     }
 
-    // **** APIs tests ****
+    #[test]
+    fn supported_standards() {
+        let canister = test_canister();
+        let standards = canister.icrc1_supported_standards();
+        let names: Vec<_> = standards.iter().map(|s| s.name.as_str()).collect();
+        assert!(names.contains(&"ICRC-1"));
+        assert!(names.contains(&"IS20"));
+        assert!(names.contains(&"ICRC-2"));
+        assert!(names.contains(&"ICRC-3"));
 
-    #[tokio::test]
-    #[cfg_attr(coverage_nightly, no_coverage)]
-    async fn set_name() {
-        let (ctx, canister) = test_context();
-        ctx.update_id(john());
-        canister_call!(canister.set_name("War and Piece".to_string()), Result<(), TxError>)
-            .await
-            .unwrap()
-            .unwrap();
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
-            .await
-            .unwrap();
+        for standard in &standards {
+            assert!(standard.url.starts_with("https://github.com/"));
+            assert!(!standard.url.contains(char::is_whitespace));
+        }
+    }
 
-        assert_eq!(info.metadata.name, "War and Piece".to_string());
+    #[test]
+    fn icrc3_get_blocks() {
+        use crate::state::blocks::{GetBlocksArgs, Icrc3Value};
 
-        ctx.update_id(bob());
-        let res = canister_call!(canister.set_name("Crime and Punishment".to_string()), Result<(), TxError>)
-            .await
-            .unwrap();
+        let canister = test_canister();
+        for amount in [100u128, 200, 300] {
+            canister
+                .transfer(TransferArgs {
+                    from_subaccount: None,
+                    to: bob().into(),
+                    amount: amount.into(),
+                    fee: None,
+                    max_fee: None,
+                    memo: None,
+                    created_at_time: None,
+                    valid_until: None,
+                    tags: None,
+                    tip: None,
+                })
+                .unwrap();
+        }
 
-        assert_eq!(res, Err(TxError::Unauthorized));
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
-            .await
-            .unwrap();
+        let range = |start: u64, length: u64| GetBlocksArgs {
+            start: start.into(),
+            length: length.into(),
+        };
+        let parent_hash = |block: &Icrc3Value| match block {
+            Icrc3Value::Map(entries) => entries.iter().find_map(|(key, value)| match value {
+                Icrc3Value::Blob(hash) if key == "phash" => Some(hash.clone()),
+                _ => None,
+            }),
+            _ => panic!("block is not a map"),
+        };
 
-        assert_eq!(info.metadata.name, "War and Piece".to_string());
-        let name = canister_call!(canister.icrc1_name(), String).await.unwrap();
-        assert_eq!(name, "War and Piece".to_string());
-    }
+        let result = canister.icrc3_get_blocks(vec![range(0, 10)]);
+        assert_eq!(result.log_length, 4u64.into());
+        assert_eq!(result.blocks.len(), 4);
+        assert_eq!(parent_hash(&result.blocks[0].block), None);
+        for pair in result.blocks.windows(2) {
+            assert_eq!(
+                parent_hash(&pair[1].block),
+                Some(pair[0].block.hash().to_vec())
+            );
+        }
 
-    #[tokio::test]
-    #[cfg_attr(coverage_nightly, no_coverage)]
-    async fn set_symbol() {
-        let (ctx, canister) = test_context();
-        ctx.update_id(john());
-        canister_call!(canister.set_symbol("MAX".to_string()), Result<(), TxError>)
-            .await
-            .unwrap()
-            .unwrap();
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
-            .await
-            .unwrap();
+        let result = canister.icrc3_get_blocks(vec![range(1, 2), range(3, 5)]);
+        let ids = result
+            .blocks
+            .iter()
+            .map(|block| block.id.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1u64.into(), 2u64.into(), 3u64.into()]);
 
-        assert_eq!(info.metadata.symbol, "MAX".to_string());
+        // The chain is kept when the oldest blocks are removed.
+        let second_block_hash = canister.icrc3_get_blocks(vec![range(1, 1)]).blocks[0]
+            .block
+            .hash();
+        LedgerData::remove_oldest(2);
+        let result = canister.icrc3_get_blocks(vec![range(0, 10)]);
+        assert_eq!(result.log_length, 4u64.into());
+        assert_eq!(result.blocks.len(), 2);
+        assert_eq!(result.blocks[0].id, 2u64.into());
+        assert_eq!(
+            parent_hash(&result.blocks[0].block),
+            Some(second_block_hash.to_vec())
+        );
+    }
 
-        ctx.update_id(bob());
-        let res = canister_call!(canister.set_symbol("BOB".to_string()), Result<(), TxError>)
-            .await
-            .unwrap();
+    #[test]
+    fn operation_counts() {
+        let canister = test_canister();
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: bob().into(),
+            amount: 100.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
 
-        assert_eq!(res, Err(TxError::Unauthorized));
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
-            .await
-            .unwrap();
+        canister.transfer(transfer.clone()).unwrap();
+        canister.icrc1_transfer(transfer).unwrap();
+        canister.mint(john(), None, 100.into(), None).unwrap();
+        canister.icrc2_approve(approve_args(bob(), 300)).unwrap();
 
-        assert_eq!(info.metadata.symbol, "MAX".to_string());
-        let symbol = canister_call!(canister.icrc1_symbol(), String)
-            .await
+        get_context().update_caller(bob());
+        canister
+            .icrc2_transfer_from(transfer_from_args(alice(), john(), 50))
             .unwrap();
-        assert_eq!(symbol, "MAX".to_string());
+        canister.burn(None, None, 10.into(), None).unwrap();
+        canister.burn(None, None, 10.into(), None).unwrap();
+
+        assert_eq!(
+            canister.operation_counts(),
+            OperationCounts {
+                transfers: 2,
+                transfer_froms: 1,
+                // Including the initial mint.
+                mints: 2,
+                burns: 2,
+                approves: 1,
+                auctions: 0,
+            }
+        );
     }
 
     #[tokio::test]
     #[cfg_attr(coverage_nightly, no_coverage)]
-    async fn set_fee() {
-        let (ctx, canister) = test_context();
-        ctx.update_id(john());
-        canister_call!(canister.set_fee(100500.into()), Result<(), TxError>)
-            .await
-            .unwrap()
-            .unwrap();
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
-            .await
-            .unwrap();
-
-        assert_eq!(info.metadata.fee, 100500.into());
+    async fn mint_and_burn_memo() {
+        let canister = test_canister();
+        let memo = Some([1; 32]);
 
-        ctx.update_id(bob());
-        let res = canister_call!(canister.set_fee(0.into()), Result<(), TxError>)
-            .await
-            .unwrap();
+        let id = canister.mint(bob(), None, 100.into(), memo).unwrap() as TxId;
+        let record = canister.get_transaction(id).await.unwrap();
+        assert_eq!(record.operation, Operation::Mint);
+        assert_eq!(record.memo, memo);
 
-        assert_eq!(res, Err(TxError::Unauthorized));
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
-            .await
-            .unwrap();
+        let id = canister
+            .burn(Some(bob()), None, 50.into(), Some([2; 32]))
+            .unwrap() as TxId;
+        let record = canister.get_transaction(id).await.unwrap();
+        assert_eq!(record.operation, Operation::Burn);
+        assert_eq!(record.memo, Some([2; 32]));
 
-        assert_eq!(info.metadata.fee, 100500.into());
-        let fee = canister_call!(canister.icrc1_fee(), Tokens128)
-            .await
-            .unwrap();
-        assert_eq!(fee, 100500.into());
+        let id = canister.burn(None, None, 50.into(), None).unwrap() as TxId;
+        assert_eq!(canister.get_transaction(id).await.unwrap().memo, None);
     }
 
     #[tokio::test]
     #[cfg_attr(coverage_nightly, no_coverage)]
-    async fn set_fee_to() {
-        let (ctx, canister) = test_context();
-        ctx.update_id(john());
-        canister_call!(canister.set_fee_to(alice()), Result<(), TxError>)
-            .await
-            .unwrap()
-            .unwrap();
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
-            .await
-            .unwrap();
+    async fn maintenance_mode() {
+        let canister = test_canister();
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: bob().into(),
+            amount: 100.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+        let id = canister.transfer(transfer.clone()).unwrap() as TxId;
 
-        assert_eq!(info.metadata.fee_to, alice());
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.set_maintenance_mode(true),
+            Err(TxError::Unauthorized)
+        );
 
-        ctx.update_id(bob());
-        let res = canister_call!(canister.set_fee_to(bob()), Result<(), TxError>)
-            .await
-            .unwrap();
+        get_context().update_caller(alice());
+        canister.set_maintenance_mode(true).unwrap();
+        assert!(canister.is_under_maintenance());
 
-        assert_eq!(res, Err(TxError::Unauthorized));
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
-            .await
-            .unwrap();
+        // Queries are served as usual.
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 100.into());
+        assert_eq!(
+            canister.get_transaction(id).await.unwrap().amount,
+            100.into()
+        );
+        assert_eq!(canister.history_size(), 2);
 
-        assert_eq!(info.metadata.fee_to, alice());
+        // Updates are rejected.
+        assert_eq!(
+            canister.transfer(transfer.clone()),
+            Err(TxError::UnderMaintenance)
+        );
+        assert_eq!(
+            canister.icrc1_transfer(transfer.clone()),
+            Err(TransferError::TemporarilyUnavailable)
+        );
+        assert_eq!(
+            canister.mint(bob(), None, 100.into(), None),
+            Err(TxError::UnderMaintenance)
+        );
+        assert_eq!(
+            canister.burn(None, None, 100.into(), None),
+            Err(TxError::UnderMaintenance)
+        );
+        assert_eq!(
+            canister.icrc2_approve(approve_args(bob(), 100)),
+            Err(TxError::UnderMaintenance)
+        );
+        assert_eq!(canister.set_fee(10.into()), Err(TxError::UnderMaintenance));
+        assert_eq!(
+            canister.set_outgoing_disabled(None, true),
+            Err(TxError::UnderMaintenance)
+        );
+        assert_eq!(
+            inspect::inspect_message("transfer", alice()).unwrap_err(),
+            "The canister is under maintenance. Rejecting."
+        );
+        assert!(inspect::inspect_message("set_maintenance_mode", alice()).is_ok());
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 100.into());
+
+        canister.set_maintenance_mode(false).unwrap();
+        canister.transfer(transfer).unwrap();
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 200.into());
     }
 
-    #[tokio::test]
-    #[cfg_attr(coverage_nightly, no_coverage)]
-    async fn set_owner() {
-        let (ctx, canister) = test_context();
-        ctx.update_id(john());
-        canister_call!(canister.set_owner(alice()), Result<(), TxError>)
-            .await
-            .unwrap()
-            .unwrap();
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
-            .await
-            .unwrap();
+    #[test]
+    fn paginated_holders() {
+        let canister = test_canister();
+        for (i, to) in [bob(), john(), xtc(), bob()].into_iter().enumerate() {
+            canister
+                .mint(to, Some([i as u8; 32]), 100.into(), None)
+                .unwrap();
+        }
+        StableBalances.insert(AccountInternal::new(john(), None), Tokens128::ZERO);
+        assert_eq!(canister.holders_count(), 5);
 
-        assert_eq!(info.metadata.owner, alice());
+        let mut pages = vec![];
+        let mut start = None;
+        loop {
+            let page = canister.get_holders(start, 2);
+            if page.is_empty() {
+                break;
+            }
+            assert!(page.len() <= 2);
+            start = page.last().map(|(account, _)| *account);
+            pages.extend(page);
+        }
 
-        ctx.update_id(bob());
-        let res = canister_call!(canister.set_owner(bob()), Result<(), TxError>)
-            .await
-            .unwrap();
+        let all = canister.get_holders(None, 10);
+        assert_eq!(all.len(), 5);
+        assert_eq!(pages, all);
+        assert!(!all.contains(&(Account::new(john(), None), Tokens128::ZERO)));
 
-        assert_eq!(res, Err(TxError::Unauthorized));
-        let info = canister_call!(canister.get_token_info(), TokenInfo)
-            .await
-            .unwrap();
+        // The page after a removed account continues from its position.
+        let (removed, _) = all[1];
+        StableBalances.remove(&removed.into());
+        assert_eq!(canister.get_holders(Some(removed), 10), all[2..].to_vec());
+        assert_eq!(canister.holders_count(), 4);
 
-        assert_eq!(info.metadata.owner, alice());
-        let owner = canister_call!(canister.owner(), Principal).await.unwrap();
-        assert_eq!(owner, alice());
+        // Accounts emptied to zero are not listed.
+        let (emptied, _) = all[2];
+        StableBalances.insert(emptied.into(), Tokens128::ZERO);
+        assert_eq!(canister.holders_count(), 3);
+        assert!(!canister
+            .get_holders(None, 10)
+            .iter()
+            .any(|(account, _)| *account == emptied));
+    }
 
-        let minting_account = canister_call!(canister.icrc1_minting_account(), Principal)
-            .await
+    #[test]
+    fn top_holders() {
+        let canister = test_canister();
+        canister.mint(bob(), None, 300.into(), None).unwrap();
+        canister.mint(john(), None, 2000.into(), None).unwrap();
+        canister.mint(xtc(), None, 300.into(), None).unwrap();
+        canister
+            .mint(bob(), Some([1; 32]), 50.into(), None)
             .unwrap();
-        assert_eq!(minting_account, Some(alice().into()));
+        StableBalances.insert(auction_account(), 5000.into());
+
+        let (first_tied, second_tied) = if bob().as_slice() < xtc().as_slice() {
+            (bob(), xtc())
+        } else {
+            (xtc(), bob())
+        };
+        assert_eq!(
+            canister.top_holders(3),
+            vec![
+                (Account::new(john(), None), 2000.into()),
+                (Account::new(alice(), None), 1000.into()),
+                (Account::new(first_tied, None), 300.into()),
+            ]
+        );
+        assert_eq!(
+            canister.top_holders(4)[3],
+            (Account::new(second_tied, None), 300.into())
+        );
+        assert_eq!(canister.top_holders(10).len(), 5);
+        assert!(canister.top_holders(0).is_empty());
     }
 
-    #[tokio::test]
-    #[cfg_attr(coverage_nightly, no_coverage)]
-    async fn list_subaccounts() {
+    #[test]
+    fn balances_of() {
         let canister = test_canister();
-        let subaccount: Subaccount = [1; 32];
         canister
-            .transfer(TransferArgs {
-                from_subaccount: None,
-                to: Account::new(alice(), Some(subaccount)),
-                amount: 100.into(),
-                fee: None,
-                memo: None,
-                created_at_time: None,
-            })
+            .mint(bob(), Some([1; 32]), 200.into(), None)
             .unwrap();
 
-        get_context().update_id(alice());
-        let list = canister_call!(canister.list_subaccounts(), std::collections::HashMap<Subaccount, Tokens128>).await.unwrap();
-        assert_eq!(list.len(), 2);
-        assert_eq!(list[&DEFAULT_SUBACCOUNT], 900.into());
-        assert_eq!(list[&subaccount], 100.into());
+        assert_eq!(
+            canister.balances_of(vec![
+                Account::new(bob(), None),
+                Account::new(alice(), None),
+                Account::new(bob(), Some([1; 32])),
+                Account::new(john(), None),
+                Account::new(alice(), Some(DEFAULT_SUBACCOUNT)),
+            ]),
+            vec![0.into(), 1000.into(), 200.into(), 0.into(), 1000.into()]
+        );
+
+        let accounts = vec![Account::new(alice(), None); MAX_BALANCE_QUERY_LEN + 1];
+        assert_eq!(canister.balances_of(accounts).len(), MAX_BALANCE_QUERY_LEN);
+    }
+
+    #[test]
+    fn accounts_in_range() {
+        let (ctx, canister) = test_context();
+        ctx.update_caller(john());
+        canister.mint(bob(), None, 50.into(), None).unwrap();
+        canister.mint(xtc(), None, 5000.into(), None).unwrap();
+        StableBalances.insert(auction_account(), 500.into());
+
+        let mut accounts = canister.accounts_in_range(100.into(), 2000.into(), 0, 10);
+        accounts.sort_by_key(|(acc, _)| acc.owner);
+        let mut expected = vec![
+            (Account::from(alice()), Tokens128::from(1000)),
+            (Account::from(john()), Tokens128::from(1000)),
+        ];
+        expected.sort_by_key(|(acc, _)| acc.owner);
+        assert_eq!(accounts, expected);
+
+        assert_eq!(
+            canister.accounts_in_range(50.into(), 50.into(), 0, 10),
+            vec![(Account::from(bob()), Tokens128::from(50))]
+        );
+        assert_eq!(
+            canister
+                .accounts_in_range(0.into(), u128::MAX.into(), 0, 10)
+                .len(),
+            4
+        );
+        assert_eq!(
+            canister
+                .accounts_in_range(100.into(), 2000.into(), 1, 10)
+                .len(),
+            1
+        );
+        assert_eq!(
+            canister
+                .accounts_in_range(100.into(), 2000.into(), 0, 1)
+                .len(),
+            1
+        );
+        assert!(canister
+            .accounts_in_range(2000.into(), 100.into(), 0, 10)
+            .is_empty());
     }
 }
@@ -33,13 +33,23 @@ pub fn icrc1_transfer(
     if caller.inner() == minter {
         // Minting transfers must have zero fees.
         check_zero_fee()?;
-        return mint(caller.inner().owner, transfer.to.into(), amount);
+        return mint(
+            caller.inner().owner,
+            transfer.to.into(),
+            amount,
+            transfer.memo,
+        );
     }
 
     if caller.recipient() == minter {
         // Burning transfers must have zero fees.
         check_zero_fee()?;
-        return burn(caller.recipient().owner, caller.inner(), amount);
+        return burn(
+            caller.recipient().owner,
+            caller.inner(),
+            amount,
+            transfer.memo,
+        );
     }
 
     is20_transfer(caller, transfer, auction_fee_ratio)
@@ -63,8 +73,8 @@ mod tests {
     use crate::error::{TransferError, TxError};
     use crate::mock::*;
     use crate::state::balances::{Balances, StableBalances};
-    use crate::state::config::{Metadata, DEFAULT_MIN_CYCLES};
-    use crate::state::ledger::{LedgerData, Operation, TransactionStatus};
+    use crate::state::config::{FeeMode, FeeRatio, Metadata, DEFAULT_MIN_CYCLES};
+    use crate::state::ledger::{BatchTransferArgs, LedgerData, Operation, TransactionStatus};
 
     use super::*;
 
@@ -100,6 +110,7 @@ mod tests {
                 fee: Tokens128::from(0),
                 fee_to: john(),
                 is_test_token: None,
+                max_supply: None,
             },
             Tokens128::from(1000),
         );
@@ -112,7 +123,7 @@ mod tests {
         stats.min_cycles = 0;
         TokenConfig::set_stable(stats);
 
-        canister.mint(alice(), None, 1000.into()).unwrap();
+        canister.mint(alice(), None, 1000.into(), None).unwrap();
         context.update_caller(alice());
 
         (context, canister)
@@ -136,8 +147,12 @@ mod tests {
             to,
             amount: Tokens128::from(100),
             fee: Some(1.into()),
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         assert!(
@@ -158,8 +173,12 @@ mod tests {
             to,
             amount: Tokens128::from(100),
             fee: Some(1.into()),
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         assert!(
@@ -184,8 +203,12 @@ mod tests {
             to: Account::from(bob()),
             amount: Tokens128::from(100),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         assert!(canister.icrc1_transfer(transfer1).is_ok());
@@ -200,7 +223,7 @@ mod tests {
 
         ctx.update_caller(john());
         assert!(canister
-            .mint(alice(), Some(alice_sub), Tokens128::from(100))
+            .mint(alice(), Some(alice_sub), Tokens128::from(100), None)
             .is_ok());
 
         ctx.update_caller(alice());
@@ -209,8 +232,12 @@ mod tests {
             to: Account::new(bob(), Some(bob_sub)),
             amount: Tokens128::from(50),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         assert!(canister.icrc1_transfer(transfer2).is_ok());
         assert_eq!(
@@ -240,8 +267,12 @@ mod tests {
             to: Account::from(bob()),
             amount: Tokens128::from(200),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         assert!(canister.icrc1_transfer(transfer1).is_ok());
@@ -260,7 +291,7 @@ mod tests {
 
         ctx.update_caller(john());
         assert!(canister
-            .mint(alice(), Some(alice_sub), Tokens128::from(1000))
+            .mint(alice(), Some(alice_sub), Tokens128::from(1000), None)
             .is_ok());
 
         ctx.update_caller(alice());
@@ -270,8 +301,12 @@ mod tests {
 
             amount: Tokens128::from(500),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         assert!(canister.icrc1_transfer(transfer2).is_ok());
 
@@ -285,6 +320,73 @@ mod tests {
         );
     }
 
+    #[test]
+    fn transfer_fee_to_subaccount() {
+        let (ctx, canister) = test_context();
+        let fee_sub = gen_subaccount();
+
+        let mut stats = TokenConfig::get_stable();
+        stats.fee = Tokens128::from(100);
+        TokenConfig::set_stable(stats);
+
+        ctx.update_caller(bob());
+        assert_eq!(
+            canister.set_fee_to(Account::new(bob(), Some(fee_sub))),
+            Err(TxError::Unauthorized)
+        );
+
+        ctx.update_caller(john());
+        canister
+            .set_fee_to(Account::new(john(), Some(fee_sub)))
+            .unwrap();
+        assert_eq!(
+            TokenConfig::get_stable().fee_info(),
+            (Tokens128::from(100), Account::new(john(), Some(fee_sub)))
+        );
+
+        ctx.update_caller(alice());
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: Account::from(bob()),
+            amount: Tokens128::from(200),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+        canister.icrc1_transfer(transfer).unwrap();
+        canister
+            .batch_transfer(
+                None,
+                vec![BatchTransferArgs {
+                    receiver: Account::from(bob()),
+                    amount: Tokens128::from(100),
+                    fee_override: None,
+                }],
+            )
+            .unwrap();
+
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            Tokens128::from(300)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            Tokens128::from(500)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(john(), Some(fee_sub))),
+            Tokens128::from(200)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(john(), None)),
+            Tokens128::from(1000)
+        );
+    }
+
     #[test]
     fn transfer_fee_exceeded() {
         let canister = test_canister();
@@ -299,8 +401,12 @@ mod tests {
             to: Account::from(bob()),
             amount: Tokens128::from(200),
             fee: Some(Tokens128::from(100)),
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         assert!(canister.icrc1_transfer(transfer1).is_ok());
@@ -310,8 +416,12 @@ mod tests {
             to: Account::from(bob()),
             amount: Tokens128::from(200),
             fee: Some(Tokens128::from(50)),
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         assert_eq!(
             canister.icrc1_transfer(transfer2),
@@ -325,8 +435,12 @@ mod tests {
             to: Account::new(bob(), Some(gen_subaccount())),
             amount: Tokens128::from(200),
             fee: Some(Tokens128::from(50)),
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         assert_eq!(
             canister.icrc1_transfer(transfer3),
@@ -357,8 +471,12 @@ mod tests {
             to: Account::from(bob()),
             amount: Tokens128::from(100),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         canister.icrc1_transfer(transfer1).unwrap();
@@ -380,6 +498,133 @@ mod tests {
         );
     }
 
+    #[test]
+    fn owner_set_auction_fee_ratio() {
+        let canister = test_canister();
+
+        let mut stats = TokenConfig::get_stable();
+        stats.fee = Tokens128::from(100);
+        stats.fee_to = john();
+        stats.min_cycles = DEFAULT_MIN_CYCLES;
+        TokenConfig::set_stable(stats);
+
+        canister
+            .auction_state()
+            .borrow_mut()
+            .bidding_state
+            .fee_ratio = 0.5;
+
+        assert_eq!(
+            canister.set_auction_fee_ratio(Some(FeeRatio::new(0.2))),
+            Err(TxError::Unauthorized)
+        );
+
+        get_context().update_caller(john());
+        canister
+            .set_auction_fee_ratio(Some(FeeRatio::new(0.2)))
+            .unwrap();
+        assert_eq!(canister.auction_fee_ratio(), Some(FeeRatio::new(0.2)));
+
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: Account::from(bob()),
+            amount: Tokens128::from(100),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        get_context().update_caller(alice());
+        canister.icrc1_transfer(transfer.clone()).unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(john(), None)),
+            Tokens128::from(1080)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(auction_account().into()),
+            Tokens128::from(20)
+        );
+
+        // Without the override the ratio computed by the auction is used again.
+        get_context().update_caller(john());
+        canister.set_auction_fee_ratio(None).unwrap();
+
+        get_context().update_caller(alice());
+        canister.icrc1_transfer(transfer).unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(john(), None)),
+            Tokens128::from(1130)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(auction_account().into()),
+            Tokens128::from(70)
+        );
+    }
+
+    #[test]
+    fn basis_points_fees_with_auction_enabled() {
+        let canister = test_canister();
+        assert_eq!(
+            canister.set_fee_mode(FeeMode::BasisPoints(30)),
+            Err(TxError::Unauthorized)
+        );
+
+        let mut stats = TokenConfig::get_stable();
+        stats.fee_mode = Some(FeeMode::BasisPoints(30));
+        stats.min_cycles = DEFAULT_MIN_CYCLES;
+        TokenConfig::set_stable(stats);
+
+        canister
+            .auction_state()
+            .borrow_mut()
+            .bidding_state
+            .fee_ratio = 0.5;
+
+        let transfer = |amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: Account::from(bob()),
+            amount: Tokens128::from(amount),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        // 0.3% of 667 is 2.001, so the fee is 2, split between the owner and the auction.
+        canister.icrc1_transfer(transfer(667)).unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            Tokens128::from(331)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(john(), None)),
+            Tokens128::from(1001)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(auction_account().into()),
+            Tokens128::from(1)
+        );
+
+        // 0.3% of 330 is 0.99, which is rounded down to zero.
+        let id = canister.icrc1_transfer(transfer(330)).unwrap();
+        assert_eq!(LedgerData::get(id as u64).unwrap().fee, Tokens128::ZERO);
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            Tokens128::from(1)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            Tokens128::from(997)
+        );
+    }
+
     #[test]
     fn transfer_insufficient_balance() {
         let canister = test_canister();
@@ -389,8 +634,12 @@ mod tests {
             to: Account::from(bob()),
             amount: Tokens128::from(1001),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         let balance = canister.icrc1_balance_of(Account::new(alice(), None));
         assert_eq!(
@@ -421,8 +670,12 @@ mod tests {
             to: Account::from(bob()),
             amount: Tokens128::from(950),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         let balance = canister.icrc1_balance_of(Account::new(alice(), None));
@@ -451,8 +704,12 @@ mod tests {
             to: Account::from(bob()),
             amount: Tokens128::from(100),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         assert!(matches!(
             canister.icrc1_transfer(transfer1),
@@ -473,8 +730,9 @@ mod tests {
         );
     }
 
-    #[test]
-    fn transfer_saved_into_history() {
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn transfer_saved_into_history() {
         let (ctx, canister) = test_context();
 
         let mut stats = TokenConfig::get_stable();
@@ -488,8 +746,12 @@ mod tests {
             to: Account::from(bob()),
             amount: Tokens128::from(1001),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         canister.icrc1_transfer(transfer1).unwrap_err();
@@ -503,13 +765,17 @@ mod tests {
                 to: Account::from(bob()),
                 amount: Tokens128::from(100 + i as u128),
                 fee: None,
+                max_fee: None,
                 memo: None,
                 created_at_time: None,
+                valid_until: None,
+                tags: None,
+                tip: None,
             };
             ctx.add_time(10);
             let id = canister.icrc1_transfer(transfer1).unwrap();
             assert_eq!(canister.history_size() - before_history_size, 1 + i);
-            let tx = canister.get_transaction(id as u64);
+            let tx = canister.get_transaction(id as u64).await.unwrap();
             assert_eq!(tx.amount, Tokens128::from(100 + i as u128));
             assert_eq!(tx.fee, Tokens128::from(10));
             assert_eq!(tx.operation, Operation::Transfer);
@@ -529,7 +795,7 @@ mod tests {
         let canister = test_canister();
         get_context().update_caller(bob());
         assert_eq!(
-            canister.mint(alice(), None, Tokens128::from(100)),
+            canister.mint(alice(), None, Tokens128::from(100), None),
             Err(TxError::Unauthorized)
         );
 
@@ -537,8 +803,12 @@ mod tests {
         stats.is_test_token = true;
         TokenConfig::set_stable(stats);
 
-        assert!(canister.mint(alice(), None, Tokens128::from(2000)).is_ok());
-        assert!(canister.mint(bob(), None, Tokens128::from(5000)).is_ok());
+        assert!(canister
+            .mint(alice(), None, Tokens128::from(2000), None)
+            .is_ok());
+        assert!(canister
+            .mint(bob(), None, Tokens128::from(5000), None)
+            .is_ok());
 
         assert_eq!(
             canister.icrc1_balance_of(Account::new(alice(), None)),
@@ -549,7 +819,7 @@ mod tests {
             Tokens128::from(5000)
         );
         assert!(canister
-            .mint(alice(), Some(alice_sub), Tokens128::from(1000))
+            .mint(alice(), Some(alice_sub), Tokens128::from(1000), None)
             .is_ok());
         assert_eq!(
             canister.icrc1_balance_of(Account::new(alice(), Some(alice_sub))),
@@ -563,8 +833,12 @@ mod tests {
         let alice_sub = gen_subaccount();
         let bob_sub = gen_subaccount();
         ctx.update_caller(john());
-        assert!(canister.mint(alice(), None, Tokens128::from(2000)).is_ok());
-        assert!(canister.mint(bob(), None, Tokens128::from(5000)).is_ok());
+        assert!(canister
+            .mint(alice(), None, Tokens128::from(2000), None)
+            .is_ok());
+        assert!(canister
+            .mint(bob(), None, Tokens128::from(5000), None)
+            .is_ok());
         assert_eq!(
             canister.icrc1_balance_of(Account::new(alice(), None)),
             Tokens128::from(3000)
@@ -577,10 +851,10 @@ mod tests {
 
         //     mint to subaccounts
         assert!(canister
-            .mint(alice(), Some(alice_sub), Tokens128::from(2000))
+            .mint(alice(), Some(alice_sub), Tokens128::from(2000), None)
             .is_ok());
         assert!(canister
-            .mint(bob(), Some(bob_sub), Tokens128::from(5000))
+            .mint(bob(), Some(bob_sub), Tokens128::from(5000), None)
             .is_ok());
 
         assert_eq!(
@@ -594,8 +868,9 @@ mod tests {
         assert_eq!(canister.icrc1_total_supply(), Tokens128::from(16000));
     }
 
-    #[test]
-    fn mint_saved_into_history() {
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn mint_saved_into_history() {
         let (ctx, canister) = test_context();
 
         let mut stats = TokenConfig::get_stable();
@@ -611,10 +886,10 @@ mod tests {
         for i in 0..COUNT {
             ctx.add_time(10);
             let id = canister
-                .mint(bob(), None, Tokens128::from(100 + i as u128))
+                .mint(bob(), None, Tokens128::from(100 + i as u128), None)
                 .unwrap();
             assert_eq!(canister.history_size(), 3 + i);
-            let tx = canister.get_transaction(id as u64);
+            let tx = canister.get_transaction(id as u64).await.unwrap();
             assert_eq!(tx.amount, Tokens128::from(100 + i as u128));
             assert_eq!(tx.fee, Tokens128::from(0));
             assert_eq!(tx.operation, Operation::Mint);
@@ -631,7 +906,9 @@ mod tests {
     #[test]
     fn burn_by_owner() {
         let canister = test_canister();
-        assert!(canister.burn(None, None, Tokens128::from(100)).is_ok());
+        assert!(canister
+            .burn(None, None, Tokens128::from(100), None)
+            .is_ok());
         assert_eq!(
             canister.icrc1_balance_of(Account::new(alice(), None)),
             Tokens128::from(900)
@@ -644,7 +921,7 @@ mod tests {
         let canister = test_canister();
         let balance = canister.icrc1_balance_of(Account::new(alice(), None));
         assert_eq!(
-            canister.burn(None, None, Tokens128::from(1001)),
+            canister.burn(None, None, Tokens128::from(1001), None),
             Err(TxError::InsufficientFunds { balance })
         );
         assert_eq!(
@@ -661,7 +938,7 @@ mod tests {
         get_context().update_caller(bob());
         let balance = canister.icrc1_balance_of(Account::new(bob(), None));
         assert_eq!(
-            canister.burn(None, None, Tokens128::from(100)),
+            canister.burn(None, None, Tokens128::from(100), None),
             Err(TxError::InsufficientFunds { balance })
         );
         assert_eq!(
@@ -677,13 +954,13 @@ mod tests {
         let (ctx, canister) = test_context();
         let bob_balance = Tokens128::from(1000);
         ctx.update_caller(john());
-        canister.mint(bob(), None, bob_balance).unwrap();
+        canister.mint(bob(), None, bob_balance, None).unwrap();
         assert_eq!(
             canister.icrc1_balance_of(Account::new(bob(), None)),
             bob_balance
         );
         canister
-            .burn(Some(bob()), None, Tokens128::from(100))
+            .burn(Some(bob()), None, Tokens128::from(100), None)
             .unwrap();
         assert_eq!(
             canister.icrc1_balance_of(Account::new(bob(), None)),
@@ -691,13 +968,15 @@ mod tests {
         );
         assert_eq!(canister.icrc1_total_supply(), Tokens128::from(2900));
         //     Burn from subaccount
-        canister.mint(bob(), Some(bob_sub), bob_balance).unwrap();
+        canister
+            .mint(bob(), Some(bob_sub), bob_balance, None)
+            .unwrap();
         assert_eq!(
             canister.icrc1_balance_of(Account::new(bob(), Some(bob_sub))),
             bob_balance
         );
         canister
-            .burn(Some(bob()), Some(bob_sub), Tokens128::from(100))
+            .burn(Some(bob()), Some(bob_sub), Tokens128::from(100), None)
             .unwrap();
         assert_eq!(
             canister.icrc1_balance_of(Account::new(bob(), Some(bob_sub))),
@@ -711,7 +990,7 @@ mod tests {
 
         get_context().update_caller(bob());
         assert_eq!(
-            canister.burn(Some(alice()), None, Tokens128::from(100)),
+            canister.burn(Some(alice()), None, Tokens128::from(100), None),
             Err(TxError::Unauthorized)
         );
 
@@ -722,8 +1001,9 @@ mod tests {
         assert_eq!(canister.icrc1_total_supply(), Tokens128::from(2000));
     }
 
-    #[test]
-    fn burn_saved_into_history() {
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn burn_saved_into_history() {
         let (ctx, canister) = test_context();
 
         let mut stats = TokenConfig::get_stable();
@@ -740,10 +1020,10 @@ mod tests {
         for i in 0..COUNT {
             ctx.add_time(10);
             let id = canister
-                .burn(None, None, Tokens128::from(100 + i as u128))
+                .burn(None, None, Tokens128::from(100 + i as u128), None)
                 .unwrap();
             assert_eq!(canister.history_size(), history_size_before + 1 + i);
-            let tx = canister.get_transaction(id as u64);
+            let tx = canister.get_transaction(id as u64).await.unwrap();
             assert_eq!(tx.amount, Tokens128::from(100 + i as u128));
             assert_eq!(tx.fee, Tokens128::from(0));
             assert_eq!(tx.operation, Operation::Burn);
@@ -756,16 +1036,21 @@ mod tests {
         }
     }
 
-    #[test]
-    fn get_transactions_test() {
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn get_transactions_test() {
         let canister = test_canister();
         let transfer1 = TransferArgs {
             from_subaccount: None,
             to: Account::from(bob()),
             amount: Tokens128::from(10),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         for _ in 1..=5 {
@@ -777,8 +1062,12 @@ mod tests {
 
             amount: Tokens128::from(10),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         canister.icrc1_transfer(transfer2).unwrap();
         let transfer3 = TransferArgs {
@@ -786,8 +1075,12 @@ mod tests {
             to: Account::from(xtc()),
             amount: Tokens128::from(10),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         canister.icrc1_transfer(transfer3).unwrap();
         let transfer4 = TransferArgs {
@@ -795,38 +1088,64 @@ mod tests {
             to: Account::from(john()),
             amount: Tokens128::from(10),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         canister.icrc1_transfer(transfer4).unwrap();
 
-        assert_eq!(canister.get_transactions(None, 11, None).result.len(), 10);
-        assert_eq!(canister.get_transactions(None, 10, Some(3)).result.len(), 4);
+        assert_eq!(
+            canister.get_transactions(None, 11, None).await.result.len(),
+            10
+        );
+        assert_eq!(
+            canister
+                .get_transactions(None, 10, Some(3))
+                .await
+                .result
+                .len(),
+            4
+        );
         assert_eq!(
             canister
                 .get_transactions(Some(bob()), 10, None)
+                .await
                 .result
                 .len(),
             6
         );
         assert_eq!(
-            canister.get_transactions(Some(xtc()), 5, None).result.len(),
+            canister
+                .get_transactions(Some(xtc()), 5, None)
+                .await
+                .result
+                .len(),
             1
         );
         assert_eq!(
             canister
                 .get_transactions(Some(alice()), 10, Some(5))
+                .await
                 .result
                 .len(),
             5
         );
-        assert_eq!(canister.get_transactions(None, 5, None).next, Some(4));
+        assert_eq!(canister.get_transactions(None, 5, None).await.next, Some(4));
         assert_eq!(
-            canister.get_transactions(Some(alice()), 3, Some(5)).next,
+            canister
+                .get_transactions(Some(alice()), 3, Some(5))
+                .await
+                .next,
             Some(2)
         );
         assert_eq!(
-            canister.get_transactions(Some(bob()), 3, Some(2)).next,
+            canister
+                .get_transactions(Some(bob()), 3, Some(2))
+                .await
+                .next,
             None
         );
 
@@ -835,34 +1154,321 @@ mod tests {
             to: Account::from(bob()),
             amount: Tokens128::from(10),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         for _ in 1..=10 {
             canister.icrc1_transfer(transfer5.clone()).unwrap();
         }
 
-        let txn = canister.get_transactions(None, 5, None);
+        let txn = canister.get_transactions(None, 5, None).await;
         assert_eq!(txn.result[0].index, 19);
         assert_eq!(txn.result[1].index, 18);
         assert_eq!(txn.result[2].index, 17);
         assert_eq!(txn.result[3].index, 16);
         assert_eq!(txn.result[4].index, 15);
-        let txn2 = canister.get_transactions(None, 5, txn.next);
+        let txn2 = canister.get_transactions(None, 5, txn.next).await;
         assert_eq!(txn2.result[0].index, 14);
         assert_eq!(txn2.result[1].index, 13);
         assert_eq!(txn2.result[2].index, 12);
         assert_eq!(txn2.result[3].index, 11);
         assert_eq!(txn2.result[4].index, 10);
-        assert_eq!(canister.get_transactions(None, 5, txn.next).next, Some(9));
+        assert_eq!(
+            canister.get_transactions(None, 5, txn.next).await.next,
+            Some(9)
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn minting_transfer_memo() {
+        let (ctx, canister) = test_context();
+        ctx.update_caller(john());
+
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: Account::from(bob()),
+            amount: Tokens128::from(100),
+            fee: None,
+            max_fee: None,
+            memo: Some([3; 32]),
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        let id = canister.icrc1_transfer(transfer).unwrap() as u64;
+        let tx = canister.get_transaction(id).await.unwrap();
+        assert_eq!(tx.operation, Operation::Mint);
+        assert_eq!(tx.memo, Some([3; 32]));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn get_transaction_not_existing() {
+        let canister = test_canister();
+        assert_eq!(
+            canister.get_transaction(2).await,
+            Err(TxError::TransactionDoesNotExist)
+        );
+        assert_eq!(
+            canister.get_transaction(u64::MAX).await,
+            Err(TxError::TransactionDoesNotExist)
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn get_transaction_by_id() {
+        let canister = test_canister();
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: Account::from(bob()),
+            amount: Tokens128::from(100),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        let id = canister.icrc1_transfer(transfer).unwrap() as u64;
+        let tx = canister.get_transaction(id).await.unwrap();
+        assert_eq!(tx.index, id);
+        assert_eq!(tx.from, Account::from(alice()));
+        assert_eq!(tx.to, Account::from(bob()));
+        assert_eq!(tx.amount, Tokens128::from(100));
     }
 
     #[test]
-    #[should_panic]
-    fn get_transaction_not_existing() {
+    fn transaction_fee_split() {
         let canister = test_canister();
-        canister.get_transaction(2);
+
+        let mut stats = TokenConfig::get_stable();
+        stats.fee = Tokens128::from(100);
+        stats.fee_to = john();
+        stats.min_cycles = DEFAULT_MIN_CYCLES;
+        TokenConfig::set_stable(stats);
+
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: Account::from(bob()),
+            amount: Tokens128::from(10),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        let mut ids = vec![];
+        for ratio in [0.5, 0.2, 0.0] {
+            canister
+                .auction_state()
+                .borrow_mut()
+                .bidding_state
+                .fee_ratio = ratio;
+            ids.push(canister.icrc1_transfer(transfer.clone()).unwrap() as u64);
+        }
+
+        assert_eq!(
+            canister.transaction_fee_split(ids[0]),
+            Ok((Tokens128::from(50), Tokens128::from(50)))
+        );
+        assert_eq!(
+            canister.transaction_fee_split(ids[1]),
+            Ok((Tokens128::from(80), Tokens128::from(20)))
+        );
+        assert_eq!(
+            canister.transaction_fee_split(ids[2]),
+            Ok((Tokens128::from(100), Tokens128::ZERO))
+        );
+
+        // The mint of the test canister does not charge a fee.
+        assert_eq!(
+            canister.transaction_fee_split(0),
+            Ok((Tokens128::ZERO, Tokens128::ZERO))
+        );
+        assert_eq!(
+            canister.transaction_fee_split(100),
+            Err(TxError::TransactionDoesNotExist)
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn transfer_royalty() {
+        let (ctx, canister) = test_context();
+        ctx.update_caller(john());
+        canister
+            .set_royalty(Some((Account::from(xtc()), 500)))
+            .unwrap();
+        canister.set_fee(10.into()).unwrap();
+        ctx.update_caller(alice());
+
+        let transfer = |to: Principal, amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: Account::from(to),
+            amount: Tokens128::from(amount),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+        let total_supply = canister.icrc1_total_supply();
+        let fees = || {
+            (canister.icrc1_balance_of(Account::from(john()))
+                + canister.icrc1_balance_of(auction_account().into()))
+            .unwrap()
+        };
+        let fees_before = fees();
+
+        // 5% of 200 is 10.
+        let id = canister.icrc1_transfer(transfer(bob(), 200)).unwrap() as u64;
+        assert_eq!(
+            canister.icrc1_balance_of(Account::from(alice())),
+            Tokens128::from(790)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::from(bob())),
+            Tokens128::from(190)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::from(xtc())),
+            Tokens128::from(10)
+        );
+        assert_eq!(fees(), (fees_before + Tokens128::from(10)).unwrap());
+        assert_eq!(canister.icrc1_total_supply(), total_supply);
+
+        let tx = canister.get_transaction(id).await.unwrap();
+        assert_eq!(tx.amount, Tokens128::from(200));
+        assert_eq!(tx.royalty, Some(Tokens128::from(10)));
+        assert_eq!(
+            tx.balance_change(bob().into()),
+            (Tokens128::from(190), Tokens128::ZERO)
+        );
+
+        // The royalty of small amounts is rounded down to zero.
+        canister.icrc1_transfer(transfer(bob(), 19)).unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(Account::from(bob())),
+            Tokens128::from(209)
+        );
+
+        // Transfers to and from the royalty account are exempt.
+        canister.icrc1_transfer(transfer(xtc(), 100)).unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(Account::from(xtc())),
+            Tokens128::from(110)
+        );
+        ctx.update_caller(xtc());
+        canister.icrc1_transfer(transfer(bob(), 100)).unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(Account::from(bob())),
+            Tokens128::from(309)
+        );
+        assert_eq!(canister.icrc1_total_supply(), total_supply);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn transfer_burn() {
+        let (ctx, canister) = test_context();
+        ctx.update_caller(john());
+        canister.set_burn_rate(Some(1000)).unwrap();
+        assert_eq!(canister.burn_rate(), Some(1000));
+        ctx.update_caller(alice());
+
+        let transfer = |amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: Account::from(bob()),
+            amount: Tokens128::from(amount),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+        let total_supply = canister.icrc1_total_supply();
+
+        // 10% of 200 is 20.
+        let id = canister.icrc1_transfer(transfer(200)).unwrap() as u64;
+        assert_eq!(
+            canister.icrc1_balance_of(Account::from(alice())),
+            Tokens128::from(800)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::from(bob())),
+            Tokens128::from(180)
+        );
+        assert_eq!(
+            canister.icrc1_total_supply(),
+            (total_supply - Tokens128::from(20)).unwrap()
+        );
+
+        let burn = canister.get_transaction(id + 1).await.unwrap();
+        assert_eq!(burn.operation, Operation::Burn);
+        assert_eq!(burn.from, Account::from(bob()));
+        assert_eq!(burn.amount, Tokens128::from(20));
+
+        canister
+            .batch_transfer(
+                None,
+                vec![
+                    BatchTransferArgs {
+                        receiver: Account::from(bob()),
+                        amount: Tokens128::from(100),
+                        fee_override: None,
+                    },
+                    BatchTransferArgs {
+                        receiver: Account::from(xtc()),
+                        amount: Tokens128::from(50),
+                        fee_override: None,
+                    },
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(Account::from(bob())),
+            Tokens128::from(270)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::from(xtc())),
+            Tokens128::from(45)
+        );
+        assert_eq!(
+            canister.icrc1_total_supply(),
+            (total_supply - Tokens128::from(35)).unwrap()
+        );
+
+        // The burn is capped by the amount.
+        ctx.update_caller(john());
+        canister.set_burn_rate(Some(20_000)).unwrap();
+        ctx.update_caller(alice());
+        canister.icrc1_transfer(transfer(100)).unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(Account::from(bob())),
+            Tokens128::from(270)
+        );
+        assert_eq!(
+            canister.icrc1_total_supply(),
+            (total_supply - Tokens128::from(135)).unwrap()
+        );
     }
 
     #[test]
@@ -875,8 +1481,12 @@ mod tests {
 
             amount: Tokens128::from(10),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         for _ in 1..COUNT {
             canister.icrc1_transfer(transfer1.clone()).unwrap();
@@ -899,8 +1509,12 @@ mod tests {
             to: Account::from(bob()),
             amount: Tokens128::from(10),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: Some(system_time as u64 + 30_000_000_000),
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         assert!(canister.icrc1_transfer(transfer).is_ok());
     }
@@ -919,8 +1533,12 @@ mod tests {
             to: Account::from(bob()),
             amount: Tokens128::from(10),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: Some(system_time as u64 - TX_WINDOW * 2),
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         assert!(canister.icrc1_transfer(transfer).is_err());
 
@@ -929,8 +1547,12 @@ mod tests {
             to: Account::from(bob()),
             amount: Tokens128::from(10),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: Some(system_time as u64 + TX_WINDOW * 2),
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         assert!(canister.icrc1_transfer(transfer).is_err());
     }
@@ -947,8 +1569,12 @@ mod tests {
             to: Account::from(alice()),
             amount: Tokens128::from(100),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         assert!(canister.icrc1_transfer(transfer).is_err());
 
@@ -964,8 +1590,12 @@ mod tests {
             to: Account::new(alice(), Some(alice_sub)),
             amount: Tokens128::from(100),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         assert!(canister.icrc1_transfer(transfer.clone()).is_err());
@@ -995,8 +1625,12 @@ mod tests {
 
             amount: Tokens128::from(100),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         assert!(canister.icrc1_transfer(transfer).is_ok());
 
@@ -1016,8 +1650,12 @@ mod tests {
             to: Account::new(alice(), Some(alice_sub2)),
             amount: Tokens128::from(10),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         assert!(canister.icrc1_transfer(transfer).is_ok());
         assert_eq!(
@@ -1157,6 +1795,7 @@ mod proptests {
                 fee,
                 fee_to,
                 is_test_token: None,
+                max_supply: None,
             };
 
             let principal = Principal::from_text("mfufu-x6j4c-gomzb-geilq").unwrap();
@@ -1201,7 +1840,7 @@ mod proptests {
                     Mint { minter, recipient, amount } => {
                         get_context().update_caller(minter);
                         let original = canister.icrc1_total_supply();
-                        let res = canister.mint(recipient, None,amount);
+                        let res = canister.mint(recipient, None,amount, None);
                         let expected = if minter == canister.owner() {
                             total_minted = (total_minted + amount).unwrap();
                             assert!(matches!(res, Ok(_)));
@@ -1216,7 +1855,7 @@ mod proptests {
                         get_context().update_caller(burner);
                         let original = canister.icrc1_total_supply();
                         let balance = canister.icrc1_balance_of(Account::new(burner, None));
-                        let res = canister.burn(Some(burner), None, amount);
+                        let res = canister.burn(Some(burner), None, amount, None);
                         if balance < amount {
                             prop_assert_eq!(res, Err(TxError::InsufficientFunds { balance }));
                             prop_assert_eq!(original, canister.icrc1_total_supply());
@@ -1244,8 +1883,12 @@ mod proptests {
                             to:Account::new(to, None),
                             amount,
                             fee: fee_limit,
+                            max_fee: None,
                             memo: None,
                             created_at_time: None,
+                            valid_until: None,
+                            tags: None,
+                            tip: None,
                         };
                         let res = canister.icrc1_transfer(transfer1);
 
@@ -1270,13 +1913,13 @@ mod proptests {
                             return Ok(());
                         }
 
-                        if fee_to == from {
+                        if fee_to == Account::new(from, None) {
                             prop_assert!(matches!(res, Ok(_)));
                             prop_assert_eq!((from_balance - amount).unwrap(), canister.icrc1_balance_of(Account::new(from, None)));
                             return Ok(());
                         }
 
-                        if fee_to == to {
+                        if fee_to == Account::new(to, None) {
                             prop_assert!(matches!(res, Ok(_)));
                             prop_assert_eq!(((to_balance + amount).unwrap() + fee).unwrap(), canister.icrc1_balance_of(Account::new(to, None)));
                             return Ok(());
@@ -3,20 +3,75 @@ use candid::{Nat, Principal};
 use crate::state::{
     balances::{Balances, StableBalances},
     config::TokenConfig,
+    permissions::Minters,
 };
 
 static OWNER_METHODS: &[&str] = &[
+    "add_minter",
+    "archive_transactions",
+    "batch_burn",
+    "batch_mint",
+    "cancel_owner_action",
+    "cancel_ownership_transfer",
+    "create_vesting",
+    "execute_owner_action",
+    "freeze_account",
+    "import_balances_chunk",
+    "propose_new_owner",
+    "reclaim_dormant",
+    "reclaim_expired_claims",
+    "register_token_aware_canister",
+    "remove_minter",
+    "rotate_auction_account",
+    "set_analytics_enabled",
+    "set_archive_options",
+    "set_auction_beneficiary",
+    "set_auction_dust_sinking",
+    "set_auction_fee_ratio",
     "set_auction_period",
+    "set_auction_pool_trigger",
+    "set_block_canister_recipients",
+    "set_burn_rate",
+    "set_circulation_excluded_accounts",
+    "set_claim_expiry",
+    "set_dormancy_period",
+    "set_dynamic_fee",
+    "set_failed_tx_log_enabled",
     "set_fee",
+    "set_fee_mode",
+    "set_fee_oracle",
+    "set_fee_quote_ttl",
     "set_fee_to",
+    "set_governance_delay",
+    "set_maintenance_mode",
     "set_logo",
+    "set_max_concurrent_batches",
+    "set_max_query_page_size",
+    "set_min_auction_bid",
     "set_min_cycles",
     "set_name",
     "set_symbol",
     "set_owner",
+    "set_paused",
+    "set_permitted_drift",
+    "set_prepared_transfer_ttl",
+    "set_reject_zero_transfers",
+    "set_royalty",
+    "set_storage_high_water_mark",
+    "set_transferable",
+    "set_tx_window",
+    "unfreeze_account",
 ];
 
-static TRANSACTION_METHODS: &[&str] = &["burn", "icrc1_transfer"];
+static TRANSACTION_METHODS: &[&str] = &[
+    "burn",
+    "decrease_allowance",
+    "icrc1_transfer",
+    "icrc2_approve",
+    "icrc2_transfer_from",
+    "increase_allowance",
+    "redeem",
+];
 
 /// Reason why the method may be accepted.
 #[derive(Debug, Clone, Copy)]
@@ -34,6 +89,9 @@ pub enum AcceptReason {
 pub fn inspect_message(method: &str, caller: Principal) -> Result<AcceptReason, &'static str> {
     let stats = TokenConfig::get_stable();
     match method {
+        // While under maintenance, the only accepted update is turning the maintenance off.
+        "set_maintenance_mode" if caller == stats.owner => Ok(AcceptReason::Valid),
+        _ if stats.is_under_maintenance() => Err("The canister is under maintenance. Rejecting."),
         // These are query methods, so no checks are needed.
         #[cfg(feature = "mint_burn")]
         "mint" if stats.is_test_token => Ok(AcceptReason::Valid),
@@ -41,6 +99,12 @@ pub fn inspect_message(method: &str, caller: Principal) -> Result<AcceptReason,
         "mint" if caller == stats.owner => Ok(AcceptReason::Valid),
         #[cfg(feature = "mint_burn")]
         "mint" => Err("Only the owner can mint"),
+        #[cfg(feature = "mint_burn")]
+        "mint_as_minter" if Minters::contains(&caller) => Ok(AcceptReason::Valid),
+        #[cfg(feature = "mint_burn")]
+        "mint_as_minter" => Err("Only the minters can mint. Rejecting."),
+        "accept_ownership" if stats.pending_owner == Some(caller) => Ok(AcceptReason::Valid),
+        "accept_ownership" => Err("Only the proposed owner can accept the ownership. Rejecting."),
         // Owner
         m if OWNER_METHODS.contains(&m) && caller == stats.owner => Ok(AcceptReason::Valid),
         // Not owner
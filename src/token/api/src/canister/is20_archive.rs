@@ -0,0 +1,419 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_kit::ic;
+
+use crate::error::TxError;
+use crate::state::config::TokenConfig;
+use crate::state::ledger::{LedgerData, PaginatedResult};
+use crate::tx_record::{TxId, TxRecord};
+
+/// Maximum number of transactions sent to the archive in one call, to stay within the message
+/// size limit.
+const MAX_ARCHIVE_CHUNK: u64 = 1_000;
+
+thread_local! {
+    static ARCHIVING: Cell<bool> = Cell::new(false);
+}
+
+/// Configuration of moving the old transactions to the archive canister.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct ArchiveOptions {
+    /// Canister storing the archived transactions.
+    pub archive: Principal,
+    /// Transactions are archived once more than this number of them are stored in the canister.
+    pub trigger_threshold: u64,
+    /// Number of the oldest transactions moved to the archive at once.
+    pub num_to_archive: u64,
+}
+
+pub type ArchiveCall<T> = Pin<Box<dyn Future<Output = Result<T, TxError>>>>;
+
+/// Storage of the transactions removed from the ledger.
+pub trait Archive {
+    /// Stores the `records`, which follow the ones stored before.
+    fn append(&self, records: Vec<TxRecord>) -> ArchiveCall<()>;
+
+    fn get_transaction(&self, id: TxId) -> ArchiveCall<Option<TxRecord>>;
+
+    /// Returns the archived transactions in the same way as `LedgerData::get_transactions`.
+    fn get_transactions(
+        &self,
+        who: Option<Principal>,
+        count: usize,
+        transaction_id: Option<TxId>,
+    ) -> ArchiveCall<PaginatedResult>;
+}
+
+/// Archive canister, which implements the `append_transactions`, `get_transaction` and
+/// `get_transactions` methods.
+#[derive(Debug, Clone, Copy)]
+pub struct ArchiveCanister(pub Principal);
+
+impl ArchiveCanister {
+    /// Returns the archive canister of the token, if set.
+    pub fn get() -> Option<Self> {
+        TokenConfig::get_stable()
+            .archive_options
+            .map(|options| Self(options.archive))
+    }
+}
+
+impl Archive for ArchiveCanister {
+    fn append(&self, records: Vec<TxRecord>) -> ArchiveCall<()> {
+        let archive = self.0;
+        Box::pin(async move {
+            ic::call::<_, (), _>(archive, "append_transactions", (records,))
+                .await
+                .map_err(call_failed)
+        })
+    }
+
+    fn get_transaction(&self, id: TxId) -> ArchiveCall<Option<TxRecord>> {
+        let archive = self.0;
+        Box::pin(async move {
+            ic::call::<_, (Option<TxRecord>,), _>(archive, "get_transaction", (id,))
+                .await
+                .map(|(record,)| record)
+                .map_err(call_failed)
+        })
+    }
+
+    fn get_transactions(
+        &self,
+        who: Option<Principal>,
+        count: usize,
+        transaction_id: Option<TxId>,
+    ) -> ArchiveCall<PaginatedResult> {
+        let archive = self.0;
+        Box::pin(async move {
+            ic::call::<_, (PaginatedResult,), _>(
+                archive,
+                "get_transactions",
+                (who, count, transaction_id),
+            )
+            .await
+            .map(|(page,)| page)
+            .map_err(call_failed)
+        })
+    }
+}
+
+fn call_failed((code, message): (impl std::fmt::Debug, String)) -> TxError {
+    TxError::ArchiveCallFailed {
+        message: format!("{code:?}: {message}"),
+    }
+}
+
+/// Marks the archiving as in progress for as long as the guard is alive.
+struct ArchivingGuard;
+
+impl ArchivingGuard {
+    fn enter() -> Result<Self, TxError> {
+        if ARCHIVING.with(|archiving| archiving.replace(true)) {
+            return Err(TxError::ArchivingInProgress);
+        }
+
+        Ok(Self)
+    }
+}
+
+impl Drop for ArchivingGuard {
+    fn drop(&mut self) {
+        ARCHIVING.with(|archiving| archiving.set(false))
+    }
+}
+
+/// Moves up to `num_to_archive` oldest transactions to the `archive`, if more than
+/// `trigger_threshold` of them are stored. Returns the number of the archived transactions.
+///
+/// The transactions are removed from the ledger only after the archive has stored them, so the
+/// ones of a failed call stay in the ledger.
+pub async fn archive_transactions(
+    archive: &impl Archive,
+    options: ArchiveOptions,
+) -> Result<u64, TxError> {
+    let _guard = ArchivingGuard::enter()?;
+    let stored = LedgerData::stored_len() as u64;
+    if stored <= options.trigger_threshold {
+        return Ok(0);
+    }
+
+    let mut remaining = options.num_to_archive.min(stored);
+    let mut archived = 0;
+    while remaining > 0 {
+        let records = LedgerData::oldest(remaining.min(MAX_ARCHIVE_CHUNK) as usize);
+        let end = match records.last() {
+            Some(last) => last.index + 1,
+            None => break,
+        };
+        let count = records.len() as u64;
+
+        archive.append(records).await?;
+
+        // The oldest records could be removed from the history while the call was in flight.
+        let archived_stored = end.saturating_sub(LedgerData::first_local_index());
+        LedgerData::remove_oldest(archived_stored as usize);
+
+        archived += count;
+        remaining -= count;
+    }
+
+    Ok(archived)
+}
+
+/// Returns the transaction with the given id from the ledger, or from the `archive` if it was
+/// moved there.
+pub async fn get_transaction(
+    archive: Option<&impl Archive>,
+    id: TxId,
+) -> Result<TxRecord, TxError> {
+    if let Some(record) = LedgerData::get(id) {
+        return Ok(record);
+    }
+
+    match archive {
+        Some(archive) if id < LedgerData::first_local_index() => archive
+            .get_transaction(id)
+            .await?
+            .ok_or(TxError::TransactionDoesNotExist),
+        _ => Err(TxError::TransactionDoesNotExist),
+    }
+}
+
+/// Returns the page of the transactions as `LedgerData::get_transactions`, continuing with the
+/// transactions of the `archive` once the stored ones are exhausted. If the archive call fails,
+/// the page contains only the stored transactions, and `next` points to the archived ones.
+pub async fn get_transactions(
+    archive: Option<&impl Archive>,
+    who: Option<Principal>,
+    count: usize,
+    transaction_id: Option<TxId>,
+) -> PaginatedResult {
+    let mut page = LedgerData::get_transactions(who, count, transaction_id);
+    let first_local_index = LedgerData::first_local_index();
+    let archive = match archive {
+        Some(archive) if page.next.is_none() && first_local_index > 0 => archive,
+        _ => return page,
+    };
+
+    let start = transaction_id.map_or(first_local_index - 1, |id| id.min(first_local_index - 1));
+    let remaining = count.saturating_sub(page.result.len());
+    if remaining == 0 {
+        page.next = Some(start);
+        return page;
+    }
+
+    match archive.get_transactions(who, remaining, Some(start)).await {
+        Ok(archived) => {
+            page.result.extend(archived.result);
+            page.next = archived.next;
+        }
+        Err(_) => page.next = Some(start),
+    }
+
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use canister_sdk::ic_canister::Canister;
+    use canister_sdk::ic_helpers::tokens::Tokens128;
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john};
+    use canister_sdk::ic_kit::MockContext;
+
+    use super::*;
+    use crate::account::Account;
+    use crate::canister::TokenCanisterAPI;
+    use crate::mock::TokenCanisterMock;
+    use crate::state::balances::StableBalances;
+    use crate::state::config::Metadata;
+    use crate::state::ledger::TransferArgs;
+
+    #[derive(Default)]
+    struct MockArchive {
+        records: RefCell<Vec<TxRecord>>,
+        unavailable: Cell<bool>,
+    }
+
+    impl MockArchive {
+        fn check_available(&self) -> Result<(), TxError> {
+            if self.unavailable.get() {
+                return Err(TxError::ArchiveCallFailed {
+                    message: "unavailable".to_string(),
+                });
+            }
+
+            Ok(())
+        }
+    }
+
+    impl Archive for MockArchive {
+        fn append(&self, records: Vec<TxRecord>) -> ArchiveCall<()> {
+            let result = self
+                .check_available()
+                .map(|_| self.records.borrow_mut().extend(records));
+            Box::pin(async move { result })
+        }
+
+        fn get_transaction(&self, id: TxId) -> ArchiveCall<Option<TxRecord>> {
+            let result = self.check_available().map(|_| {
+                self.records
+                    .borrow()
+                    .iter()
+                    .find(|tx| tx.index == id)
+                    .cloned()
+            });
+            Box::pin(async move { result })
+        }
+
+        fn get_transactions(
+            &self,
+            who: Option<Principal>,
+            count: usize,
+            transaction_id: Option<TxId>,
+        ) -> ArchiveCall<PaginatedResult> {
+            let result = self.check_available().map(|_| {
+                let mut result = self
+                    .records
+                    .borrow()
+                    .iter()
+                    .rev()
+                    .filter(|tx| who.map_or(true, |who| tx.contains(who)))
+                    .filter(|tx| transaction_id.map_or(true, |id| id >= tx.index))
+                    .take(count + 1)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let next = (result.len() == count + 1).then(|| result.remove(count).index);
+                PaginatedResult { result, next }
+            });
+            Box::pin(async move { result })
+        }
+    }
+
+    fn test_canister() -> TokenCanisterMock {
+        let context = MockContext::new().with_caller(alice()).inject();
+
+        let principal = Principal::from_text("mfufu-x6j4c-gomzb-geilq").unwrap();
+        let canister = TokenCanisterMock::from_principal(principal);
+        context.update_id(canister.principal());
+
+        // Refresh canister's state.
+        TokenConfig::set_stable(TokenConfig::default());
+        StableBalances.clear();
+        LedgerData::clear();
+
+        canister.init(
+            Metadata {
+                name: "".to_string(),
+                symbol: "".to_string(),
+                decimals: 8,
+                owner: alice(),
+                fee: Tokens128::from(0),
+                fee_to: alice(),
+                is_test_token: None,
+                max_supply: None,
+            },
+            Tokens128::from(1000),
+        );
+
+        canister
+    }
+
+    fn transfer(canister: &TokenCanisterMock, to: Principal, amount: u128) {
+        canister
+            .transfer(TransferArgs {
+                from_subaccount: None,
+                to: Account::from(to),
+                amount: amount.into(),
+                fee: None,
+                max_fee: None,
+                memo: None,
+                created_at_time: None,
+                valid_until: None,
+                tags: None,
+                tip: None,
+            })
+            .unwrap();
+    }
+
+    fn options(trigger_threshold: u64, num_to_archive: u64) -> ArchiveOptions {
+        ArchiveOptions {
+            archive: john(),
+            trigger_threshold,
+            num_to_archive,
+        }
+    }
+
+    #[tokio::test]
+    async fn archived_transactions_remain_retrievable() {
+        let canister = test_canister();
+        for amount in 1..=5 {
+            transfer(&canister, bob(), amount);
+        }
+
+        let archive = MockArchive::default();
+        assert_eq!(archive_transactions(&archive, options(6, 4)).await, Ok(0));
+        assert_eq!(archive_transactions(&archive, options(5, 4)).await, Ok(4));
+        assert_eq!(LedgerData::first_local_index(), 4);
+        assert_eq!(LedgerData::stored_len(), 2);
+        assert_eq!(canister.history_size(), 6);
+
+        for id in 0..6 {
+            let record = get_transaction(Some(&archive), id).await.unwrap();
+            assert_eq!(record.index, id);
+        }
+        assert_eq!(
+            get_transaction(Some(&archive), 6).await,
+            Err(TxError::TransactionDoesNotExist)
+        );
+        assert_eq!(
+            get_transaction(None::<&MockArchive>, 0).await,
+            Err(TxError::TransactionDoesNotExist)
+        );
+
+        let page = get_transactions(Some(&archive), None, 4, None).await;
+        let ids: Vec<TxId> = page.result.iter().map(|tx| tx.index).collect();
+        assert_eq!(ids, vec![5, 4, 3, 2]);
+        assert_eq!(page.next, Some(1));
+
+        let page = get_transactions(Some(&archive), None, 4, page.next).await;
+        let ids: Vec<TxId> = page.result.iter().map(|tx| tx.index).collect();
+        assert_eq!(ids, vec![1, 0]);
+        assert_eq!(page.next, None);
+
+        // The initial mint to alice is not a transaction of bob.
+        let page = get_transactions(Some(&archive), Some(bob()), 10, None).await;
+        assert_eq!(page.result.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn failed_archive_call_keeps_transactions() {
+        let canister = test_canister();
+        for amount in 1..=3 {
+            transfer(&canister, bob(), amount);
+        }
+
+        let archive = MockArchive::default();
+        archive.unavailable.set(true);
+        assert!(matches!(
+            archive_transactions(&archive, options(2, 2)).await,
+            Err(TxError::ArchiveCallFailed { .. })
+        ));
+        assert_eq!(LedgerData::first_local_index(), 0);
+        assert_eq!(LedgerData::stored_len(), 4);
+
+        archive.unavailable.set(false);
+        assert_eq!(archive_transactions(&archive, options(2, 2)).await, Ok(2));
+
+        // The stored transactions are returned, pointing to the archived ones for the retry.
+        archive.unavailable.set(true);
+        let page = get_transactions(Some(&archive), None, 10, None).await;
+        assert_eq!(page.result.len(), 2);
+        assert_eq!(page.next, Some(1));
+    }
+}
@@ -1,24 +1,36 @@
 //! This module contains APIs from IS20 standard providing cycle auction related functionality.
 
+use std::cell::RefCell;
+
 use canister_sdk::{
     ic_auction::{
+        api::Auction,
         error::AuctionError,
-        state::{AuctionInfo, AuctionState},
+        state::{AuctionInfo, AuctionState, MIN_BIDDING_AMOUNT},
     },
     ic_helpers::tokens::Tokens128,
     ic_kit::ic,
+    ic_metrics::Interval,
 };
 use ic_exports::Principal;
 
+use crate::error::TxError;
 use crate::state::ledger::{BatchTransferArgs, LedgerData};
 use crate::{
     account::AccountInternal,
     state::balances::{Balances, StableBalances},
 };
-use crate::{canister::auction_account, state::config::TokenConfig};
+use crate::{
+    canister::{auction_account, escrow_account, vesting_account},
+    state::config::{AuctionBeneficiary, TokenConfig},
+};
 
 use super::is20_transactions::batch_transfer_internal;
 
+/// Minimum period between the auctions, so that the auctions and their disbursement transactions
+/// cannot be run too often.
+pub const MIN_AUCTION_PERIOD_SECONDS: u64 = 60;
+
 pub fn disburse_rewards(auction_state: &AuctionState) -> Result<AuctionInfo, AuctionError> {
     let AuctionState {
         ref bidding_state,
@@ -26,75 +38,273 @@ pub fn disburse_rewards(auction_state: &AuctionState) -> Result<AuctionInfo, Auc
         ..
     } = *auction_state;
 
+    let total_cycles = bidding_state.cycles_since_auction;
+    let first_transaction_id = LedgerData::len();
+
+    let stats = TokenConfig::get_stable();
+    let beneficiary = stats.auction_beneficiary.unwrap_or_default();
+    let transferred_amount = match beneficiary {
+        AuctionBeneficiary::Bidders => distribute_to_bidders(auction_state)?,
+        AuctionBeneficiary::SpecificAccount(account) => transfer_pool(account.into()),
+        AuctionBeneficiary::Burn => burn_pool(),
+    };
+
+    let last_transaction_id = LedgerData::len() - 1;
+    let result = AuctionInfo {
+        auction_id: history.len(),
+        auction_time: canister_sdk::ic_kit::ic::time(),
+        tokens_distributed: transferred_amount,
+        cycles_collected: total_cycles,
+        fee_ratio: stats.effective_fee_ratio(bidding_state.fee_ratio),
+        first_transaction_id,
+        last_transaction_id,
+    };
+
+    Ok(result)
+}
+
+fn distribute_to_bidders(auction_state: &AuctionState) -> Result<Tokens128, AuctionError> {
+    let bidding_state = &auction_state.bidding_state;
     let total_amount = accumulated_fees();
     let mut transferred_amount = Tokens128::from(0u128);
     let total_cycles = bidding_state.cycles_since_auction;
 
-    let first_transaction_id = LedgerData::len();
-
     let mut transfers = vec![];
     for (bidder, cycles) in &bidding_state.bids {
-        let amount = (total_amount * cycles / total_cycles)
-            .ok_or(AuctionError::NoBids)?
-            .to_tokens128()
-            .unwrap_or(Tokens128::MAX);
+        let amount = bidder_reward(total_amount, cycles, total_cycles)?;
         transfers.push(BatchTransferArgs {
             receiver: (*bidder).into(),
             amount,
+            fee_override: None,
         });
-        LedgerData::record_auction(*bidder, amount);
+        LedgerData::record_auction((*bidder).into(), amount);
         transferred_amount = (transferred_amount + amount)
             .ok_or_else(|| ic::trap("Token amount overflow on auction bids distribution."))
             .unwrap();
     }
 
     let stats = TokenConfig::get_stable();
-    let (fee, fee_to) = stats.fee_info();
-
     if let Err(e) = batch_transfer_internal(
         auction_account(),
         &transfers,
         &mut StableBalances,
-        fee,
-        fee_to,
-        auction_state.bidding_state.fee_ratio,
+        &stats,
+        stats.effective_fee_ratio(bidding_state.fee_ratio),
     ) {
         ic::trap(&format!("Failed to transfer tokens to the bidders: {e}"));
     }
 
-    let last_transaction_id = LedgerData::len() - 1;
-    let result = AuctionInfo {
-        auction_id: history.len(),
-        auction_time: canister_sdk::ic_kit::ic::time(),
-        tokens_distributed: transferred_amount,
-        cycles_collected: total_cycles,
-        fee_ratio: bidding_state.fee_ratio,
-        first_transaction_id,
-        last_transaction_id,
-    };
+    Ok(transferred_amount)
+}
 
-    Ok(result)
+/// Returns at most `limit` auctions from the `auction_state` history, the latest first, starting
+/// from the auction with id `start`.
+pub fn auction_history(
+    auction_state: &AuctionState,
+    start: Option<u64>,
+    limit: usize,
+) -> Vec<AuctionInfo> {
+    let history = &auction_state.history;
+    let end = start.map_or(history.len(), |start| {
+        usize::try_from(start)
+            .map_or(history.len(), |start| start.saturating_add(1))
+            .min(history.len())
+    });
+
+    history[..end].iter().rev().take(limit).cloned().collect()
+}
+
+/// Returns the reward the `bidder` would receive if the auction was run now. If the auction pool
+/// is not distributed between the bidders, the reward is zero.
+pub fn pending_reward_for(auction_state: &AuctionState, bidder: Principal) -> Tokens128 {
+    let beneficiary = TokenConfig::get_stable()
+        .auction_beneficiary
+        .unwrap_or_default();
+    if beneficiary != AuctionBeneficiary::Bidders {
+        return Tokens128::ZERO;
+    }
+
+    let bidding_state = &auction_state.bidding_state;
+    bidding_state
+        .bids
+        .get(&bidder)
+        .and_then(|cycles| {
+            bidder_reward(
+                accumulated_fees(),
+                cycles,
+                bidding_state.cycles_since_auction,
+            )
+            .ok()
+        })
+        .unwrap_or_default()
+}
+
+/// Pro-rata share of the `pool` for the bidder who bid `cycles` out of `total_cycles`.
+fn bidder_reward(
+    pool: Tokens128,
+    cycles: &u64,
+    total_cycles: u64,
+) -> Result<Tokens128, AuctionError> {
+    Ok((pool * cycles / total_cycles)
+        .ok_or(AuctionError::NoBids)?
+        .to_tokens128()
+        .unwrap_or(Tokens128::MAX))
+}
+
+/// Moves the whole auction pool to the `beneficiary` account without charging a fee.
+fn transfer_pool(beneficiary: AccountInternal) -> Tokens128 {
+    let amount = accumulated_fees();
+    let balance = (StableBalances.balance_of(&beneficiary) + amount)
+        .unwrap_or_else(|| ic::trap("Token amount overflow on auction pool transfer."));
+
+    StableBalances.remove(&auction_account());
+    StableBalances.insert(beneficiary, balance);
+    LedgerData::record_auction(beneficiary, amount);
+
+    amount
+}
+
+/// Burns the whole auction pool.
+fn burn_pool() -> Tokens128 {
+    let amount = accumulated_fees();
+    StableBalances.remove(&auction_account());
+    LedgerData::burn(auction_account(), auction_account(), amount, None);
+
+    amount
+}
+
+/// Minimum amount of cycles accepted as a bid.
+pub fn min_auction_bid() -> u64 {
+    TokenConfig::get_stable()
+        .min_auction_bid
+        .map_or(MIN_BIDDING_AMOUNT, |min_bid| {
+            min_bid.max(MIN_BIDDING_AMOUNT)
+        })
+}
+
+/// Accepts the cycles attached to the call as a bid of the `bidder`. Bids smaller than
+/// `min_auction_bid` are rejected. If the auction pool is not distributed between the bidders,
+/// all bids are rejected with `AuctionError::Unauthorized` describing the reason, so that no
+/// cycles are taken for nothing.
+pub fn bid_cycles(
+    auction_state: &RefCell<AuctionState>,
+    bidder: Principal,
+) -> Result<u64, AuctionError> {
+    let beneficiary = TokenConfig::get_stable()
+        .auction_beneficiary
+        .unwrap_or_default();
+    if beneficiary != AuctionBeneficiary::Bidders {
+        return Err(AuctionError::Unauthorized(
+            "bids are not accepted as the auction pool is not distributed between the bidders"
+                .to_string(),
+        ));
+    }
+
+    let amount = ic::msg_cycles_available();
+    if amount < min_auction_bid() {
+        return Err(AuctionError::BiddingTooSmall);
+    }
+
+    let accepted = ic::msg_cycles_accept(amount);
+    let bidding_state = &mut auction_state.borrow_mut().bidding_state;
+    bidding_state.cycles_since_auction += accepted;
+    *bidding_state.bids.entry(bidder).or_default() += accepted;
+
+    Ok(accepted)
+}
+
+/// Sets the period between the auctions. Can be called only by the auction controller. Periods
+/// shorter than `MIN_AUCTION_PERIOD_SECONDS` are rejected with `AuctionError::Unauthorized`
+/// describing the limit, as it's the only `AuctionError` variant which carries a message.
+pub fn set_auction_period(
+    auction_state: &RefCell<AuctionState>,
+    interval: Interval,
+) -> Result<(), AuctionError> {
+    let caller = ic::caller();
+    let mut state = auction_state.borrow_mut();
+    if caller != state.controller {
+        return Err(AuctionError::Unauthorized(caller.to_string()));
+    }
+
+    let period = interval.nanos();
+    if period < MIN_AUCTION_PERIOD_SECONDS * 1_000_000_000 {
+        return Err(AuctionError::Unauthorized(format!(
+            "auction period cannot be shorter than {MIN_AUCTION_PERIOD_SECONDS} seconds"
+        )));
+    }
+
+    state.bidding_state.auction_period = period;
+    Ok(())
+}
+
+/// Runs the auction before the end of the auction period if the accumulated fees have reached the
+/// `auction_pool_trigger` amount. Returns `None` if the trigger is not set or not reached.
+///
+/// If the auction fails (e.g. there are no bids), the auction schedule is left unchanged.
+pub fn run_auction_on_pool_trigger(
+    canister: &impl Auction,
+) -> Option<Result<AuctionInfo, AuctionError>> {
+    let trigger = TokenConfig::get_stable().auction_pool_trigger?;
+    if accumulated_fees() < trigger {
+        return None;
+    }
+
+    let state = canister.auction_state();
+    let last_auction = state.borrow().bidding_state.last_auction;
+    {
+        let bidding_state = &mut state.borrow_mut().bidding_state;
+        // Pretend the auction period is over, so the auction can be started right now.
+        bidding_state.last_auction =
+            ic::time().saturating_sub(bidding_state.auction_period.saturating_add(1));
+    }
+
+    let result = canister.run_auction();
+    if result.is_err() {
+        state.borrow_mut().bidding_state.last_auction = last_auction;
+    }
+
+    Some(result)
 }
 
 pub fn accumulated_fees() -> Tokens128 {
-    let account = AccountInternal::new(Principal::management_canister(), None);
-    StableBalances.balance_of(&account)
+    StableBalances.balance_of(&auction_account())
+}
+
+/// Checks that the `new_account` can become the auction account. Only the subaccounts of the
+/// management canister can hold the pool, as nobody can spend the tokens from them. The escrow and
+/// vesting subaccounts are already in use, and so is any account with a balance, so that the pool
+/// is never mixed with other balances.
+pub fn check_auction_account(new_account: AccountInternal) -> Result<(), TxError> {
+    if new_account.owner != Principal::management_canister() {
+        return Err(TxError::InvalidAuctionAccount);
+    }
+
+    if [escrow_account(), vesting_account()].contains(&new_account)
+        || !StableBalances.balance_of(&new_account).is_zero()
+    {
+        return Err(TxError::AuctionAccountInUse);
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use canister_sdk::{
-        ic_auction::{api::Auction, state::MIN_BIDDING_AMOUNT},
         ic_canister::Canister,
         ic_kit::{
-            mock_principals::{alice, bob},
+            mock_principals::{alice, bob, john, xtc},
             MockContext,
         },
         ic_metrics::Interval,
     };
 
+    use crate::account::Account;
+    use crate::canister::TokenCanisterAPI;
+    use crate::error::TxError;
     use crate::mock::*;
     use crate::state::config::Metadata;
+    use crate::state::ledger::TransferArgs;
 
     use super::*;
 
@@ -120,6 +330,7 @@ mod tests {
                 fee: Tokens128::from(0),
                 fee_to: alice(),
                 is_test_token: None,
+                max_supply: None,
             },
             Tokens128::from(1000),
         );
@@ -156,6 +367,38 @@ mod tests {
         );
     }
 
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn configured_min_auction_bid() {
+        let (context, canister) = test_context();
+        let min_bid = MIN_BIDDING_AMOUNT * 10;
+
+        context.update_caller(bob());
+        assert_eq!(
+            canister.set_min_auction_bid(Some(min_bid)),
+            Err(TxError::Unauthorized)
+        );
+
+        context.update_caller(alice());
+        canister.set_min_auction_bid(Some(min_bid)).unwrap();
+        assert_eq!(canister.min_auction_bid(), min_bid);
+
+        context.update_msg_cycles(min_bid - 1);
+        assert_eq!(
+            canister.bid_cycles(alice()),
+            Err(AuctionError::BiddingTooSmall)
+        );
+        assert_eq!(canister.bidding_info().total_cycles, 0);
+
+        context.update_msg_cycles(min_bid);
+        assert_eq!(canister.bid_cycles(alice()), Ok(min_bid));
+        assert_eq!(canister.bidding_info().caller_cycles, min_bid);
+
+        // The configured minimum cannot be lower than the one of the auction.
+        canister.set_min_auction_bid(Some(1)).unwrap();
+        assert_eq!(canister.min_auction_bid(), MIN_BIDDING_AMOUNT);
+    }
+
     #[test]
     #[cfg_attr(coverage_nightly, no_coverage)]
     fn bidding_multiple_times() {
@@ -200,6 +443,326 @@ mod tests {
         assert_eq!(retrieved_result, result);
     }
 
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn pending_reward_preview() {
+        let (context, canister) = test_context();
+        context.update_msg_cycles(1_000_000);
+        canister.bid_cycles(alice()).unwrap();
+
+        context.update_msg_cycles(2_000_000);
+        canister.bid_cycles(bob()).unwrap();
+
+        context.update_msg_cycles(3_000_000);
+        canister.bid_cycles(john()).unwrap();
+
+        StableBalances.insert(auction_account(), Tokens128::from(6000));
+
+        let previews = [alice(), bob(), john()].map(|bidder| canister.pending_reward_for(bidder));
+        assert_eq!(previews, [1_000, 2_000, 3_000].map(Tokens128::from));
+        assert_eq!(canister.pending_reward_for(xtc()), Tokens128::ZERO);
+
+        let total = previews
+            .into_iter()
+            .try_fold(Tokens128::ZERO, |total, reward| total + reward)
+            .unwrap();
+        assert_eq!(total, accumulated_fees());
+
+        context.add_time(10u64.pow(9) * 60 * 60 * 300);
+        canister.run_auction().unwrap();
+        assert_eq!(
+            StableBalances.balance_of(&alice().into()),
+            (Tokens128::from(1000) + previews[0]).unwrap()
+        );
+        assert_eq!(StableBalances.balance_of(&bob().into()), previews[1]);
+        assert_eq!(StableBalances.balance_of(&john().into()), previews[2]);
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn auction_on_pool_trigger() {
+        let (context, canister) = test_context();
+        context.update_msg_cycles(2_000_000);
+        canister.bid_cycles(alice()).unwrap();
+
+        context.update_msg_cycles(4_000_000);
+        canister.bid_cycles(bob()).unwrap();
+
+        assert_eq!(run_auction_on_pool_trigger(&canister), None);
+
+        canister
+            .set_auction_pool_trigger(Some(Tokens128::from(6000)))
+            .unwrap();
+        assert_eq!(canister.auction_pool_trigger(), Some(Tokens128::from(6000)));
+
+        StableBalances.insert(auction_account(), Tokens128::from(5999));
+        assert_eq!(run_auction_on_pool_trigger(&canister), None);
+        assert_eq!(StableBalances.balance_of(&bob().into()), Tokens128::ZERO);
+
+        StableBalances.insert(auction_account(), Tokens128::from(6000));
+        let result = run_auction_on_pool_trigger(&canister).unwrap().unwrap();
+        assert_eq!(result.tokens_distributed, Tokens128::from(6000));
+        assert_eq!(
+            StableBalances.balance_of(&bob().into()),
+            Tokens128::from(4_000)
+        );
+        assert_eq!(canister.auction_info(result.auction_id).unwrap(), result);
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn auction_on_pool_trigger_without_bids() {
+        let (_, canister) = test_context();
+        canister
+            .set_auction_pool_trigger(Some(Tokens128::from(100)))
+            .unwrap();
+        StableBalances.insert(auction_account(), Tokens128::from(6000));
+
+        let last_auction = canister.auction_state().borrow().bidding_state.last_auction;
+        assert_eq!(
+            run_auction_on_pool_trigger(&canister),
+            Some(Err(AuctionError::NoBids))
+        );
+        assert_eq!(
+            canister.auction_state().borrow().bidding_state.last_auction,
+            last_auction
+        );
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn transaction_runs_auction_on_pool_trigger() {
+        let (context, canister) = test_context();
+        context.update_msg_cycles(2_000_000);
+        canister.bid_cycles(bob()).unwrap();
+        canister
+            .set_auction_pool_trigger(Some(Tokens128::from(6000)))
+            .unwrap();
+        StableBalances.insert(auction_account(), Tokens128::from(6000));
+
+        let transfer = |amount: u128| TransferArgs {
+            from_subaccount: None,
+            to: john().into(),
+            amount: Tokens128::from(amount),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        // Neither failed transactions nor other updates run the auction.
+        assert!(canister.transfer(transfer(2000)).is_err());
+        canister.set_fee(Tokens128::from(0)).unwrap();
+        assert_eq!(accumulated_fees(), Tokens128::from(6000));
+
+        canister.transfer(transfer(100)).unwrap();
+        assert_eq!(
+            StableBalances.balance_of(&bob().into()),
+            Tokens128::from(6000)
+        );
+    }
+
+    fn run_auction_with_pool(
+        context: &mut MockContext,
+        canister: &TokenCanisterMock,
+        beneficiary: AuctionBeneficiary,
+    ) -> AuctionInfo {
+        context.update_msg_cycles(2_000_000);
+        canister.bid_cycles(alice()).unwrap();
+        context.update_msg_cycles(4_000_000);
+        canister.bid_cycles(bob()).unwrap();
+        canister.set_auction_beneficiary(beneficiary).unwrap();
+
+        StableBalances.insert(auction_account(), Tokens128::from(6000));
+        context.add_time(10u64.pow(9) * 60 * 60 * 300);
+
+        canister.run_auction().unwrap()
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn auction_history() {
+        let (context, canister) = test_context();
+        assert!(canister.get_auction_history(None, 10).is_empty());
+
+        let first = run_auction_with_pool(context, &canister, AuctionBeneficiary::Bidders);
+        let second = run_auction_with_pool(context, &canister, AuctionBeneficiary::Bidders);
+        let ids = |history: Vec<AuctionInfo>| {
+            history
+                .into_iter()
+                .map(|auction| auction.auction_id)
+                .collect::<Vec<_>>()
+        };
+
+        let history = canister.get_auction_history(None, 10);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].auction_id, second.auction_id);
+        assert_eq!(history[0].auction_time, second.auction_time);
+        assert!(history[0].auction_time > history[1].auction_time);
+        assert_eq!(history[1].auction_id, first.auction_id);
+        assert_eq!(history[1].cycles_collected, 6_000_000);
+        assert_eq!(history[1].tokens_distributed, Tokens128::from(6000));
+
+        assert_eq!(
+            ids(canister.get_auction_history(None, 1)),
+            [second.auction_id]
+        );
+        assert_eq!(
+            ids(canister.get_auction_history(Some(first.auction_id as u64), 10)),
+            [first.auction_id]
+        );
+        assert_eq!(
+            ids(canister.get_auction_history(Some(u64::MAX), 10)),
+            [second.auction_id, first.auction_id]
+        );
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn auction_beneficiary_bidders() {
+        let (context, canister) = test_context();
+        assert_eq!(canister.auction_beneficiary(), AuctionBeneficiary::Bidders);
+
+        let result = run_auction_with_pool(context, &canister, AuctionBeneficiary::Bidders);
+        assert_eq!(result.tokens_distributed, Tokens128::from(6000));
+        assert_eq!(
+            StableBalances.balance_of(&bob().into()),
+            Tokens128::from(4000)
+        );
+        assert_eq!(accumulated_fees(), Tokens128::ZERO);
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn auction_beneficiary_specific_account() {
+        let (context, canister) = test_context();
+        let treasury = Account::new(john(), Some([1; 32]));
+        let result = run_auction_with_pool(
+            context,
+            &canister,
+            AuctionBeneficiary::SpecificAccount(treasury),
+        );
+        assert_eq!(result.tokens_distributed, Tokens128::from(6000));
+        assert_eq!(
+            StableBalances.balance_of(&treasury.into()),
+            Tokens128::from(6000)
+        );
+        assert_eq!(StableBalances.balance_of(&bob().into()), Tokens128::ZERO);
+        assert_eq!(accumulated_fees(), Tokens128::ZERO);
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn auction_beneficiary_burn() {
+        let (context, canister) = test_context();
+        let result = run_auction_with_pool(context, &canister, AuctionBeneficiary::Burn);
+        assert_eq!(result.tokens_distributed, Tokens128::from(6000));
+        assert_eq!(accumulated_fees(), Tokens128::ZERO);
+        assert_eq!(StableBalances.balance_of(&bob().into()), Tokens128::ZERO);
+        assert_eq!(canister.icrc1_total_supply(), Tokens128::from(1000));
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn rotate_auction_account() {
+        let (context, canister) = test_context();
+        let old_account = auction_account();
+        StableBalances.insert(old_account, Tokens128::from(6000));
+        let new_account = Account::new(Principal::management_canister(), Some([7; 32]));
+
+        context.update_caller(bob());
+        assert_eq!(
+            canister.rotate_auction_account(new_account),
+            Err(TxError::Unauthorized)
+        );
+
+        // The pool cannot be moved to an account which can spend it.
+        context.update_caller(alice());
+        assert_eq!(
+            canister.rotate_auction_account(Account::new(john(), Some([7; 32]))),
+            Err(TxError::InvalidAuctionAccount)
+        );
+        for account in [escrow_account(), vesting_account()] {
+            assert_eq!(
+                canister.rotate_auction_account(account.into()),
+                Err(TxError::AuctionAccountInUse)
+            );
+        }
+
+        // The rotation is delayed by the governance delay.
+        canister.set_governance_delay(Some(1_000)).unwrap();
+        let id = canister
+            .rotate_auction_account(new_account)
+            .unwrap()
+            .unwrap();
+        assert_eq!(canister.auction_account(), old_account.into());
+        context.add_time(1_000);
+        canister.execute_owner_action(id).unwrap();
+
+        assert_eq!(canister.auction_account(), new_account);
+        assert_eq!(accumulated_fees(), Tokens128::from(6000));
+        assert_eq!(StableBalances.balance_of(&old_account), Tokens128::ZERO);
+
+        canister
+            .transfer(TransferArgs {
+                from_subaccount: None,
+                to: bob().into(),
+                amount: Tokens128::from(100),
+                fee: None,
+                max_fee: None,
+                memo: None,
+                created_at_time: None,
+                valid_until: None,
+                tags: None,
+                tip: Some(Tokens128::from(50)),
+            })
+            .unwrap();
+        assert_eq!(
+            StableBalances.balance_of(&new_account.into()),
+            Tokens128::from(6050)
+        );
+        assert_eq!(StableBalances.balance_of(&old_account), Tokens128::ZERO);
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn bids_rejected_without_bidders_beneficiary() {
+        let (context, canister) = test_context();
+        let rejected = Err(AuctionError::Unauthorized(
+            "bids are not accepted as the auction pool is not distributed between the bidders"
+                .to_string(),
+        ));
+        for beneficiary in [
+            AuctionBeneficiary::SpecificAccount(Account::new(john(), None)),
+            AuctionBeneficiary::Burn,
+        ] {
+            canister.set_auction_beneficiary(beneficiary).unwrap();
+            context.update_msg_cycles(2_000_000);
+            assert_eq!(canister.bid_cycles(alice()), rejected);
+            assert_eq!(canister.bidding_info().total_cycles, 0);
+        }
+
+        canister
+            .set_auction_beneficiary(AuctionBeneficiary::Bidders)
+            .unwrap();
+        context.update_msg_cycles(2_000_000);
+        assert_eq!(canister.bid_cycles(alice()), Ok(2_000_000));
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn set_auction_beneficiary_not_authorized() {
+        let (context, canister) = test_context();
+        context.update_caller(bob());
+        assert_eq!(
+            canister.set_auction_beneficiary(AuctionBeneficiary::Burn),
+            Err(TxError::Unauthorized)
+        );
+    }
+
     #[test]
     #[cfg_attr(coverage_nightly, no_coverage)]
     fn auction_without_bids() {
@@ -266,6 +829,37 @@ mod tests {
         );
     }
 
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn setting_min_auction_period() {
+        let (_, canister) = test_context();
+        canister
+            .set_auction_period(Interval::Period {
+                seconds: MIN_AUCTION_PERIOD_SECONDS,
+            })
+            .unwrap();
+        assert_eq!(
+            canister.bidding_info().auction_period,
+            MIN_AUCTION_PERIOD_SECONDS * 10u64.pow(9)
+        );
+    }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn setting_too_short_auction_period() {
+        let (_, canister) = test_context();
+        let period = canister.bidding_info().auction_period;
+        assert_eq!(
+            canister.set_auction_period(Interval::Period {
+                seconds: MIN_AUCTION_PERIOD_SECONDS - 1,
+            }),
+            Err(AuctionError::Unauthorized(format!(
+                "auction period cannot be shorter than {MIN_AUCTION_PERIOD_SECONDS} seconds"
+            )))
+        );
+        assert_eq!(canister.bidding_info().auction_period, period);
+    }
+
     #[test]
     #[cfg_attr(coverage_nightly, no_coverage)]
     fn setting_auction_period_not_authorized() {
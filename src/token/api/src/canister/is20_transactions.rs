@@ -4,47 +4,279 @@ use canister_sdk::ic_kit::ic;
 use canister_sdk::ledger::{AccountIdentifier, Subaccount as SubaccountIdentifier};
 use ic_exports::Principal;
 
-use super::auction_account;
-use super::icrc1_transfer::{PERMITTED_DRIFT, TX_WINDOW};
-use crate::account::{AccountInternal, CheckedAccount, Subaccount, WithRecipient};
+use super::{auction_account, escrow_account, vesting_account};
+use crate::account::{Account, AccountInternal, CheckedAccount, Subaccount, WithRecipient};
 use crate::error::TxError;
-use crate::principal::{CheckedPrincipal, Owner, TestNet};
+use crate::principal::{CheckedPrincipal, Minter, Owner, TestNet};
+use crate::state::allowances::Allowances;
+use crate::state::auction_dust::AuctionDust;
 use crate::state::balances::{Balances, LocalBalances, StableBalances};
-use crate::state::config::{FeeRatio, TokenConfig};
-use crate::state::ledger::{BatchTransferArgs, LedgerData, TransferArgs, TxReceipt};
-use crate::tx_record::TxId;
+use crate::state::batch_guard::BatchGuard;
+use crate::state::certification::certify_state;
+#[cfg(feature = "claim")]
+use crate::state::claims::Claims;
+use crate::state::config::{FeeRatio, Timestamp, TokenConfig};
+use crate::state::escrow::{Condition, Escrow, Escrows};
+use crate::state::fee_quotes::FeeQuotes;
+use crate::state::ledger::{
+    ApproveArgs, BatchTransferArgs, LedgerData, Memo, MultiSourceTransferArgs, NotifyReceipt,
+    TransferArgs, TransferFromArgs, TxReceipt, MAX_REDEMPTION_REF_LENGTH, MAX_TAG_LENGTH,
+    MAX_TRANSFER_TAGS,
+};
+use crate::state::migration::MigrationState;
+use crate::state::permissions::{FrozenAccounts, OutgoingDisabledAccounts, TokenAwareCanisters};
+use crate::state::prepared_transfers::{PendingTransfer, PreparedTransfer, PreparedTransfers};
+use crate::state::storage::check_storage_growth;
+use crate::state::timelocks::{Timelock, Timelocks};
+use crate::state::vesting::Vestings;
+use crate::tx_record::{FeeSplit, TransferCharges, TxId};
 
 pub fn is20_transfer(
     caller: CheckedAccount<WithRecipient>,
     transfer: &TransferArgs,
     auction_fee_ratio: f64,
 ) -> TxReceipt {
-    let from = caller.inner();
-    let to = caller.recipient();
+    check_zero_amount(&TokenConfig::get_stable(), transfer.amount)?;
+    let (fee, created_at_time) = validate_transfer(caller.inner(), transfer)?;
+    apply_transfer(
+        caller.inner(),
+        caller.recipient(),
+        transfer,
+        fee,
+        created_at_time,
+        auction_fee_ratio,
+    )
+    .map(|(id, _)| id.into())
+}
+
+/// Calls the `notify_method` of the recipient principal with the id of the committed transfer and
+/// the amount received by the recipient. The notification is best-effort: the transfer stays
+/// committed if the call fails, and the failure is reported in the receipt.
+pub async fn notify_transfer(
+    tx_id: u128,
+    transfer: &TransferArgs,
+    notify_method: String,
+) -> NotifyReceipt {
+    let received = LedgerData::get(tx_id as TxId)
+        .map(|record| record.received_amount())
+        .unwrap_or(transfer.amount);
+
+    let notify_error = ic::call::<_, (), _>(transfer.to.owner, notify_method, (tx_id, received))
+        .await
+        .err()
+        .map(|(code, message)| format!("{code:?}: {message}"));
+
+    NotifyReceipt {
+        tx_id,
+        notify_error,
+    }
+}
+
+/// Returns an error if the transfers are paused or the token is not transferable.
+fn check_transferable(stats: &TokenConfig) -> Result<(), TxError> {
+    if stats.is_paused() {
+        return Err(TxError::TokenPaused);
+    }
+
+    if !stats.is_transferable() {
+        return Err(TxError::NonTransferable);
+    }
+
+    Ok(())
+}
+
+/// Rejects zero amount transfers early if the token is configured to do so.
+fn check_zero_amount(stats: &TokenConfig, amount: Tokens128) -> Result<(), TxError> {
+    if amount.is_zero() && stats.is_zero_transfers_rejection_enabled() {
+        return Err(TxError::AmountTooSmall);
+    }
+
+    Ok(())
+}
+
+/// Validates the transfer of the caller without applying it. Returns the fee to be charged and
+/// the timestamp of the transaction.
+fn validate_transfer(
+    from: AccountInternal,
+    transfer: &TransferArgs,
+) -> Result<(Tokens128, u64), TxError> {
     let created_at_time = validate_and_get_tx_ts(from.owner, transfer)?;
-    let TransferArgs { amount, memo, .. } = transfer;
+    validate_tags(transfer)?;
 
     let stats = TokenConfig::get_stable();
-    let (fee, fee_to) = stats.fee_info();
+    check_transferable(&stats)?;
+
+    let fee = FeeQuotes::honored_fee(
+        from.owner,
+        transfer.amount,
+        stats.effective_fee(transfer.amount),
+        transfer.max_fee,
+    );
+    check_fee(fee, transfer.fee, transfer.max_fee)?;
+
+    Ok((fee, created_at_time))
+}
 
-    if let Some(requested_fee) = transfer.fee {
-        if fee != requested_fee {
-            return Err(TxError::BadFee { expected_fee: fee });
+/// Returns `TxError::BadFee` if the `fee` to be charged exceeds the `max_fee` or, if no maximum
+/// is given, differs from the `requested_fee`.
+fn check_fee(
+    fee: Tokens128,
+    requested_fee: Option<Tokens128>,
+    max_fee: Option<Tokens128>,
+) -> Result<(), TxError> {
+    match (max_fee, requested_fee) {
+        (Some(max_fee), _) if fee > max_fee => Err(TxError::BadFee { expected_fee: fee }),
+        (None, Some(requested_fee)) if fee != requested_fee => {
+            Err(TxError::BadFee { expected_fee: fee })
         }
+        _ => Ok(()),
     }
+}
 
-    transfer_internal(
-        &mut StableBalances,
+/// Applies the validated transfer from `from` to `to`. Returns the id of the transaction and the
+/// charges of the transfer.
+fn apply_transfer(
+    from: AccountInternal,
+    to: AccountInternal,
+    transfer: &TransferArgs,
+    fee: Tokens128,
+    created_at_time: u64,
+    auction_fee_ratio: f64,
+) -> Result<(TxId, TransferCharges), TxError> {
+    let amount = transfer.amount;
+    let stats = TokenConfig::get_stable();
+    let fee_to = stats.fee_to_account();
+
+    // The tip is staged together with the transfer, so either both or none of them are applied.
+    let mut updates = stage_balances(&StableBalances, &stats, [from, to]);
+
+    let charges = transfer_internal(
+        &mut updates,
         from,
         to,
-        *amount,
+        amount,
         fee,
-        fee_to.into(),
+        fee_to,
         FeeRatio::new(auction_fee_ratio),
     )?;
 
-    let id = LedgerData::transfer(from, to, *amount, fee, *memo, created_at_time);
-    Ok(id.into())
+    if let Some(tip) = transfer.tip {
+        stage_tip(&mut updates, from, tip).map_err(|err| match err {
+            TxError::InsufficientFunds { .. } => TxError::InsufficientFunds {
+                balance: StableBalances.balance_of(&from),
+            },
+            other => other,
+        })?;
+    }
+
+    check_storage_growth(&StableBalances, &updates)?;
+    StableBalances.apply_updates(updates.list_balances(0, usize::MAX));
+
+    let id = LedgerData::transfer(from, to, transfer, fee, created_at_time, charges);
+    Ok((id, charges))
+}
+
+/// First phase of a two-phase transfer: validates the transfer and stores it together with the
+/// fee to be charged. The transfer is executed by `commit_transfer` with the returned token.
+pub fn prepare_transfer(
+    caller: CheckedAccount<WithRecipient>,
+    transfer: &TransferArgs,
+) -> Result<PreparedTransfer, TxError> {
+    let from = caller.inner();
+    let (fee, created_at_time) = validate_transfer(from, transfer)?;
+
+    let balance = StableBalances.balance_of(&from);
+    let required = (transfer.amount + fee)
+        .and_then(|required| required + transfer.tip.unwrap_or(Tokens128::ZERO))
+        .ok_or(TxError::AmountOverflow)?;
+    if balance < required {
+        return Err(TxError::InsufficientFunds { balance });
+    }
+
+    // The transaction timestamp is fixed, so the deduplication applies to the commit. The prepared
+    // transfer cannot outlive the deduplication window.
+    let args = TransferArgs {
+        created_at_time: Some(created_at_time),
+        ..transfer.clone()
+    };
+    let stats = TokenConfig::get_stable();
+    let ttl = stats
+        .prepared_transfer_ttl_nanos()
+        .min(stats.tx_window_nanos());
+
+    Ok(PreparedTransfers::prepare(args, fee, ttl))
+}
+
+/// Second phase of a two-phase transfer: executes the prepared transfer charging the previewed
+/// fee. The token cannot be used again, even if the transfer fails.
+pub fn commit_transfer(token: u64, auction_fee_ratio: f64) -> TxReceipt {
+    let PendingTransfer { args, fee, .. } = PreparedTransfers::take(token)?;
+    let caller = CheckedAccount::with_recipient(args.to.into(), args.from_subaccount)?;
+
+    // The state could change since the transfer was prepared, so the checks not related to the
+    // fee are repeated.
+    let created_at_time = validate_and_get_tx_ts(caller.inner().owner, &args)?;
+    check_transferable(&TokenConfig::get_stable())?;
+
+    apply_transfer(
+        caller.inner(),
+        caller.recipient(),
+        &args,
+        fee,
+        created_at_time,
+        auction_fee_ratio,
+    )
+    .map(|(id, _)| id.into())
+}
+
+/// Moves the auction pool to the `new_account`. The tokens the account received in the meantime
+/// are kept.
+pub(crate) fn move_auction_pool(new_account: AccountInternal) {
+    let old_account = auction_account();
+    if new_account == old_account {
+        return;
+    }
+
+    if let Some(pool) = StableBalances.remove(&old_account) {
+        let balance = (StableBalances.balance_of(&new_account) + pool)
+            .expect("total supply integer overflow"); // Checked at mint
+        StableBalances.insert(new_account, balance);
+        certify_state();
+    }
+}
+
+/// Stages the balances of the `accounts` together with the accounts receiving the charges of a
+/// transfer: the fee, auction and royalty accounts. `transfer_internal` reads the balances of
+/// these accounts from the staged updates, so all of them must be present there.
+fn stage_balances(
+    balances: &impl Balances,
+    stats: &TokenConfig,
+    accounts: impl IntoIterator<Item = AccountInternal>,
+) -> LocalBalances {
+    let royalty_to = stats.royalty.map(|(account, _)| account.into());
+    accounts
+        .into_iter()
+        .chain([stats.fee_to_account(), auction_account()])
+        .chain(royalty_to)
+        .map(|account| (account, balances.balance_of(&account)))
+        .collect()
+}
+
+/// Moves the `tip` from the `from` account directly into the auction pool.
+fn stage_tip(
+    updates: &mut LocalBalances,
+    from: AccountInternal,
+    tip: Tokens128,
+) -> Result<(), TxError> {
+    let balance = updates.balance_of(&from);
+    let updated_from_balance = (balance - tip).ok_or(TxError::InsufficientFunds { balance })?;
+    updates.insert(from, updated_from_balance);
+
+    let updated_auction_balance =
+        (updates.balance_of(&auction_account()) + tip).ok_or(TxError::AmountOverflow)?;
+    updates.insert(auction_account(), updated_auction_balance);
+
+    Ok(())
 }
 
 pub(crate) fn transfer_internal(
@@ -55,11 +287,25 @@ pub(crate) fn transfer_internal(
     fee: Tokens128,
     fee_to: AccountInternal,
     auction_fee_ratio: FeeRatio,
-) -> Result<(), TxError> {
+) -> Result<TransferCharges, TxError> {
     if amount.is_zero() {
         return Err(TxError::AmountTooSmall);
     }
 
+    if OutgoingDisabledAccounts::contains(&from) {
+        return Err(TxError::OutgoingDisabled);
+    }
+
+    // The auction rewards are disbursed in one batch, which must not be failed by a frozen bidder.
+    if from != auction_account() {
+        FrozenAccounts::check_transfer(&from, &to)?;
+    }
+
+    let (royalty_to, royalty) = royalty_for(from, to, amount).unwrap_or((fee_to, Tokens128::ZERO));
+    // The burn is taken from what is left after the royalty, so together they never exceed the
+    // amount.
+    let burned = burn_for(from, amount).min(amount.saturating_sub(royalty));
+
     // We use `updates` structure because sometimes from or to can be equal to fee_to or even to
     // auction_account, so we must take a carefull approach.
     let mut updates = LocalBalances::from_iter([
@@ -67,6 +313,7 @@ pub(crate) fn transfer_internal(
         (to, balances.balance_of(&to)),
         (fee_to, balances.balance_of(&fee_to)),
         (auction_account(), balances.balance_of(&auction_account())),
+        (royalty_to, balances.balance_of(&royalty_to)),
     ]);
 
     // If `amount + fee` overflows max `Tokens128` value, the balance cannot be larger than this
@@ -81,10 +328,90 @@ pub(crate) fn transfer_internal(
         })?;
     updates.insert(from, updated_from_balance);
 
-    let updated_to_balance = (updates.balance_of(&to) + amount).ok_or(TxError::AmountOverflow)?;
+    // The royalty and the burn never exceed the amount, so the value is conserved exactly.
+    let received = amount.saturating_sub(royalty).saturating_sub(burned);
+    let updated_to_balance = (updates.balance_of(&to) + received).ok_or(TxError::AmountOverflow)?;
     updates.insert(to, updated_to_balance);
 
-    let (owner_fee, auction_fee) = auction_fee_ratio.get_value(fee);
+    let updated_royalty_balance =
+        (updates.balance_of(&royalty_to) + royalty).ok_or(TxError::AmountOverflow)?;
+    updates.insert(royalty_to, updated_royalty_balance);
+
+    let fee_split = distribute_fee(&mut updates, fee, fee_to, auction_fee_ratio)?;
+
+    check_storage_growth(balances, &updates)?;
+
+    // At this point all the checks are done and no further errors are possible, so we modify the
+    // canister state only at this point.
+    balances.apply_updates(updates.list_balances(0, usize::MAX));
+
+    Ok(TransferCharges {
+        fee_split,
+        royalty,
+        burned,
+    })
+}
+
+/// Returns the royalty account and the royalty charged on the transfer, if any. Transfers to and
+/// from the royalty account and the payouts from the auction, escrow and vesting accounts are
+/// exempt.
+fn royalty_for(
+    from: AccountInternal,
+    to: AccountInternal,
+    amount: Tokens128,
+) -> Option<(AccountInternal, Tokens128)> {
+    let (royalty_to, royalty) = TokenConfig::get_stable().royalty(amount)?;
+    let exempt = [
+        royalty_to,
+        auction_account(),
+        escrow_account(),
+        vesting_account(),
+    ]
+    .contains(&from)
+        || to == royalty_to;
+    (!exempt && !royalty.is_zero()).then_some((royalty_to, royalty))
+}
+
+/// Returns the amount burned on the transfer. The payouts from the auction and escrow accounts are
+/// exempt, as the tokens were already charged when they were paid in, and so are the releases of
+/// the vested tokens. Claims do not go through the transfer at all and are never burned.
+fn burn_for(from: AccountInternal, amount: Tokens128) -> Tokens128 {
+    if [auction_account(), escrow_account(), vesting_account()].contains(&from) {
+        return Tokens128::ZERO;
+    }
+
+    TokenConfig::get_stable().transfer_burn(amount)
+}
+
+/// Debits the `fee` from the `payer` and distributes it between the `fee_to` account and the
+/// auction pool. Returns the applied split of the fee.
+fn charge_fee(
+    updates: &mut LocalBalances,
+    payer: AccountInternal,
+    fee: Tokens128,
+    fee_to: AccountInternal,
+    auction_fee_ratio: FeeRatio,
+) -> Result<FeeSplit, TxError> {
+    let balance = updates.balance_of(&payer);
+    let updated_payer_balance = (balance - fee).ok_or(TxError::InsufficientFunds { balance })?;
+    updates.insert(payer, updated_payer_balance);
+
+    distribute_fee(updates, fee, fee_to, auction_fee_ratio)
+}
+
+fn distribute_fee(
+    updates: &mut LocalBalances,
+    fee: Tokens128,
+    fee_to: AccountInternal,
+    auction_fee_ratio: FeeRatio,
+) -> Result<FeeSplit, TxError> {
+    let (mut owner_fee, mut auction_fee) = auction_fee_ratio.get_value(fee);
+    if TokenConfig::get_stable().is_auction_dust_sinking_enabled() {
+        // The flushed dust is a part of the owner fee, as `get_value` rounds the auction fee down.
+        let flushed_dust = AuctionDust::accumulate(fee, auction_fee_ratio).min(owner_fee);
+        owner_fee = owner_fee.saturating_sub(flushed_dust);
+        auction_fee = (auction_fee + flushed_dust).ok_or(TxError::AmountOverflow)?;
+    }
 
     let updated_fee_to_balance =
         (updates.balance_of(&fee_to) + owner_fee).ok_or(TxError::AmountOverflow)?;
@@ -94,9 +421,14 @@ pub(crate) fn transfer_internal(
         (updates.balance_of(&auction_account()) + auction_fee).ok_or(TxError::AmountOverflow)?;
     updates.insert(auction_account(), updated_auction_balance);
 
-    // At this point all the checks are done and no further errors are possible, so we modify the
-    // canister state only at this point.
-    balances.apply_updates(updates.list_balances(0, usize::MAX));
+    Ok((owner_fee, auction_fee))
+}
+
+fn validate_tags(transfer_args: &TransferArgs) -> Result<(), TxError> {
+    let tags = transfer_args.tags.as_deref().unwrap_or_default();
+    if tags.len() > MAX_TRANSFER_TAGS || tags.iter().any(|tag| tag.len() > MAX_TAG_LENGTH) {
+        return Err(TxError::InvalidTags);
+    }
 
     Ok(())
 }
@@ -106,35 +438,31 @@ fn validate_and_get_tx_ts(caller: Principal, transfer_args: &TransferArgs) -> Re
     let from = AccountInternal::new(caller, transfer_args.from_subaccount);
     let to = transfer_args.to.into();
 
+    // The deadline takes precedence over the `created_at_time` checks.
+    if let Some(valid_until) = transfer_args.valid_until {
+        if valid_until < now {
+            return Err(TxError::Expired { valid_until });
+        }
+    }
+
     let created_at_time = match transfer_args.created_at_time {
         Some(created_at_time) => {
-            if now.saturating_sub(created_at_time) > TX_WINDOW {
+            let stats = TokenConfig::get_stable();
+            let tx_window = stats.tx_window_nanos();
+            if now.saturating_sub(created_at_time) > tx_window {
                 return Err(TxError::TooOld {
-                    allowed_window_nanos: TX_WINDOW,
+                    allowed_window_nanos: tx_window,
                 });
             }
 
-            if created_at_time.saturating_sub(now) > PERMITTED_DRIFT {
+            if created_at_time.saturating_sub(now) > stats.permitted_drift_nanos() {
                 return Err(TxError::CreatedInFuture { ledger_time: now });
             }
 
-            let txs = LedgerData::list_transactions();
-            for tx in txs.iter().rev() {
-                if now.saturating_sub(tx.timestamp) > TX_WINDOW + PERMITTED_DRIFT {
-                    break;
-                }
-
-                if tx.timestamp == created_at_time
-                    && AccountInternal::from(tx.from) == from
-                    && AccountInternal::from(tx.to) == to
-                    && tx.memo == transfer_args.memo
-                    && tx.amount == transfer_args.amount
-                    && tx.fee == transfer_args.fee.unwrap_or(tx.fee)
-                {
-                    return Err(TxError::Duplicate {
-                        duplicate_of: tx.index,
-                    });
-                }
+            if let Some(duplicate_of) =
+                LedgerData::find_duplicate(from, to, transfer_args, created_at_time)
+            {
+                return Err(TxError::Duplicate { duplicate_of });
             }
 
             created_at_time
@@ -146,180 +474,1069 @@ fn validate_and_get_tx_ts(caller: Principal, transfer_args: &TransferArgs) -> Re
     Ok(created_at_time)
 }
 
-pub fn mint(caller: Principal, to: AccountInternal, amount: Tokens128) -> TxReceipt {
+pub fn mint(
+    caller: Principal,
+    to: AccountInternal,
+    amount: Tokens128,
+    memo: Option<Memo>,
+) -> TxReceipt {
     let total_supply = StableBalances.total_supply();
-    if (total_supply + amount).is_none() {
-        // If we allow to mint more then Tokens128::MAX then simple operations such as getting
-        // total supply or token stats will panic, So we add this check to prevent this.
-        return Err(TxError::AmountOverflow);
-    }
+    // If we allow to mint more then Tokens128::MAX then simple operations such as getting
+    // total supply or token stats will panic, So we add this check to prevent this.
+    let total_supply = (total_supply + amount).ok_or(TxError::AmountOverflow)?;
+    check_max_supply(total_supply)?;
 
     let balance = StableBalances.balance_of(&to);
     let new_balance = (balance + amount).ok_or(TxError::AmountOverflow)?;
+    check_storage_growth(
+        &StableBalances,
+        &LocalBalances::from_iter([(to, new_balance)]),
+    )?;
     StableBalances.insert(to, new_balance);
 
-    let id = LedgerData::mint(caller.into(), to, amount);
+    let id = LedgerData::mint(caller.into(), to, amount, memo);
 
     Ok(id.into())
 }
 
+/// Checks that the `total_supply` after a mint is within the configured maximum supply.
+fn check_max_supply(total_supply: Tokens128) -> Result<(), TxError> {
+    match TokenConfig::get_stable().max_supply {
+        Some(max_supply) if total_supply > max_supply => {
+            Err(TxError::MaxSupplyExceeded { max_supply })
+        }
+        _ => Ok(()),
+    }
+}
+
 pub fn mint_test_token(
     caller: CheckedPrincipal<TestNet>,
     to: Principal,
     to_subaccount: Option<Subaccount>,
     amount: Tokens128,
+    memo: Option<Memo>,
 ) -> TxReceipt {
     mint(
         caller.inner(),
         AccountInternal::new(to, to_subaccount),
         amount,
+        memo,
     )
 }
 
-pub fn mint_as_owner(
-    caller: CheckedPrincipal<Owner>,
+/// Mints tokens as one of the minters added by the owner.
+pub fn mint_as_minter(
+    caller: CheckedPrincipal<Minter>,
     to: Principal,
     to_subaccount: Option<Subaccount>,
     amount: Tokens128,
+    memo: Option<Memo>,
 ) -> TxReceipt {
     mint(
         caller.inner(),
         AccountInternal::new(to, to_subaccount),
         amount,
+        memo,
     )
 }
 
-pub fn burn(caller: Principal, from: AccountInternal, amount: Tokens128) -> TxReceipt {
-    let balance = StableBalances.balance_of(&from);
+/// Mints tokens as the owner. Mints to the owner's own subaccounts are deposits to the claim
+/// accounts, which can be claimed with `claim`.
+pub fn mint_as_owner(
+    caller: CheckedPrincipal<Owner>,
+    to: Principal,
+    to_subaccount: Option<Subaccount>,
+    amount: Tokens128,
+    memo: Option<Memo>,
+) -> TxReceipt {
+    let account = AccountInternal::new(to, to_subaccount);
+    let id = mint(caller.inner(), account, amount, memo)?;
 
-    if !amount.is_zero() && balance.is_zero() {
-        return Err(TxError::InsufficientFunds { balance });
+    #[cfg(feature = "claim")]
+    if to == caller.inner() && to_subaccount.is_some() {
+        Claims::record_deposit(account);
     }
 
-    let new_balance = (balance - amount).ok_or(TxError::InsufficientFunds { balance })?;
+    Ok(id)
+}
 
-    if new_balance == Tokens128::ZERO {
-        StableBalances.remove(&from);
-    } else {
-        StableBalances.insert(from, new_balance)
+/// Mints tokens to each of the `mints` targets. All the mints are validated before any balance
+/// is changed, so either all of them are applied or none.
+///
+/// Returns ids of the mint transactions in the order of the `mints`.
+pub fn batch_mint(
+    caller: CheckedPrincipal<Owner>,
+    mints: &[(Principal, Option<Subaccount>, Tokens128)],
+) -> Result<Vec<TxId>, TxError> {
+    let mut total_supply = StableBalances.total_supply();
+    let mut updates = LocalBalances::new();
+    for (to, to_subaccount, amount) in mints {
+        total_supply = (total_supply + *amount).ok_or(TxError::AmountOverflow)?;
+        check_max_supply(total_supply)?;
+
+        let to = AccountInternal::new(*to, *to_subaccount);
+        let balance = updates
+            .get(&to)
+            .unwrap_or_else(|| StableBalances.balance_of(&to));
+        let new_balance = (balance + *amount).ok_or(TxError::AmountOverflow)?;
+        updates.insert(to, new_balance);
     }
 
-    let id = LedgerData::burn(caller.into(), from, amount);
-    Ok(id.into())
+    check_storage_growth(&StableBalances, &updates)?;
+    StableBalances.apply_updates(updates.list_balances(0, usize::MAX));
+
+    Ok(mints
+        .iter()
+        .map(|(to, to_subaccount, amount)| {
+            LedgerData::mint(
+                caller.inner().into(),
+                AccountInternal::new(*to, *to_subaccount),
+                *amount,
+                None,
+            )
+        })
+        .collect())
 }
 
-pub fn burn_own_tokens(from_subaccount: Option<Subaccount>, amount: Tokens128) -> TxReceipt {
-    let caller = ic::caller();
-    burn(
-        caller,
-        AccountInternal::new(caller, from_subaccount),
-        amount,
-    )
-}
+/// Credit the `entries` balances migrated from another token canister. Imported balances are not
+/// recorded in the ledger. If `chunk_index` is given, the same chunk cannot be imported twice.
+///
+/// Returns the total amount of tokens imported so far.
+pub fn import_balances_chunk(
+    _caller: CheckedPrincipal<Owner>,
+    entries: Vec<(Account, Tokens128)>,
+    chunk_index: Option<u64>,
+) -> Result<Tokens128, TxError> {
+    let mut migration = MigrationState::get_stable();
+    if let Some(chunk_index) = chunk_index {
+        if migration.is_chunk_imported(chunk_index) {
+            return Err(TxError::ChunkAlreadyImported { chunk_index });
+        }
+    }
 
-pub fn burn_as_owner(
-    caller: CheckedPrincipal<Owner>,
-    from: Principal,
-    from_subaccount: Option<Subaccount>,
-    amount: Tokens128,
-) -> TxReceipt {
-    burn(
-        caller.inner(),
-        AccountInternal::new(from, from_subaccount),
-        amount,
-    )
-}
+    let mut total_supply = StableBalances.total_supply();
+    let mut imported_total = migration.imported_total;
+    let mut updates = LocalBalances::new();
+    for (account, amount) in entries {
+        if amount.is_zero() {
+            continue;
+        }
 
-#[cfg(feature = "claim")]
-pub fn get_claim_subaccount(
-    claimer: Principal,
-    claimer_subaccount: Option<Subaccount>,
-) -> Subaccount {
-    let account_id = AccountIdentifier::new(
-        claimer.into(),
-        Some(SubaccountIdentifier(claimer_subaccount.unwrap_or_default())),
-    );
+        let account = AccountInternal::from(account);
+        let balance = updates
+            .get(&account)
+            .unwrap_or_else(|| StableBalances.balance_of(&account));
 
-    account_id.to_address()
+        total_supply = (total_supply + amount).ok_or(TxError::AmountOverflow)?;
+        imported_total = (imported_total + amount).ok_or(TxError::AmountOverflow)?;
+        updates.insert(account, (balance + amount).ok_or(TxError::AmountOverflow)?);
+    }
+
+    check_max_supply(total_supply)?;
+    check_storage_growth(&StableBalances, &updates)?;
+    StableBalances.apply_updates(updates.list_balances(0, usize::MAX));
+
+    migration.imported_total = imported_total;
+    migration.imported_chunks.extend(chunk_index);
+    MigrationState::set_stable(migration);
+
+    // The import is not recorded in the ledger, so the state is certified here.
+    certify_state();
+
+    Ok(imported_total)
 }
 
-#[cfg(feature = "claim")]
-pub fn claim(holder: Principal, subaccount: Option<Subaccount>) -> TxReceipt {
-    let caller = canister_sdk::ic_kit::ic::caller();
-    let claim_subaccount = get_claim_subaccount(caller, subaccount);
-    let claim_account = AccountInternal::new(holder, Some(claim_subaccount));
-    let amount = StableBalances.balance_of(&claim_account);
-    if amount.is_zero() {
-        return Err(TxError::NothingToClaim);
+/// Move the whole balance of the dormant `account` to the owner's default account. The account is
+/// dormant if the latest transaction involving it is older than the configured dormancy period.
+///
+/// The history is not preserved over upgrades and its oldest records are removed, so an account
+/// without transactions in the history may still be active. Such accounts are never considered
+/// dormant. The accounts holding the tokens of the auction, escrows, timelocks and vestings and
+/// the frozen accounts cannot be reclaimed either.
+pub fn reclaim_dormant(caller: CheckedPrincipal<Owner>, account: AccountInternal) -> TxReceipt {
+    let stats = TokenConfig::get_stable();
+    let period = stats
+        .dormancy_period_nanos
+        .ok_or(TxError::DormancyReclaimDisabled)?;
+
+    if [auction_account(), escrow_account(), vesting_account()].contains(&account) {
+        return Err(TxError::ReservedAccount);
     }
 
-    let stats = TokenConfig::get_stable();
-    transfer_internal(
-        &mut StableBalances,
-        claim_account,
-        caller.into(),
-        amount,
-        0.into(),
-        stats.owner.into(),
-        FeeRatio::default(),
-    )?;
-    let id = LedgerData::claim(claim_account, AccountInternal::new(caller, None), amount);
-    Ok(id.into())
-}
+    if FrozenAccounts::contains(&account.owner) {
+        return Err(TxError::AccountFrozen);
+    }
 
-pub fn batch_transfer(
-    from_subaccount: Option<Subaccount>,
-    transfers: Vec<BatchTransferArgs>,
-    auction_fee_ratio: f64,
-) -> Result<Vec<TxId>, TxError> {
-    let caller = canister_sdk::ic_kit::ic::caller();
-    let from = AccountInternal::new(caller, from_subaccount);
+    let last_activity = LedgerData::last_activity(account).unwrap_or_else(ic::time);
+    if ic::time().saturating_sub(last_activity) <= period {
+        return Err(TxError::AccountNotDormant { last_activity });
+    }
 
-    let stats = TokenConfig::get_stable();
-    let (fee, fee_to) = stats.fee_info();
+    let amount = StableBalances.balance_of(&account);
+    if amount.is_zero() {
+        return Err(TxError::InsufficientFunds { balance: amount });
+    }
 
-    batch_transfer_internal(
-        from,
-        &transfers,
-        &mut StableBalances,
-        fee,
-        fee_to,
-        auction_fee_ratio,
-    )?;
-    let id = LedgerData::batch_transfer(from, transfers, fee);
-    Ok(id)
+    let treasury = AccountInternal::new(stats.owner, None);
+    if treasury == account {
+        return Err(TxError::SelfTransfer);
+    }
+
+    move_balance(&mut StableBalances, account, treasury, amount)?;
+    let id = LedgerData::reclaim(caller.inner(), account, treasury, amount);
+    Ok(id.into())
 }
 
-pub(crate) fn batch_transfer_internal(
-    from: AccountInternal,
-    transfers: &Vec<BatchTransferArgs>,
+/// Moves the `amount` from the `from` to the `to` account without any charges.
+fn move_balance(
     balances: &mut impl Balances,
-    fee: Tokens128,
-    fee_to: Principal,
-    auction_fee_ratio: f64,
+    from: AccountInternal,
+    to: AccountInternal,
+    amount: Tokens128,
 ) -> Result<(), TxError> {
-    let fee_to = AccountInternal::new(fee_to, None);
-    let auction_acc = auction_account();
-
     let mut updates = LocalBalances::from_iter([
         (from, balances.balance_of(&from)),
-        (fee_to, balances.balance_of(&fee_to)),
-        (auction_acc, balances.balance_of(&auction_acc)),
+        (to, balances.balance_of(&to)),
     ]);
 
-    for transfer in transfers {
-        let receiver = transfer.receiver.into();
-        updates.insert(receiver, balances.balance_of(&receiver));
+    let balance = updates.balance_of(&from);
+    let updated_from_balance = (balance - amount).ok_or(TxError::InsufficientFunds { balance })?;
+    updates.insert(from, updated_from_balance);
+
+    let updated_to_balance = (updates.balance_of(&to) + amount).ok_or(TxError::AmountOverflow)?;
+    updates.insert(to, updated_to_balance);
+
+    check_storage_growth(balances, &updates)?;
+    balances.apply_updates(updates.list_balances(0, usize::MAX));
+
+    Ok(())
+}
+
+/// Transfers the tokens drawn from several subaccounts of the caller to `to`. Each source is
+/// debited exactly by its amount, and `to` receives the total minus a single fee. The fee is
+/// charged from the largest source. Every source is recorded as a separate transaction, which is
+/// validated and deduplicated in the same way as a single transfer.
+pub fn multi_source_transfer(
+    args: &MultiSourceTransferArgs,
+    auction_fee_ratio: f64,
+) -> Result<Vec<TxId>, TxError> {
+    let caller = ic::caller();
+    let sources = &args.sources;
+    let to = AccountInternal::from(args.to);
+    let stats = TokenConfig::get_stable();
+    check_transferable(&stats)?;
+    for (_, amount) in sources {
+        check_zero_amount(&stats, *amount)?;
+    }
+
+    let total = sources
+        .iter()
+        .try_fold(Tokens128::ZERO, |total, (_, amount)| total + *amount)
+        .ok_or(TxError::AmountOverflow)?;
+    let fee = stats.effective_fee(total);
+    check_fee(fee, args.fee, args.max_fee)?;
+    let fee_to = stats.fee_to_account();
+    let fee_source = sources
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, (_, amount))| *amount)
+        .map(|(index, _)| index)
+        .ok_or(TxError::AmountTooSmall)?;
+
+    TokenAwareCanisters::check_recipient(&to)?;
+
+    // Arguments, fees and timestamps of the transactions to record for each source.
+    let mut legs = Vec::with_capacity(sources.len());
+    for (index, (subaccount, amount)) in sources.iter().enumerate() {
+        let from = AccountInternal::new(caller, *subaccount);
+        if from == to {
+            return Err(TxError::SelfTransfer);
+        }
+
+        let leg_fee = if index == fee_source {
+            fee
+        } else {
+            Tokens128::ZERO
+        };
+        let leg_amount = (*amount - leg_fee).ok_or(TxError::AmountTooSmall)?;
+        let leg_args = args.leg(*subaccount, leg_amount);
+        validate_tags(&leg_args)?;
+        let created_at_time = validate_and_get_tx_ts(caller, &leg_args)?;
+        legs.push((from, leg_args, leg_fee, created_at_time));
+    }
+
+    let sources = legs.iter().map(|(from, ..)| *from);
+    let mut updates = stage_balances(&StableBalances, &stats, sources.chain([to]));
+    let mut charges = Vec::with_capacity(legs.len());
+    for (from, leg_args, leg_fee, _) in &legs {
+        charges.push(transfer_internal(
+            &mut updates,
+            *from,
+            to,
+            leg_args.amount,
+            *leg_fee,
+            fee_to,
+            FeeRatio::new(auction_fee_ratio),
+        )?);
+    }
+
+    check_storage_growth(&StableBalances, &updates)?;
+    StableBalances.apply_updates(updates.list_balances(0, usize::MAX));
+
+    Ok(legs
+        .into_iter()
+        .zip(charges)
+        .map(|((from, leg_args, fee, created_at_time), charges)| {
+            LedgerData::transfer(from, to, &leg_args, fee, created_at_time, charges)
+        })
+        .collect())
+}
+
+/// Sets the allowance of the `spender` over the caller's account to `amount`, replacing the
+/// previous one. A zero `amount` revokes the allowance. The fee is paid by the owner.
+pub fn icrc2_approve(args: &ApproveArgs, auction_fee_ratio: f64) -> TxReceipt {
+    let owner = AccountInternal::new(ic::caller(), args.from_subaccount);
+    let spender = AccountInternal::from(args.spender);
+    if owner == spender {
+        return Err(TxError::SelfTransfer);
+    }
+
+    if let Some(expires_at) = args.expires_at {
+        if expires_at <= ic::time() {
+            return Err(TxError::ApprovalExpired { expires_at });
+        }
+    }
+
+    set_allowance(
+        owner,
+        spender,
+        args.amount,
+        args.expires_at,
+        args.fee,
+        auction_fee_ratio,
+    )
+}
+
+/// Increases the allowance of the `spender` over the caller's `from_subaccount` by `delta`,
+/// keeping its expiration time. Unlike `icrc2_approve`, the result doesn't depend on whether the
+/// spender has used the previous allowance in the meantime.
+pub fn increase_allowance(
+    from_subaccount: Option<Subaccount>,
+    spender: Account,
+    delta: Tokens128,
+    auction_fee_ratio: f64,
+) -> TxReceipt {
+    let owner = AccountInternal::new(ic::caller(), from_subaccount);
+    let spender = AccountInternal::from(spender);
+    if owner == spender {
+        return Err(TxError::SelfTransfer);
+    }
+
+    let (allowance, expires_at) = Allowances::find(owner, spender)
+        .map(|allowance| (allowance.amount, allowance.expires_at))
+        .unwrap_or_default();
+    let amount = (allowance + delta).ok_or(TxError::AmountOverflow)?;
+    set_allowance(owner, spender, amount, expires_at, None, auction_fee_ratio)
+}
+
+/// Decreases the allowance of the `spender` over the caller's `from_subaccount` by `delta`,
+/// keeping its expiration time. The allowance is removed if `delta` is not less than the
+/// remaining allowance.
+pub fn decrease_allowance(
+    from_subaccount: Option<Subaccount>,
+    spender: Account,
+    delta: Tokens128,
+    auction_fee_ratio: f64,
+) -> TxReceipt {
+    let owner = AccountInternal::new(ic::caller(), from_subaccount);
+    let spender = AccountInternal::from(spender);
+    if owner == spender {
+        return Err(TxError::SelfTransfer);
+    }
+
+    let (allowance, expires_at) = Allowances::find(owner, spender)
+        .map(|allowance| (allowance.amount, allowance.expires_at))
+        .unwrap_or_default();
+    let amount = (allowance - delta).unwrap_or(Tokens128::ZERO);
+    set_allowance(owner, spender, amount, expires_at, None, auction_fee_ratio)
+}
+
+fn set_allowance(
+    owner: AccountInternal,
+    spender: AccountInternal,
+    amount: Tokens128,
+    expires_at: Option<Timestamp>,
+    requested_fee: Option<Tokens128>,
+    auction_fee_ratio: f64,
+) -> TxReceipt {
+    let stats = TokenConfig::get_stable();
+    // No tokens are moved by the approval, so it's charged as a zero amount transfer.
+    let fee = stats.icrc1_fee();
+    let fee_to = stats.fee_to_account();
+    check_fee(fee, requested_fee, None)?;
+
+    let mut updates = LocalBalances::from_iter([
+        (owner, StableBalances.balance_of(&owner)),
+        (fee_to, StableBalances.balance_of(&fee_to)),
+        (
+            auction_account(),
+            StableBalances.balance_of(&auction_account()),
+        ),
+    ]);
+    let fee_split = charge_fee(
+        &mut updates,
+        owner,
+        fee,
+        fee_to,
+        FeeRatio::new(auction_fee_ratio),
+    )?;
+
+    check_storage_growth(&StableBalances, &updates)?;
+    StableBalances.apply_updates(updates.list_balances(0, usize::MAX));
+    Allowances::set(owner, spender, amount, expires_at);
+
+    let id = LedgerData::approve(owner, spender, amount, fee, fee_split);
+    Ok(id.into())
+}
+
+/// Transfers `amount` from the `from` account using the allowance granted to the caller. The
+/// allowance is decreased by `amount`, while the fee is paid by the spender. A frozen spender
+/// can't use its allowances.
+pub fn icrc2_transfer_from(args: &TransferFromArgs, auction_fee_ratio: f64) -> TxReceipt {
+    let spender = AccountInternal::new(ic::caller(), args.spender_subaccount);
+    let from = AccountInternal::from(args.from);
+    let to = AccountInternal::from(args.to);
+    if from == to {
+        return Err(TxError::SelfTransfer);
+    }
+
+    if FrozenAccounts::contains(&spender.owner) {
+        return Err(TxError::AccountFrozen);
+    }
+
+    TokenAwareCanisters::check_recipient(&to)?;
+
+    let stats = TokenConfig::get_stable();
+    check_transferable(&stats)?;
+
+    let fee = stats.effective_fee(args.amount);
+    let fee_to = stats.fee_to_account();
+    check_fee(fee, args.fee, None)?;
+
+    let (allowance, expires_at) = Allowances::find(from, spender)
+        .map(|allowance| (allowance.amount, allowance.expires_at))
+        .unwrap_or_default();
+    let remaining_allowance =
+        (allowance - args.amount).ok_or(TxError::InsufficientAllowance { allowance })?;
+
+    let mut updates = stage_balances(&StableBalances, &stats, [from, to, spender]);
+    let auction_fee_ratio = FeeRatio::new(auction_fee_ratio);
+    let mut charges = transfer_internal(
+        &mut updates,
+        from,
+        to,
+        args.amount,
+        Tokens128::ZERO,
+        fee_to,
+        auction_fee_ratio,
+    )?;
+    charges.fee_split = charge_fee(&mut updates, spender, fee, fee_to, auction_fee_ratio)?;
+
+    check_storage_growth(&StableBalances, &updates)?;
+    StableBalances.apply_updates(updates.list_balances(0, usize::MAX));
+    Allowances::set(from, spender, remaining_allowance, expires_at);
+
+    let id = LedgerData::transfer_from(spender, from, to, args.amount, fee, charges);
+    Ok(id.into())
+}
+
+/// Locks the `amount` on the escrow account until the escrow is released to the recipient or
+/// refunded to the sender. The fee and the royalty are charged when the tokens are locked, so the
+/// escrow holds the amount without the royalty. Returns id of the escrow.
+pub fn transfer_conditional(
+    caller: CheckedAccount<WithRecipient>,
+    amount: Tokens128,
+    condition: Condition,
+    auction_fee_ratio: f64,
+) -> Result<u64, TxError> {
+    let from = caller.inner();
+    let to = caller.recipient();
+
+    let stats = TokenConfig::get_stable();
+    check_transferable(&stats)?;
+
+    let fee = stats.effective_fee(amount);
+    let charges = transfer_internal(
+        &mut StableBalances,
+        from,
+        escrow_account(),
+        amount,
+        fee,
+        stats.fee_to_account(),
+        FeeRatio::new(auction_fee_ratio),
+    )?;
+    LedgerData::record_transfer(from, escrow_account(), amount, fee, charges);
+
+    let escrowed = amount
+        .saturating_sub(charges.royalty)
+        .saturating_sub(charges.burned);
+    Ok(Escrows::open(from.into(), to.into(), escrowed, condition))
+}
+
+/// Moves the escrowed tokens to the recipient. Only the sender and the arbiter of the escrow can
+/// release it, and only before it expires.
+pub fn release_escrow(id: u64) -> TxReceipt {
+    let escrow = Escrows::get(id)?;
+    if !escrow.can_release(ic::caller()) {
+        return Err(TxError::Unauthorized);
+    }
+
+    if escrow.is_expired() {
+        return Err(TxError::EscrowExpired {
+            expires_at: escrow.expires_at,
+        });
+    }
+
+    settle_escrow(&escrow, escrow.to.into())
+}
+
+/// Returns the escrowed tokens to the sender once the escrow has expired.
+pub fn refund_escrow(id: u64) -> TxReceipt {
+    let escrow = Escrows::get(id)?;
+    if !escrow.is_expired() {
+        return Err(TxError::EscrowNotExpired {
+            expires_at: escrow.expires_at,
+        });
+    }
+
+    settle_escrow(&escrow, escrow.from.into())
+}
+
+fn settle_escrow(escrow: &Escrow, to: AccountInternal) -> TxReceipt {
+    let id = pay_from_escrow_account(to, escrow.amount)?;
+    Escrows::close(escrow.id);
+    Ok(id.into())
+}
+
+/// Moves the `amount` held by the escrow account to `to` without charging a fee.
+fn pay_from_escrow_account(to: AccountInternal, amount: Tokens128) -> Result<TxId, TxError> {
+    let charges = transfer_internal(
+        &mut StableBalances,
+        escrow_account(),
+        to,
+        amount,
+        Tokens128::ZERO,
+        escrow_account(),
+        FeeRatio::default(),
+    )?;
+
+    Ok(LedgerData::record_transfer(
+        escrow_account(),
+        to,
+        amount,
+        Tokens128::ZERO,
+        charges,
+    ))
+}
+
+/// Locks the transferred amount on the escrow account until `release_at`, after which the
+/// recipient can claim it. The fee, the royalty and the tip are charged when the tokens are locked.
+/// The transaction is recorded and deduplicated as a transfer to the escrow account.
+/// Returns id of the timelock.
+pub fn transfer_timelocked(
+    caller: CheckedAccount<WithRecipient>,
+    transfer: &TransferArgs,
+    release_at: Timestamp,
+    auction_fee_ratio: f64,
+) -> Result<u64, TxError> {
+    let from = caller.inner();
+    let to = caller.recipient();
+    let args = TransferArgs {
+        to: escrow_account().into(),
+        ..transfer.clone()
+    };
+    let (fee, created_at_time) = validate_transfer(from, &args)?;
+    let (_, charges) = apply_transfer(
+        from,
+        escrow_account(),
+        &args,
+        fee,
+        created_at_time,
+        auction_fee_ratio,
+    )?;
+
+    let locked = transfer
+        .amount
+        .saturating_sub(charges.royalty)
+        .saturating_sub(charges.burned);
+    Ok(Timelocks::open(from.into(), to.into(), locked, release_at))
+}
+
+/// Moves the tokens of all the released timelocks sent to the caller to their recipient accounts.
+/// Either all the released timelocks are paid out or none of them. Returns the ids of the
+/// transactions.
+pub fn claim_timelocked() -> Result<Vec<TxId>, TxError> {
+    let (released, locked): (Vec<Timelock>, Vec<Timelock>) = Timelocks::for_recipient(ic::caller())
+        .into_iter()
+        .partition(Timelock::is_released);
+
+    if released.is_empty() {
+        return Err(match locked.iter().map(|lock| lock.release_at).min() {
+            Some(release_at) => TxError::TimelockNotReleased { release_at },
+            None => TxError::TimelockNotFound,
+        });
+    }
+
+    let stats = TokenConfig::get_stable();
+    let recipients = released.iter().map(|lock| AccountInternal::from(lock.to));
+    let mut updates = stage_balances(
+        &StableBalances,
+        &stats,
+        [escrow_account()].into_iter().chain(recipients),
+    );
+
+    let mut payouts = Vec::with_capacity(released.len());
+    for lock in &released {
+        let charges = transfer_internal(
+            &mut updates,
+            escrow_account(),
+            lock.to.into(),
+            lock.amount,
+            Tokens128::ZERO,
+            escrow_account(),
+            FeeRatio::default(),
+        )?;
+        payouts.push(charges);
+    }
+
+    check_storage_growth(&StableBalances, &updates)?;
+    StableBalances.apply_updates(updates.list_balances(0, usize::MAX));
+
+    let ids = released
+        .iter()
+        .zip(payouts)
+        .map(|(lock, charges)| {
+            Timelocks::close(lock.id);
+            LedgerData::record_transfer(
+                escrow_account(),
+                lock.to.into(),
+                lock.amount,
+                Tokens128::ZERO,
+                charges,
+            )
+        })
+        .collect();
+
+    Ok(ids)
+}
+
+/// Returns the timelocked tokens to the sender. Can be called only by the sender, before the
+/// tokens are released.
+pub fn cancel_timelocked(id: u64) -> TxReceipt {
+    let lock = Timelocks::get(id)?;
+    if lock.from.owner != ic::caller() {
+        return Err(TxError::Unauthorized);
+    }
+
+    if lock.is_released() {
+        return Err(TxError::TimelockReleased {
+            release_at: lock.release_at,
+        });
+    }
+
+    let id = pay_from_escrow_account(lock.from.into(), lock.amount)?;
+    Timelocks::close(lock.id);
+    Ok(id.into())
+}
+
+/// Mints the `total` to the vesting account and creates a schedule releasing it to the
+/// `beneficiary`. Returns id of the schedule.
+pub fn create_vesting(
+    caller: CheckedPrincipal<Owner>,
+    beneficiary: Account,
+    total: Tokens128,
+    start: Timestamp,
+    cliff: u64,
+    duration: u64,
+) -> Result<u64, TxError> {
+    if duration == 0 || cliff > duration {
+        return Err(TxError::InvalidVestingSchedule);
+    }
+
+    if total.is_zero() {
+        return Err(TxError::AmountTooSmall);
+    }
+
+    mint(caller.inner(), vesting_account(), total, None)?;
+    Ok(Vestings::create(beneficiary, total, start, cliff, duration))
+}
+
+/// Transfers the tokens vested so far but not released yet to the beneficiary of the schedule.
+/// Can be called only by the beneficiary.
+pub fn release_vested(id: u64) -> TxReceipt {
+    let schedule = Vestings::get(id)?;
+    if schedule.beneficiary.owner != ic::caller() {
+        return Err(TxError::Unauthorized);
+    }
+
+    let amount = schedule.releasable(ic::time());
+    if amount.is_zero() {
+        return Err(TxError::NothingToRelease);
+    }
+
+    let to = schedule.beneficiary.into();
+    let charges = transfer_internal(
+        &mut StableBalances,
+        vesting_account(),
+        to,
+        amount,
+        Tokens128::ZERO,
+        vesting_account(),
+        FeeRatio::default(),
+    )?;
+    Vestings::record_release(id, amount);
+
+    let id = LedgerData::record_transfer(vesting_account(), to, amount, Tokens128::ZERO, charges);
+    Ok(id.into())
+}
+
+pub fn burn(
+    caller: Principal,
+    from: AccountInternal,
+    amount: Tokens128,
+    memo: Option<Memo>,
+) -> TxReceipt {
+    debit_burned(from, amount)?;
+
+    let id = LedgerData::burn(caller.into(), from, amount, memo);
+    Ok(id.into())
+}
+
+/// Burns `amount` of the caller's tokens and records the redemption with the given reference, so
+/// an off-chain processor can reconcile the payout.
+pub fn redeem(amount: Tokens128, redemption_ref: Vec<u8>) -> TxReceipt {
+    if amount.is_zero() {
+        return Err(TxError::AmountTooSmall);
+    }
+
+    if redemption_ref.is_empty() || redemption_ref.len() > MAX_REDEMPTION_REF_LENGTH {
+        return Err(TxError::InvalidRedemptionRef);
+    }
+
+    let from = AccountInternal::new(ic::caller(), None);
+    debit_burned(from, amount)?;
+
+    let id = LedgerData::redeem(from, amount, redemption_ref);
+    Ok(id.into())
+}
+
+fn debit_burned(from: AccountInternal, amount: Tokens128) -> Result<(), TxError> {
+    let balance = StableBalances.balance_of(&from);
+
+    if !amount.is_zero() && balance.is_zero() {
+        return Err(TxError::InsufficientFunds { balance });
+    }
+
+    let new_balance = (balance - amount).ok_or(TxError::InsufficientFunds { balance })?;
+
+    if new_balance == Tokens128::ZERO {
+        StableBalances.remove(&from);
+    } else {
+        StableBalances.insert(from, new_balance)
+    }
+
+    Ok(())
+}
+
+/// Burns tokens from each of the `burns` accounts. All the balances are checked before any of
+/// them is changed, so either all the burns are applied or none.
+///
+/// Returns ids of the burn transactions in the order of the `burns`.
+pub fn batch_burn(
+    caller: CheckedPrincipal<Owner>,
+    burns: &[(Account, Tokens128)],
+) -> Result<Vec<TxId>, TxError> {
+    let mut updates = LocalBalances::new();
+    for (from, amount) in burns {
+        let from = AccountInternal::from(*from);
+        let balance = updates
+            .get(&from)
+            .unwrap_or_else(|| StableBalances.balance_of(&from));
+        if !amount.is_zero() && balance.is_zero() {
+            return Err(TxError::InsufficientFunds { balance });
+        }
+
+        let new_balance = (balance - *amount).ok_or(TxError::InsufficientFunds { balance })?;
+        updates.insert(from, new_balance);
+    }
+
+    for (from, new_balance) in updates.list_balances(0, usize::MAX) {
+        if new_balance == Tokens128::ZERO {
+            StableBalances.remove(&from);
+        } else {
+            StableBalances.insert(from, new_balance)
+        }
+    }
+
+    Ok(burns
+        .iter()
+        .map(|(from, amount)| {
+            LedgerData::burn(caller.inner().into(), (*from).into(), *amount, None)
+        })
+        .collect())
+}
+
+pub fn burn_own_tokens(
+    from_subaccount: Option<Subaccount>,
+    amount: Tokens128,
+    memo: Option<Memo>,
+) -> TxReceipt {
+    let caller = ic::caller();
+    burn(
+        caller,
+        AccountInternal::new(caller, from_subaccount),
+        amount,
+        memo,
+    )
+}
+
+pub fn burn_as_owner(
+    caller: CheckedPrincipal<Owner>,
+    from: Principal,
+    from_subaccount: Option<Subaccount>,
+    amount: Tokens128,
+    memo: Option<Memo>,
+) -> TxReceipt {
+    burn(
+        caller.inner(),
+        AccountInternal::new(from, from_subaccount),
+        amount,
+        memo,
+    )
+}
+
+#[cfg(feature = "claim")]
+pub fn get_claim_subaccount(
+    claimer: Principal,
+    claimer_subaccount: Option<Subaccount>,
+) -> Subaccount {
+    let account_id = AccountIdentifier::new(
+        claimer.into(),
+        Some(SubaccountIdentifier(claimer_subaccount.unwrap_or_default())),
+    );
+
+    account_id.to_address()
+}
+
+#[cfg(feature = "claim")]
+pub fn claim(holder: Principal, subaccount: Option<Subaccount>) -> TxReceipt {
+    let caller = canister_sdk::ic_kit::ic::caller();
+    let claim_subaccount = get_claim_subaccount(caller, subaccount);
+    let claim_account = AccountInternal::new(holder, Some(claim_subaccount));
+    let amount = StableBalances.balance_of(&claim_account);
+    if amount.is_zero() {
+        return Err(TxError::NothingToClaim);
+    }
+
+    let stats = TokenConfig::get_stable();
+    if Claims::is_expired(claim_account, stats.claim_expiry_nanos()) {
+        return Err(TxError::ClaimExpired);
+    }
+
+    let to = AccountInternal::new(caller, None);
+    if OutgoingDisabledAccounts::contains(&claim_account) {
+        return Err(TxError::OutgoingDisabled);
+    }
+    FrozenAccounts::check_transfer(&claim_account, &to)?;
+
+    // The tokens were charged when they were deposited to the claim account, so the claim itself
+    // is free of any royalty or burn.
+    move_balance(&mut StableBalances, claim_account, to, amount)?;
+    Claims::remove(claim_account);
+    let id = LedgerData::claim(claim_account, to, amount, TransferCharges::default());
+    Ok(id.into())
+}
+
+/// Moves the balances of the expired claims back to the default accounts of their holders.
+/// Returns the total amount swept back. If a balance can't be moved, e.g. because the storage is
+/// full, the sweep stops and the remaining claims are kept.
+#[cfg(feature = "claim")]
+pub fn reclaim_expired_claims(caller: CheckedPrincipal<Owner>) -> Result<Tokens128, TxError> {
+    let expired = Claims::expired(TokenConfig::get_stable().claim_expiry_nanos());
+
+    let mut total = Tokens128::ZERO;
+    for claim_account in expired {
+        let amount = StableBalances.balance_of(&claim_account);
+        if !amount.is_zero() {
+            let holder = AccountInternal::new(claim_account.owner, None);
+            move_balance(&mut StableBalances, claim_account, holder, amount)?;
+            LedgerData::reclaim(caller.inner(), claim_account, holder, amount);
+            total = (total + amount).ok_or(TxError::AmountOverflow)?;
+        }
+
+        Claims::remove(claim_account);
+    }
+
+    Ok(total)
+}
+
+/// Transfers `amount` of the caller's tokens to the claim account of the account identifier with
+/// the `to` address. The tokens are held under the caller's principal, so the owner of the account
+/// identifier claims them with `claim`, giving the caller as the holder.
+#[cfg(feature = "claim")]
+pub fn transfer_to_account_id(
+    from_subaccount: Option<Subaccount>,
+    to: Subaccount,
+    amount: Tokens128,
+    fee: Option<Tokens128>,
+    auction_fee_ratio: f64,
+) -> TxReceipt {
+    let claim_account = AccountInternal::new(ic::caller(), Some(to));
+    let transfer = TransferArgs {
+        from_subaccount,
+        to: claim_account.into(),
+        amount,
+        fee,
+        max_fee: None,
+        memo: None,
+        created_at_time: None,
+        valid_until: None,
+        tags: None,
+        tip: None,
+    };
+
+    let caller = CheckedAccount::with_recipient(claim_account, from_subaccount)?;
+    let id = is20_transfer(caller, &transfer, auction_fee_ratio)?;
+    Claims::record_deposit(claim_account);
+    Ok(id)
+}
+
+pub fn batch_transfer(
+    from_subaccount: Option<Subaccount>,
+    transfers: Vec<BatchTransferArgs>,
+    auction_fee_ratio: f64,
+) -> Result<Vec<TxId>, TxError> {
+    let caller = canister_sdk::ic_kit::ic::caller();
+    let from = AccountInternal::new(caller, from_subaccount);
+
+    let stats = TokenConfig::get_stable();
+    for transfer in &transfers {
+        check_zero_amount(&stats, transfer.amount)?;
+    }
+
+    check_transferable(&stats)?;
+
+    let _guard = BatchGuard::enter(caller, stats.max_concurrent_batches())?;
+    validate_fee_overrides(caller, &transfers, &stats)?;
+
+    let charges = batch_transfer_internal(
+        from,
+        &transfers,
+        &mut StableBalances,
+        &stats,
+        auction_fee_ratio,
+    )?;
+
+    let transfers = transfers
+        .into_iter()
+        .zip(charges)
+        .map(|(transfer, charges)| {
+            let fee = transfer.fee(&stats);
+            (transfer, fee, charges)
+        })
+        .collect();
+    let id = LedgerData::batch_transfer(from, transfers);
+    Ok(id)
+}
+
+/// Applies each of the `transfers` independently. Failed transfers are skipped, while the
+/// successful ones are applied and recorded. If zero transfers are rejected, zero amount transfers
+/// fail with `TxError::AmountTooSmall`.
+pub fn batch_transfer_best_effort(
+    from_subaccount: Option<Subaccount>,
+    transfers: Vec<BatchTransferArgs>,
+    auction_fee_ratio: f64,
+) -> Result<Vec<Result<TxId, TxError>>, TxError> {
+    let caller = ic::caller();
+    let from = AccountInternal::new(caller, from_subaccount);
+
+    let stats = TokenConfig::get_stable();
+    check_transferable(&stats)?;
+
+    let _guard = BatchGuard::enter(caller, stats.max_concurrent_batches())?;
+    validate_fee_overrides(caller, &transfers, &stats)?;
+    let fee_to = stats.fee_to_account();
+
+    let results = transfers
+        .into_iter()
+        .map(|transfer| {
+            check_zero_amount(&stats, transfer.amount)?;
+            let to = CheckedAccount::with_recipient(transfer.receiver.into(), from_subaccount)?
+                .recipient();
+            let fee = transfer.fee(&stats);
+            let charges = transfer_internal(
+                &mut StableBalances,
+                from,
+                to,
+                transfer.amount,
+                fee,
+                fee_to,
+                FeeRatio::new(auction_fee_ratio),
+            )?;
+
+            Ok(LedgerData::record_transfer(
+                from,
+                to,
+                transfer.amount,
+                fee,
+                charges,
+            ))
+        })
+        .collect();
+
+    Ok(results)
+}
+
+/// Only the owner can set a fee override lower than the standard fee of the transfer.
+fn validate_fee_overrides(
+    caller: Principal,
+    transfers: &[BatchTransferArgs],
+    stats: &TokenConfig,
+) -> Result<(), TxError> {
+    let waives_fee = transfers.iter().any(|transfer| {
+        transfer
+            .fee_override
+            .map_or(false, |fee| fee < stats.effective_fee(transfer.amount))
+    });
+    if waives_fee && caller != stats.owner {
+        return Err(TxError::Unauthorized);
     }
 
+    Ok(())
+}
+
+/// Applies all the `transfers` or none of them. Returns the charges of the transfers in the order
+/// of the `transfers`.
+pub(crate) fn batch_transfer_internal(
+    from: AccountInternal,
+    transfers: &Vec<BatchTransferArgs>,
+    balances: &mut impl Balances,
+    stats: &TokenConfig,
+    auction_fee_ratio: f64,
+) -> Result<Vec<TransferCharges>, TxError> {
+    let fee_to = stats.fee_to_account();
+    let receivers = transfers.iter().map(|transfer| transfer.receiver.into());
+    let mut updates = stage_balances(balances, stats, std::iter::once(from).chain(receivers));
+
+    let mut charges = Vec::with_capacity(transfers.len());
     for transfer in transfers {
         let receiver = transfer.receiver.into();
-        transfer_internal(
+        let transfer_charges = transfer_internal(
             &mut updates,
             from,
             receiver,
             transfer.amount,
-            fee,
+            transfer.fee(stats),
             fee_to,
             FeeRatio::new(auction_fee_ratio),
         )
@@ -329,10 +1546,12 @@ pub(crate) fn batch_transfer_internal(
             },
             other => other,
         })?;
+        charges.push(transfer_charges);
     }
 
+    check_storage_growth(balances, &updates)?;
     balances.apply_updates(updates.list_balances(0, usize::MAX));
-    Ok(())
+    Ok(charges)
 }
 
 #[cfg(test)]
@@ -346,9 +1565,13 @@ mod tests {
 
     use super::*;
     use crate::account::{Account, DEFAULT_SUBACCOUNT};
+    use crate::canister::icrc1_transfer::TX_WINDOW;
     use crate::canister::TokenCanisterAPI;
     use crate::mock::TokenCanisterMock;
-    use crate::state::config::Metadata;
+    use crate::state::config::{
+        Metadata, DEFAULT_FEE_QUOTE_TTL_NANOS, DEFAULT_PREPARED_TRANSFER_TTL_NANOS,
+        MAX_TX_WINDOW_NANOS,
+    };
 
     fn test_canister() -> TokenCanisterMock {
         let context = MockContext::new().with_caller(alice()).inject();
@@ -371,6 +1594,7 @@ mod tests {
                 fee: Tokens128::from(0),
                 fee_to: alice(),
                 is_test_token: None,
+                max_supply: None,
             },
             Tokens128::from(1000),
         );
@@ -396,10 +1620,12 @@ mod tests {
         let transfer1 = BatchTransferArgs {
             receiver: Account::new(bob(), None),
             amount: Tokens128::from(100),
+            fee_override: None,
         };
         let transfer2 = BatchTransferArgs {
             receiver: Account::new(john(), None),
             amount: Tokens128::from(200),
+            fee_override: None,
         };
         let receipt = canister
             .batch_transfer(None, vec![transfer1, transfer2])
@@ -419,6 +1645,215 @@ mod tests {
         );
     }
 
+    #[test]
+    fn batch_transfer_concurrency_limit() {
+        let canister = test_canister();
+        canister.set_max_concurrent_batches(Some(2)).unwrap();
+        let transfers = vec![BatchTransferArgs {
+            receiver: Account::new(bob(), None),
+            amount: Tokens128::from(100),
+            fee_override: None,
+        }];
+
+        // Simulate the batches of the caller which are still being processed.
+        let first = BatchGuard::enter(alice(), 2).unwrap();
+        let second = BatchGuard::enter(alice(), 2).unwrap();
+        assert_eq!(canister.in_flight_batches(alice()), 2);
+        assert_eq!(canister.in_flight_batches(bob()), 0);
+        assert_eq!(
+            canister.batch_transfer(None, transfers.clone()),
+            Err(TxError::TooManyConcurrentBatches { limit: 2 })
+        );
+
+        drop(first);
+        canister.batch_transfer(None, transfers).unwrap();
+        assert_eq!(canister.in_flight_batches(alice()), 1);
+
+        drop(second);
+        assert_eq!(canister.in_flight_batches(alice()), 0);
+    }
+
+    #[test]
+    fn batch_transfer_best_effort_skips_failures() {
+        let canister = test_canister();
+        let transfers = vec![
+            BatchTransferArgs {
+                receiver: Account::new(bob(), None),
+                amount: Tokens128::from(100),
+                fee_override: None,
+            },
+            BatchTransferArgs {
+                receiver: Account::new(john(), None),
+                amount: Tokens128::from(2000),
+                fee_override: None,
+            },
+            BatchTransferArgs {
+                receiver: Account::new(alice(), None),
+                amount: Tokens128::from(100),
+                fee_override: None,
+            },
+            BatchTransferArgs {
+                receiver: Account::new(xtc(), None),
+                amount: Tokens128::from(200),
+                fee_override: None,
+            },
+        ];
+
+        let results = canister
+            .batch_transfer_best_effort(None, transfers)
+            .unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0], Ok(1));
+        assert_eq!(
+            results[1],
+            Err(TxError::InsufficientFunds {
+                balance: Tokens128::from(900)
+            })
+        );
+        assert_eq!(results[2], Err(TxError::SelfTransfer));
+        assert_eq!(results[3], Ok(2));
+
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            Tokens128::from(700)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            Tokens128::from(100)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(john(), None)),
+            Tokens128::from(0)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(xtc(), None)),
+            Tokens128::from(200)
+        );
+        assert_eq!(canister.history_size(), 3);
+    }
+
+    #[test]
+    fn batch_mint() {
+        let canister = test_canister();
+        let ids = canister
+            .batch_mint(vec![
+                (bob(), None, Tokens128::from(100)),
+                (john(), Some([1; 32]), Tokens128::from(200)),
+                (bob(), None, Tokens128::from(50)),
+            ])
+            .unwrap();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            Tokens128::from(150)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(john(), Some([1; 32]))),
+            Tokens128::from(200)
+        );
+        assert_eq!(canister.icrc1_total_supply(), Tokens128::from(1350));
+    }
+
+    #[test]
+    fn batch_mint_overflow_rolls_back() {
+        let canister = test_canister();
+        let res = canister.batch_mint(vec![
+            (bob(), None, Tokens128::from(100)),
+            (john(), None, Tokens128::MAX),
+        ]);
+        assert_eq!(res, Err(TxError::AmountOverflow));
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            Tokens128::from(0)
+        );
+        assert_eq!(canister.icrc1_total_supply(), Tokens128::from(1000));
+        assert_eq!(canister.history_size(), 1);
+
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.batch_mint(vec![(bob(), None, Tokens128::from(100))]),
+            Err(TxError::Unauthorized)
+        );
+    }
+
+    #[test]
+    fn batch_burn() {
+        let canister = test_canister();
+        canister.mint(bob(), None, 100.into(), None).unwrap();
+
+        let ids = canister
+            .batch_burn(vec![
+                (Account::new(alice(), None), Tokens128::from(300)),
+                (Account::new(bob(), None), Tokens128::from(100)),
+            ])
+            .unwrap();
+        assert_eq!(ids, vec![2, 3]);
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            Tokens128::from(700)
+        );
+        assert!(StableBalances.get_subaccounts(bob()).is_empty());
+        assert_eq!(canister.icrc1_total_supply(), Tokens128::from(700));
+    }
+
+    #[test]
+    fn batch_burn_insufficient_funds() {
+        let canister = test_canister();
+        canister.mint(bob(), None, 100.into(), None).unwrap();
+
+        let res = canister.batch_burn(vec![
+            (Account::new(alice(), None), Tokens128::from(300)),
+            (Account::new(bob(), None), Tokens128::from(60)),
+            (Account::new(bob(), None), Tokens128::from(60)),
+        ]);
+        assert_eq!(
+            res,
+            Err(TxError::InsufficientFunds {
+                balance: Tokens128::from(40)
+            })
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            Tokens128::from(1000)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            Tokens128::from(100)
+        );
+        assert_eq!(canister.history_size(), 2);
+    }
+
+    #[test]
+    fn get_batch() {
+        let canister = test_canister();
+        let transfers = vec![
+            BatchTransferArgs {
+                receiver: Account::new(bob(), None),
+                amount: Tokens128::from(100),
+                fee_override: None,
+            },
+            BatchTransferArgs {
+                receiver: Account::new(john(), None),
+                amount: Tokens128::from(200),
+                fee_override: None,
+            },
+        ];
+        let first = canister.batch_transfer(None, transfers.clone()).unwrap();
+        let second = canister.batch_transfer(None, transfers).unwrap();
+
+        let legs = canister.get_batch(second[0]);
+        assert_eq!(legs.iter().map(|tx| tx.index).collect::<Vec<_>>(), second);
+        assert_eq!(
+            legs.iter().map(|tx| tx.to).collect::<Vec<_>>(),
+            vec![Account::new(bob(), None), Account::new(john(), None)]
+        );
+        assert_eq!(canister.get_batch(first[0]).len(), 2);
+
+        // Transactions which are not the start of a batch are not batch ids.
+        assert!(canister.get_batch(0).is_empty());
+        assert!(canister.get_batch(first[1]).is_empty());
+    }
+
     #[test]
     fn batch_transfer_with_fee() {
         let canister = test_canister();
@@ -435,98 +1870,393 @@ mod tests {
         let transfer1 = BatchTransferArgs {
             receiver: Account::new(bob(), None),
             amount: Tokens128::from(100),
+            fee_override: None,
         };
         let transfer2 = BatchTransferArgs {
             receiver: Account::new(xtc(), None),
             amount: Tokens128::from(200),
+            fee_override: None,
         };
         let receipt = canister
             .batch_transfer(None, vec![transfer1, transfer2])
             .unwrap();
         assert_eq!(receipt.len(), 2);
         assert_eq!(
-            canister.icrc1_balance_of(Account::new(alice(), None)),
-            Tokens128::from(600)
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            Tokens128::from(600)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            Tokens128::from(100)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(xtc(), None)),
+            Tokens128::from(200)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(john(), None)),
+            Tokens128::from(100)
+        );
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn batch_transfer_with_fee_override() {
+        let canister = test_canister();
+        canister.set_fee(Tokens128::from(10)).unwrap();
+        canister.set_fee_to(john().into()).unwrap();
+        let transfers = vec![
+            BatchTransferArgs {
+                receiver: Account::new(bob(), None),
+                amount: Tokens128::from(100),
+                fee_override: None,
+            },
+            BatchTransferArgs {
+                receiver: Account::new(xtc(), None),
+                amount: Tokens128::from(100),
+                fee_override: Some(Tokens128::from(0)),
+            },
+            BatchTransferArgs {
+                receiver: Account::new(xtc(), Some([1; 32])),
+                amount: Tokens128::from(100),
+                fee_override: Some(Tokens128::from(25)),
+            },
+        ];
+
+        let ids = canister.batch_transfer(None, transfers.clone()).unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            Tokens128::from(665)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(john(), None)),
+            Tokens128::from(35)
+        );
+        let mut fees = vec![];
+        for id in ids {
+            fees.push(canister.get_transaction(id).await.unwrap().fee);
+        }
+        assert_eq!(fees, [10, 0, 25].map(Tokens128::from));
+
+        canister
+            .mint(bob(), None, Tokens128::from(500), None)
+            .unwrap();
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.batch_transfer(None, transfers[1..].to_vec()),
+            Err(TxError::Unauthorized)
+        );
+        canister
+            .batch_transfer(None, transfers[2..].to_vec())
+            .unwrap();
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            Tokens128::from(475)
+        );
+    }
+
+    #[test]
+    fn batch_transfer_insufficient_balance() {
+        let canister = test_canister();
+
+        let transfer1 = BatchTransferArgs {
+            receiver: Account::new(bob(), None),
+            amount: Tokens128::from(500),
+            fee_override: None,
+        };
+        let transfer2 = BatchTransferArgs {
+            receiver: Account::new(john(), None),
+            amount: Tokens128::from(600),
+            fee_override: None,
+        };
+        let receipt = canister.batch_transfer(None, vec![transfer1, transfer2]);
+        assert!(receipt.is_err());
+        let balance = canister.icrc1_balance_of(Account::new(alice(), None));
+        assert_eq!(receipt.unwrap_err(), TxError::InsufficientFunds { balance });
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            Tokens128::from(1000)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            Tokens128::from(0)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(john(), None)),
+            Tokens128::from(0)
+        );
+    }
+
+    #[test]
+    fn batch_transfer_overflow() {
+        let canister = test_canister();
+
+        let transfer1 = BatchTransferArgs {
+            receiver: Account::new(bob(), None),
+            amount: Tokens128::from(u128::MAX - 10),
+            fee_override: None,
+        };
+        let transfer2 = BatchTransferArgs {
+            receiver: Account::new(john(), None),
+            amount: Tokens128::from(20),
+            fee_override: None,
+        };
+        let res = canister.batch_transfer(None, vec![transfer1, transfer2]);
+        assert_eq!(
+            res,
+            Err(TxError::InsufficientFunds {
+                balance: 1000.into()
+            })
+        );
+    }
+
+    #[test]
+    fn batch_transfer_zero_amount() {
+        let canister = test_canister();
+
+        let transfer1 = BatchTransferArgs {
+            receiver: Account::new(bob(), None),
+            amount: Tokens128::from(100),
+            fee_override: None,
+        };
+        let transfer2 = BatchTransferArgs {
+            receiver: Account::new(john(), None),
+            amount: Tokens128::from(0),
+            fee_override: None,
+        };
+        let res = canister.batch_transfer(None, vec![transfer1, transfer2]);
+        assert_eq!(res, Err(TxError::AmountTooSmall));
+    }
+
+    #[test]
+    fn reject_zero_transfers() {
+        let canister = test_canister();
+        canister.set_fee(Tokens128::from(10)).unwrap();
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: Account::new(bob(), None),
+            amount: 0.into(),
+            fee: Some(5.into()),
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+        let batch = vec![BatchTransferArgs {
+            receiver: Account::new(bob(), None),
+            amount: 0.into(),
+            fee_override: None,
+        }];
+
+        // Without the flag the zero amount is rejected only after the transfer is validated.
+        assert!(!canister.rejects_zero_transfers());
+        assert_eq!(
+            canister.transfer(transfer.clone()),
+            Err(TxError::BadFee {
+                expected_fee: 10.into()
+            })
+        );
+
+        canister.set_reject_zero_transfers(true).unwrap();
+        assert!(canister.rejects_zero_transfers());
+        assert_eq!(
+            canister.transfer(transfer.clone()),
+            Err(TxError::AmountTooSmall)
         );
+
+        // Zero amounts are rejected even if the token is paused.
+        canister.set_paused(true).unwrap();
         assert_eq!(
-            canister.icrc1_balance_of(Account::new(bob(), None)),
-            Tokens128::from(100)
+            canister.batch_transfer(None, batch.clone()),
+            Err(TxError::AmountTooSmall)
         );
+        canister.set_paused(false).unwrap();
         assert_eq!(
-            canister.icrc1_balance_of(Account::new(xtc(), None)),
-            Tokens128::from(200)
+            canister.batch_transfer(None, batch.clone()),
+            Err(TxError::AmountTooSmall)
+        );
+        assert_eq!(canister.history_size(), 1);
+
+        // The best-effort batch rejects the zero amount transfers only.
+        let mut mixed = batch;
+        mixed.push(BatchTransferArgs {
+            receiver: Account::new(john(), None),
+            amount: 100.into(),
+            fee_override: None,
+        });
+        assert_eq!(
+            canister.batch_transfer_best_effort(None, mixed),
+            Ok(vec![Err(TxError::AmountTooSmall), Ok(1)])
         );
+        assert_eq!(canister.history_size(), 2);
+
+        canister.set_reject_zero_transfers(false).unwrap();
         assert_eq!(
-            canister.icrc1_balance_of(Account::new(john(), None)),
-            Tokens128::from(100)
+            canister.transfer(transfer),
+            Err(TxError::BadFee {
+                expected_fee: 10.into()
+            })
         );
     }
 
     #[test]
-    fn batch_transfer_insufficient_balance() {
+    fn transfer_with_max_fee() {
         let canister = test_canister();
-
-        let transfer1 = BatchTransferArgs {
-            receiver: Account::new(bob(), None),
-            amount: Tokens128::from(500),
-        };
-        let transfer2 = BatchTransferArgs {
-            receiver: Account::new(john(), None),
-            amount: Tokens128::from(600),
+        canister.set_fee(Tokens128::from(10)).unwrap();
+        canister.set_fee_to(john().into()).unwrap();
+        let transfer = |fee: Option<u128>, max_fee: Option<u128>| TransferArgs {
+            from_subaccount: None,
+            to: Account::new(bob(), None),
+            amount: 100.into(),
+            fee: fee.map(Tokens128::from),
+            max_fee: max_fee.map(Tokens128::from),
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
-        let receipt = canister.batch_transfer(None, vec![transfer1, transfer2]);
-        assert!(receipt.is_err());
-        let balance = canister.icrc1_balance_of(Account::new(alice(), None));
-        assert_eq!(receipt.unwrap_err(), TxError::InsufficientFunds { balance });
+
+        canister.icrc1_transfer(transfer(None, Some(15))).unwrap();
         assert_eq!(
             canister.icrc1_balance_of(Account::new(alice(), None)),
-            Tokens128::from(1000)
+            Tokens128::from(890)
         );
         assert_eq!(
-            canister.icrc1_balance_of(Account::new(bob(), None)),
-            Tokens128::from(0)
+            canister.icrc1_balance_of(Account::new(john(), None)),
+            Tokens128::from(10)
+        );
+
+        // The cap replaces the exact match of the fee.
+        canister
+            .icrc1_transfer(transfer(Some(15), Some(10)))
+            .unwrap();
+        assert_eq!(
+            canister.transfer(transfer(None, Some(5))),
+            Err(TxError::BadFee {
+                expected_fee: Tokens128::from(10)
+            })
         );
         assert_eq!(
-            canister.icrc1_balance_of(Account::new(john(), None)),
-            Tokens128::from(0)
+            canister.transfer(transfer(Some(15), None)),
+            Err(TxError::BadFee {
+                expected_fee: Tokens128::from(10)
+            })
         );
     }
 
     #[test]
-    fn batch_transfer_overflow() {
+    fn configurable_tx_window() {
         let canister = test_canister();
+        assert_eq!(
+            canister.set_tx_window(Some(MAX_TX_WINDOW_NANOS + 1)),
+            Err(TxError::TxWindowTooLong {
+                max_nanos: MAX_TX_WINDOW_NANOS
+            })
+        );
+        assert_eq!(
+            canister.set_permitted_drift(Some(MAX_TX_WINDOW_NANOS + 1)),
+            Err(TxError::TxWindowTooLong {
+                max_nanos: MAX_TX_WINDOW_NANOS
+            })
+        );
 
-        let transfer1 = BatchTransferArgs {
-            receiver: Account::new(bob(), None),
-            amount: Tokens128::from(u128::MAX - 10),
-        };
-        let transfer2 = BatchTransferArgs {
-            receiver: Account::new(john(), None),
-            amount: Tokens128::from(20),
+        canister.set_tx_window(Some(1_000)).unwrap();
+        canister.set_permitted_drift(Some(100)).unwrap();
+
+        get_context().update_caller(bob());
+        assert_eq!(canister.set_tx_window(None), Err(TxError::Unauthorized));
+        get_context().update_caller(alice());
+
+        let now = ic::time();
+        let transfer = |created_at_time: u64| TransferArgs {
+            from_subaccount: None,
+            to: Account::new(bob(), None),
+            amount: 100.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: Some(created_at_time),
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
-        let res = canister.batch_transfer(None, vec![transfer1, transfer2]);
+
         assert_eq!(
-            res,
-            Err(TxError::InsufficientFunds {
-                balance: 1000.into()
+            canister.transfer(transfer(now - 1_001)),
+            Err(TxError::TooOld {
+                allowed_window_nanos: 1_000
             })
         );
+        assert_eq!(
+            canister.transfer(transfer(now + 101)),
+            Err(TxError::CreatedInFuture { ledger_time: now })
+        );
+        assert!(canister.transfer(transfer(now - 1_000)).is_ok());
+        assert!(canister.transfer(transfer(now + 100)).is_ok());
+
+        canister.set_tx_window(None).unwrap();
+        assert_eq!(TokenConfig::get_stable().tx_window_nanos(), TX_WINDOW);
+        assert!(canister.transfer(transfer(now - 1_001)).is_ok());
     }
 
     #[test]
-    fn batch_transfer_zero_amount() {
+    fn transfer_deadline() {
         let canister = test_canister();
-
-        let transfer1 = BatchTransferArgs {
-            receiver: Account::new(bob(), None),
-            amount: Tokens128::from(100),
-        };
-        let transfer2 = BatchTransferArgs {
-            receiver: Account::new(john(), None),
-            amount: Tokens128::from(0),
+        let now = ic::time();
+        let transfer = |created_at_time: Option<u64>, valid_until: Option<u64>| TransferArgs {
+            from_subaccount: None,
+            to: Account::new(bob(), None),
+            amount: 100.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time,
+            valid_until,
+            tags: None,
+            tip: None,
         };
-        let res = canister.batch_transfer(None, vec![transfer1, transfer2]);
-        assert_eq!(res, Err(TxError::AmountTooSmall));
+        let expired = now - 1;
+
+        for created_at_time in [
+            None,
+            Some(now),
+            Some(now - TX_WINDOW / 2),
+            Some(now - 2 * TX_WINDOW),
+        ] {
+            assert_eq!(
+                validate_and_get_tx_ts(alice(), &transfer(created_at_time, Some(expired))),
+                Err(TxError::Expired {
+                    valid_until: expired
+                })
+            );
+        }
+
+        assert_eq!(
+            validate_and_get_tx_ts(alice(), &transfer(Some(now), Some(now))),
+            Ok(now)
+        );
+        assert_eq!(
+            validate_and_get_tx_ts(alice(), &transfer(None, Some(now + TX_WINDOW))),
+            Ok(now)
+        );
+
+        // An expired duplicate is reported as expired.
+        let tx_id = canister.icrc1_transfer(transfer(Some(now), None)).unwrap();
+        assert_eq!(
+            validate_and_get_tx_ts(alice(), &transfer(Some(now), None)),
+            Err(TxError::Duplicate {
+                duplicate_of: tx_id as u64
+            })
+        );
+        assert_eq!(
+            validate_and_get_tx_ts(alice(), &transfer(Some(now), Some(expired))),
+            Err(TxError::Expired {
+                valid_until: expired
+            })
+        );
     }
 
     #[test]
@@ -539,8 +2269,12 @@ mod tests {
             to: Account::new(bob(), None),
             amount: 10_000.into(),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: Some(curr_time),
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         assert!(validate_and_get_tx_ts(alice(), &transfer).is_ok());
@@ -565,8 +2299,12 @@ mod tests {
             to: Account::new(bob(), None),
             amount: 10_000.into(),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: Some(curr_time),
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         let _ = canister.icrc1_transfer(transfer.clone()).unwrap();
@@ -601,8 +2339,12 @@ mod tests {
             to: Account::new(bob(), None),
             amount: 10_000.into(),
             fee: None,
+            max_fee: None,
             memo: Some([1; 32]),
             created_at_time: Some(curr_time),
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         let _ = canister.icrc1_transfer(transfer.clone()).unwrap();
@@ -626,8 +2368,12 @@ mod tests {
             to: Account::new(bob(), None),
             amount: 10_000.into(),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         let _ = canister.icrc1_transfer(transfer.clone()).unwrap();
@@ -642,8 +2388,12 @@ mod tests {
             to: bob().into(),
             amount: 0.into(),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         let caller = CheckedAccount::with_recipient(transfer.to.into(), None).unwrap();
@@ -665,8 +2415,12 @@ mod tests {
             to: bob().into(),
             amount: (u128::MAX - 100000).into(),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         let caller = CheckedAccount::with_recipient(transfer.to.into(), None).unwrap();
@@ -684,11 +2438,60 @@ mod tests {
     fn mint_too_much() {
         let _ = test_canister(); // initialize context
 
-        mint(alice(), bob().into(), Tokens128::from(u128::MAX - 2000)).unwrap();
-        let res = mint(alice(), john().into(), Tokens128::from(2000));
+        mint(
+            alice(),
+            bob().into(),
+            Tokens128::from(u128::MAX - 2000),
+            None,
+        )
+        .unwrap();
+        let res = mint(alice(), john().into(), Tokens128::from(2000), None);
         assert_eq!(res, Err(TxError::AmountOverflow));
     }
 
+    #[test]
+    fn mint_up_to_max_supply() {
+        let canister = test_canister();
+        let mut stats = TokenConfig::get_stable();
+        stats.max_supply = Some(Tokens128::from(1500));
+        TokenConfig::set_stable(stats);
+        assert_eq!(
+            canister.get_token_info().metadata.max_supply,
+            Some(Tokens128::from(1500))
+        );
+
+        canister.mint(bob(), None, 400.into(), None).unwrap();
+        assert_eq!(
+            canister.batch_mint(vec![
+                (bob(), None, Tokens128::from(50)),
+                (john(), None, Tokens128::from(51)),
+            ]),
+            Err(TxError::MaxSupplyExceeded {
+                max_supply: Tokens128::from(1500)
+            })
+        );
+        assert_eq!(
+            canister.mint(john(), None, 101.into(), None),
+            Err(TxError::MaxSupplyExceeded {
+                max_supply: Tokens128::from(1500)
+            })
+        );
+        assert_eq!(canister.icrc1_total_supply(), Tokens128::from(1400));
+
+        canister.mint(john(), None, 100.into(), None).unwrap();
+        assert_eq!(canister.icrc1_total_supply(), Tokens128::from(1500));
+
+        // Burned tokens free up the supply.
+        canister.burn(None, None, 10.into(), None).unwrap();
+        canister.mint(john(), None, 10.into(), None).unwrap();
+        assert_eq!(
+            canister.mint(john(), None, 1.into(), None),
+            Err(TxError::MaxSupplyExceeded {
+                max_supply: Tokens128::from(1500)
+            })
+        );
+    }
+
     #[test]
     fn transfer_to_own_subaccount() {
         let canister = test_canister();
@@ -697,8 +2500,12 @@ mod tests {
             to: Account::new(alice(), Some([1; 32])),
             amount: (200).into(),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         let caller = CheckedAccount::with_recipient(transfer.to.into(), None).unwrap();
 
@@ -715,8 +2522,12 @@ mod tests {
             to: Account::new(bob(), Some(DEFAULT_SUBACCOUNT)),
             amount: 200.into(),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         let caller = CheckedAccount::with_recipient(transfer.to.into(), None).unwrap();
 
@@ -736,8 +2547,12 @@ mod tests {
             to: bob().into(),
             amount: 200.into(),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: Some(now + 121_000_000_000),
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
         let caller = CheckedAccount::with_recipient(bob().into(), None).unwrap();
         let result = is20_transfer(caller, &delayed_transfer, canister.bidding_info().fee_ratio);
@@ -748,8 +2563,12 @@ mod tests {
             to: bob().into(),
             amount: 200.into(),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: Some(now),
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         let caller = CheckedAccount::with_recipient(bob().into(), None).unwrap();
@@ -794,16 +2613,20 @@ mod tests {
             })
         );
 
-        // This last transfer is needed to check if the deduplication logic stops at the right
-        // moment when iterating over old transactions. It is visible in the test coverage report
-        // only though.
+        // This last transfer is needed to check if the deduplication index drops the keys of the
+        // transactions outside of the window. It is visible in the test coverage report only
+        // though.
         let transfer = TransferArgs {
             from_subaccount: None,
             to: bob().into(),
             amount: 200.into(),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: Some(ic::time()),
+            valid_until: None,
+            tags: None,
+            tip: None,
         };
 
         let caller = CheckedAccount::with_recipient(bob().into(), None).unwrap();
@@ -822,10 +2645,269 @@ mod tests {
     #[test]
     fn burn_removes_empty_entry() {
         let _ = test_canister();
-        mint(alice(), bob().into(), Tokens128::from(1_000_000)).unwrap();
+        mint(alice(), bob().into(), Tokens128::from(1_000_000), None).unwrap();
         assert_ne!(StableBalances.get(&bob().into()), None);
 
-        burn(alice(), bob().into(), Tokens128::from(1_000_000)).unwrap();
+        burn(alice(), bob().into(), Tokens128::from(1_000_000), None).unwrap();
         assert_eq!(StableBalances.get(&bob().into()), None);
     }
+
+    #[test]
+    fn auction_dust_sinking() {
+        let canister = test_canister();
+        let mut stats = TokenConfig::get_stable();
+        stats.auction_dust_sinking = Some(true);
+        TokenConfig::set_stable(stats);
+
+        for _ in 0..10 {
+            transfer_internal(
+                &mut StableBalances,
+                alice().into(),
+                bob().into(),
+                10.into(),
+                1.into(),
+                john().into(),
+                FeeRatio::new(0.25),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(StableBalances.balance_of(&auction_account()), 2.into());
+        assert_eq!(canister.icrc1_balance_of(john().into()), 8.into());
+        assert_eq!(AuctionDust::pending(), 500_000);
+
+        for _ in 0..2 {
+            transfer_internal(
+                &mut StableBalances,
+                alice().into(),
+                bob().into(),
+                10.into(),
+                1.into(),
+                john().into(),
+                FeeRatio::new(0.25),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(StableBalances.balance_of(&auction_account()), 3.into());
+        assert_eq!(canister.icrc1_balance_of(john().into()), 9.into());
+        assert_eq!(AuctionDust::pending(), 0);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    async fn transfer_with_tip() {
+        let canister = test_canister();
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: bob().into(),
+            amount: 100.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: Some(5.into()),
+        };
+
+        let id = canister.transfer(transfer.clone()).unwrap() as TxId;
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 895.into());
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 100.into());
+        assert_eq!(StableBalances.balance_of(&auction_account()), 5.into());
+        assert_eq!(
+            canister.get_transaction(id).await.unwrap().tip,
+            Some(5.into())
+        );
+
+        canister.set_fee(10.into()).unwrap();
+        canister.set_fee_to(john().into()).unwrap();
+        canister.transfer(transfer.clone()).unwrap();
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 780.into());
+        assert_eq!(
+            (canister.icrc1_balance_of(john().into())
+                + StableBalances.balance_of(&auction_account()))
+            .unwrap(),
+            20.into()
+        );
+
+        let auction_balance = StableBalances.balance_of(&auction_account());
+        assert_eq!(
+            canister.transfer(TransferArgs {
+                tip: Some(671.into()),
+                ..transfer
+            }),
+            Err(TxError::InsufficientFunds {
+                balance: 780.into()
+            })
+        );
+        assert_eq!(canister.icrc1_balance_of(alice().into()), 780.into());
+        assert_eq!(canister.icrc1_balance_of(bob().into()), 200.into());
+        assert_eq!(
+            StableBalances.balance_of(&auction_account()),
+            auction_balance
+        );
+    }
+
+    #[test]
+    fn prepare_and_commit_transfer() {
+        let canister = test_canister();
+        let mut stats = TokenConfig::get_stable();
+        stats.fee = 10.into();
+        stats.fee_to = john();
+        TokenConfig::set_stable(stats);
+
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: Account::new(bob(), None),
+            amount: 100.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+        let prepared = canister.prepare_transfer(transfer).unwrap();
+        assert_eq!(prepared.effective_fee, Tokens128::from(10));
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            Tokens128::from(1000)
+        );
+
+        // The fee change doesn't affect the prepared transfer.
+        let mut stats = TokenConfig::get_stable();
+        stats.fee = 20.into();
+        TokenConfig::set_stable(stats);
+
+        get_context().update_caller(bob());
+        assert_eq!(
+            canister.commit_transfer(prepared.token),
+            Err(TxError::Unauthorized)
+        );
+
+        get_context().update_caller(alice());
+        let id = canister.commit_transfer(prepared.token).unwrap();
+        let record = LedgerData::get(id as u64).unwrap();
+        assert_eq!(record.to, Account::new(bob(), None));
+        assert_eq!(record.fee, Tokens128::from(10));
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(alice(), None)),
+            Tokens128::from(890)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            Tokens128::from(100)
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(john(), None)),
+            Tokens128::from(10)
+        );
+
+        assert_eq!(
+            canister.commit_transfer(prepared.token),
+            Err(TxError::PreparedTransferNotFound)
+        );
+    }
+
+    #[test]
+    fn transfer_with_fee_quote() {
+        let canister = test_canister();
+        canister.set_fee(10.into()).unwrap();
+        let transfer = |max_fee: Option<u128>| TransferArgs {
+            from_subaccount: None,
+            to: Account::new(bob(), None),
+            amount: 100.into(),
+            fee: None,
+            max_fee: max_fee.map(Tokens128::from),
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        let quote = canister.quote_fee(100.into());
+        assert_eq!(quote.fee, Tokens128::from(10));
+        assert_eq!(
+            quote.valid_until,
+            canister_sdk::ic_kit::ic::time() + DEFAULT_FEE_QUOTE_TTL_NANOS
+        );
+
+        // The fee raised within the quote window doesn't affect the quoted transfer.
+        canister.set_fee(20.into()).unwrap();
+        let id = canister.transfer(transfer(Some(10))).unwrap();
+        assert_eq!(LedgerData::get(id as u64).unwrap().fee, Tokens128::from(10));
+
+        // The quote is honored only for the quoted amount and with the `max_fee` covering it.
+        assert_eq!(
+            canister.transfer(TransferArgs {
+                amount: 50.into(),
+                ..transfer(Some(10))
+            }),
+            Err(TxError::BadFee {
+                expected_fee: 20.into()
+            })
+        );
+        let id = canister.transfer(transfer(None)).unwrap();
+        assert_eq!(LedgerData::get(id as u64).unwrap().fee, Tokens128::from(20));
+
+        get_context().add_time(DEFAULT_FEE_QUOTE_TTL_NANOS + 1);
+        assert_eq!(
+            canister.transfer(transfer(Some(10))),
+            Err(TxError::BadFee {
+                expected_fee: 20.into()
+            })
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            Tokens128::from(200)
+        );
+    }
+
+    #[test]
+    fn commit_stale_prepared_transfer() {
+        let canister = test_canister();
+        let transfer = TransferArgs {
+            from_subaccount: None,
+            to: Account::new(bob(), None),
+            amount: 100.into(),
+            fee: None,
+            max_fee: None,
+            memo: None,
+            created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
+        };
+
+        let prepared = canister.prepare_transfer(transfer.clone()).unwrap();
+        get_context().add_time(DEFAULT_PREPARED_TRANSFER_TTL_NANOS);
+        assert_eq!(
+            canister.commit_transfer(prepared.token),
+            Err(TxError::PreparedTransferExpired {
+                expires_at: prepared.expires_at
+            })
+        );
+        assert_eq!(
+            canister.commit_transfer(prepared.token),
+            Err(TxError::PreparedTransferNotFound)
+        );
+
+        // The amount exceeding the balance is rejected at the preparation.
+        assert_eq!(
+            canister.prepare_transfer(TransferArgs {
+                amount: 2000.into(),
+                ..transfer
+            }),
+            Err(TxError::InsufficientFunds {
+                balance: 1000.into()
+            })
+        );
+        assert_eq!(
+            canister.icrc1_balance_of(Account::new(bob(), None)),
+            Tokens128::ZERO
+        );
+    }
 }
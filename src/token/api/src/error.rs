@@ -3,7 +3,7 @@ use candid::{CandidType, Deserialize};
 use canister_sdk::ic_helpers::tokens::Tokens128;
 use thiserror::Error;
 
-#[derive(CandidType, Debug, PartialEq, Deserialize, Error, Eq)]
+#[derive(CandidType, Debug, Clone, PartialEq, Deserialize, Error, Eq)]
 pub enum TxError {
     #[error("unauthorized")]
     Unauthorized,
@@ -27,6 +27,96 @@ pub enum TxError {
     AccountNotFound,
     #[error("no claimable tokens are on the requested subaccount")]
     NothingToClaim,
+    #[error("owner action is not found")]
+    OwnerActionNotFound,
+    #[error("owner action cannot be executed before {executable_at}")]
+    OwnerActionTimelocked { executable_at: Timestamp },
+    #[error("balances chunk {chunk_index} is already imported")]
+    ChunkAlreadyImported { chunk_index: u64 },
+    #[error("stable memory usage is above the high-water mark")]
+    StorageFull,
+    #[error("outgoing transfers are disabled for the account")]
+    OutgoingDisabled,
+    #[error("transaction tags exceed the allowed count or length")]
+    InvalidTags,
+    #[error("reclaiming dormant balances is disabled")]
+    DormancyReclaimDisabled,
+    #[error("account is active since {last_activity}")]
+    AccountNotDormant { last_activity: Timestamp },
+    #[error("fee oracle is not configured")]
+    FeeOracleNotSet,
+    #[error("token is not transferable")]
+    NonTransferable,
+    #[error("redemption reference is empty or too long")]
+    InvalidRedemptionRef,
+    #[error("cursor does not belong to the transaction history")]
+    InvalidCursor,
+    #[error("transactions after the cursor are removed, the first available is {first_available}")]
+    CursorPruned { first_available: u64 },
+    #[error("escrow is not found")]
+    EscrowNotFound,
+    #[error("escrow has expired at {expires_at}")]
+    EscrowExpired { expires_at: Timestamp },
+    #[error("escrow cannot be refunded before {expires_at}")]
+    EscrowNotExpired { expires_at: Timestamp },
+    #[error("account is already in use and cannot hold the auction pool")]
+    AuctionAccountInUse,
+    #[error("auction account must be a subaccount of the management canister")]
+    InvalidAuctionAccount,
+    #[error("insufficient allowance : {allowance}")]
+    InsufficientAllowance { allowance: Tokens128 },
+    #[error("approval has expired at {expires_at}")]
+    ApprovalExpired { expires_at: Timestamp },
+    #[error("too many batch operations are in flight, the limit is {limit}")]
+    TooManyConcurrentBatches { limit: u32 },
+    #[error("recipient is a canister which is not registered as token-aware")]
+    RecipientNotTokenAware,
+    #[error("transaction has expired at {valid_until}")]
+    Expired { valid_until: Timestamp },
+    #[error("prepared transfer is not found or is already committed")]
+    PreparedTransferNotFound,
+    #[error("prepared transfer has expired at {expires_at}")]
+    PreparedTransferExpired { expires_at: Timestamp },
+    #[error("transaction does not exist")]
+    TransactionDoesNotExist,
+    #[error("canister is under maintenance")]
+    UnderMaintenance,
+    #[error("the claim has expired")]
+    ClaimExpired,
+    #[error("transfers are paused")]
+    TokenPaused,
+    #[error("account is frozen")]
+    AccountFrozen,
+    #[error("total supply cannot exceed {max_supply}")]
+    MaxSupplyExceeded { max_supply: Tokens128 },
+    #[error("transaction window cannot exceed {max_nanos} nanoseconds")]
+    TxWindowTooLong { max_nanos: u64 },
+    #[error("fee ratio must be within [0, 1]")]
+    InvalidFeeRatio,
+    #[error("vesting schedule is not found")]
+    VestingNotFound,
+    #[error("vesting cliff must not exceed the non-zero duration")]
+    InvalidVestingSchedule,
+    #[error("no vested tokens to release")]
+    NothingToRelease,
+    #[error("timelock is not found")]
+    TimelockNotFound,
+    #[error("timelocked tokens cannot be claimed before {release_at}")]
+    TimelockNotReleased { release_at: Timestamp },
+    #[error("timelocked tokens are released at {release_at} and cannot be cancelled")]
+    TimelockReleased { release_at: Timestamp },
+    #[error("account is reserved by the token")]
+    ReservedAccount,
+    #[error("transactions after the id are removed, the first available is {first_available}")]
+    HistoryPruned { first_available: u64 },
+    #[error("archive canister is not set")]
+    ArchiveNotSet,
+    #[error("transactions are already being archived")]
+    ArchivingInProgress,
+    #[error("archive canister call failed: {message}")]
+    ArchiveCallFailed { message: String },
+    #[error("fee oracle call failed: {message}")]
+    FeeOracleCallFailed { message: String },
 }
 
 // This type is the exact error type from ICRC-1 standard. We use it as the return type for
@@ -54,6 +144,7 @@ impl From<TxError> for TransferError {
             TxError::Duplicate { duplicate_of } => Self::Duplicate {
                 duplicate_of: duplicate_of as u128,
             },
+            TxError::UnderMaintenance | TxError::TokenPaused => Self::TemporarilyUnavailable,
             _ => TransferError::GenericError {
                 error_code: 500,
                 message: format!("{err}"),
@@ -36,7 +36,7 @@ impl TokenCanisterMock {
         let owner_account = AccountInternal::new(metadata.owner, None);
         StableBalances.insert(owner_account, amount);
 
-        LedgerData::mint(metadata.owner.into(), metadata.owner.into(), amount);
+        LedgerData::mint(metadata.owner.into(), metadata.owner.into(), amount, None);
 
         TokenConfig::set_stable(metadata.into());
 
@@ -70,6 +70,14 @@ impl Auction for TokenCanisterMock {
     fn disburse_rewards(&self) -> Result<AuctionInfo, AuctionError> {
         crate::canister::is20_auction::disburse_rewards(&self.auction_state().borrow())
     }
+
+    fn set_auction_period(&self, interval: Interval) -> Result<(), AuctionError> {
+        crate::canister::is20_auction::set_auction_period(&self.auction_state(), interval)
+    }
+
+    fn bid_cycles(&self, bidder: Principal) -> Result<u64, AuctionError> {
+        crate::canister::is20_auction::bid_cycles(&self.auction_state(), bidder)
+    }
 }
 
 impl TokenCanisterAPI for TokenCanisterMock {}
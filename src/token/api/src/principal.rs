@@ -1,6 +1,7 @@
 use ic_exports::Principal;
 
-use crate::{error::TxError, state::config::TokenConfig};
+use crate::error::TxError;
+use crate::state::{config::TokenConfig, permissions::Minters};
 use canister_sdk::ic_kit::ic;
 
 /// Canister owner
@@ -10,6 +11,9 @@ pub struct Owner;
 /// has is_test_token set to true
 pub struct TestNet;
 
+/// Principal added to the minters by the owner
+pub struct Minter;
+
 pub struct CheckedPrincipal<T>(Principal, T);
 
 impl<T> CheckedPrincipal<T> {
@@ -39,3 +43,14 @@ impl CheckedPrincipal<TestNet> {
         }
     }
 }
+
+impl CheckedPrincipal<Minter> {
+    pub fn minter() -> Result<Self, TxError> {
+        let caller = ic::caller();
+        if Minters::contains(&caller) {
+            Ok(Self(caller, Minter))
+        } else {
+            Err(TxError::Unauthorized)
+        }
+    }
+}
@@ -1,3 +1,26 @@
+pub mod allowances;
+pub mod analytics;
+pub mod auction_dust;
+pub mod balance_tree;
 pub mod balances;
+pub mod batch_guard;
+pub mod blocks;
+pub mod certification;
+pub mod claims;
 pub mod config;
+pub mod escrow;
+pub mod failures;
+pub mod fee_history;
+pub mod fee_oracle;
+pub mod fee_quotes;
+pub mod fingerprint;
+pub mod governance;
+pub mod layout;
 pub mod ledger;
+pub mod migration;
+pub mod permissions;
+pub mod prepared_transfers;
+pub mod statistics;
+pub mod storage;
+pub mod timelocks;
+pub mod vesting;
@@ -0,0 +1,168 @@
+use std::{borrow::Cow, cell::RefCell};
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+use ic_stable_structures::{BoundedStorable, MemoryId, StableMultimap, Storable};
+
+use crate::account::{Account, AccountInternal};
+use crate::state::config::Timestamp;
+
+/// Amount of tokens the `spender` is allowed to transfer from the `owner` account.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Allowance {
+    pub owner: Account,
+    pub spender: Account,
+    pub amount: Tokens128,
+    /// Time after which the allowance cannot be used anymore.
+    pub expires_at: Option<Timestamp>,
+}
+
+/// Allowances granted by the `icrc2_approve` calls. Only non-zero allowances are stored.
+pub struct Allowances;
+
+impl Allowances {
+    /// Returns the allowance of the `spender` for the `owner` account, if it exists and hasn't
+    /// expired yet.
+    pub fn find(owner: AccountInternal, spender: AccountInternal) -> Option<Allowance> {
+        let value = MAP.with(|map| {
+            map.borrow_mut()
+                .get(&AccountKey(owner), &AccountKey(spender))
+        })?;
+        if value.is_expired(ic::time()) {
+            return None;
+        }
+
+        Some(Allowance {
+            owner: owner.into(),
+            spender: spender.into(),
+            amount: value.amount,
+            expires_at: value.expires_at,
+        })
+    }
+
+    /// Returns the amount the `spender` can transfer from the `owner` account. Expired allowances
+    /// are treated as zero.
+    pub fn get(owner: AccountInternal, spender: AccountInternal) -> Tokens128 {
+        Self::find(owner, spender)
+            .map(|allowance| allowance.amount)
+            .unwrap_or_default()
+    }
+
+    /// Sets the allowance of the `spender` for the `owner` account. A zero `amount` removes the
+    /// allowance. Expired allowances of the `owner` are dropped on every write.
+    pub fn set(
+        owner: AccountInternal,
+        spender: AccountInternal,
+        amount: Tokens128,
+        expires_at: Option<Timestamp>,
+    ) {
+        let owner_key = AccountKey(owner);
+        let spender_key = AccountKey(spender);
+        let now = ic::time();
+        MAP.with(|map| {
+            let mut map = map.borrow_mut();
+            let expired: Vec<_> = map
+                .range(&owner_key)
+                .filter(|(_, value)| value.is_expired(now))
+                .map(|(spender, _)| spender)
+                .collect();
+            for spender in expired {
+                map.remove(&owner_key, &spender);
+            }
+
+            if amount.is_zero() {
+                map.remove(&owner_key, &spender_key);
+            } else {
+                map.insert(
+                    &owner_key,
+                    &spender_key,
+                    &AllowanceValue { amount, expires_at },
+                );
+            }
+        });
+    }
+}
+
+const PRINCIPAL_MAX_LENGTH_IN_BYTES: usize = 29;
+const SUBACCOUNT_LENGTH_IN_BYTES: usize = 32;
+const ACCOUNT_KEY_LENGTH_IN_BYTES: usize =
+    1 + PRINCIPAL_MAX_LENGTH_IN_BYTES + SUBACCOUNT_LENGTH_IN_BYTES;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AccountKey(AccountInternal);
+
+impl Storable for AccountKey {
+    /// The length of the principal, the principal padded with zeroes to the max length and the
+    /// subaccount.
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let principal = self.0.owner.as_slice();
+        let mut buf = Vec::with_capacity(ACCOUNT_KEY_LENGTH_IN_BYTES);
+        buf.push(principal.len() as u8);
+        buf.extend_from_slice(principal);
+        buf.resize(1 + PRINCIPAL_MAX_LENGTH_IN_BYTES, 0);
+        buf.extend_from_slice(&self.0.subaccount);
+        buf.into()
+    }
+
+    /// Expected `bytes.len() == ACCOUNT_KEY_LENGTH_IN_BYTES`.
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let principal_len = bytes[0] as usize;
+        let owner = Principal::from_slice(&bytes[1..1 + principal_len]);
+        let mut subaccount = [0u8; SUBACCOUNT_LENGTH_IN_BYTES];
+        subaccount.copy_from_slice(&bytes[1 + PRINCIPAL_MAX_LENGTH_IN_BYTES..]);
+        Self(AccountInternal::new(owner, Some(subaccount)))
+    }
+}
+
+impl BoundedStorable for AccountKey {
+    const MAX_SIZE: u32 = ACCOUNT_KEY_LENGTH_IN_BYTES as _;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AllowanceValue {
+    amount: Tokens128,
+    expires_at: Option<Timestamp>,
+}
+
+impl AllowanceValue {
+    fn is_expired(&self, now: Timestamp) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| expires_at <= now)
+    }
+}
+
+const ALLOWANCE_VALUE_LENGTH_IN_BYTES: usize = 16 + 1 + 8;
+
+impl Storable for AllowanceValue {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut buf = Vec::with_capacity(ALLOWANCE_VALUE_LENGTH_IN_BYTES);
+        buf.extend_from_slice(&self.amount.amount.to_le_bytes());
+        buf.push(self.expires_at.is_some() as u8);
+        buf.extend_from_slice(&self.expires_at.unwrap_or_default().to_le_bytes());
+        buf.into()
+    }
+
+    /// Expected `bytes.len() == ALLOWANCE_VALUE_LENGTH_IN_BYTES`.
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let amount = u128::from_le_bytes(bytes[..16].try_into().expect("invalid amount bytes"));
+        let expires_at = u64::from_le_bytes(bytes[17..].try_into().expect("invalid time bytes"));
+        Self {
+            amount: Tokens128::from(amount),
+            expires_at: (bytes[16] != 0).then_some(expires_at),
+        }
+    }
+}
+
+impl BoundedStorable for AllowanceValue {
+    const MAX_SIZE: u32 = ALLOWANCE_VALUE_LENGTH_IN_BYTES as _;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+const ALLOWANCES_MEMORY_ID: MemoryId = MemoryId::new(12);
+
+thread_local! {
+    static MAP: RefCell<StableMultimap<AccountKey, AccountKey, AllowanceValue>> =
+        RefCell::new(StableMultimap::new(ALLOWANCES_MEMORY_ID));
+}
@@ -0,0 +1,99 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+
+use crate::state::config::{Timestamp, TokenConfig};
+use crate::state::fee_oracle::OraclePrice;
+use crate::state::ledger::Operation;
+use crate::tx_record::{TxId, TxRecord};
+
+const MAX_ANALYTICS_LENGTH: usize = 100_000;
+
+thread_local! {
+    static ANALYTICS_LEDGER: RefCell<HashMap<Principal, VecDeque<AnalyticsRecord>>> = RefCell::default();
+}
+
+/// Transaction record enriched with the data useful for analytics.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct AnalyticsRecord {
+    pub tx_id: TxId,
+    pub timestamp: Timestamp,
+    pub operation: Operation,
+    pub amount: Tokens128,
+    pub fee: Tokens128,
+    /// Fiat value of the amount at the price reported by the fee oracle, if the price is fresh.
+    pub fiat_value: Option<u128>,
+    pub tags: Vec<String>,
+}
+
+/// Bounded log of the analytics records kept in heap memory separately from the ledger. It is
+/// written only while the analytics ledger is enabled, and is never read by the transaction
+/// logic.
+pub struct AnalyticsLedger;
+
+impl AnalyticsLedger {
+    /// Store the analytics record for the transaction, if the analytics ledger is enabled.
+    pub fn record(tx: &TxRecord) {
+        let config = TokenConfig::get_stable();
+        if !config.is_analytics_enabled() {
+            return;
+        }
+
+        let fiat_value = config.fee_oracle.and_then(|oracle| {
+            let price = OraclePrice::get_stable().fresh_price(oracle.price_ttl_nanos)?;
+            let units = 10u128.checked_pow(config.decimals as u32)?;
+            tx.amount
+                .amount
+                .checked_mul(price)
+                .map(|value| value / units)
+        });
+
+        let record = AnalyticsRecord {
+            tx_id: tx.index,
+            timestamp: tx.timestamp,
+            operation: tx.operation,
+            amount: tx.amount,
+            fee: tx.fee,
+            fiat_value,
+            tags: tx.tags.clone().unwrap_or_default(),
+        };
+
+        Self::with_log(|log| {
+            if log.len() == MAX_ANALYTICS_LENGTH {
+                log.pop_front();
+            }
+            log.push_back(record);
+        })
+    }
+
+    /// List up to `count` records of the transactions with ids not less than `start`, the oldest
+    /// first.
+    pub fn list(start: TxId, count: usize) -> Vec<AnalyticsRecord> {
+        Self::with_log(|log| {
+            log.iter()
+                .skip_while(|record| record.tx_id < start)
+                .take(count)
+                .cloned()
+                .collect()
+        })
+    }
+
+    pub fn clear() {
+        Self::with_log(|log| log.clear())
+    }
+
+    fn with_log<F, R>(f: F) -> R
+    where
+        F: FnOnce(&mut VecDeque<AnalyticsRecord>) -> R,
+    {
+        ANALYTICS_LEDGER.with(|logs| {
+            let canister_id = ic::id();
+            let mut borrowed = logs.borrow_mut();
+            let log = borrowed.entry(canister_id).or_default();
+            f(log)
+        })
+    }
+}
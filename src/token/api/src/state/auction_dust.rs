@@ -0,0 +1,46 @@
+use std::cell::RefCell;
+
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{MemoryId, StableCell};
+
+use crate::state::config::FeeRatio;
+
+/// Number of dust units in one token.
+pub const DUST_UNITS_PER_TOKEN: u64 = 1_000_000;
+const AUCTION_DUST_MEMORY_ID: MemoryId = MemoryId::new(5);
+
+thread_local! {
+    static PENDING_AUCTION_DUST: RefCell<StableCell<u64>> =
+        RefCell::new(StableCell::new(AUCTION_DUST_MEMORY_ID, 0)
+            .expect("unable to initialize pending auction dust"));
+}
+
+/// Accumulator of the auction fee portions lost to rounding.
+pub struct AuctionDust;
+
+impl AuctionDust {
+    /// Accumulated fractional auction fees in `1 / DUST_UNITS_PER_TOKEN` token units.
+    pub fn pending() -> u64 {
+        PENDING_AUCTION_DUST.with(|dust| *dust.borrow().get())
+    }
+
+    /// Adds the fractional part of the auction portion of the `fee`, that is rounded down by
+    /// `FeeRatio::get_value`, to the accumulator. Returns the number of whole tokens accumulated,
+    /// which must be moved into the auction pool.
+    pub fn accumulate(fee: Tokens128, ratio: FeeRatio) -> Tokens128 {
+        let exact_auction_fee = f64::from(fee) * f64::from(ratio);
+        let dust = (exact_auction_fee.fract() * DUST_UNITS_PER_TOKEN as f64) as u64;
+        let total = Self::pending().saturating_add(dust);
+
+        Self::set_pending(total % DUST_UNITS_PER_TOKEN);
+        Tokens128::from((total / DUST_UNITS_PER_TOKEN) as u128)
+    }
+
+    fn set_pending(value: u64) {
+        PENDING_AUCTION_DUST.with(|dust| {
+            dust.borrow_mut()
+                .set(value)
+                .expect("fail to write pending auction dust")
+        });
+    }
+}
@@ -0,0 +1,428 @@
+//! Merkle tree over the balances, which is updated on every balance change instead of being
+//! rebuilt from all the balances. The tree is a crit-bit trie keyed by the hash of the account, so
+//! its shape (and root) depends only on the set of the balances, not on the order of the changes.
+//!
+//! The tree lives on the heap. It is built from the stable balances on the first use after an
+//! upgrade and then kept in sync by `StableBalances`.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+
+use candid::Principal;
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use sha2::{Digest, Sha256};
+
+use crate::account::{AccountInternal, Subaccount};
+use crate::state::balances::{Balances, StableBalances};
+use crate::state::fingerprint::{balance_hash, hash_pair, Hash, ProofStep};
+
+#[derive(Debug)]
+enum Node {
+    Leaf {
+        key: Hash,
+        amount: Tokens128,
+        hash: Hash,
+    },
+    Branch {
+        /// Index of the first bit in which the keys of the children differ. Keys of the left
+        /// child have the bit unset.
+        bit: usize,
+        left: Box<Node>,
+        right: Box<Node>,
+        hash: Hash,
+    },
+}
+
+impl Node {
+    fn leaf(account: &AccountInternal, key: Hash, amount: Tokens128) -> Box<Self> {
+        Box::new(Self::Leaf {
+            key,
+            amount,
+            hash: balance_hash(account, amount),
+        })
+    }
+
+    fn branch(bit: usize, left: Box<Node>, right: Box<Node>) -> Box<Self> {
+        let hash = hash_pair(left.hash(), right.hash());
+        Box::new(Self::Branch {
+            bit,
+            left,
+            right,
+            hash,
+        })
+    }
+
+    fn hash(&self) -> &Hash {
+        match self {
+            Self::Leaf { hash, .. } | Self::Branch { hash, .. } => hash,
+        }
+    }
+
+    /// Returns the key of the leaf the search for the `key` ends in.
+    fn closest_key(&self, key: &Hash) -> &Hash {
+        match self {
+            Self::Leaf { key, .. } => key,
+            Self::Branch {
+                bit, left, right, ..
+            } => {
+                if bit_at(key, *bit) {
+                    right.closest_key(key)
+                } else {
+                    left.closest_key(key)
+                }
+            }
+        }
+    }
+}
+
+/// Merkle tree over the balances along with the aggregates of the balances.
+#[derive(Debug, Default)]
+pub struct BalanceTree {
+    root: Option<Box<Node>>,
+    total_supply: Tokens128,
+    /// Number of the accounts of each holder.
+    holders: HashMap<Principal, usize>,
+    /// Non-zero balances ordered by the principal bytes and then by the subaccount, for the
+    /// paginated list of the holders.
+    holder_accounts: BTreeMap<(Vec<u8>, Subaccount), Tokens128>,
+}
+
+impl BalanceTree {
+    pub fn from_balances(balances: impl IntoIterator<Item = (AccountInternal, Tokens128)>) -> Self {
+        let mut tree = Self::default();
+        for (account, amount) in balances {
+            tree.insert(&account, amount);
+        }
+
+        tree
+    }
+
+    /// Root hash of the tree. The root of an empty tree is all zeroes.
+    pub fn root_hash(&self) -> Hash {
+        self.root
+            .as_ref()
+            .map_or_else(Hash::default, |root| *root.hash())
+    }
+
+    pub fn total_supply(&self) -> Tokens128 {
+        self.total_supply
+    }
+
+    /// Number of the principals having at least one account.
+    pub fn holder_count(&self) -> usize {
+        self.holders.len()
+    }
+
+    /// Number of the accounts with non-zero balances.
+    pub fn nonzero_account_count(&self) -> usize {
+        self.holder_accounts.len()
+    }
+
+    /// Returns up to `limit` accounts with non-zero balances following the `start` account in the
+    /// order of `holder_accounts`.
+    pub fn holders_after(
+        &self,
+        start: Option<&AccountInternal>,
+        limit: usize,
+    ) -> Vec<(AccountInternal, Tokens128)> {
+        let from = start.map_or(Bound::Unbounded, |start| Bound::Excluded(holder_key(start)));
+        self.holder_accounts
+            .range((from, Bound::Unbounded))
+            .take(limit)
+            .map(|((owner, subaccount), amount)| {
+                let owner = Principal::from_slice(owner);
+                (AccountInternal::new(owner, Some(*subaccount)), *amount)
+            })
+            .collect()
+    }
+
+    /// Sets the balance of the `account`.
+    pub fn insert(&mut self, account: &AccountInternal, amount: Tokens128) {
+        let key = account_key(account);
+        let old_amount = match self.root.take() {
+            None => {
+                self.root = Some(Node::leaf(account, key, amount));
+                None
+            }
+            Some(root) => {
+                let crit_bit = first_different_bit(root.closest_key(&key), &key);
+                let (root, old_amount) = insert(root, account, key, amount, crit_bit);
+                self.root = Some(root);
+                old_amount
+            }
+        };
+
+        if old_amount.is_none() {
+            *self.holders.entry(account.owner).or_default() += 1;
+        }
+
+        if amount.is_zero() {
+            self.holder_accounts.remove(&holder_key(account));
+        } else {
+            self.holder_accounts.insert(holder_key(account), amount);
+        }
+
+        self.total_supply = Tokens128::from(
+            self.total_supply
+                .amount
+                .checked_sub(old_amount.unwrap_or_default().amount)
+                .and_then(|supply| supply.checked_add(amount.amount))
+                .expect("total supply integer overflow"), // Checked at mint
+        );
+    }
+
+    /// Removes the balance of the `account`.
+    pub fn remove(&mut self, account: &AccountInternal) {
+        let key = account_key(account);
+        let (root, removed) = match self.root.take() {
+            Some(root) => remove(root, &key),
+            None => return,
+        };
+        self.root = root;
+        self.holder_accounts.remove(&holder_key(account));
+
+        if let Some(amount) = removed {
+            self.total_supply = Tokens128::from(self.total_supply.amount - amount.amount);
+            if let Some(count) = self.holders.get_mut(&account.owner) {
+                *count -= 1;
+                if *count == 0 {
+                    self.holders.remove(&account.owner);
+                }
+            }
+        }
+    }
+
+    /// Returns the path from the leaf of the `account` to the root. `None` if the account has no
+    /// balance in the tree.
+    pub fn witness(&self, account: &AccountInternal) -> Option<Vec<ProofStep>> {
+        let key = account_key(account);
+        let mut node = self.root.as_deref()?;
+        let mut path = vec![];
+        loop {
+            match node {
+                Node::Leaf { key: leaf_key, .. } => {
+                    if *leaf_key != key {
+                        return None;
+                    }
+
+                    path.reverse();
+                    return Some(path);
+                }
+                Node::Branch {
+                    bit, left, right, ..
+                } => {
+                    let (next, sibling, is_left) = if bit_at(&key, *bit) {
+                        (right, left, true)
+                    } else {
+                        (left, right, false)
+                    };
+                    path.push(ProofStep {
+                        hash: *sibling.hash(),
+                        is_left,
+                    });
+                    node = next;
+                }
+            }
+        }
+    }
+}
+
+/// Inserts the leaf into the subtree. The `crit_bit` is the first bit in which the `key` differs
+/// from the keys of the tree. Returns the updated subtree and the previous balance of the account.
+fn insert(
+    node: Box<Node>,
+    account: &AccountInternal,
+    key: Hash,
+    amount: Tokens128,
+    crit_bit: usize,
+) -> (Box<Node>, Option<Tokens128>) {
+    match *node {
+        Node::Leaf {
+            key: leaf_key,
+            amount: old_amount,
+            ..
+        } if leaf_key == key => (Node::leaf(account, key, amount), Some(old_amount)),
+        Node::Branch {
+            bit, left, right, ..
+        } if bit < crit_bit => {
+            let (left, right, old_amount) = if bit_at(&key, bit) {
+                let (right, old_amount) = insert(right, account, key, amount, crit_bit);
+                (left, right, old_amount)
+            } else {
+                let (left, old_amount) = insert(left, account, key, amount, crit_bit);
+                (left, right, old_amount)
+            };
+            (Node::branch(bit, left, right), old_amount)
+        }
+        node => {
+            let leaf = Node::leaf(account, key, amount);
+            let node = Box::new(node);
+            let branch = if bit_at(&key, crit_bit) {
+                Node::branch(crit_bit, node, leaf)
+            } else {
+                Node::branch(crit_bit, leaf, node)
+            };
+            (branch, None)
+        }
+    }
+}
+
+/// Removes the leaf with the `key` from the subtree. Returns the updated subtree and the balance
+/// of the removed leaf.
+fn remove(node: Box<Node>, key: &Hash) -> (Option<Box<Node>>, Option<Tokens128>) {
+    match *node {
+        Node::Leaf {
+            key: leaf_key,
+            amount,
+            ..
+        } if leaf_key == *key => (None, Some(amount)),
+        Node::Branch {
+            bit, left, right, ..
+        } => {
+            let (left, right, removed) = if bit_at(key, bit) {
+                let (right, removed) = remove(right, key);
+                (Some(left), right, removed)
+            } else {
+                let (left, removed) = remove(left, key);
+                (left, Some(right), removed)
+            };
+            let node = match (left, right) {
+                (Some(left), Some(right)) => Node::branch(bit, left, right),
+                (Some(child), None) | (None, Some(child)) => child,
+                (None, None) => unreachable!("branch always has two children"),
+            };
+            (Some(node), removed)
+        }
+        node => (Some(Box::new(node)), None),
+    }
+}
+
+fn account_key(account: &AccountInternal) -> Hash {
+    let owner = account.owner.as_slice();
+    let mut hasher = Sha256::new();
+    hasher.update([owner.len() as u8]);
+    hasher.update(owner);
+    hasher.update(account.subaccount);
+    hasher.finalize().into()
+}
+
+fn holder_key(account: &AccountInternal) -> (Vec<u8>, Subaccount) {
+    (account.owner.as_slice().to_vec(), account.subaccount)
+}
+
+fn bit_at(key: &Hash, bit: usize) -> bool {
+    key[bit / 8] & (0x80 >> (bit % 8)) != 0
+}
+
+fn first_different_bit(a: &Hash, b: &Hash) -> usize {
+    a.iter()
+        .zip(b)
+        .position(|(a, b)| a != b)
+        .map_or(a.len() * 8, |byte| {
+            byte * 8 + (a[byte] ^ b[byte]).leading_zeros() as usize
+        })
+}
+
+thread_local! {
+    static TREE: RefCell<Option<BalanceTree>> = RefCell::new(None);
+}
+
+/// Runs `f` with the tree of the current balances, building the tree if it was not built yet.
+pub fn with_balance_tree<R>(f: impl FnOnce(&BalanceTree) -> R) -> R {
+    TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        let tree = tree.get_or_insert_with(|| {
+            BalanceTree::from_balances(StableBalances.list_balances(0, usize::MAX))
+        });
+        f(tree)
+    })
+}
+
+/// Updates the tree after the balance of the `account` was set to `amount` or removed. Does
+/// nothing if the tree is not built yet, as it will be built from the updated balances.
+pub(crate) fn on_balance_change(account: &AccountInternal, amount: Option<Tokens128>) {
+    TREE.with(|tree| {
+        if let Some(tree) = tree.borrow_mut().as_mut() {
+            match amount {
+                Some(amount) => tree.insert(account, amount),
+                None => tree.remove(account),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use canister_sdk::ic_kit::mock_principals::{alice, bob, john, xtc};
+
+    use super::*;
+    use crate::state::fingerprint::root_from_proof;
+
+    fn accounts() -> Vec<(AccountInternal, Tokens128)> {
+        [alice(), bob(), john(), xtc()]
+            .into_iter()
+            .flat_map(|owner| {
+                (0..5u8).map(move |i| {
+                    let account = AccountInternal::new(owner, Some([i; 32]));
+                    (account, Tokens128::from(i as u128 * 10 + 1))
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty_tree() {
+        let tree = BalanceTree::default();
+        assert_eq!(tree.root_hash(), [0; 32]);
+        assert_eq!(tree.total_supply(), Tokens128::ZERO);
+        assert_eq!(tree.witness(&AccountInternal::new(alice(), None)), None);
+    }
+
+    #[test]
+    fn witness_leads_to_root() {
+        let balances = accounts();
+        let tree = BalanceTree::from_balances(balances.clone());
+        assert_eq!(tree.holder_count(), 4);
+        assert_eq!(tree.total_supply(), Tokens128::from(4 * 105));
+
+        for (account, amount) in &balances {
+            let witness = tree.witness(account).unwrap();
+            let leaf = balance_hash(account, *amount);
+            assert_eq!(root_from_proof(leaf, &witness), tree.root_hash());
+
+            let wrong_leaf = balance_hash(account, Tokens128::from(amount.amount + 1));
+            assert_ne!(root_from_proof(wrong_leaf, &witness), tree.root_hash());
+        }
+    }
+
+    #[test]
+    fn root_depends_only_on_balances() {
+        let balances = accounts();
+        let tree = BalanceTree::from_balances(balances.clone());
+        let reversed = BalanceTree::from_balances(balances.iter().rev().copied());
+        assert_eq!(tree.root_hash(), reversed.root_hash());
+
+        // Updating and removing the balances leads to the same tree as building it from scratch.
+        let mut updated = BalanceTree::from_balances(balances.clone());
+        let (changed, _) = balances[3];
+        updated.insert(&changed, Tokens128::from(1000));
+        for (account, _) in &balances[10..] {
+            updated.remove(account);
+        }
+
+        let mut expected = balances[..10].to_vec();
+        expected[3].1 = Tokens128::from(1000);
+        let expected = BalanceTree::from_balances(expected);
+        assert_eq!(updated.root_hash(), expected.root_hash());
+        assert_eq!(updated.total_supply(), expected.total_supply());
+        assert_eq!(updated.holder_count(), 2);
+
+        for (account, _) in &balances {
+            updated.remove(account);
+        }
+        assert_eq!(updated.root_hash(), [0; 32]);
+        assert_eq!(updated.total_supply(), Tokens128::ZERO);
+        assert_eq!(updated.holder_count(), 0);
+    }
+}
@@ -7,6 +7,7 @@ use canister_sdk::ic_helpers::tokens::Tokens128;
 use ic_stable_structures::{BoundedStorable, MemoryId, StableMultimap, Storable};
 
 use crate::account::{AccountInternal, Subaccount};
+use crate::state::balance_tree::on_balance_change;
 
 pub trait Balances {
     /// Write or re-write amount of tokens for specified account.
@@ -27,9 +28,14 @@ pub trait Balances {
         self.get(account).unwrap_or_default()
     }
 
-    /// Update balances according to `updates` iterator.
+    /// Update balances according to `updates` iterator. Zero balances of the accounts without a
+    /// balance are skipped, so staged but untouched accounts don't create new entries.
     fn apply_updates(&mut self, updates: impl IntoIterator<Item = (AccountInternal, Tokens128)>) {
         for (account, amount) in updates {
+            if amount.is_zero() && self.get(&account).is_none() {
+                continue;
+            }
+
             self.insert(account, amount);
         }
     }
@@ -104,6 +110,7 @@ impl Balances for StableBalances {
             map.borrow_mut()
                 .insert(&principal_key, &subaccount_key, &token.amount)
         });
+        on_balance_change(&account, Some(token));
     }
 
     /// Get amount of tokens for the specified account from stable memory.
@@ -118,8 +125,11 @@ impl Balances for StableBalances {
     fn remove(&mut self, account: &AccountInternal) -> Option<Tokens128> {
         let principal_key = PrincipalKey(account.owner);
         let subaccount_key = SubaccountKey(account.subaccount);
-        MAP.with(|map| map.borrow_mut().remove(&principal_key, &subaccount_key))
-            .map(Tokens128::from)
+        let removed = MAP
+            .with(|map| map.borrow_mut().remove(&principal_key, &subaccount_key))
+            .map(Tokens128::from);
+        on_balance_change(account, None);
+        removed
     }
 
     fn get_subaccounts(&self, owner: Principal) -> HashMap<Subaccount, Tokens128> {
@@ -206,7 +216,7 @@ const PRINCIPAL_MAX_LENGTH_IN_BYTES: usize = 29;
 const SUBACCOUNT_MAX_LENGTH_IN_BYTES: usize = 32;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct PrincipalKey(Principal);
+pub(crate) struct PrincipalKey(pub(crate) Principal);
 
 impl Storable for PrincipalKey {
     fn to_bytes(&self) -> Cow<'_, [u8]> {
@@ -220,7 +230,7 @@ impl Storable for PrincipalKey {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct SubaccountKey(Subaccount);
+pub(crate) struct SubaccountKey(pub(crate) Subaccount);
 
 impl Storable for SubaccountKey {
     fn to_bytes(&self) -> Cow<'_, [u8]> {
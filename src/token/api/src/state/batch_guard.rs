@@ -0,0 +1,63 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use candid::Principal;
+use canister_sdk::ic_kit::ic;
+
+use crate::error::TxError;
+
+thread_local! {
+    static IN_FLIGHT_BATCHES: RefCell<HashMap<Principal, HashMap<Principal, u32>>> =
+        RefCell::default();
+}
+
+/// Marks a batch operation of the caller as in flight for as long as the guard is alive.
+pub struct BatchGuard {
+    caller: Principal,
+}
+
+impl BatchGuard {
+    /// Registers a new batch operation of the `caller`. Returns
+    /// `TxError::TooManyConcurrentBatches` if the caller already has `limit` batches in flight.
+    pub fn enter(caller: Principal, limit: u32) -> Result<Self, TxError> {
+        with_counters(|counters| {
+            let count = counters.entry(caller).or_default();
+            if *count >= limit {
+                return Err(TxError::TooManyConcurrentBatches { limit });
+            }
+
+            *count += 1;
+            Ok(Self { caller })
+        })
+    }
+
+    /// Number of the batch operations of the `caller` currently in flight.
+    pub fn in_flight(caller: Principal) -> u32 {
+        with_counters(|counters| counters.get(&caller).copied().unwrap_or_default())
+    }
+}
+
+impl Drop for BatchGuard {
+    fn drop(&mut self) {
+        with_counters(|counters| {
+            if let Some(count) = counters.get_mut(&self.caller) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    counters.remove(&self.caller);
+                }
+            }
+        })
+    }
+}
+
+fn with_counters<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut HashMap<Principal, u32>) -> R,
+{
+    IN_FLIGHT_BATCHES.with(|batches| {
+        let canister_id = ic::id();
+        let mut borrowed = batches.borrow_mut();
+        let counters = borrowed.entry(canister_id).or_default();
+        f(counters)
+    })
+}
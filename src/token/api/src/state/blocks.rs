@@ -0,0 +1,197 @@
+//! ICRC-3 representation of the transaction history. Every transaction record is encoded as a
+//! block value map, which includes the hash of the previous block, so the history can be verified
+//! as a hash chain.
+
+use candid::{CandidType, Deserialize, Int, Nat};
+use num_traits::ToPrimitive;
+use sha2::{Digest, Sha256};
+
+use crate::account::Account;
+use crate::state::fingerprint::Hash;
+use crate::state::ledger::Operation;
+use crate::tx_record::TxRecord;
+
+/// Generic value of the ICRC-3 block.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub enum Icrc3Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(Nat),
+    Int(Int),
+    Array(Vec<Icrc3Value>),
+    Map(Vec<(String, Icrc3Value)>),
+}
+
+impl Icrc3Value {
+    /// Representation-independent hash of the value, as defined by the ICRC-3 standard.
+    pub fn hash(&self) -> Hash {
+        match self {
+            Self::Blob(bytes) => Sha256::digest(bytes).into(),
+            Self::Text(text) => Sha256::digest(text.as_bytes()).into(),
+            Self::Nat(nat) => {
+                let mut bytes = vec![];
+                nat.encode(&mut bytes).expect("failed to encode nat");
+                Sha256::digest(bytes).into()
+            }
+            Self::Int(int) => {
+                let mut bytes = vec![];
+                int.encode(&mut bytes).expect("failed to encode int");
+                Sha256::digest(bytes).into()
+            }
+            Self::Array(values) => {
+                let mut hasher = Sha256::new();
+                for value in values {
+                    hasher.update(value.hash());
+                }
+                hasher.finalize().into()
+            }
+            Self::Map(entries) => {
+                let mut entry_hashes = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        let mut entry_hash = Sha256::digest(key.as_bytes()).to_vec();
+                        entry_hash.extend_from_slice(&value.hash());
+                        entry_hash
+                    })
+                    .collect::<Vec<_>>();
+                entry_hashes.sort();
+
+                let mut hasher = Sha256::new();
+                for entry_hash in entry_hashes {
+                    hasher.update(entry_hash);
+                }
+                hasher.finalize().into()
+            }
+        }
+    }
+}
+
+impl From<Account> for Icrc3Value {
+    fn from(account: Account) -> Self {
+        let mut parts = vec![Self::Blob(account.owner.as_slice().to_vec())];
+        // The default subaccount is omitted.
+        if let Some(subaccount) = account.subaccount.filter(|s| *s != [0u8; 32]) {
+            parts.push(Self::Blob(subaccount.to_vec()));
+        }
+        Self::Array(parts)
+    }
+}
+
+/// Encodes the transaction record as an ICRC-3 block. The `parent_hash` is the hash of the
+/// previous block, if there is one.
+pub fn encode_block(record: &TxRecord, parent_hash: Option<Hash>) -> Icrc3Value {
+    let op = match record.operation {
+        Operation::Mint => "mint",
+        Operation::Burn => "burn",
+        Operation::Approve => "approve",
+        Operation::Transfer
+        | Operation::TransferFrom
+        | Operation::Auction
+        | Operation::Claim
+        | Operation::Reclaim
+        | Operation::Redemption => "xfer",
+    };
+
+    let mut tx = vec![
+        ("op".to_string(), Icrc3Value::Text(op.to_string())),
+        (
+            "amt".to_string(),
+            Icrc3Value::Nat(record.amount.amount.into()),
+        ),
+    ];
+    match record.operation {
+        Operation::Mint => tx.push(("to".to_string(), record.to.into())),
+        Operation::Burn => tx.push(("from".to_string(), record.from.into())),
+        Operation::Approve => {
+            tx.push(("from".to_string(), record.from.into()));
+            tx.push(("spender".to_string(), record.to.into()));
+        }
+        _ => {
+            tx.push(("from".to_string(), record.from.into()));
+            tx.push(("to".to_string(), record.to.into()));
+        }
+    }
+    if let Some(memo) = record.memo {
+        tx.push(("memo".to_string(), Icrc3Value::Blob(memo.to_vec())));
+    }
+
+    let mut block = vec![
+        ("ts".to_string(), Icrc3Value::Nat(record.timestamp.into())),
+        ("fee".to_string(), Icrc3Value::Nat(record.fee.amount.into())),
+        ("tx".to_string(), Icrc3Value::Map(tx)),
+    ];
+    if let Some(parent_hash) = parent_hash {
+        block.push(("phash".to_string(), Icrc3Value::Blob(parent_hash.to_vec())));
+    }
+
+    Icrc3Value::Map(block)
+}
+
+/// Range of the blocks requested by `icrc3_get_blocks`.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct GetBlocksArgs {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+impl GetBlocksArgs {
+    /// Id of the first requested block. Values out of the `u64` range are saturated.
+    pub fn start(&self) -> u64 {
+        self.start.0.to_u64().unwrap_or(u64::MAX)
+    }
+
+    /// Number of the requested blocks. Values out of the `u64` range are saturated.
+    pub fn length(&self) -> u64 {
+        self.length.0.to_u64().unwrap_or(u64::MAX)
+    }
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct BlockWithId {
+    pub id: Nat,
+    pub block: Icrc3Value,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct GetBlocksResult {
+    /// Total number of the blocks in the log, including the removed ones.
+    pub log_length: Nat,
+    pub blocks: Vec<BlockWithId>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nat_hash() {
+        // Example from the ICRC-3 standard.
+        assert_eq!(
+            Icrc3Value::Nat(42u64.into()).hash(),
+            [
+                0x68, 0x48, 0x88, 0xc0, 0xeb, 0xb1, 0x7f, 0x37, 0x42, 0x98, 0xb6, 0x5e, 0xe2, 0x80,
+                0x75, 0x26, 0xc0, 0x66, 0x09, 0x4c, 0x70, 0x1b, 0xcc, 0x7e, 0xbb, 0xe1, 0xc1, 0x09,
+                0x5f, 0x49, 0x4f, 0xc1
+            ]
+        );
+    }
+
+    #[test]
+    fn map_hash_does_not_depend_on_entries_order() {
+        let entries = vec![
+            ("a".to_string(), Icrc3Value::Nat(1u64.into())),
+            ("b".to_string(), Icrc3Value::Text("value".to_string())),
+        ];
+        let mut reversed = entries.clone();
+        reversed.reverse();
+
+        assert_eq!(
+            Icrc3Value::Map(entries.clone()).hash(),
+            Icrc3Value::Map(reversed).hash()
+        );
+        assert_ne!(
+            Icrc3Value::Map(entries.clone()).hash(),
+            Icrc3Value::Array(entries.into_iter().map(|(_, value)| value).collect()).hash()
+        );
+    }
+}
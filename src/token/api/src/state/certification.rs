@@ -0,0 +1,70 @@
+//! Certification of the token state. After every recorded transaction the canister sets its
+//! certified data to the hash of the total supply and the merkle root of the balances, so the
+//! clients can verify the query responses against the IC certificate instead of trusting a single
+//! replica.
+
+use candid::{CandidType, Deserialize};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+use sha2::{Digest, Sha256};
+
+use crate::account::AccountInternal;
+use crate::state::balance_tree::with_balance_tree;
+use crate::state::balances::{Balances, StableBalances};
+use crate::state::fingerprint::{balance_hash, root_from_proof, Hash, ProofStep};
+
+/// Balance of an account along with the data needed to verify it.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct CertifiedBalance {
+    pub balance: Tokens128,
+    pub total_supply: Tokens128,
+    /// Path from the account balance to the merkle root of the balances. Empty for the accounts
+    /// without a balance, which cannot be verified.
+    pub witness: Vec<ProofStep>,
+    /// IC certificate of the canister certified data. Only available in query calls.
+    pub certificate: Option<Vec<u8>>,
+}
+
+impl CertifiedBalance {
+    pub fn get(account: AccountInternal) -> Self {
+        let (total_supply, witness) = with_balance_tree(|tree| {
+            (
+                tree.total_supply(),
+                tree.witness(&account).unwrap_or_default(),
+            )
+        });
+
+        Self {
+            balance: StableBalances.balance_of(&account),
+            total_supply,
+            witness,
+            certificate: ic::data_certificate(),
+        }
+    }
+
+    /// Returns the certified data the balance of the `account` is proven against. It must be
+    /// equal to the data in the certificate.
+    pub fn certified_data(&self, account: AccountInternal) -> Hash {
+        let root = root_from_proof(balance_hash(&account, self.balance), &self.witness);
+        state_hash(self.total_supply, &root)
+    }
+}
+
+/// Hash of the current total supply and balances.
+pub fn certified_data() -> Hash {
+    with_balance_tree(|tree| state_hash(tree.total_supply(), &tree.root_hash()))
+}
+
+/// Sets the canister certified data to the hash of the current state. Must be called after every
+/// change of the balances. The balance tree is updated together with the balances, so the call is
+/// cheap unless the tree is not built yet.
+pub fn certify_state() {
+    ic::set_certified_data(&certified_data());
+}
+
+fn state_hash(total_supply: Tokens128, balances_root: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(total_supply.amount.to_be_bytes());
+    hasher.update(balances_root);
+    hasher.finalize().into()
+}
@@ -0,0 +1,90 @@
+use std::{borrow::Cow, cell::RefCell};
+
+use candid::{CandidType, Decode, Deserialize, Encode};
+use canister_sdk::ic_kit::ic;
+use ic_stable_structures::{MemoryId, StableCell, Storable};
+
+use crate::account::AccountInternal;
+use crate::state::config::Timestamp;
+
+/// Creation times of the claims, keyed by the claim account. A claim is created by the first
+/// deposit to its account and is removed when it is claimed or reclaimed. Claim accounts without a
+/// creation time never expire.
+#[derive(Debug, Default, Clone, CandidType, Deserialize)]
+pub struct Claims {
+    created_at: Vec<(AccountInternal, Timestamp)>,
+}
+
+impl Claims {
+    /// Get claims stored in stable memory.
+    pub fn get_stable() -> Self {
+        CELL.with(|c| c.borrow().get().clone())
+    }
+
+    /// Store claims in stable memory.
+    pub fn set_stable(claims: Self) {
+        CELL.with(|c| c.borrow_mut().set(claims))
+            .expect("unable to set claims to stable memory");
+    }
+
+    /// Records the deposit to the claim `account`. Deposits to an existing claim keep its
+    /// creation time.
+    pub fn record_deposit(account: AccountInternal) {
+        let mut stored = Self::get_stable();
+        if !stored.created_at.iter().any(|(a, _)| *a == account) {
+            stored.created_at.push((account, ic::time()));
+            Self::set_stable(stored);
+        }
+    }
+
+    pub fn created_at(account: AccountInternal) -> Option<Timestamp> {
+        Self::get_stable()
+            .created_at
+            .into_iter()
+            .find(|(a, _)| *a == account)
+            .map(|(_, created_at)| created_at)
+    }
+
+    pub fn is_expired(account: AccountInternal, expiry_nanos: u64) -> bool {
+        Self::created_at(account).map_or(false, |created_at| is_expired(created_at, expiry_nanos))
+    }
+
+    pub fn remove(account: AccountInternal) {
+        let mut stored = Self::get_stable();
+        stored.created_at.retain(|(a, _)| *a != account);
+        Self::set_stable(stored);
+    }
+
+    /// Returns the accounts of the expired claims.
+    pub fn expired(expiry_nanos: u64) -> Vec<AccountInternal> {
+        Self::get_stable()
+            .created_at
+            .into_iter()
+            .filter(|(_, created_at)| is_expired(*created_at, expiry_nanos))
+            .map(|(account, _)| account)
+            .collect()
+    }
+}
+
+fn is_expired(created_at: Timestamp, expiry_nanos: u64) -> bool {
+    created_at.saturating_add(expiry_nanos) <= ic::time()
+}
+
+impl Storable for Claims {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode claims"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode claims")
+    }
+}
+
+const CLAIMS_MEMORY_ID: MemoryId = MemoryId::new(14);
+
+thread_local! {
+    static CELL: RefCell<StableCell<Claims>> = {
+            RefCell::new(StableCell::new(CLAIMS_MEMORY_ID, Claims::default())
+                .expect("stable memory claims initialization failed"))
+    }
+}
@@ -5,6 +5,12 @@ use ic_exports::candid::{CandidType, Decode, Deserialize, Encode, Int, Nat};
 use ic_exports::Principal;
 use ic_stable_structures::{MemoryId, StableCell, Storable};
 
+use crate::account::{Account, AccountInternal, Subaccount};
+use crate::canister::icrc1_transfer::{PERMITTED_DRIFT, TX_WINDOW};
+use crate::canister::is20_archive::ArchiveOptions;
+use crate::state::fee_oracle::FeeOracle;
+use crate::state::ledger::LedgerData;
+
 #[derive(Deserialize, CandidType, Clone, Debug)]
 pub struct TokenConfig {
     pub name: String,
@@ -16,6 +22,105 @@ pub struct TokenConfig {
     pub deploy_time: u64,
     pub min_cycles: u64,
     pub is_test_token: bool,
+    /// Maximum number of records returned by one page of a paginated query. If not set, the
+    /// default limits of the queries are used.
+    pub max_query_page_size: Option<usize>,
+    /// If set, the cycle auction is run as soon as the accumulated fees reach this amount, even
+    /// if the auction period is not over yet.
+    pub auction_pool_trigger: Option<Tokens128>,
+    /// If set, owner updates of the canister configuration are queued and can be executed only
+    /// after this delay (in nanoseconds) has passed.
+    pub governance_delay_nanos: Option<u64>,
+    /// If enabled, rejected transfers are stored in the failed transactions log.
+    pub failed_tx_log_enabled: Option<bool>,
+    /// If set, operations that add new balance entries are rejected with `TxError::StorageFull`
+    /// while the stable memory size (in bytes) is not less than this value.
+    pub storage_high_water_mark: Option<u64>,
+    /// Treasury and other accounts which balances are not counted in the circulating supply.
+    pub circulation_excluded_accounts: Option<Vec<Account>>,
+    /// If enabled, the fractions of the auction fees lost to rounding are accumulated and moved
+    /// into the auction pool once they sum up to a whole token.
+    pub auction_dust_sinking: Option<bool>,
+    /// The way the transfer fee is calculated. If not set, `FeeMode::Flat` is used.
+    pub fee_mode: Option<FeeMode>,
+    /// If set, the owner can reclaim balances of the accounts with no activity for this period
+    /// (in nanoseconds). See `reclaim_dormant` method for details.
+    pub dormancy_period_nanos: Option<u64>,
+    /// Receiver of the tokens accumulated in the auction pool. If not set, the pool is
+    /// distributed between the auction bidders.
+    pub auction_beneficiary: Option<AuctionBeneficiary>,
+    /// If set, the effective fee is capped by a fiat amount using the price reported by the
+    /// oracle.
+    pub fee_oracle: Option<FeeOracle>,
+    /// If set to `false`, tokens can only be minted and burned, but not transferred. Not set
+    /// value means `true`.
+    pub transferable: Option<bool>,
+    /// If set, the flat fee grows with the ledger length instead of being `fee`.
+    pub dynamic_fee: Option<DynamicFeeParams>,
+    /// If enabled, an enriched analytics record is stored for every transaction.
+    pub analytics_enabled: Option<bool>,
+    /// Account holding the auction pool. If not set, the default account of the management
+    /// canister principal is used.
+    pub auction_account: Option<Account>,
+    /// Maximum number of batch operations one caller can have in flight at the same time. If not
+    /// set, `DEFAULT_MAX_CONCURRENT_BATCHES` is used.
+    pub max_concurrent_batches: Option<u32>,
+    /// If enabled, transfers to canisters which are not registered as token-aware are rejected.
+    /// Disabled by default, as ICRC-1 doesn't restrict the recipients.
+    pub block_canister_recipients: Option<bool>,
+    /// Principal which installed the canister. For the tokens deployed by the factory this is
+    /// the factory canister, which holds the upgrade rights over the token.
+    pub deployer: Option<Principal>,
+    /// Period (in nanoseconds) during which a prepared transfer can be committed. If not set,
+    /// `DEFAULT_PREPARED_TRANSFER_TTL_NANOS` is used.
+    pub prepared_transfer_ttl_nanos: Option<u64>,
+    /// Period (in nanoseconds) during which a fee quote is honored. If not set,
+    /// `DEFAULT_FEE_QUOTE_TTL_NANOS` is used.
+    pub fee_quote_ttl_nanos: Option<u64>,
+    /// If enabled, the queries work as usual, but all the update methods are rejected with
+    /// `TxError::UnderMaintenance`, except for turning the maintenance mode off.
+    pub maintenance: Option<bool>,
+    /// Period (in nanoseconds) after which the unclaimed claims expire. If not set, the claims
+    /// effectively never expire.
+    pub claim_expiry_nanos: Option<u64>,
+    /// Account receiving the royalty on transfers and the royalty rate in basis points of the
+    /// transferred amount. The royalty is deducted from the amount received by the recipient.
+    pub royalty: Option<(Account, u16)>,
+    /// Emergency stop of all the transfers. Unlike the non-transferable token, the pause is
+    /// expected to be lifted. The owner can still mint and burn tokens while paused.
+    pub paused: Option<bool>,
+    /// Maximum total supply of the token. Mints which would raise the total supply above it are
+    /// rejected. If not set, the supply is unlimited.
+    pub max_supply: Option<Tokens128>,
+    /// Token logo, usually a data URL of the image. Reported as `icrc1:logo` metadata if set.
+    pub logo: Option<String>,
+    /// Subaccount of `fee_to` receiving the fees. If not set, the fees go to the default
+    /// subaccount.
+    pub fee_to_subaccount: Option<Subaccount>,
+    /// Period (in nanoseconds) during which a transaction with `created_at_time` is accepted and
+    /// deduplicated. If not set, `TX_WINDOW` is used.
+    pub tx_window_nanos: Option<u64>,
+    /// Allowed clock difference (in nanoseconds) for the transactions created in the future. If
+    /// not set, `PERMITTED_DRIFT` is used.
+    pub permitted_drift_nanos: Option<u64>,
+    /// Part of every transfer burned, in basis points of the transferred amount. The burned
+    /// tokens are deducted from the amount received by the recipient.
+    pub burn_rate: Option<u16>,
+    /// Principal proposed as the new owner. The ownership is transferred when it calls
+    /// `accept_ownership`.
+    pub pending_owner: Option<Principal>,
+    /// Part of the fees going to the auction, set by the owner. If not set, the ratio computed by
+    /// the cycle auction is used.
+    pub auction_fee_ratio: Option<FeeRatio>,
+    /// Minimum amount of cycles accepted as an auction bid. Cannot be lower than the
+    /// `MIN_BIDDING_AMOUNT` of the auction.
+    pub min_auction_bid: Option<u64>,
+    /// If enabled, zero amount transfers are rejected with `TxError::AmountTooSmall` before any
+    /// other validation of the transfer.
+    pub reject_zero_transfers: Option<bool>,
+    /// If set, the old transactions are moved to the archive canister instead of being kept in
+    /// the canister memory.
+    pub archive_options: Option<ArchiveOptions>,
 }
 
 impl TokenConfig {
@@ -30,12 +135,155 @@ impl TokenConfig {
             .expect("unable to set token config to stable memory")
     }
 
-    pub fn fee_info(&self) -> (Tokens128, Principal) {
-        (self.fee, self.fee_to)
+    pub fn fee_info(&self) -> (Tokens128, Account) {
+        (self.fee, self.fee_to_account().into())
+    }
+
+    /// Account receiving the owner part of the fees.
+    pub fn fee_to_account(&self) -> AccountInternal {
+        AccountInternal::new(self.fee_to, self.fee_to_subaccount)
+    }
+
+    /// Returns the fee charged for a transfer of the `amount` of tokens.
+    pub fn effective_fee(&self, amount: Tokens128) -> Tokens128 {
+        let fee = self.fee_before_cap(amount);
+        match self.fee_oracle {
+            Some(oracle) => oracle.cap(fee, self.decimals),
+            None => fee,
+        }
+    }
+
+    fn fee_before_cap(&self, amount: Tokens128) -> Tokens128 {
+        match self.fee_mode.unwrap_or(FeeMode::Flat) {
+            FeeMode::Flat => self.flat_fee(),
+            FeeMode::BasisPoints(basis_points) => basis_points_of(amount, basis_points),
+        }
+    }
+
+    /// Returns the royalty account and the royalty charged on a transfer of the `amount` of
+    /// tokens, if the royalty is configured. The royalty never exceeds the `amount`.
+    pub fn royalty(&self, amount: Tokens128) -> Option<(AccountInternal, Tokens128)> {
+        self.royalty.map(|(account, basis_points)| {
+            let royalty = basis_points_of(amount, basis_points).min(amount);
+            (account.into(), royalty)
+        })
+    }
+
+    /// Returns the amount burned on a transfer of the `amount` of tokens. The burn never exceeds
+    /// the `amount`.
+    pub fn transfer_burn(&self, amount: Tokens128) -> Tokens128 {
+        self.burn_rate
+            .map(|basis_points| basis_points_of(amount, basis_points).min(amount))
+            .unwrap_or(Tokens128::ZERO)
+    }
+
+    /// Returns the auction part of the fees: the ratio set by the owner if any, or the
+    /// `auction_ratio` computed by the cycle auction otherwise.
+    pub fn effective_fee_ratio(&self, auction_ratio: f64) -> f64 {
+        self.auction_fee_ratio
+            .map_or(auction_ratio, |ratio| ratio.value())
+    }
+
+    /// Returns the fee reported by `icrc1_fee` and the `icrc1:fee` metadata entry, which is the fee
+    /// of a zero amount transfer. In `FeeMode::BasisPoints` mode the fee grows with the amount, so
+    /// this is the minimum fee.
+    pub fn icrc1_fee(&self) -> Tokens128 {
+        self.effective_fee(Tokens128::ZERO)
+    }
+
+    /// Returns the fee charged in `FeeMode::Flat` mode, taking the dynamic fee into account.
+    pub fn flat_fee(&self) -> Tokens128 {
+        match self.dynamic_fee {
+            Some(params) => params.fee(LedgerData::len()),
+            None => self.fee,
+        }
+    }
+
+    /// Returns the maximum page size for a paginated query with the given default limit. The
+    /// configured value can never exceed `MAX_QUERY_PAGE_SIZE`.
+    pub fn query_page_size(&self, default: usize) -> usize {
+        self.max_query_page_size
+            .map_or(default, |size| size.min(MAX_QUERY_PAGE_SIZE))
+    }
+
+    pub fn is_transferable(&self) -> bool {
+        self.transferable.unwrap_or(true)
     }
 
+    pub fn is_analytics_enabled(&self) -> bool {
+        self.analytics_enabled.unwrap_or(false)
+    }
+
+    pub fn max_concurrent_batches(&self) -> u32 {
+        self.max_concurrent_batches
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_BATCHES)
+    }
+
+    pub fn prepared_transfer_ttl_nanos(&self) -> u64 {
+        self.prepared_transfer_ttl_nanos
+            .unwrap_or(DEFAULT_PREPARED_TRANSFER_TTL_NANOS)
+    }
+
+    pub fn fee_quote_ttl_nanos(&self) -> u64 {
+        self.fee_quote_ttl_nanos
+            .unwrap_or(DEFAULT_FEE_QUOTE_TTL_NANOS)
+    }
+
+    pub fn tx_window_nanos(&self) -> u64 {
+        self.tx_window_nanos.unwrap_or(TX_WINDOW)
+    }
+
+    pub fn permitted_drift_nanos(&self) -> u64 {
+        self.permitted_drift_nanos.unwrap_or(PERMITTED_DRIFT)
+    }
+
+    pub fn is_canister_recipients_blocking_enabled(&self) -> bool {
+        self.block_canister_recipients.unwrap_or(false)
+    }
+
+    /// Principal allowed to upgrade the canister: the deployer if known, the owner otherwise.
+    pub fn upgrade_authority(&self) -> Principal {
+        self.deployer.unwrap_or(self.owner)
+    }
+
+    /// Principals known to control the canister, without duplicates.
+    pub fn controllers(&self) -> Vec<Principal> {
+        let authority = self.upgrade_authority();
+        if authority == self.owner {
+            vec![authority]
+        } else {
+            vec![authority, self.owner]
+        }
+    }
+
+    pub fn is_under_maintenance(&self) -> bool {
+        self.maintenance.unwrap_or(false)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.unwrap_or(false)
+    }
+
+    pub fn is_zero_transfers_rejection_enabled(&self) -> bool {
+        self.reject_zero_transfers.unwrap_or(false)
+    }
+
+    pub fn claim_expiry_nanos(&self) -> u64 {
+        self.claim_expiry_nanos.unwrap_or(u64::MAX)
+    }
+
+    pub fn is_failed_tx_log_enabled(&self) -> bool {
+        self.failed_tx_log_enabled.unwrap_or(false)
+    }
+
+    pub fn is_auction_dust_sinking_enabled(&self) -> bool {
+        self.auction_dust_sinking.unwrap_or(false)
+    }
+
+    /// Returns the standards implemented by the canister. ICRC-2 is listed only if the transfer
+    /// methods are enabled, as `icrc2_approve` and `icrc2_transfer_from` are part of them.
     pub fn supported_standards(&self) -> Vec<StandardRecord> {
-        vec![
+        let mut standards = vec![
             StandardRecord::new(
                 "ICRC-1".to_string(),
                 "https://github.com/dfinity/ICRC-1".to_string(),
@@ -44,19 +292,42 @@ impl TokenConfig {
                 "IS20".to_string(),
                 "https://github.com/infinity-swap/is20".to_string(),
             ),
-        ]
+        ];
+
+        if cfg!(feature = "transfer") {
+            standards.push(StandardRecord::new(
+                "ICRC-2".to_string(),
+                "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-2".to_string(),
+            ));
+        }
+
+        standards.push(StandardRecord::new(
+            "ICRC-3".to_string(),
+            "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-3".to_string(),
+        ));
+
+        standards
     }
 
     pub fn icrc1_metadata(&self) -> Vec<(String, Value)> {
-        vec![
+        let mut metadata = vec![
             ("icrc1:symbol".to_string(), Value::Text(self.symbol.clone())),
             ("icrc1:name".to_string(), Value::Text(self.name.clone())),
             (
                 "icrc1:decimals".to_string(),
                 Value::Nat(Nat::from(self.decimals)),
             ),
-            ("icrc1:fee".to_string(), Value::Nat(self.fee.amount.into())),
-        ]
+            (
+                "icrc1:fee".to_string(),
+                Value::Nat(self.icrc1_fee().amount.into()),
+            ),
+        ];
+
+        if let Some(logo) = &self.logo {
+            metadata.push(("icrc1:logo".to_string(), Value::Text(logo.clone())));
+        }
+
+        metadata
     }
 
     pub fn get_metadata(&self) -> Metadata {
@@ -68,6 +339,7 @@ impl TokenConfig {
             fee: self.fee,
             fee_to: self.fee_to,
             is_test_token: Some(self.is_test_token),
+            max_supply: self.max_supply,
         }
     }
 }
@@ -84,6 +356,41 @@ impl Default for TokenConfig {
             deploy_time: 0,
             min_cycles: 0,
             is_test_token: false,
+            max_query_page_size: None,
+            auction_pool_trigger: None,
+            governance_delay_nanos: None,
+            failed_tx_log_enabled: None,
+            storage_high_water_mark: None,
+            circulation_excluded_accounts: None,
+            auction_dust_sinking: None,
+            fee_mode: None,
+            dormancy_period_nanos: None,
+            auction_beneficiary: None,
+            fee_oracle: None,
+            transferable: None,
+            dynamic_fee: None,
+            analytics_enabled: None,
+            auction_account: None,
+            max_concurrent_batches: None,
+            block_canister_recipients: None,
+            deployer: None,
+            prepared_transfer_ttl_nanos: None,
+            fee_quote_ttl_nanos: None,
+            maintenance: None,
+            claim_expiry_nanos: None,
+            royalty: None,
+            paused: None,
+            max_supply: None,
+            logo: None,
+            fee_to_subaccount: None,
+            tx_window_nanos: None,
+            permitted_drift_nanos: None,
+            burn_rate: None,
+            pending_owner: None,
+            auction_fee_ratio: None,
+            min_auction_bid: None,
+            reject_zero_transfers: None,
+            archive_options: None,
         }
     }
 }
@@ -100,6 +407,63 @@ impl Storable for TokenConfig {
     }
 }
 
+const BASIS_POINTS_IN_UNIT: u128 = 10_000;
+
+fn basis_points_of(amount: Tokens128, basis_points: u16) -> Tokens128 {
+    // Split the amount to avoid overflow of the multiplication.
+    let basis_points = basis_points as u128;
+    let amount = amount.amount;
+    Tokens128::from(
+        amount / BASIS_POINTS_IN_UNIT * basis_points
+            + amount % BASIS_POINTS_IN_UNIT * basis_points / BASIS_POINTS_IN_UNIT,
+    )
+}
+
+/// The way the transfer fee is calculated.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum FeeMode {
+    /// The `TokenConfig::fee` amount is charged for every transfer.
+    Flat,
+    /// The fee is the given number of basis points (1/10000) of the transferred amount, rounded
+    /// down.
+    BasisPoints(u16),
+}
+
+/// Congestion pricing parameters: the fee is raised by `fee_step` every `ledger_step`
+/// transactions recorded in the ledger, up to `max_fee`.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct DynamicFeeParams {
+    pub base_fee: Tokens128,
+    pub ledger_step: u64,
+    pub fee_step: Tokens128,
+    pub max_fee: Tokens128,
+}
+
+impl DynamicFeeParams {
+    /// Returns the fee for the ledger with `ledger_len` transactions.
+    pub fn fee(&self, ledger_len: u64) -> Tokens128 {
+        if self.ledger_step == 0 {
+            return self.base_fee.min(self.max_fee);
+        }
+
+        let steps = (ledger_len / self.ledger_step) as u128;
+        let increase = self.fee_step.amount.saturating_mul(steps);
+        Tokens128::from(self.base_fee.amount.saturating_add(increase)).min(self.max_fee)
+    }
+}
+
+/// Receiver of the tokens accumulated in the auction pool.
+#[derive(Debug, Default, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum AuctionBeneficiary {
+    /// The pool is distributed between the bidders proportionally to their bids.
+    #[default]
+    Bidders,
+    /// The whole pool is transferred to the account.
+    SpecificAccount(Account),
+    /// The pool is burned.
+    Burn,
+}
+
 #[derive(Debug, CandidType, Deserialize, Clone, PartialEq, Eq)]
 pub struct StandardRecord {
     pub name: String,
@@ -122,12 +486,26 @@ pub struct Metadata {
     pub fee: Tokens128,
     pub fee_to: Principal,
     pub is_test_token: Option<bool>,
+    pub max_supply: Option<Tokens128>,
 }
 
 // 10T cycles is an equivalent of approximately $10. This should be enough to last the canister
 // for the default auction cycle, which is 1 day.
 pub const DEFAULT_MIN_CYCLES: u64 = 10_000_000_000_000;
 
+/// Hard limit for the page size of paginated queries, to keep the response within the message
+/// size limits whatever page size the owner configures.
+pub const MAX_QUERY_PAGE_SIZE: usize = 10_000;
+
+pub const DEFAULT_MAX_CONCURRENT_BATCHES: u32 = 4;
+
+pub const DEFAULT_PREPARED_TRANSFER_TTL_NANOS: u64 = 30_000_000_000;
+
+pub const DEFAULT_FEE_QUOTE_TTL_NANOS: u64 = 60_000_000_000;
+
+/// Upper limit for the configurable transaction window and permitted drift, which is 24 hours.
+pub const MAX_TX_WINDOW_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
 impl From<Metadata> for TokenConfig {
     fn from(md: Metadata) -> Self {
         Self {
@@ -140,6 +518,9 @@ impl From<Metadata> for TokenConfig {
             deploy_time: canister_sdk::ic_kit::ic::time(),
             min_cycles: DEFAULT_MIN_CYCLES,
             is_test_token: md.is_test_token.unwrap_or(false),
+            deployer: Some(canister_sdk::ic_kit::ic::caller()),
+            max_supply: md.max_supply,
+            ..Default::default()
         }
     }
 }
@@ -175,14 +556,32 @@ impl FeeRatio {
         Self(adj_value)
     }
 
+    pub fn value(&self) -> f64 {
+        self.0
+    }
+
+    /// Whether the ratio is within `[0, 1]`. A deserialized ratio is not clamped.
+    pub fn is_valid(&self) -> bool {
+        (0.0..=1.0).contains(&self.0)
+    }
+
     /// Returns the tupple (raw_fee, auction_fee). Raw fee is the fee amount to be transferred to
     /// the canister owner, and auction_fee is the portion of the fee for the cycle auction.
+    ///
+    /// The auction fee is rounded down, so the rounding remainder always goes to the owner and
+    /// the two parts sum up to exactly `fee`.
     pub(crate) fn get_value(&self, fee: Tokens128) -> (Tokens128, Tokens128) {
         // Both auction fee and owner fee have the same purpose of providing the tokens to pay for
         // the canister operations. As such we do not care much about rounding errors in this case.
         // The only important thing to make sure that the sum of auction fee and the owner fee is
         // equal to the total fee amount.
         let auction_fee_amount = Tokens128::from((f64::from(fee) * self.0) as u128);
+        // Conversion of large fees to `f64` can round them up, so the product can exceed the fee.
+        let auction_fee_amount = if auction_fee_amount > fee {
+            fee
+        } else {
+            auction_fee_amount
+        };
         let owner_fee_amount = fee.saturating_sub(auction_fee_amount);
 
         (owner_fee_amount, auction_fee_amount)
@@ -203,3 +602,43 @@ thread_local! {
                 .expect("stable memory token config initialization failed"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn fee_split_remainder_goes_to_owner() {
+        let half = FeeRatio::new(0.5);
+        assert_eq!(
+            half.get_value(Tokens128::from(1)),
+            (Tokens128::from(1), Tokens128::from(0))
+        );
+        assert_eq!(
+            half.get_value(Tokens128::from(3)),
+            (Tokens128::from(2), Tokens128::from(1))
+        );
+        assert_eq!(
+            half.get_value(Tokens128::ZERO),
+            (Tokens128::ZERO, Tokens128::ZERO)
+        );
+        // Not representable in `f64`, rounded up by the conversion.
+        let fee = Tokens128::from((1u128 << 54) + 3);
+        assert_eq!(FeeRatio::new(1.0).get_value(fee), (Tokens128::ZERO, fee));
+        assert_eq!(
+            FeeRatio::new(1.0).get_value(Tokens128::MAX),
+            (Tokens128::ZERO, Tokens128::MAX)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn fee_split_conserves_fee(fee in any::<u128>(), ratio in 0.0..=1.0f64) {
+            let fee = Tokens128::from(fee);
+            let (owner_fee, auction_fee) = FeeRatio::new(ratio).get_value(fee);
+            prop_assert_eq!(owner_fee + auction_fee, Some(fee));
+        }
+    }
+}
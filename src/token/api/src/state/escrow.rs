@@ -0,0 +1,114 @@
+use std::{borrow::Cow, cell::RefCell};
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::{ic_helpers::tokens::Tokens128, ic_kit::ic};
+use ic_stable_structures::{MemoryId, StableCell, Storable};
+
+use crate::account::Account;
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+
+/// Condition of releasing the escrowed tokens to the recipient.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Condition {
+    /// Principal allowed to release the escrow in addition to the sender.
+    pub arbiter: Option<Principal>,
+    /// Period (in nanoseconds) after which the escrow cannot be released anymore, and can only
+    /// be refunded to the sender.
+    pub timeout_nanos: u64,
+}
+
+/// Tokens locked by a conditional transfer.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Escrow {
+    pub id: u64,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Tokens128,
+    pub arbiter: Option<Principal>,
+    pub expires_at: Timestamp,
+}
+
+impl Escrow {
+    /// Returns true if the `principal` is allowed to release the escrow.
+    pub fn can_release(&self, principal: Principal) -> bool {
+        self.from.owner == principal || self.arbiter == Some(principal)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        ic::time() >= self.expires_at
+    }
+}
+
+/// Open escrows waiting for release or refund.
+#[derive(Debug, Default, Clone, CandidType, Deserialize)]
+pub struct Escrows {
+    next_id: u64,
+    escrows: Vec<Escrow>,
+}
+
+impl Escrows {
+    /// Get open escrows stored in stable memory.
+    pub fn get_stable() -> Self {
+        CELL.with(|c| c.borrow().get().clone())
+    }
+
+    /// Store open escrows in stable memory.
+    pub fn set_stable(escrows: Self) {
+        CELL.with(|c| c.borrow_mut().set(escrows))
+            .expect("unable to set escrows to stable memory");
+    }
+
+    /// Store a new escrow of the `amount` locked for `to`. Returns id of the escrow.
+    pub fn open(from: Account, to: Account, amount: Tokens128, condition: Condition) -> u64 {
+        let mut stored = Self::get_stable();
+        let id = stored.next_id;
+
+        stored.next_id += 1;
+        stored.escrows.push(Escrow {
+            id,
+            from,
+            to,
+            amount,
+            arbiter: condition.arbiter,
+            expires_at: ic::time().saturating_add(condition.timeout_nanos),
+        });
+        Self::set_stable(stored);
+
+        id
+    }
+
+    pub fn get(id: u64) -> Result<Escrow, TxError> {
+        Self::get_stable()
+            .escrows
+            .into_iter()
+            .find(|escrow| escrow.id == id)
+            .ok_or(TxError::EscrowNotFound)
+    }
+
+    /// Remove the settled escrow.
+    pub fn close(id: u64) {
+        let mut stored = Self::get_stable();
+        stored.escrows.retain(|escrow| escrow.id != id);
+        Self::set_stable(stored);
+    }
+}
+
+impl Storable for Escrows {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode escrows"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode escrows")
+    }
+}
+
+const ESCROWS_MEMORY_ID: MemoryId = MemoryId::new(11);
+
+thread_local! {
+    static CELL: RefCell<StableCell<Escrows>> = {
+            RefCell::new(StableCell::new(ESCROWS_MEMORY_ID, Escrows::default())
+                .expect("stable memory escrows initialization failed"))
+    }
+}
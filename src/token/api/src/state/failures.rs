@@ -0,0 +1,82 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+
+use crate::account::{Account, Subaccount};
+use crate::error::TxError;
+use crate::state::config::{Timestamp, TokenConfig};
+
+const MAX_FAILED_TX_LOG_LENGTH: usize = 1_000;
+
+thread_local! {
+    static FAILED_TX_LOG: RefCell<HashMap<Principal, VecDeque<FailedTx>>> = RefCell::default();
+}
+
+/// Transfer attempt that was rejected by the canister.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct FailedTx {
+    pub caller: Principal,
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub amount: Tokens128,
+    pub error: TxError,
+    pub timestamp: Timestamp,
+}
+
+/// Bounded log of the recent failed transfer attempts. The log is kept in heap memory separately
+/// from the ledger, so the oldest records are dropped once the log is full.
+pub struct FailedTxLog;
+
+impl FailedTxLog {
+    /// Store the failed attempt, if failed transactions logging is enabled.
+    pub fn record(
+        from_subaccount: Option<Subaccount>,
+        to: Account,
+        amount: Tokens128,
+        error: &TxError,
+    ) {
+        if !TokenConfig::get_stable().is_failed_tx_log_enabled() {
+            return;
+        }
+
+        let failure = FailedTx {
+            caller: ic::caller(),
+            from_subaccount,
+            to,
+            amount,
+            error: error.clone(),
+            timestamp: ic::time(),
+        };
+
+        Self::with_log(|log| {
+            if log.len() == MAX_FAILED_TX_LOG_LENGTH {
+                log.pop_front();
+            }
+            log.push_back(failure);
+        })
+    }
+
+    /// List up to `count` most recent failures, the newest first.
+    pub fn recent(count: usize) -> Vec<FailedTx> {
+        Self::with_log(|log| log.iter().rev().take(count).cloned().collect())
+    }
+
+    pub fn clear() {
+        Self::with_log(|log| log.clear())
+    }
+
+    fn with_log<F, R>(f: F) -> R
+    where
+        F: FnOnce(&mut VecDeque<FailedTx>) -> R,
+    {
+        FAILED_TX_LOG.with(|logs| {
+            let canister_id = ic::id();
+            let mut borrowed = logs.borrow_mut();
+            let log = borrowed.entry(canister_id).or_default();
+            f(log)
+        })
+    }
+}
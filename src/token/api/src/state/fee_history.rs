@@ -0,0 +1,83 @@
+use std::{borrow::Cow, cell::RefCell};
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::{ic_helpers::tokens::Tokens128, ic_kit::ic};
+use ic_stable_structures::{MemoryId, StableCell, Storable};
+
+use crate::state::config::Timestamp;
+
+const MAX_FEE_HISTORY_LENGTH: usize = 100;
+
+/// Change of the transfer fee applied to the token configuration.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct FeeChange {
+    pub timestamp: Timestamp,
+    pub old_fee: Tokens128,
+    pub new_fee: Tokens128,
+    pub changed_by: Principal,
+}
+
+/// Bounded audit trail of the fee changes. The oldest records are dropped once the history is
+/// full.
+#[derive(Debug, Default, Clone, CandidType, Deserialize)]
+pub struct FeeHistory {
+    changes: Vec<FeeChange>,
+}
+
+impl FeeHistory {
+    /// Get fee history stored in stable memory.
+    pub fn get_stable() -> Self {
+        CELL.with(|c| c.borrow().get().clone())
+    }
+
+    /// Store fee history in stable memory.
+    pub fn set_stable(history: Self) {
+        CELL.with(|c| c.borrow_mut().set(history))
+            .expect("unable to set fee history to stable memory");
+    }
+
+    /// Store the fee change made by the current caller.
+    pub fn record(old_fee: Tokens128, new_fee: Tokens128) {
+        let mut history = Self::get_stable();
+        if history.changes.len() == MAX_FEE_HISTORY_LENGTH {
+            history.changes.remove(0);
+        }
+
+        history.changes.push(FeeChange {
+            timestamp: ic::time(),
+            old_fee,
+            new_fee,
+            changed_by: ic::caller(),
+        });
+        Self::set_stable(history);
+    }
+
+    /// List up to `count` most recent fee changes, the newest first.
+    pub fn recent(count: usize) -> Vec<FeeChange> {
+        Self::get_stable()
+            .changes
+            .into_iter()
+            .rev()
+            .take(count)
+            .collect()
+    }
+}
+
+impl Storable for FeeHistory {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode fee history"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode fee history")
+    }
+}
+
+const FEE_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(9);
+
+thread_local! {
+    static CELL: RefCell<StableCell<FeeHistory>> = {
+            RefCell::new(StableCell::new(FEE_HISTORY_MEMORY_ID, FeeHistory::default())
+                .expect("stable memory fee history initialization failed"))
+    }
+}
@@ -0,0 +1,140 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::{borrow::Cow, cell::RefCell};
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+use ic_stable_structures::{MemoryId, StableCell, Storable};
+
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+
+/// Configuration of the fee cap expressed in a fiat amount.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct FeeOracle {
+    /// Canister queried for the token price.
+    pub oracle: Principal,
+    /// Maximum fee value in fiat units.
+    pub max_fee_fiat: u128,
+    /// Period (in nanoseconds) for which the price returned by the oracle is cached. After it, the
+    /// price is considered stale.
+    pub price_ttl_nanos: u64,
+}
+
+impl FeeOracle {
+    /// Caps the `fee` so that its fiat value does not exceed `max_fee_fiat`. If there is no
+    /// cached price or it is stale, the `fee` is returned unchanged.
+    pub fn cap(&self, fee: Tokens128, decimals: u8) -> Tokens128 {
+        let price = match OraclePrice::get_stable().fresh_price(self.price_ttl_nanos) {
+            Some(price) if price > 0 => price,
+            _ => return fee,
+        };
+
+        // Price is the fiat value of one whole token, i.e. of `10^decimals` token units.
+        let max_fee = 10u128
+            .checked_pow(decimals as u32)
+            .and_then(|units| self.max_fee_fiat.checked_mul(units))
+            .map(|value| Tokens128::from(value / price));
+
+        match max_fee {
+            Some(max_fee) => fee.min(max_fee),
+            None => fee,
+        }
+    }
+}
+
+/// The latest token price returned by the fee oracle.
+#[derive(Debug, Default, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct OraclePrice {
+    /// Fiat value of one whole token.
+    pub price: Option<u128>,
+    pub reported_at: Timestamp,
+}
+
+impl OraclePrice {
+    /// Get the cached price stored in stable memory.
+    pub fn get_stable() -> Self {
+        CELL.with(|c| *c.borrow().get())
+    }
+
+    /// Store the cached price in stable memory.
+    pub fn set_stable(price: Self) {
+        CELL.with(|c| c.borrow_mut().set(price))
+            .expect("unable to set oracle price to stable memory");
+    }
+
+    pub fn report(price: u128) {
+        Self::set_stable(Self {
+            price: Some(price),
+            reported_at: ic::time(),
+        })
+    }
+
+    /// Returns the cached price, if it was returned by the oracle within the last `ttl` nanoseconds,
+    /// or requests the current one from the `oracle` otherwise. If the request fails, the cached
+    /// price is kept and is still used until it becomes stale.
+    pub async fn refresh(oracle: &impl PriceOracle, ttl: u64) -> Result<u128, TxError> {
+        if let Some(price) = Self::get_stable().fresh_price(ttl) {
+            return Ok(price);
+        }
+
+        let price = oracle.get_price().await?;
+        Self::report(price);
+        Ok(price)
+    }
+
+    /// Returns the price, if it was reported less than `ttl` nanoseconds ago.
+    pub fn fresh_price(&self, ttl: u64) -> Option<u128> {
+        if ic::time().saturating_sub(self.reported_at) > ttl {
+            return None;
+        }
+
+        self.price
+    }
+}
+
+impl Storable for OraclePrice {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode oracle price"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode oracle price")
+    }
+}
+
+pub type OracleCall<T> = Pin<Box<dyn Future<Output = Result<T, TxError>>>>;
+
+/// Source of the token price.
+pub trait PriceOracle {
+    /// Returns the fiat value of one whole token.
+    fn get_price(&self) -> OracleCall<u128>;
+}
+
+/// Oracle canister, which returns the fiat value of one whole token from its `get_price` method.
+#[derive(Debug, Clone, Copy)]
+pub struct OracleCanister(pub Principal);
+
+impl PriceOracle for OracleCanister {
+    fn get_price(&self) -> OracleCall<u128> {
+        let oracle = self.0;
+        Box::pin(async move {
+            ic::call::<_, (u128,), _>(oracle, "get_price", ())
+                .await
+                .map(|(price,)| price)
+                .map_err(|(code, message)| TxError::FeeOracleCallFailed {
+                    message: format!("{code:?}: {message}"),
+                })
+        })
+    }
+}
+
+const ORACLE_PRICE_MEMORY_ID: MemoryId = MemoryId::new(8);
+
+thread_local! {
+    static CELL: RefCell<StableCell<OraclePrice>> = {
+            RefCell::new(StableCell::new(ORACLE_PRICE_MEMORY_ID, OraclePrice::default())
+                .expect("stable memory oracle price initialization failed"))
+    }
+}
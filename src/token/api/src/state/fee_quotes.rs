@@ -0,0 +1,87 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+
+use crate::state::config::Timestamp;
+
+thread_local! {
+    static FEE_QUOTES: RefCell<HashMap<Principal, FeeQuotes>> = RefCell::default();
+}
+
+/// Fee quoted to the caller for a transfer of some amount. A transfer of the same amount with
+/// `max_fee` not lower than the quoted `fee` is charged at most the quoted fee until
+/// `valid_until`, even if the fee is raised in the meantime.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct FeeQuote {
+    pub fee: Tokens128,
+    pub valid_until: Timestamp,
+}
+
+/// The latest fee quote of every caller together with the quoted amount. The quotes are
+/// short-lived, so they are kept in heap memory and are dropped on upgrade.
+#[derive(Debug, Default)]
+pub struct FeeQuotes {
+    quotes: HashMap<Principal, (Tokens128, FeeQuote)>,
+}
+
+impl FeeQuotes {
+    /// Stores the quote of the `fee` for a transfer of the `amount` by the caller, replacing the
+    /// previous quote of the caller. Expired quotes are dropped on the way.
+    pub fn quote(amount: Tokens128, fee: Tokens128, ttl_nanos: u64) -> FeeQuote {
+        let now = ic::time();
+        let quote = FeeQuote {
+            fee,
+            valid_until: now.saturating_add(ttl_nanos),
+        };
+
+        Self::with_quotes(|quotes| {
+            quotes
+                .quotes
+                .retain(|_, (_, quote)| quote.valid_until >= now);
+            quotes.quotes.insert(ic::caller(), (amount, quote.clone()));
+        });
+
+        quote
+    }
+
+    /// Returns the fee to be charged for the transfer of the `amount` by the `caller`, if the
+    /// current fee is `fee`. A valid quote for the same amount is honored if the `max_fee` covers
+    /// it, so the caller never pays more than the quoted fee.
+    pub fn honored_fee(
+        caller: Principal,
+        amount: Tokens128,
+        fee: Tokens128,
+        max_fee: Option<Tokens128>,
+    ) -> Tokens128 {
+        let max_fee = match max_fee {
+            Some(max_fee) => max_fee,
+            None => return fee,
+        };
+
+        Self::with_quotes(|quotes| match quotes.quotes.get(&caller) {
+            Some((quoted_amount, quote))
+                if *quoted_amount == amount
+                    && quote.valid_until >= ic::time()
+                    && quote.fee <= max_fee =>
+            {
+                fee.min(quote.fee)
+            }
+            _ => fee,
+        })
+    }
+
+    fn with_quotes<F, R>(f: F) -> R
+    where
+        F: FnOnce(&mut FeeQuotes) -> R,
+    {
+        FEE_QUOTES.with(|quotes| {
+            let canister_id = ic::id();
+            let mut borrowed = quotes.borrow_mut();
+            let quotes = borrowed.entry(canister_id).or_default();
+            f(quotes)
+        })
+    }
+}
@@ -0,0 +1,86 @@
+//! Compact hash-based summary of the token state. Comparing fingerprints of several canisters
+//! (e.g. mirrors of the same token) allows to detect a divergence of their states.
+
+use candid::{CandidType, Deserialize, Encode};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use sha2::{Digest, Sha256};
+
+use crate::account::AccountInternal;
+use crate::state::balance_tree::with_balance_tree;
+use crate::state::ledger::LedgerData;
+use crate::tx_record::TxRecord;
+
+pub type Hash = [u8; 32];
+
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct StateFingerprint {
+    pub supply: Tokens128,
+    pub holder_count: u64,
+    pub ledger_len: u64,
+    /// Hash of the last transaction record, if the ledger is not empty.
+    pub ledger_tip_hash: Option<Hash>,
+    /// Root of the merkle tree over all the balances. See `balance_tree` for the tree layout.
+    pub balances_merkle_root: Hash,
+}
+
+impl StateFingerprint {
+    pub fn compute() -> Self {
+        let (supply, holder_count, balances_merkle_root) =
+            with_balance_tree(|tree| (tree.total_supply(), tree.holder_count(), tree.root_hash()));
+
+        let ledger_len = LedgerData::len();
+        let ledger_tip_hash = ledger_len
+            .checked_sub(1)
+            .and_then(LedgerData::get)
+            .map(|tx| tx_record_hash(&tx));
+
+        Self {
+            supply,
+            holder_count: holder_count as u64,
+            ledger_len,
+            ledger_tip_hash,
+            balances_merkle_root,
+        }
+    }
+}
+
+/// A sibling node on the path from a merkle tree leaf to the root.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct ProofStep {
+    pub hash: Hash,
+    /// Whether the sibling is the left node of the pair.
+    pub is_left: bool,
+}
+
+pub fn tx_record_hash(tx: &TxRecord) -> Hash {
+    let bytes = Encode!(tx).expect("failed to encode transaction record");
+    Sha256::digest(bytes).into()
+}
+
+pub fn balance_hash(account: &AccountInternal, amount: Tokens128) -> Hash {
+    let owner = account.owner.as_slice();
+    let mut hasher = Sha256::new();
+    hasher.update([owner.len() as u8]);
+    hasher.update(owner);
+    hasher.update(account.subaccount);
+    hasher.update(amount.amount.to_be_bytes());
+    hasher.finalize().into()
+}
+
+/// Computes the merkle tree root from the `leaf` and the path returned by `BalanceTree::witness`.
+pub fn root_from_proof(leaf: Hash, proof: &[ProofStep]) -> Hash {
+    proof.iter().fold(leaf, |node, step| {
+        if step.is_left {
+            hash_pair(&step.hash, &node)
+        } else {
+            hash_pair(&node, &step.hash)
+        }
+    })
+}
+
+pub fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
@@ -0,0 +1,113 @@
+use std::{borrow::Cow, cell::RefCell};
+
+use candid::{CandidType, Decode, Deserialize, Encode};
+use canister_sdk::ic_kit::ic;
+use ic_stable_structures::{MemoryId, StableCell, Storable};
+
+use crate::canister::CanisterUpdate;
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+
+/// Owner action queued until the governance delay passes.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct PendingOwnerAction {
+    pub id: u64,
+    pub update: CanisterUpdate,
+    pub queued_at: Timestamp,
+    pub executable_at: Timestamp,
+}
+
+/// Queue of owner actions waiting for the governance delay to pass.
+#[derive(Debug, Default, Clone, CandidType, Deserialize)]
+pub struct PendingOwnerActions {
+    next_id: u64,
+    actions: Vec<PendingOwnerAction>,
+}
+
+impl PendingOwnerActions {
+    /// Get pending actions stored in stable memory.
+    pub fn get_stable() -> Self {
+        CELL.with(|c| c.borrow().get().clone())
+    }
+
+    /// Store pending actions in stable memory.
+    pub fn set_stable(actions: Self) {
+        CELL.with(|c| c.borrow_mut().set(actions))
+            .expect("unable to set pending owner actions to stable memory");
+    }
+
+    /// Queue the `update` to be executable after `delay` nanoseconds. Returns id of the action.
+    pub fn queue(update: CanisterUpdate, delay: u64) -> u64 {
+        let mut pending = Self::get_stable();
+        let id = pending.next_id;
+        let now = ic::time();
+
+        pending.next_id += 1;
+        pending.actions.push(PendingOwnerAction {
+            id,
+            update,
+            queued_at: now,
+            executable_at: now.saturating_add(delay),
+        });
+        Self::set_stable(pending);
+
+        id
+    }
+
+    pub fn list() -> Vec<PendingOwnerAction> {
+        Self::get_stable().actions
+    }
+
+    /// Remove the action from the queue and return its update, if the delay of the action has
+    /// passed.
+    pub fn take_executable(id: u64) -> Result<CanisterUpdate, TxError> {
+        let mut pending = Self::get_stable();
+        let index = pending.position(id)?;
+
+        let executable_at = pending.actions[index].executable_at;
+        if ic::time() < executable_at {
+            return Err(TxError::OwnerActionTimelocked { executable_at });
+        }
+
+        let action = pending.actions.remove(index);
+        Self::set_stable(pending);
+
+        Ok(action.update)
+    }
+
+    /// Remove the action from the queue without executing it.
+    pub fn cancel(id: u64) -> Result<(), TxError> {
+        let mut pending = Self::get_stable();
+        let index = pending.position(id)?;
+        pending.actions.remove(index);
+        Self::set_stable(pending);
+
+        Ok(())
+    }
+
+    fn position(&self, id: u64) -> Result<usize, TxError> {
+        self.actions
+            .iter()
+            .position(|action| action.id == id)
+            .ok_or(TxError::OwnerActionNotFound)
+    }
+}
+
+impl Storable for PendingOwnerActions {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode pending owner actions"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode pending owner actions")
+    }
+}
+
+const PENDING_ACTIONS_MEMORY_ID: MemoryId = MemoryId::new(3);
+
+thread_local! {
+    static CELL: RefCell<StableCell<PendingOwnerActions>> = {
+            RefCell::new(StableCell::new(PENDING_ACTIONS_MEMORY_ID, PendingOwnerActions::default())
+                .expect("stable memory pending owner actions initialization failed"))
+    }
+}
@@ -0,0 +1,60 @@
+use std::cell::RefCell;
+
+use ic_stable_structures::{MemoryId, StableCell};
+
+/// Version of the stable memory layout used by this code. Must be bumped whenever the schema of
+/// any stable structure changes in a way the previous code can't read.
+pub const STABLE_LAYOUT_VERSION: u32 = 1;
+
+/// The oldest stable memory layout version this code is able to read.
+pub const MIN_COMPATIBLE_LAYOUT_VERSION: u32 = 1;
+
+const STABLE_LAYOUT_MEMORY_ID: MemoryId = MemoryId::new(10);
+
+thread_local! {
+    // Canisters installed before the layout version was tracked use the layout version 1.
+    static STORED_LAYOUT_VERSION: RefCell<StableCell<u64>> =
+        RefCell::new(StableCell::new(STABLE_LAYOUT_MEMORY_ID, 1)
+            .expect("unable to initialize stable layout version"));
+}
+
+/// Version of the layout of the data in the stable memory.
+pub struct StableLayout;
+
+impl StableLayout {
+    /// Layout version of the stable memory written by the last installed code.
+    pub fn stored_version() -> u32 {
+        STORED_LAYOUT_VERSION.with(|version| *version.borrow().get() as u32)
+    }
+
+    pub fn set_stored_version(version: u32) {
+        STORED_LAYOUT_VERSION.with(|stored| {
+            stored
+                .borrow_mut()
+                .set(version as u64)
+                .expect("fail to write stable layout version")
+        });
+    }
+
+    /// Checks that the stable memory written by the previously installed code can be read by this
+    /// code, and records the current layout version. Must be called in `post_upgrade`.
+    pub fn upgrade() -> Result<(), String> {
+        let stored = Self::stored_version();
+        if stored > STABLE_LAYOUT_VERSION {
+            return Err(format!(
+                "Stable memory layout version {stored} is newer than the supported version \
+                {STABLE_LAYOUT_VERSION}. Downgrading the canister is not allowed."
+            ));
+        }
+
+        if stored < MIN_COMPATIBLE_LAYOUT_VERSION {
+            return Err(format!(
+                "Stable memory layout version {stored} is older than the minimum compatible \
+                version {MIN_COMPATIBLE_LAYOUT_VERSION}."
+            ));
+        }
+
+        Self::set_stored_version(STABLE_LAYOUT_VERSION);
+        Ok(())
+    }
+}
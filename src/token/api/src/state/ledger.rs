@@ -1,25 +1,36 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use candid::{CandidType, Deserialize, Principal};
 use canister_sdk::ic_helpers::tokens::Tokens128;
 use canister_sdk::ic_kit::ic;
-use ic_stable_structures::{MemoryId, StableCell};
+use ic_stable_structures::{MemoryId, StableCell, StableMultimap};
 
 use crate::account::{Account, AccountInternal, Subaccount};
 use crate::error::TxError;
-use crate::state::config::Timestamp;
-use crate::tx_record::{TxId, TxRecord};
+use crate::state::analytics::AnalyticsLedger;
+use crate::state::balances::{Balances, PrincipalKey, SubaccountKey};
+use crate::state::blocks::{encode_block, BlockWithId};
+use crate::state::certification::certify_state;
+use crate::state::config::{Timestamp, TokenConfig};
+use crate::state::fingerprint::Hash;
+use crate::state::statistics::{LedgerCounters, MetricPoint};
+use crate::tx_record::{FeeSplit, TransferCharges, TxId, TxRecord};
 
 const MAX_HISTORY_LENGTH: usize = 1_000_000;
 const HISTORY_REMOVAL_BATCH_SIZE: usize = 10_000;
 const TOTAL_TX_COUNT_MEMORY_ID: MemoryId = MemoryId::new(2);
+const TRANSACTED_ACCOUNTS_MEMORY_ID: MemoryId = MemoryId::new(19);
 
 thread_local! {
     static LEDGER: RefCell<HashMap<Principal, Ledger>> = RefCell::default();
     static TOTAL_TX_COUNT: RefCell<StableCell<u64>> =
         RefCell::new(StableCell::new(TOTAL_TX_COUNT_MEMORY_ID, 0)
             .expect("unable to initialize index offset for ledger"));
+    // Id of the latest transaction of every account which has ever been the sender or the
+    // recipient of a transaction. Unlike the history, it's never pruned.
+    static TRANSACTED_ACCOUNTS: RefCell<StableMultimap<PrincipalKey, SubaccountKey, u64>> =
+        RefCell::new(StableMultimap::new(TRANSACTED_ACCOUNTS_MEMORY_ID));
 }
 
 pub struct LedgerData;
@@ -45,6 +56,71 @@ impl LedgerData {
         Self::with_ledger(|ledger| ledger.get_transactions(who, count, transaction_id))
     }
 
+    pub fn transactions_after(
+        cursor: Option<Cursor>,
+        count: usize,
+    ) -> Result<(Vec<TxRecord>, Option<Cursor>), TxError> {
+        Self::with_ledger(|ledger| ledger.transactions_after(cursor, count))
+    }
+
+    pub fn remove_oldest(count: usize) {
+        Self::with_ledger(|ledger| ledger.remove_oldest(count))
+    }
+
+    /// Id of the oldest transaction stored in the canister. The older ones are archived or
+    /// removed from the history.
+    pub fn first_local_index() -> TxId {
+        Self::with_ledger(|ledger| ledger.first_stored_tx_id())
+    }
+
+    /// Number of the transactions stored in the canister.
+    pub fn stored_len() -> usize {
+        Self::with_ledger(|ledger| ledger.iter().len())
+    }
+
+    /// Returns up to `count` oldest stored transactions, the oldest first.
+    pub fn oldest(count: usize) -> Vec<TxRecord> {
+        Self::with_ledger(|ledger| ledger.iter().take(count).cloned().collect())
+    }
+
+    /// Returns id of the latest transaction that the transfer with the given parameters would
+    /// duplicate.
+    pub fn find_duplicate(
+        from: AccountInternal,
+        to: AccountInternal,
+        transfer_args: &TransferArgs,
+        created_at_time: Timestamp,
+    ) -> Option<TxId> {
+        Self::with_ledger(|ledger| ledger.find_duplicate(from, to, transfer_args, created_at_time))
+    }
+
+    /// Reconstructs the token metrics at the end of `num_points` intervals before now. See
+    /// `MetricPoint::timeseries` for details.
+    pub fn metrics_timeseries(
+        balances: &impl Balances,
+        interval: u64,
+        num_points: usize,
+    ) -> Vec<MetricPoint> {
+        Self::with_ledger(|ledger| {
+            MetricPoint::timeseries(
+                balances,
+                ledger.iter(),
+                ledger.first_stored_tx_id() == 0,
+                ic::time(),
+                interval,
+                num_points,
+            )
+        })
+    }
+
+    pub fn get_blocks(start: TxId, length: u64, limit: usize) -> Vec<BlockWithId> {
+        Self::with_ledger(|ledger| ledger.get_blocks(start, length, limit))
+    }
+
+    pub fn get_batch(batch_id: TxId) -> Vec<TxRecord> {
+        Self::with_ledger(|ledger| ledger.get_batch(batch_id))
+    }
+
     pub fn list_transactions() -> Vec<TxRecord> {
         Self::with_ledger(|ledger| ledger.iter().cloned().collect())
     }
@@ -53,39 +129,139 @@ impl LedgerData {
         Self::with_ledger(|ledger| ledger.get_len_user_history(user))
     }
 
+    pub fn balance_delta_since(
+        account: AccountInternal,
+        since: TxId,
+    ) -> Result<BalanceDelta, TxError> {
+        Self::with_ledger(|ledger| ledger.balance_delta_since(account, since))
+    }
+
+    pub fn has_transacted(account: AccountInternal) -> bool {
+        Self::with_ledger(|ledger| ledger.has_transacted(account))
+    }
+
+    pub fn transactions_by_tag(tag: &str, start: usize, count: usize) -> Vec<TxRecord> {
+        Self::with_ledger(|ledger| ledger.transactions_by_tag(tag, start, count))
+    }
+
+    pub fn account_tx_ids(
+        account: AccountInternal,
+        from_id: TxId,
+        to_id: TxId,
+        limit: usize,
+    ) -> Vec<TxId> {
+        Self::with_ledger(|ledger| ledger.account_tx_ids(account, from_id, to_id, limit))
+    }
+
+    pub fn get_account_transactions(
+        account: AccountInternal,
+        start: Option<TxId>,
+        limit: usize,
+    ) -> Vec<TxRecord> {
+        Self::with_ledger(|ledger| ledger.get_account_transactions(account, start, limit))
+    }
+
+    pub fn account_transactions(account: AccountInternal, count: usize) -> Vec<TxRecord> {
+        Self::with_ledger(|ledger| ledger.account_transactions(account, count))
+    }
+
     pub fn transfer(
         from: AccountInternal,
         to: AccountInternal,
-        amount: Tokens128,
+        transfer: &TransferArgs,
         fee: Tokens128,
-        memo: Option<Memo>,
         created_at_time: Timestamp,
+        charges: TransferCharges,
     ) -> TxId {
-        Self::with_ledger(|ledger| ledger.transfer(from, to, amount, fee, memo, created_at_time))
+        Self::with_ledger(|ledger| {
+            ledger.transfer(from, to, transfer, fee, created_at_time, charges)
+        })
     }
 
     pub fn batch_transfer(
         from: AccountInternal,
-        transfers: Vec<BatchTransferArgs>,
-        fee: Tokens128,
+        transfers: Vec<(BatchTransferArgs, Tokens128, TransferCharges)>,
     ) -> Vec<TxId> {
-        Self::with_ledger(|ledger| ledger.batch_transfer(from, transfers, fee))
+        Self::with_ledger(|ledger| ledger.batch_transfer(from, transfers))
     }
 
-    pub fn mint(from: AccountInternal, to: AccountInternal, amount: Tokens128) -> TxId {
-        Self::with_ledger(|ledger| ledger.mint(from, to, amount))
+    pub fn approve(
+        owner: AccountInternal,
+        spender: AccountInternal,
+        amount: Tokens128,
+        fee: Tokens128,
+        fee_split: FeeSplit,
+    ) -> TxId {
+        Self::with_ledger(|ledger| ledger.approve(owner, spender, amount, fee, fee_split))
+    }
+
+    pub fn transfer_from(
+        spender: AccountInternal,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        fee: Tokens128,
+        charges: TransferCharges,
+    ) -> TxId {
+        Self::with_ledger(|ledger| ledger.transfer_from(spender, from, to, amount, fee, charges))
     }
 
-    pub fn burn(caller: AccountInternal, from: AccountInternal, amount: Tokens128) -> TxId {
-        Self::with_ledger(|ledger| ledger.burn(caller, from, amount))
+    pub fn record_transfer(
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        fee: Tokens128,
+        charges: TransferCharges,
+    ) -> TxId {
+        Self::with_ledger(|ledger| ledger.record_transfer(from, to, amount, fee, charges))
     }
 
-    pub fn record_auction(to: Principal, amount: Tokens128) {
+    pub fn mint(
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        memo: Option<Memo>,
+    ) -> TxId {
+        Self::with_ledger(|ledger| ledger.mint(from, to, amount, memo))
+    }
+
+    pub fn burn(
+        caller: AccountInternal,
+        from: AccountInternal,
+        amount: Tokens128,
+        memo: Option<Memo>,
+    ) -> TxId {
+        Self::with_ledger(|ledger| ledger.burn(caller, from, amount, memo))
+    }
+
+    pub fn redeem(from: AccountInternal, amount: Tokens128, redemption_ref: Vec<u8>) -> TxId {
+        Self::with_ledger(|ledger| ledger.redeem(from, amount, redemption_ref))
+    }
+
+    pub fn record_auction(to: AccountInternal, amount: Tokens128) {
         Self::with_ledger(|ledger| ledger.record_auction(to, amount))
     }
 
-    pub fn claim(claim_account: AccountInternal, to: AccountInternal, amount: Tokens128) -> TxId {
-        Self::with_ledger(|ledger| ledger.claim(claim_account, to, amount))
+    pub fn claim(
+        claim_account: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        charges: TransferCharges,
+    ) -> TxId {
+        Self::with_ledger(|ledger| ledger.claim(claim_account, to, amount, charges))
+    }
+
+    pub fn reclaim(
+        caller: Principal,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+    ) -> TxId {
+        Self::with_ledger(|ledger| ledger.reclaim(caller, from, to, amount))
+    }
+
+    pub fn last_activity(account: AccountInternal) -> Option<Timestamp> {
+        Self::with_ledger(|ledger| ledger.last_activity(account))
     }
 
     pub fn clear() {
@@ -108,6 +284,41 @@ impl LedgerData {
 #[derive(Debug, Default, CandidType, Deserialize)]
 pub struct Ledger {
     history: Vec<TxRecord>,
+    tag_index: HashMap<String, Vec<TxId>>,
+    // Ids of the transactions in which the account is the sender, the recipient or the caller, in
+    // ascending order.
+    account_index: HashMap<AccountInternal, Vec<TxId>>,
+    dedup_index: HashMap<DedupKey, Vec<TxId>>,
+    // Keys of the `dedup_index` by the transaction timestamp, to remove the ones outside of the
+    // deduplication window.
+    dedup_expiry: BTreeMap<Timestamp, Vec<DedupKey>>,
+    // ICRC-3 hashes of the blocks of the `history` records.
+    block_hashes: Vec<Hash>,
+    // Hash of the last block removed from the history, which is the parent of the first stored one.
+    removed_block_hash: Option<Hash>,
+}
+
+/// Transaction fields compared by the deduplication check, except for the fee, which is compared
+/// only if it's given in the transfer arguments.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq, Hash)]
+struct DedupKey {
+    from: AccountInternal,
+    to: AccountInternal,
+    memo: Option<Memo>,
+    amount: u128,
+    created_at_time: Timestamp,
+}
+
+impl DedupKey {
+    fn of(record: &TxRecord) -> Self {
+        Self {
+            from: record.from.into(),
+            to: record.to.into(),
+            memo: record.memo,
+            amount: record.amount.amount,
+            created_at_time: record.timestamp,
+        }
+    }
 }
 
 impl Ledger {
@@ -155,12 +366,181 @@ impl Ledger {
         }
     }
 
-    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &TxRecord> {
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &TxRecord> + ExactSizeIterator {
         self.history.iter()
     }
 
+    /// Returns up to `count` transactions recorded after the `cursor` position, in ascending order,
+    /// and the cursor to request the next page with. If `cursor` is `None`, the oldest stored
+    /// transactions are returned. The returned cursor is `None` once the end of the history is
+    /// reached.
+    pub fn transactions_after(
+        &self,
+        cursor: Option<Cursor>,
+        count: usize,
+    ) -> Result<(Vec<TxRecord>, Option<Cursor>), TxError> {
+        let first_stored_tx_id = self.first_stored_tx_id();
+        let start = match cursor {
+            None => first_stored_tx_id,
+            Some(cursor) if cursor.last_seen >= self.len() => return Err(TxError::InvalidCursor),
+            Some(cursor) if cursor.last_seen + 1 < first_stored_tx_id => {
+                return Err(TxError::CursorPruned {
+                    first_available: first_stored_tx_id,
+                })
+            }
+            Some(cursor) => cursor.last_seen + 1,
+        };
+
+        let transactions = self
+            .history
+            .iter()
+            .skip((start - first_stored_tx_id) as usize)
+            .take(count)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let next = transactions
+            .last()
+            .filter(|tx| tx.index + 1 < self.len())
+            .map(|tx| Cursor {
+                last_seen: tx.index,
+            });
+
+        Ok((transactions, next))
+    }
+
+    pub fn find_duplicate(
+        &self,
+        from: AccountInternal,
+        to: AccountInternal,
+        transfer_args: &TransferArgs,
+        created_at_time: Timestamp,
+    ) -> Option<TxId> {
+        let key = DedupKey {
+            from,
+            to,
+            memo: transfer_args.memo,
+            amount: transfer_args.amount.amount,
+            created_at_time,
+        };
+
+        self.dedup_index
+            .get(&key)?
+            .iter()
+            .rev()
+            .copied()
+            .find(|&id| {
+                self.get(id)
+                    .map_or(false, |tx| tx.fee == transfer_args.fee.unwrap_or(tx.fee))
+            })
+    }
+
+    fn index_for_account(&mut self, record: &TxRecord) {
+        for account in participants(record) {
+            self.account_index
+                .entry(account)
+                .or_default()
+                .push(record.index);
+        }
+    }
+
+    /// Returns the ids of the stored transactions in which the `account` is the sender, the
+    /// recipient or the caller, in ascending order.
+    fn account_ids(&self, account: AccountInternal) -> &[TxId] {
+        self.account_index
+            .get(&account)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn index_for_dedup(&mut self, record: &TxRecord) {
+        // Transactions older than the window can't be duplicated, so their keys are removed.
+        let stats = TokenConfig::get_stable();
+        let window_start = ic::time()
+            .saturating_sub(stats.tx_window_nanos())
+            .saturating_sub(stats.permitted_drift_nanos());
+        let in_window = self.dedup_expiry.split_off(&window_start);
+        let expired = std::mem::replace(&mut self.dedup_expiry, in_window);
+        for key in expired.into_values().flatten() {
+            self.dedup_index.remove(&key);
+        }
+
+        let key = DedupKey::of(record);
+        self.dedup_index.entry(key).or_default().push(record.index);
+        self.dedup_expiry
+            .entry(record.timestamp)
+            .or_default()
+            .push(key);
+    }
+
+    /// Returns the stored transactions of the batch with the given id.
+    pub fn get_batch(&self, batch_id: TxId) -> Vec<TxRecord> {
+        let start = match self.get_index(batch_id) {
+            Some(start) => start,
+            None => return vec![],
+        };
+
+        self.history
+            .iter()
+            .skip(start)
+            .take_while(|tx| tx.batch_id == Some(batch_id))
+            .cloned()
+            .collect()
+    }
+
+    /// Removes up to `count` oldest records from the history, together with their ids in the tag
+    /// and the account indexes.
+    pub fn remove_oldest(&mut self, count: usize) {
+        let count = count.min(self.history.len());
+        let removed = self.history.drain(..count).collect::<Vec<_>>();
+        if let Some(last_removed) = self.block_hashes.drain(..count).last() {
+            self.removed_block_hash = Some(last_removed);
+        }
+
+        let first_stored_tx_id = self.first_stored_tx_id();
+        for record in &removed {
+            for tag in record.tags.iter().flatten() {
+                trim_index(&mut self.tag_index, tag, first_stored_tx_id);
+            }
+
+            for account in participants(record) {
+                trim_index(&mut self.account_index, &account, first_stored_tx_id);
+            }
+        }
+    }
+
+    fn parent_block_hash(&self, index: usize) -> Option<Hash> {
+        match index.checked_sub(1) {
+            Some(parent) => self.block_hashes.get(parent).copied(),
+            None => self.removed_block_hash,
+        }
+    }
+
+    /// Returns the ICRC-3 blocks of the stored transactions with ids in the range
+    /// `start..start + length`, but no more than `limit` of them.
+    pub fn get_blocks(&self, start: TxId, length: u64, limit: usize) -> Vec<BlockWithId> {
+        let first = start.max(self.first_stored_tx_id());
+        let end = start.saturating_add(length).min(self.next_id());
+
+        (first..end)
+            .take(limit)
+            .filter_map(|id| {
+                let index = self.get_index(id)?;
+                let record = self.history.get(index)?;
+                Some(BlockWithId {
+                    id: id.into(),
+                    block: encode_block(record, self.parent_block_hash(index)),
+                })
+            })
+            .collect()
+    }
+
+    pub fn first_stored_tx_id(&self) -> TxId {
+        Self::read_total_tx_count() - self.history.len() as u64 // Always >= 0
+    }
+
     fn get_index(&self, id: TxId) -> Option<usize> {
-        let first_stored_tx_id = Self::read_total_tx_count() - self.history.len() as u64; // Always >= 0
+        let first_stored_tx_id = self.first_stored_tx_id();
         if id < first_stored_tx_id || id > usize::MAX as TxId {
             None
         } else {
@@ -172,44 +552,262 @@ impl Ledger {
         self.history.iter().filter(|&tx| tx.contains(user)).count()
     }
 
+    /// Returns `true` if the `account` has ever been the sender or the recipient of a
+    /// transaction, including the ones removed from the history.
+    pub fn has_transacted(&self, account: AccountInternal) -> bool {
+        TRANSACTED_ACCOUNTS.with(|map| {
+            map.borrow_mut()
+                .get(
+                    &PrincipalKey(account.owner),
+                    &SubaccountKey(account.subaccount),
+                )
+                .is_some()
+        })
+    }
+
+    fn mark_transacted(record: &TxRecord) {
+        TRANSACTED_ACCOUNTS.with(|map| {
+            let mut map = map.borrow_mut();
+            for account in [record.from, record.to] {
+                let account = AccountInternal::from(account);
+                map.insert(
+                    &PrincipalKey(account.owner),
+                    &SubaccountKey(account.subaccount),
+                    &record.index,
+                );
+            }
+        });
+    }
+
+    /// Returns up to `count` transactions with the `tag`, starting from the `start`-th of them in
+    /// the order they were recorded. Transactions removed from the history are skipped.
+    pub fn transactions_by_tag(&self, tag: &str, start: usize, count: usize) -> Vec<TxRecord> {
+        self.tag_index
+            .get(tag)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| self.get(*id))
+            .skip(start)
+            .take(count)
+            .collect()
+    }
+
+    /// Returns up to `limit` ids of the transactions involving the `account` with ids in the range
+    /// `from_id..=to_id`, in ascending order.
+    pub fn account_tx_ids(
+        &self,
+        account: AccountInternal,
+        from_id: TxId,
+        to_id: TxId,
+        limit: usize,
+    ) -> Vec<TxId> {
+        let ids = self.account_ids(account);
+        ids[ids.partition_point(|id| *id < from_id)..]
+            .iter()
+            .take_while(|id| **id <= to_id)
+            .filter_map(|id| self.get(*id))
+            .filter(|tx| tx.involves(account))
+            .map(|tx| tx.index)
+            .take(limit)
+            .collect()
+    }
+
+    /// Returns up to `limit` transactions in which the `account` is the sender, the recipient or
+    /// the caller, with ids not greater than `start`, the newest first. Transactions removed from
+    /// the history are skipped.
+    pub fn get_account_transactions(
+        &self,
+        account: AccountInternal,
+        start: Option<TxId>,
+        limit: usize,
+    ) -> Vec<TxRecord> {
+        let ids = self.account_ids(account);
+        let end = match start {
+            Some(start) => ids.partition_point(|id| *id <= start),
+            None => ids.len(),
+        };
+
+        ids[..end]
+            .iter()
+            .rev()
+            .map_while(|id| self.get(*id))
+            .take(limit)
+            .collect()
+    }
+
+    /// Returns up to `count` most recent transactions involving the `account`, the newest first.
+    pub fn account_transactions(&self, account: AccountInternal, count: usize) -> Vec<TxRecord> {
+        self.history
+            .iter()
+            .rev()
+            .filter(|tx| tx.involves(account))
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns `TxError::HistoryPruned` if some of the transactions after `since` are removed from
+    /// the history, as the delta would be incomplete.
+    pub fn balance_delta_since(
+        &self,
+        account: AccountInternal,
+        since: TxId,
+    ) -> Result<BalanceDelta, TxError> {
+        let first_stored_tx_id = self.first_stored_tx_id();
+        if since.saturating_add(1) < first_stored_tx_id {
+            return Err(TxError::HistoryPruned {
+                first_available: first_stored_tx_id,
+            });
+        }
+
+        let (credited, debited) = self
+            .history
+            .iter()
+            .rev()
+            .take_while(|tx| tx.index > since)
+            .fold(
+                (Tokens128::ZERO, Tokens128::ZERO),
+                |(credited, debited), tx| {
+                    let (credit, debit) = tx.balance_change(account);
+                    (
+                        (credited + credit).unwrap_or(Tokens128::MAX),
+                        (debited + debit).unwrap_or(Tokens128::MAX),
+                    )
+                },
+            );
+
+        Ok(if credited >= debited {
+            BalanceDelta {
+                direction: DeltaDirection::Incoming,
+                amount: credited.saturating_sub(debited),
+            }
+        } else {
+            BalanceDelta {
+                direction: DeltaDirection::Outgoing,
+                amount: debited.saturating_sub(credited),
+            }
+        })
+    }
+
     pub fn transfer(
         &mut self,
         from: AccountInternal,
         to: AccountInternal,
-        amount: Tokens128,
+        transfer: &TransferArgs,
         fee: Tokens128,
-        memo: Option<Memo>,
         created_at_time: Timestamp,
+        charges: TransferCharges,
     ) -> TxId {
         let id = self.next_id();
-        self.push(TxRecord::transfer(
-            id,
-            from,
-            to,
-            amount,
-            fee,
-            memo,
-            created_at_time,
-        ));
+        self.push(TxRecord {
+            tags: transfer.tags.clone(),
+            tip: transfer.tip,
+            ..TxRecord::transfer(
+                id,
+                from,
+                to,
+                transfer.amount,
+                fee,
+                transfer.memo,
+                created_at_time,
+            )
+            .with_charges(charges)
+        });
+        self.record_transfer_burn(from, to, charges);
 
         id
     }
 
+    /// Records the `transfers` with the fee and the royalty charged for each of them.
     pub fn batch_transfer(
         &mut self,
         from: AccountInternal,
-        transfers: Vec<BatchTransferArgs>,
-        fee: Tokens128,
+        transfers: Vec<(BatchTransferArgs, Tokens128, TransferCharges)>,
     ) -> Vec<TxId> {
+        let batch_id = self.next_id();
         transfers
             .into_iter()
-            .map(|x| self.transfer(from, x.receiver.into(), x.amount, fee, None, ic::time()))
+            .map(|(x, fee, charges)| {
+                let id = self.next_id();
+                self.push(TxRecord {
+                    batch_id: Some(batch_id),
+                    ..TxRecord::transfer(
+                        id,
+                        from,
+                        x.receiver.into(),
+                        x.amount,
+                        fee,
+                        None,
+                        ic::time(),
+                    )
+                    .with_charges(charges)
+                });
+                self.record_transfer_burn(from, x.receiver.into(), charges);
+
+                id
+            })
             .collect()
     }
 
-    pub fn mint(&mut self, from: AccountInternal, to: AccountInternal, amount: Tokens128) -> TxId {
+    pub fn approve(
+        &mut self,
+        owner: AccountInternal,
+        spender: AccountInternal,
+        amount: Tokens128,
+        fee: Tokens128,
+        fee_split: FeeSplit,
+    ) -> TxId {
+        let id = self.next_id();
+        self.push(TxRecord::approve(id, owner, spender, amount, fee).with_fee_split(fee_split));
+
+        id
+    }
+
+    pub fn transfer_from(
+        &mut self,
+        spender: AccountInternal,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        fee: Tokens128,
+        charges: TransferCharges,
+    ) -> TxId {
+        let id = self.next_id();
+        self.push(
+            TxRecord::transfer_from(id, spender, from, to, amount, fee).with_charges(charges),
+        );
+        self.record_transfer_burn(spender, to, charges);
+
+        id
+    }
+
+    /// Records a transfer without memo, made at the current time.
+    pub fn record_transfer(
+        &mut self,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        fee: Tokens128,
+        charges: TransferCharges,
+    ) -> TxId {
+        let id = self.next_id();
+        self.push(
+            TxRecord::transfer(id, from, to, amount, fee, None, ic::time()).with_charges(charges),
+        );
+        self.record_transfer_burn(from, to, charges);
+
+        id
+    }
+
+    pub fn mint(
+        &mut self,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        memo: Option<Memo>,
+    ) -> TxId {
         let id = self.len();
-        self.push(TxRecord::mint(id, from, to, amount));
+        self.push(TxRecord::mint(id, from, to, amount, memo));
 
         id
     }
@@ -219,19 +817,59 @@ impl Ledger {
         caller: AccountInternal,
         from: AccountInternal,
         amount: Tokens128,
+        memo: Option<Memo>,
     ) -> TxId {
         let id = self.next_id();
-        self.push(TxRecord::burn(id, caller, from, amount));
+        self.push(TxRecord::burn(id, caller, from, amount, memo));
 
         id
     }
 
-    pub fn record_auction(&mut self, to: Principal, amount: Tokens128) {
+    /// Records the burn applied by a transfer as a burn from the recipient `to`, so that the
+    /// transfer record itself stays the same as without the burn.
+    fn record_transfer_burn(
+        &mut self,
+        caller: AccountInternal,
+        to: AccountInternal,
+        charges: TransferCharges,
+    ) {
+        if !charges.burned.is_zero() {
+            self.burn(caller, to, charges.burned, None);
+        }
+    }
+
+    pub fn redeem(
+        &mut self,
+        from: AccountInternal,
+        amount: Tokens128,
+        redemption_ref: Vec<u8>,
+    ) -> TxId {
+        let id = self.next_id();
+        self.push(TxRecord::redemption(id, from, amount, redemption_ref));
+
+        id
+    }
+
+    pub fn record_auction(&mut self, to: AccountInternal, amount: Tokens128) {
         let id = self.next_id();
-        self.push(TxRecord::auction(id, to.into(), amount))
+        self.push(TxRecord::auction(id, to, amount))
     }
 
     fn push(&mut self, record: TxRecord) {
+        LedgerCounters::record(&record);
+        for tag in record.tags.iter().flatten() {
+            self.tag_index
+                .entry(tag.clone())
+                .or_default()
+                .push(record.index);
+        }
+
+        self.index_for_account(&record);
+        self.index_for_dedup(&record);
+        Self::mark_transacted(&record);
+        let block_hash = encode_block(&record, self.parent_block_hash(self.history.len())).hash();
+        self.block_hashes.push(block_hash);
+        AnalyticsLedger::record(&record);
         self.history.push(record);
         Self::increase_total_tx_count();
         if self.history.len() > MAX_HISTORY_LENGTH + HISTORY_REMOVAL_BATCH_SIZE {
@@ -240,8 +878,11 @@ impl Ledger {
             // This removal code can later be changed to moving old history records into another
             // storage.
 
-            self.history = self.history[HISTORY_REMOVAL_BATCH_SIZE..].into();
+            self.remove_oldest(HISTORY_REMOVAL_BATCH_SIZE);
         }
+
+        // Every balance change is recorded, so the certified data is kept up to date.
+        certify_state();
     }
 
     pub fn claim(
@@ -249,15 +890,58 @@ impl Ledger {
         claim_account: AccountInternal,
         to: AccountInternal,
         amount: Tokens128,
+        charges: TransferCharges,
     ) -> TxId {
         let id = self.next_id();
-        self.push(TxRecord::claim(id, claim_account, to, amount));
+        self.push(TxRecord::claim(id, claim_account, to, amount).with_charges(charges));
+        self.record_transfer_burn(claim_account, to, charges);
 
         id
     }
 
+    pub fn reclaim(
+        &mut self,
+        caller: Principal,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+    ) -> TxId {
+        let id = self.next_id();
+        self.push(TxRecord::reclaim(id, caller, from, to, amount));
+
+        id
+    }
+
+    /// Returns the timestamp of the latest transaction involving the `account`.
+    pub fn last_activity(&self, account: AccountInternal) -> Option<Timestamp> {
+        self.account_ids(account)
+            .iter()
+            .rev()
+            .filter_map(|id| self.get(*id))
+            .find(|tx| tx.involves(account))
+            .map(|tx| tx.timestamp)
+    }
+
     pub fn clear(&mut self) {
         self.history.clear();
+        self.tag_index.clear();
+        self.account_index.clear();
+        self.dedup_index.clear();
+        self.dedup_expiry.clear();
+        self.block_hashes.clear();
+        self.removed_block_hash = None;
+        AnalyticsLedger::clear();
+        LedgerCounters::set_stable(LedgerCounters::default());
+        TRANSACTED_ACCOUNTS.with(|map| {
+            let mut map = map.borrow_mut();
+            let keys = map
+                .iter()
+                .map(|(principal, subaccount, _)| (principal, subaccount))
+                .collect::<Vec<_>>();
+            for (principal, subaccount) in keys {
+                map.remove(&principal, &subaccount);
+            }
+        });
         TOTAL_TX_COUNT.with(|count| {
             count
                 .borrow_mut()
@@ -281,8 +965,49 @@ impl Ledger {
     }
 }
 
+/// Returns the distinct accounts the `record` is indexed under: the sender, the recipient and the
+/// default account of the caller.
+fn participants(record: &TxRecord) -> Vec<AccountInternal> {
+    let mut accounts = vec![];
+    for account in [
+        AccountInternal::from(record.from),
+        AccountInternal::from(record.to),
+        AccountInternal::new(record.caller, None),
+    ] {
+        if !accounts.contains(&account) {
+            accounts.push(account);
+        }
+    }
+
+    accounts
+}
+
+/// Removes the ids of the transactions removed from the history from the `key` entry of the
+/// `index`, and the entry itself once it's empty.
+fn trim_index<K: Eq + std::hash::Hash>(
+    index: &mut HashMap<K, Vec<TxId>>,
+    key: &K,
+    first_stored_tx_id: TxId,
+) {
+    if let Some(ids) = index.get_mut(key) {
+        ids.drain(..ids.partition_point(|id| *id < first_stored_tx_id));
+        if ids.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
 pub type TxReceipt = Result<u128, TxError>;
 
+/// Result of a `transfer_notify` call. The transfer is committed even if the notification of the
+/// recipient fails.
+#[derive(CandidType, Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct NotifyReceipt {
+    pub tx_id: u128,
+    /// Rejection of the notification call, if it failed.
+    pub notify_error: Option<String>,
+}
+
 #[derive(CandidType, Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
 pub enum TransactionStatus {
     Succeeded,
@@ -298,6 +1023,15 @@ pub enum Operation {
     Burn,
     Auction,
     Claim,
+    Reclaim,
+    Redemption,
+}
+
+/// Position in the transaction history used by `get_transactions_cursor`. The cursor is opaque
+/// for the clients: it must be passed back as it was returned.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Cursor {
+    last_seen: TxId,
 }
 
 /// `PaginatedResult` is returned by paginated queries i.e `get_transactions`.
@@ -310,11 +1044,35 @@ pub struct PaginatedResult {
     pub next: Option<TxId>,
 }
 
+/// Net change of an account balance over a range of transactions.
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct BalanceDelta {
+    pub direction: DeltaDirection,
+    pub amount: Tokens128,
+}
+
+#[derive(Debug, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub enum DeltaDirection {
+    Incoming,
+    Outgoing,
+}
+
 // Batch transfer arguments.
 #[derive(Debug, Clone, CandidType, Deserialize)]
 pub struct BatchTransferArgs {
     pub receiver: Account,
     pub amount: Tokens128,
+    /// Fee charged for this transfer instead of the standard one. Only the owner can set a fee
+    /// lower than the standard fee.
+    pub fee_override: Option<Tokens128>,
+}
+
+impl BatchTransferArgs {
+    /// Fee charged for this transfer.
+    pub fn fee(&self, stats: &TokenConfig) -> Tokens128 {
+        self.fee_override
+            .unwrap_or_else(|| stats.effective_fee(self.amount))
+    }
 }
 
 /// These are the arguments which are taken in the `icrc1_transfer`
@@ -324,8 +1082,72 @@ pub struct TransferArgs {
     pub to: Account,
     pub amount: Tokens128,
     pub fee: Option<Tokens128>,
+    /// If set, the transfer is accepted as long as the fee doesn't exceed this value, and `fee`
+    /// is not checked.
+    pub max_fee: Option<Tokens128>,
     pub memo: Option<Memo>,
     pub created_at_time: Option<Timestamp>,
+    /// If set, the transfer is rejected with `TxError::Expired` after this time. The deadline is
+    /// checked before `created_at_time`, so an expired transfer is rejected even if it would
+    /// otherwise be a duplicate or too old.
+    pub valid_until: Option<Timestamp>,
+    /// Labels to categorize the transaction. At most `MAX_TRANSFER_TAGS` tags of at most
+    /// `MAX_TAG_LENGTH` bytes each are allowed.
+    pub tags: Option<Vec<String>>,
+    /// Voluntary contribution to the auction pool, debited from the sender in addition to the fee.
+    pub tip: Option<Tokens128>,
+}
+
+/// These are the arguments which are taken in the `multi_source_transfer`
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct MultiSourceTransferArgs {
+    /// Pairs of the caller's subaccount and the amount to debit from it.
+    pub sources: Vec<(Option<Subaccount>, Tokens128)>,
+    pub to: Account,
+    pub fee: Option<Tokens128>,
+    pub max_fee: Option<Tokens128>,
+    pub memo: Option<Memo>,
+    pub created_at_time: Option<Timestamp>,
+    pub valid_until: Option<Timestamp>,
+    pub tags: Option<Vec<String>>,
+}
+
+impl MultiSourceTransferArgs {
+    /// Arguments of the transfer recorded for one of the sources.
+    pub fn leg(&self, from_subaccount: Option<Subaccount>, amount: Tokens128) -> TransferArgs {
+        TransferArgs {
+            from_subaccount,
+            to: self.to,
+            amount,
+            fee: None,
+            max_fee: None,
+            memo: self.memo,
+            created_at_time: self.created_at_time,
+            valid_until: self.valid_until,
+            tags: self.tags.clone(),
+            tip: None,
+        }
+    }
+}
+
+/// These are the arguments which are taken in the `icrc2_approve`
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct ApproveArgs {
+    pub from_subaccount: Option<Subaccount>,
+    pub spender: Account,
+    pub amount: Tokens128,
+    pub fee: Option<Tokens128>,
+    pub expires_at: Option<u64>,
+}
+
+/// These are the arguments which are taken in the `icrc2_transfer_from`
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Subaccount>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Tokens128,
+    pub fee: Option<Tokens128>,
 }
 
 impl TransferArgs {
@@ -338,3 +1160,7 @@ impl TransferArgs {
 }
 
 pub type Memo = [u8; 32];
+
+pub const MAX_TRANSFER_TAGS: usize = 8;
+pub const MAX_TAG_LENGTH: usize = 64;
+pub const MAX_REDEMPTION_REF_LENGTH: usize = 128;
@@ -0,0 +1,50 @@
+use std::{borrow::Cow, cell::RefCell};
+
+use candid::{CandidType, Decode, Deserialize, Encode};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{MemoryId, StableCell, Storable};
+
+/// Progress of the inbound balances migration.
+#[derive(Debug, Default, Clone, CandidType, Deserialize)]
+pub struct MigrationState {
+    /// Sum of all the imported balances.
+    pub imported_total: Tokens128,
+    /// Indices of the chunks that were already imported.
+    pub imported_chunks: Vec<u64>,
+}
+
+impl MigrationState {
+    /// Get migration state stored in stable memory.
+    pub fn get_stable() -> Self {
+        CELL.with(|c| c.borrow().get().clone())
+    }
+
+    /// Store migration state in stable memory.
+    pub fn set_stable(state: Self) {
+        CELL.with(|c| c.borrow_mut().set(state))
+            .expect("unable to set migration state to stable memory");
+    }
+
+    pub fn is_chunk_imported(&self, chunk_index: u64) -> bool {
+        self.imported_chunks.contains(&chunk_index)
+    }
+}
+
+impl Storable for MigrationState {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode migration state"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode migration state")
+    }
+}
+
+const MIGRATION_MEMORY_ID: MemoryId = MemoryId::new(4);
+
+thread_local! {
+    static CELL: RefCell<StableCell<MigrationState>> = {
+            RefCell::new(StableCell::new(MIGRATION_MEMORY_ID, MigrationState::default())
+                .expect("stable memory migration state initialization failed"))
+    }
+}
@@ -0,0 +1,250 @@
+use std::{borrow::Cow, cell::RefCell};
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{MemoryId, StableCell, Storable};
+
+use crate::account::AccountInternal;
+use crate::error::TxError;
+use crate::state::config::TokenConfig;
+
+/// Accounts which were made receive-only by their owners.
+#[derive(Debug, Default, Clone, CandidType, Deserialize)]
+pub struct OutgoingDisabledAccounts {
+    accounts: Vec<AccountInternal>,
+}
+
+impl OutgoingDisabledAccounts {
+    /// Get receive-only accounts stored in stable memory.
+    pub fn get_stable() -> Self {
+        CELL.with(|c| c.borrow().get().clone())
+    }
+
+    /// Store receive-only accounts in stable memory.
+    pub fn set_stable(accounts: Self) {
+        CELL.with(|c| c.borrow_mut().set(accounts))
+            .expect("unable to set outgoing disabled accounts to stable memory");
+    }
+
+    pub fn contains(account: &AccountInternal) -> bool {
+        Self::get_stable().accounts.contains(account)
+    }
+
+    pub fn set(account: AccountInternal, disabled: bool) {
+        let mut stored = Self::get_stable();
+        stored.accounts.retain(|a| *a != account);
+        if disabled {
+            stored.accounts.push(account);
+        }
+        Self::set_stable(stored);
+    }
+}
+
+impl Storable for OutgoingDisabledAccounts {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode outgoing disabled accounts"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode outgoing disabled accounts")
+    }
+}
+
+const OUTGOING_DISABLED_MEMORY_ID: MemoryId = MemoryId::new(7);
+
+thread_local! {
+    static CELL: RefCell<StableCell<OutgoingDisabledAccounts>> = {
+            RefCell::new(StableCell::new(OUTGOING_DISABLED_MEMORY_ID, OutgoingDisabledAccounts::default())
+                .expect("stable memory outgoing disabled accounts initialization failed"))
+    }
+}
+
+/// Canisters which can handle the tokens sent to them. If blocking of canister recipients is
+/// enabled, transfers to other canisters are rejected.
+#[derive(Debug, Default, Clone, CandidType, Deserialize)]
+pub struct TokenAwareCanisters {
+    canisters: Vec<Principal>,
+}
+
+impl TokenAwareCanisters {
+    /// Get token-aware canisters stored in stable memory.
+    pub fn get_stable() -> Self {
+        TOKEN_AWARE_CELL.with(|c| c.borrow().get().clone())
+    }
+
+    /// Store token-aware canisters in stable memory.
+    pub fn set_stable(canisters: Self) {
+        TOKEN_AWARE_CELL
+            .with(|c| c.borrow_mut().set(canisters))
+            .expect("unable to set token-aware canisters to stable memory");
+    }
+
+    pub fn list() -> Vec<Principal> {
+        Self::get_stable().canisters
+    }
+
+    pub fn register(canister: Principal) {
+        let mut stored = Self::get_stable();
+        if !stored.canisters.contains(&canister) {
+            stored.canisters.push(canister);
+            Self::set_stable(stored);
+        }
+    }
+
+    /// Returns `TxError::RecipientNotTokenAware` if the blocking of canister recipients is enabled
+    /// and the `recipient` is owned by a canister which is not registered as token-aware.
+    pub fn check_recipient(recipient: &AccountInternal) -> Result<(), TxError> {
+        if !TokenConfig::get_stable().is_canister_recipients_blocking_enabled() {
+            return Ok(());
+        }
+
+        let owner = recipient.owner;
+        if is_canister_principal(&owner) && !Self::get_stable().canisters.contains(&owner) {
+            return Err(TxError::RecipientNotTokenAware);
+        }
+
+        Ok(())
+    }
+}
+
+/// Canister ids are opaque principals of 10 bytes, which end with the `0x01` class byte.
+fn is_canister_principal(principal: &Principal) -> bool {
+    const CANISTER_ID_LENGTH: usize = 10;
+    const OPAQUE_ID_CLASS: u8 = 0x01;
+
+    let bytes = principal.as_slice();
+    bytes.len() == CANISTER_ID_LENGTH && bytes.last() == Some(&OPAQUE_ID_CLASS)
+}
+
+impl Storable for TokenAwareCanisters {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode token-aware canisters"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode token-aware canisters")
+    }
+}
+
+const TOKEN_AWARE_CANISTERS_MEMORY_ID: MemoryId = MemoryId::new(13);
+
+thread_local! {
+    static TOKEN_AWARE_CELL: RefCell<StableCell<TokenAwareCanisters>> = {
+            RefCell::new(StableCell::new(TOKEN_AWARE_CANISTERS_MEMORY_ID, TokenAwareCanisters::default())
+                .expect("stable memory token-aware canisters initialization failed"))
+    }
+}
+
+/// Principals frozen by the owner. Frozen principals can neither send nor receive tokens, but
+/// their balances can still be burned by the owner.
+#[derive(Debug, Default, Clone, CandidType, Deserialize)]
+pub struct FrozenAccounts {
+    principals: Vec<Principal>,
+}
+
+impl FrozenAccounts {
+    /// Get frozen principals stored in stable memory.
+    pub fn get_stable() -> Self {
+        FROZEN_CELL.with(|c| c.borrow().get().clone())
+    }
+
+    /// Store frozen principals in stable memory.
+    pub fn set_stable(frozen: Self) {
+        FROZEN_CELL
+            .with(|c| c.borrow_mut().set(frozen))
+            .expect("unable to set frozen accounts to stable memory");
+    }
+
+    pub fn contains(principal: &Principal) -> bool {
+        Self::get_stable().principals.contains(principal)
+    }
+
+    pub fn set(principal: Principal, frozen: bool) {
+        let mut stored = Self::get_stable();
+        stored.principals.retain(|p| *p != principal);
+        if frozen {
+            stored.principals.push(principal);
+        }
+        Self::set_stable(stored);
+    }
+
+    /// Returns `TxError::AccountFrozen` if the owner of the sender or of the recipient is frozen.
+    pub fn check_transfer(from: &AccountInternal, to: &AccountInternal) -> Result<(), TxError> {
+        let stored = Self::get_stable();
+        if stored.principals.contains(&from.owner) || stored.principals.contains(&to.owner) {
+            return Err(TxError::AccountFrozen);
+        }
+
+        Ok(())
+    }
+}
+
+impl Storable for FrozenAccounts {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode frozen accounts"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode frozen accounts")
+    }
+}
+
+const FROZEN_ACCOUNTS_MEMORY_ID: MemoryId = MemoryId::new(15);
+
+thread_local! {
+    static FROZEN_CELL: RefCell<StableCell<FrozenAccounts>> = {
+            RefCell::new(StableCell::new(FROZEN_ACCOUNTS_MEMORY_ID, FrozenAccounts::default())
+                .expect("stable memory frozen accounts initialization failed"))
+    }
+}
+
+/// Principals allowed by the owner to mint tokens without having the other owner rights.
+#[derive(Debug, Default, Clone, CandidType, Deserialize)]
+pub struct Minters {
+    principals: Vec<Principal>,
+}
+
+impl Minters {
+    /// Get minters stored in stable memory.
+    pub fn get_stable() -> Self {
+        MINTERS_CELL.with(|c| c.borrow().get().clone())
+    }
+
+    /// Store minters in stable memory.
+    pub fn set_stable(minters: Self) {
+        MINTERS_CELL
+            .with(|c| c.borrow_mut().set(minters))
+            .expect("unable to set minters to stable memory");
+    }
+
+    pub fn contains(principal: &Principal) -> bool {
+        Self::get_stable().principals.contains(principal)
+    }
+
+    pub fn set(principal: Principal, minter: bool) {
+        let mut stored = Self::get_stable();
+        stored.principals.retain(|p| *p != principal);
+        if minter {
+            stored.principals.push(principal);
+        }
+        Self::set_stable(stored);
+    }
+}
+
+impl Storable for Minters {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode minters"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode minters")
+    }
+}
+
+const MINTERS_MEMORY_ID: MemoryId = MemoryId::new(16);
+
+thread_local! {
+    static MINTERS_CELL: RefCell<StableCell<Minters>> = {
+            RefCell::new(StableCell::new(MINTERS_MEMORY_ID, Minters::default())
+                .expect("stable memory minters initialization failed"))
+    }
+}
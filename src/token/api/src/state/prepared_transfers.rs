@@ -0,0 +1,108 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use canister_sdk::ic_kit::ic;
+
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+use crate::state::ledger::TransferArgs;
+
+thread_local! {
+    static PREPARED_TRANSFERS: RefCell<HashMap<Principal, PreparedTransfers>> = RefCell::default();
+}
+
+/// Result of the first phase of a two-phase transfer. The `token` is used to commit the transfer
+/// before `expires_at`, charging exactly `effective_fee`.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct PreparedTransfer {
+    pub token: u64,
+    pub effective_fee: Tokens128,
+    pub expires_at: Timestamp,
+}
+
+/// Validated transfer waiting for the commit.
+#[derive(Debug, Clone)]
+pub struct PendingTransfer {
+    pub caller: Principal,
+    pub args: TransferArgs,
+    pub fee: Tokens128,
+    pub expires_at: Timestamp,
+}
+
+/// Transfers prepared but not committed yet. The transfers are short-lived, so they are kept in
+/// heap memory and are dropped on upgrade.
+#[derive(Debug, Default)]
+pub struct PreparedTransfers {
+    next_token: u64,
+    pending: HashMap<u64, PendingTransfer>,
+}
+
+impl PreparedTransfers {
+    /// Store the validated transfer of the caller. Expired transfers are dropped on the way.
+    pub fn prepare(args: TransferArgs, fee: Tokens128, ttl_nanos: u64) -> PreparedTransfer {
+        let now = ic::time();
+        let expires_at = now.saturating_add(ttl_nanos);
+        let pending = PendingTransfer {
+            caller: ic::caller(),
+            args,
+            fee,
+            expires_at,
+        };
+
+        Self::with_transfers(|transfers| {
+            transfers
+                .pending
+                .retain(|_, transfer| transfer.expires_at > now);
+
+            let token = transfers.next_token;
+            transfers.next_token += 1;
+            transfers.pending.insert(token, pending);
+
+            PreparedTransfer {
+                token,
+                effective_fee: fee,
+                expires_at,
+            }
+        })
+    }
+
+    /// Remove the prepared transfer, so it cannot be committed twice. Returns an error if the
+    /// transfer is unknown, was prepared by another principal or has expired.
+    pub fn take(token: u64) -> Result<PendingTransfer, TxError> {
+        Self::with_transfers(|transfers| {
+            match transfers.pending.get(&token) {
+                None => return Err(TxError::PreparedTransferNotFound),
+                Some(transfer) if transfer.caller != ic::caller() => {
+                    return Err(TxError::Unauthorized)
+                }
+                Some(_) => {}
+            }
+
+            let transfer = transfers
+                .pending
+                .remove(&token)
+                .expect("prepared transfer is checked to exist");
+            if transfer.expires_at <= ic::time() {
+                return Err(TxError::PreparedTransferExpired {
+                    expires_at: transfer.expires_at,
+                });
+            }
+
+            Ok(transfer)
+        })
+    }
+
+    fn with_transfers<F, R>(f: F) -> R
+    where
+        F: FnOnce(&mut PreparedTransfers) -> R,
+    {
+        PREPARED_TRANSFERS.with(|transfers| {
+            let canister_id = ic::id();
+            let mut borrowed = transfers.borrow_mut();
+            let transfers = borrowed.entry(canister_id).or_default();
+            f(transfers)
+        })
+    }
+}
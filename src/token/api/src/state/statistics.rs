@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::{borrow::Cow, cell::RefCell};
+
+use candid::{CandidType, Decode, Deserialize, Encode};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{MemoryId, StableCell, Storable};
+
+use crate::account::AccountInternal;
+use crate::state::balances::Balances;
+use crate::state::config::Timestamp;
+use crate::state::ledger::Operation;
+use crate::tx_record::TxRecord;
+
+const NANOS_IN_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Summary of the token activity returned by the `token_statistics` query.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct TokenStatistics {
+    pub deploy_time: Timestamp,
+    pub age_days: u64,
+    pub total_transactions: u64,
+    pub total_volume: Tokens128,
+    pub unique_holders: u64,
+    pub total_fees: Tokens128,
+    pub last_activity: Option<Timestamp>,
+}
+
+impl TokenStatistics {
+    pub fn age_days(deploy_time: Timestamp, now: Timestamp) -> u64 {
+        now.saturating_sub(deploy_time) / NANOS_IN_DAY
+    }
+}
+
+/// Distribution of the balances, returned by the `balance_map_stats` query.
+#[derive(Debug, Default, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct BalanceMapStats {
+    pub total_accounts: u64,
+    pub auction_account_balance: Tokens128,
+    pub largest_balance: Tokens128,
+    pub smallest_nonzero_balance: Option<Tokens128>,
+    /// The largest power of two not greater than the median balance.
+    pub median_estimate: Tokens128,
+}
+
+impl BalanceMapStats {
+    /// Computes the statistics in a single pass over the `balances`. To avoid sorting, the
+    /// balances are counted in buckets by their bit length, so the median is estimated with the
+    /// precision of a power of two.
+    pub fn compute(balances: &impl Balances, auction_account: AccountInternal) -> Self {
+        let mut stats = Self::default();
+        let mut buckets = [0u64; u128::BITS as usize + 1];
+
+        for (account, balance) in balances.list_balances(0, usize::MAX) {
+            stats.total_accounts += 1;
+            if account == auction_account {
+                stats.auction_account_balance = balance;
+            }
+
+            stats.largest_balance = stats.largest_balance.max(balance);
+            if !balance.is_zero() {
+                stats.smallest_nonzero_balance = Some(
+                    stats
+                        .smallest_nonzero_balance
+                        .map_or(balance, |smallest| smallest.min(balance)),
+                );
+            }
+
+            let bit_length = u128::BITS - balance.amount.leading_zeros();
+            buckets[bit_length as usize] += 1;
+        }
+
+        let median_position = stats.total_accounts / 2;
+        let mut counted = 0;
+        for (bit_length, count) in buckets.into_iter().enumerate() {
+            counted += count;
+            if counted > median_position {
+                if bit_length > 0 {
+                    stats.median_estimate = Tokens128::from(1u128 << (bit_length - 1));
+                }
+                break;
+            }
+        }
+
+        stats
+    }
+}
+
+/// Token metrics at the end of an interval, returned by the `metrics_timeseries` query.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct MetricPoint {
+    pub timestamp: Timestamp,
+    pub total_supply: Tokens128,
+    /// Number of the accounts with non-zero balance.
+    pub holders: u64,
+    pub cumulative_volume: Tokens128,
+    /// Number of the transactions recorded up to the point.
+    pub operation_counts: OperationCounts,
+}
+
+impl MetricPoint {
+    /// Reconstructs the metrics at the end of `num_points` intervals of `interval` nanoseconds
+    /// before `now`, the latest first, by reverting the `transactions` from the current values.
+    ///
+    /// The fees received by the fee receiver and the auction account are not stored in the
+    /// records, so their balances are not reverted. If the history is not complete, only the points
+    /// covered by the stored transactions are returned.
+    pub fn timeseries<'a>(
+        balances: &impl Balances,
+        transactions: impl DoubleEndedIterator<Item = &'a TxRecord>,
+        history_complete: bool,
+        now: Timestamp,
+        interval: u64,
+        num_points: usize,
+    ) -> Vec<Self> {
+        let mut state = ReconstructedState::new(balances);
+        let mut transactions = transactions.rev().peekable();
+        let mut points = Vec::with_capacity(num_points);
+
+        for point in 0..num_points as u64 {
+            let timestamp = now.saturating_sub(interval.saturating_mul(point));
+            while let Some(tx) = transactions.next_if(|tx| tx.timestamp > timestamp) {
+                state.revert(tx);
+            }
+
+            if transactions.peek().is_none() && !history_complete {
+                break;
+            }
+
+            points.push(Self {
+                timestamp,
+                total_supply: state.total_supply,
+                holders: state.holders,
+                cumulative_volume: state.cumulative_volume,
+                operation_counts: state.operation_counts,
+            });
+        }
+
+        points
+    }
+}
+
+/// Balances and metrics of the token at some moment in the past.
+struct ReconstructedState {
+    balances: HashMap<AccountInternal, Tokens128>,
+    total_supply: Tokens128,
+    holders: u64,
+    cumulative_volume: Tokens128,
+    operation_counts: OperationCounts,
+}
+
+impl ReconstructedState {
+    fn new(balances: &impl Balances) -> Self {
+        let balances: HashMap<_, _> = balances.list_balances(0, usize::MAX).into_iter().collect();
+        let counters = LedgerCounters::get_stable();
+
+        Self {
+            total_supply: balances.values().fold(Tokens128::ZERO, |total, balance| {
+                (total + *balance).unwrap_or(Tokens128::MAX)
+            }),
+            holders: balances
+                .values()
+                .filter(|balance| !balance.is_zero())
+                .count() as u64,
+            cumulative_volume: counters.total_volume,
+            operation_counts: counters.operation_counts.unwrap_or_default(),
+            balances,
+        }
+    }
+
+    fn revert(&mut self, tx: &TxRecord) {
+        let from = AccountInternal::from(tx.from);
+        let to = AccountInternal::from(tx.to);
+        self.revert_balance(from, tx);
+        if to != from {
+            self.revert_balance(to, tx);
+        }
+
+        match tx.operation {
+            Operation::Mint => self.total_supply = self.total_supply.saturating_sub(tx.amount),
+            Operation::Burn | Operation::Redemption => {
+                self.total_supply = (self.total_supply + tx.amount).unwrap_or(Tokens128::MAX)
+            }
+            _ => {}
+        }
+
+        if tx.operation != Operation::Approve {
+            self.cumulative_volume = self.cumulative_volume.saturating_sub(tx.amount);
+        }
+
+        self.operation_counts.revert(tx.operation);
+    }
+
+    fn revert_balance(&mut self, account: AccountInternal, tx: &TxRecord) {
+        let (credited, debited) = tx.balance_change(account);
+        let balance = self.balances.entry(account).or_default();
+        let was_holder = !balance.is_zero();
+        *balance = (balance.saturating_sub(credited) + debited).unwrap_or(Tokens128::MAX);
+
+        match (was_holder, balance.is_zero()) {
+            (true, true) => self.holders -= 1,
+            (false, false) => self.holders += 1,
+            _ => {}
+        }
+    }
+}
+
+/// Counters updated with every transaction recorded in the ledger.
+#[derive(Debug, Default, Clone, CandidType, Deserialize)]
+pub struct LedgerCounters {
+    pub total_volume: Tokens128,
+    pub total_fees: Tokens128,
+    pub last_activity: Option<Timestamp>,
+    /// Not set for the counters stored before the operations were counted.
+    pub operation_counts: Option<OperationCounts>,
+}
+
+/// Number of the recorded transactions of each kind. Claims and reclaims are counted as transfers,
+/// and redemptions as burns.
+#[derive(Debug, Default, Clone, Copy, CandidType, Deserialize, PartialEq, Eq)]
+pub struct OperationCounts {
+    pub transfers: u64,
+    pub transfer_froms: u64,
+    pub mints: u64,
+    pub burns: u64,
+    pub approves: u64,
+    pub auctions: u64,
+}
+
+impl OperationCounts {
+    fn record(&mut self, operation: Operation) {
+        *self.counter(operation) += 1;
+    }
+
+    fn revert(&mut self, operation: Operation) {
+        let counter = self.counter(operation);
+        *counter = counter.saturating_sub(1);
+    }
+
+    fn counter(&mut self, operation: Operation) -> &mut u64 {
+        match operation {
+            Operation::Transfer | Operation::Claim | Operation::Reclaim => &mut self.transfers,
+            Operation::TransferFrom => &mut self.transfer_froms,
+            Operation::Mint => &mut self.mints,
+            Operation::Burn | Operation::Redemption => &mut self.burns,
+            Operation::Approve => &mut self.approves,
+            Operation::Auction => &mut self.auctions,
+        }
+    }
+}
+
+impl LedgerCounters {
+    /// Get counters stored in stable memory.
+    pub fn get_stable() -> Self {
+        CELL.with(|c| c.borrow().get().clone())
+    }
+
+    /// Store counters in stable memory.
+    pub fn set_stable(counters: Self) {
+        CELL.with(|c| c.borrow_mut().set(counters))
+            .expect("unable to set ledger counters to stable memory");
+    }
+
+    /// Add the transaction to the counters.
+    pub fn record(tx: &TxRecord) {
+        let mut counters = Self::get_stable();
+        if tx.operation != Operation::Approve {
+            counters.total_volume = (counters.total_volume + tx.amount).unwrap_or(Tokens128::MAX);
+        }
+        counters.total_fees = (counters.total_fees + tx.fee).unwrap_or(Tokens128::MAX);
+        counters.last_activity = Some(tx.timestamp);
+        counters
+            .operation_counts
+            .get_or_insert_with(OperationCounts::default)
+            .record(tx.operation);
+        Self::set_stable(counters);
+    }
+}
+
+impl Storable for LedgerCounters {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode ledger counters"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode ledger counters")
+    }
+}
+
+const LEDGER_COUNTERS_MEMORY_ID: MemoryId = MemoryId::new(6);
+
+thread_local! {
+    static CELL: RefCell<StableCell<LedgerCounters>> = {
+            RefCell::new(StableCell::new(LEDGER_COUNTERS_MEMORY_ID, LedgerCounters::default())
+                .expect("stable memory ledger counters initialization failed"))
+    }
+}
@@ -0,0 +1,56 @@
+use crate::error::TxError;
+use crate::state::balances::{Balances, LocalBalances};
+use crate::state::config::TokenConfig;
+
+#[cfg(not(target_family = "wasm"))]
+use std::cell::Cell;
+
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+#[cfg(not(target_family = "wasm"))]
+thread_local! {
+    static MOCK_STABLE_MEMORY_SIZE: Cell<u64> = Cell::new(0);
+}
+
+/// Size of the stable memory of the canister in bytes.
+#[cfg(target_family = "wasm")]
+pub fn stable_memory_size() -> u64 {
+    canister_sdk::ic_cdk::api::stable::stable64_size() * WASM_PAGE_SIZE
+}
+
+/// Size of the stable memory of the canister in bytes. Outside of wasm the value is set with
+/// `set_mock_stable_memory_pages`.
+#[cfg(not(target_family = "wasm"))]
+pub fn stable_memory_size() -> u64 {
+    MOCK_STABLE_MEMORY_SIZE.with(|size| size.get())
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn set_mock_stable_memory_pages(pages: u64) {
+    MOCK_STABLE_MEMORY_SIZE.with(|size| size.set(pages * WASM_PAGE_SIZE))
+}
+
+/// Returns `TxError::StorageFull` if the stable memory usage is above the configured high-water
+/// mark and applying the `updates` would add new entries to the `balances`. Zero balances don't
+/// add entries, as they are not applied to the accounts without a balance.
+pub fn check_storage_growth(
+    balances: &impl Balances,
+    updates: &LocalBalances,
+) -> Result<(), TxError> {
+    let is_full = TokenConfig::get_stable()
+        .storage_high_water_mark
+        .map_or(false, |mark| stable_memory_size() >= mark);
+    if !is_full {
+        return Ok(());
+    }
+
+    let adds_entries = updates
+        .list_balances(0, usize::MAX)
+        .iter()
+        .any(|(account, amount)| !amount.is_zero() && balances.get(account).is_none());
+    if adds_entries {
+        return Err(TxError::StorageFull);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use std::{borrow::Cow, cell::RefCell};
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::{ic_helpers::tokens::Tokens128, ic_kit::ic};
+use ic_stable_structures::{MemoryId, StableCell, Storable};
+
+use crate::account::Account;
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+
+/// Tokens sent by a timelocked transfer, which the recipient can claim after `release_at`.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Timelock {
+    pub id: u64,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Tokens128,
+    pub release_at: Timestamp,
+}
+
+impl Timelock {
+    pub fn is_released(&self) -> bool {
+        ic::time() >= self.release_at
+    }
+}
+
+/// Timelocked transfers which are not claimed or cancelled yet.
+#[derive(Debug, Default, Clone, CandidType, Deserialize)]
+pub struct Timelocks {
+    next_id: u64,
+    locks: Vec<Timelock>,
+}
+
+impl Timelocks {
+    /// Get timelocks stored in stable memory.
+    pub fn get_stable() -> Self {
+        CELL.with(|c| c.borrow().get().clone())
+    }
+
+    /// Store timelocks in stable memory.
+    pub fn set_stable(timelocks: Self) {
+        CELL.with(|c| c.borrow_mut().set(timelocks))
+            .expect("unable to set timelocks to stable memory");
+    }
+
+    /// Store a new timelock of the `amount` sent to `to`. Returns id of the timelock.
+    pub fn open(from: Account, to: Account, amount: Tokens128, release_at: Timestamp) -> u64 {
+        let mut stored = Self::get_stable();
+        let id = stored.next_id;
+
+        stored.next_id += 1;
+        stored.locks.push(Timelock {
+            id,
+            from,
+            to,
+            amount,
+            release_at,
+        });
+        Self::set_stable(stored);
+
+        id
+    }
+
+    pub fn get(id: u64) -> Result<Timelock, TxError> {
+        Self::get_stable()
+            .locks
+            .into_iter()
+            .find(|lock| lock.id == id)
+            .ok_or(TxError::TimelockNotFound)
+    }
+
+    /// Returns the timelocks sent to the accounts of the `recipient`.
+    pub fn for_recipient(recipient: Principal) -> Vec<Timelock> {
+        Self::get_stable()
+            .locks
+            .into_iter()
+            .filter(|lock| lock.to.owner == recipient)
+            .collect()
+    }
+
+    /// Remove the claimed or cancelled timelock.
+    pub fn close(id: u64) {
+        let mut stored = Self::get_stable();
+        stored.locks.retain(|lock| lock.id != id);
+        Self::set_stable(stored);
+    }
+}
+
+impl Storable for Timelocks {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode timelocks"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode timelocks")
+    }
+}
+
+const TIMELOCKS_MEMORY_ID: MemoryId = MemoryId::new(18);
+
+thread_local! {
+    static CELL: RefCell<StableCell<Timelocks>> = {
+            RefCell::new(StableCell::new(TIMELOCKS_MEMORY_ID, Timelocks::default())
+                .expect("stable memory timelocks initialization failed"))
+    }
+}
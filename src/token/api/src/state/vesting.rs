@@ -0,0 +1,144 @@
+use std::{borrow::Cow, cell::RefCell};
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_sdk::ic_helpers::tokens::Tokens128;
+use ic_stable_structures::{MemoryId, StableCell, Storable};
+
+use crate::account::Account;
+use crate::error::TxError;
+use crate::state::config::Timestamp;
+
+/// Tokens minted to the vesting account and released to the beneficiary over time. Nothing is
+/// vested before `start + cliff`, after that the vested amount grows linearly from `start` until
+/// the whole `total` is vested at `start + duration`.
+#[derive(Debug, Clone, CandidType, Deserialize, PartialEq, Eq)]
+pub struct VestingSchedule {
+    pub id: u64,
+    pub beneficiary: Account,
+    pub total: Tokens128,
+    pub start: Timestamp,
+    /// Period (in nanoseconds) after the `start` during which nothing is vested.
+    pub cliff: u64,
+    /// Period (in nanoseconds) after the `start` at which the whole `total` is vested.
+    pub duration: u64,
+    /// Amount already released to the beneficiary.
+    pub released: Tokens128,
+}
+
+impl VestingSchedule {
+    /// Amount vested by the time `now`, including the released tokens.
+    pub fn vested(&self, now: Timestamp) -> Tokens128 {
+        let elapsed = now.saturating_sub(self.start);
+        if elapsed < self.cliff {
+            return Tokens128::ZERO;
+        }
+
+        if elapsed >= self.duration {
+            return self.total;
+        }
+
+        (self.total * &elapsed / self.duration)
+            .and_then(|vested| vested.to_tokens128())
+            .unwrap_or(self.total)
+    }
+
+    /// Amount vested by the time `now` but not released yet.
+    pub fn releasable(&self, now: Timestamp) -> Tokens128 {
+        self.vested(now).saturating_sub(self.released)
+    }
+}
+
+/// Vesting schedules which are not fully released yet.
+#[derive(Debug, Default, Clone, CandidType, Deserialize)]
+pub struct Vestings {
+    next_id: u64,
+    schedules: Vec<VestingSchedule>,
+}
+
+impl Vestings {
+    /// Get vesting schedules stored in stable memory.
+    pub fn get_stable() -> Self {
+        CELL.with(|c| c.borrow().get().clone())
+    }
+
+    /// Store vesting schedules in stable memory.
+    pub fn set_stable(vestings: Self) {
+        CELL.with(|c| c.borrow_mut().set(vestings))
+            .expect("unable to set vesting schedules to stable memory");
+    }
+
+    /// Stores a new schedule of the `total` amount for the `beneficiary`. Returns id of the
+    /// schedule.
+    pub fn create(
+        beneficiary: Account,
+        total: Tokens128,
+        start: Timestamp,
+        cliff: u64,
+        duration: u64,
+    ) -> u64 {
+        let mut stored = Self::get_stable();
+        let id = stored.next_id;
+
+        stored.next_id += 1;
+        stored.schedules.push(VestingSchedule {
+            id,
+            beneficiary,
+            total,
+            start,
+            cliff,
+            duration,
+            released: Tokens128::ZERO,
+        });
+        Self::set_stable(stored);
+
+        id
+    }
+
+    pub fn get(id: u64) -> Result<VestingSchedule, TxError> {
+        Self::get_stable()
+            .schedules
+            .into_iter()
+            .find(|schedule| schedule.id == id)
+            .ok_or(TxError::VestingNotFound)
+    }
+
+    pub fn for_beneficiary(principal: Principal) -> Vec<VestingSchedule> {
+        Self::get_stable()
+            .schedules
+            .into_iter()
+            .filter(|schedule| schedule.beneficiary.owner == principal)
+            .collect()
+    }
+
+    /// Records the release of the `amount` from the schedule. Fully released schedules are
+    /// removed.
+    pub fn record_release(id: u64, amount: Tokens128) {
+        let mut stored = Self::get_stable();
+        if let Some(schedule) = stored.schedules.iter_mut().find(|s| s.id == id) {
+            schedule.released = (schedule.released + amount).unwrap_or(schedule.total);
+        }
+        stored
+            .schedules
+            .retain(|schedule| schedule.released < schedule.total);
+        Self::set_stable(stored);
+    }
+}
+
+impl Storable for Vestings {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode vesting schedules"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(&bytes, Self).expect("failed to decode vesting schedules")
+    }
+}
+
+const VESTINGS_MEMORY_ID: MemoryId = MemoryId::new(17);
+
+thread_local! {
+    static CELL: RefCell<StableCell<Vestings>> = {
+            RefCell::new(StableCell::new(VESTINGS_MEMORY_ID, Vestings::default())
+                .expect("stable memory vesting schedules initialization failed"))
+    }
+}
@@ -10,6 +10,20 @@ use crate::{
 
 pub type TxId = u64;
 
+/// Amounts `(owner_fee, auction_fee)` the transaction fee was split into.
+pub type FeeSplit = (Tokens128, Tokens128);
+
+/// Fee split, royalty and burn applied by a transfer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TransferCharges {
+    pub fee_split: FeeSplit,
+    /// Part of the transferred amount credited to the royalty account instead of the recipient.
+    pub royalty: Tokens128,
+    /// Part of the transferred amount burned. It is recorded as a burn from the recipient
+    /// following the transfer record.
+    pub burned: Tokens128,
+}
+
 // We use `Account` instead of `AccountInternal` in this structure for two reasons:
 // 1. It was there before `AccountInternal` was introduced, so if we want to change this type, we
 //    would need to introduce a new version of the state.
@@ -26,6 +40,18 @@ pub struct TxRecord {
     pub status: TransactionStatus,
     pub operation: Operation,
     pub memo: Option<Memo>,
+    pub tags: Option<Vec<String>>,
+    pub redemption_ref: Option<Vec<u8>>,
+    pub tip: Option<Tokens128>,
+    /// Id of the first transaction of the batch this transaction is a part of.
+    pub batch_id: Option<TxId>,
+    /// Part of the fee transferred to the `fee_to` account.
+    pub owner_fee: Option<Tokens128>,
+    /// Part of the fee transferred to the cycle auction pool.
+    pub auction_fee: Option<Tokens128>,
+    /// Part of the `amount` credited to the royalty account, so the recipient receives
+    /// `amount - royalty`.
+    pub royalty: Option<Tokens128>,
 }
 
 impl TxRecord {
@@ -49,6 +75,13 @@ impl TxRecord {
             status: TransactionStatus::Succeeded,
             operation: Operation::Transfer,
             memo,
+            tags: None,
+            redemption_ref: None,
+            tip: None,
+            batch_id: None,
+            owner_fee: None,
+            auction_fee: None,
+            royalty: None,
         }
     }
 
@@ -57,6 +90,7 @@ impl TxRecord {
         from: AccountInternal,
         to: AccountInternal,
         amount: Tokens128,
+        memo: Option<Memo>,
     ) -> Self {
         Self {
             caller: from.owner,
@@ -68,7 +102,14 @@ impl TxRecord {
             timestamp: ic::time(),
             status: TransactionStatus::Succeeded,
             operation: Operation::Mint,
-            memo: None,
+            memo,
+            tags: None,
+            redemption_ref: None,
+            tip: None,
+            batch_id: None,
+            owner_fee: None,
+            auction_fee: None,
+            royalty: None,
         }
     }
 
@@ -77,6 +118,7 @@ impl TxRecord {
         caller: AccountInternal,
         from: AccountInternal,
         amount: Tokens128,
+        memo: Option<Memo>,
     ) -> Self {
         Self {
             caller: caller.owner,
@@ -88,7 +130,98 @@ impl TxRecord {
             timestamp: ic::time(),
             status: TransactionStatus::Succeeded,
             operation: Operation::Burn,
+            memo,
+            tags: None,
+            redemption_ref: None,
+            tip: None,
+            batch_id: None,
+            owner_fee: None,
+            auction_fee: None,
+            royalty: None,
+        }
+    }
+
+    pub fn approve(
+        index: TxId,
+        owner: AccountInternal,
+        spender: AccountInternal,
+        amount: Tokens128,
+        fee: Tokens128,
+    ) -> Self {
+        Self {
+            caller: owner.owner,
+            index,
+            from: owner.into(),
+            to: spender.into(),
+            amount,
+            fee,
+            timestamp: ic::time(),
+            status: TransactionStatus::Succeeded,
+            operation: Operation::Approve,
+            memo: None,
+            tags: None,
+            redemption_ref: None,
+            tip: None,
+            batch_id: None,
+            owner_fee: None,
+            auction_fee: None,
+            royalty: None,
+        }
+    }
+
+    pub fn transfer_from(
+        index: TxId,
+        spender: AccountInternal,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+        fee: Tokens128,
+    ) -> Self {
+        Self {
+            caller: spender.owner,
+            index,
+            from: from.into(),
+            to: to.into(),
+            amount,
+            fee,
+            timestamp: ic::time(),
+            status: TransactionStatus::Succeeded,
+            operation: Operation::TransferFrom,
+            memo: None,
+            tags: None,
+            redemption_ref: None,
+            tip: None,
+            batch_id: None,
+            owner_fee: None,
+            auction_fee: None,
+            royalty: None,
+        }
+    }
+
+    pub fn redemption(
+        index: TxId,
+        from: AccountInternal,
+        amount: Tokens128,
+        redemption_ref: Vec<u8>,
+    ) -> Self {
+        Self {
+            caller: from.owner,
+            index,
+            from: from.into(),
+            to: from.into(),
+            amount,
+            fee: Tokens128::from(0u128),
+            timestamp: ic::time(),
+            status: TransactionStatus::Succeeded,
+            operation: Operation::Redemption,
             memo: None,
+            tags: None,
+            redemption_ref: Some(redemption_ref),
+            tip: None,
+            batch_id: None,
+            owner_fee: None,
+            auction_fee: None,
+            royalty: None,
         }
     }
 
@@ -104,9 +237,91 @@ impl TxRecord {
             status: TransactionStatus::Succeeded,
             operation: Operation::Auction,
             memo: None,
+            tags: None,
+            redemption_ref: None,
+            tip: None,
+            batch_id: None,
+            owner_fee: None,
+            auction_fee: None,
+            royalty: None,
         }
     }
 
+    /// Sets the split of the fee applied by the transaction.
+    pub fn with_fee_split(self, (owner_fee, auction_fee): FeeSplit) -> Self {
+        Self {
+            owner_fee: Some(owner_fee),
+            auction_fee: Some(auction_fee),
+            ..self
+        }
+    }
+
+    /// Sets the fee split and the royalty applied by the transfer.
+    pub fn with_charges(self, charges: TransferCharges) -> Self {
+        Self {
+            royalty: Some(charges.royalty).filter(|royalty| !royalty.is_zero()),
+            ..self.with_fee_split(charges.fee_split)
+        }
+    }
+
+    /// Amount received by the recipient of the transaction.
+    pub fn received_amount(&self) -> Tokens128 {
+        self.amount
+            .saturating_sub(self.royalty.unwrap_or(Tokens128::ZERO))
+    }
+
+    /// Returns the split of the fee applied by the transaction. Records that do not distribute a
+    /// fee, and the records made before the split was stored, return zero for both parts.
+    pub fn fee_split(&self) -> FeeSplit {
+        (
+            self.owner_fee.unwrap_or_default(),
+            self.auction_fee.unwrap_or_default(),
+        )
+    }
+
+    /// Returns the amounts `(credited, debited)` by this transaction to/from the given account.
+    ///
+    /// Fees received by the fee receiver and the auction account, and royalties received by the
+    /// royalty account, are not stored in the record, so they are not taken into account.
+    pub fn balance_change(&self, account: AccountInternal) -> (Tokens128, Tokens128) {
+        let is_from = AccountInternal::from(self.from) == account;
+        let is_to = AccountInternal::from(self.to) == account;
+        let credited = |cond: bool| if cond { self.amount } else { Tokens128::ZERO };
+        let received = |cond: bool| {
+            if cond {
+                self.received_amount()
+            } else {
+                Tokens128::ZERO
+            }
+        };
+
+        match self.operation {
+            Operation::Mint | Operation::Auction => (credited(is_to), Tokens128::ZERO),
+            Operation::Burn | Operation::Redemption => (Tokens128::ZERO, credited(is_from)),
+            Operation::Transfer | Operation::Claim | Operation::Reclaim => {
+                let debited = if is_from {
+                    (self.amount + self.fee)
+                        .and_then(|debited| debited + self.tip.unwrap_or(Tokens128::ZERO))
+                        .unwrap_or(Tokens128::MAX)
+                } else {
+                    Tokens128::ZERO
+                };
+                (received(is_to), debited)
+            }
+            // The fee of the `TransferFrom` is paid by the spender, who is not stored in the record.
+            Operation::TransferFrom => (received(is_to), credited(is_from)),
+            Operation::Approve => {
+                let debited = if is_from { self.fee } else { Tokens128::ZERO };
+                (Tokens128::ZERO, debited)
+            }
+        }
+    }
+
+    /// Returns true if the given account is the sender or the recipient of the transaction.
+    pub fn involves(&self, account: AccountInternal) -> bool {
+        AccountInternal::from(self.from) == account || AccountInternal::from(self.to) == account
+    }
+
     // This is a helper funntion to compare the principal of a transaction record.
     pub fn contains(&self, pid: Principal) -> bool {
         self.caller == pid || self.from.owner == pid || self.to.owner == pid
@@ -124,6 +339,41 @@ impl TxRecord {
             status: TransactionStatus::Succeeded,
             operation: Operation::Claim,
             memo: None,
+            tags: None,
+            redemption_ref: None,
+            tip: None,
+            batch_id: None,
+            owner_fee: None,
+            auction_fee: None,
+            royalty: None,
+        }
+    }
+
+    pub fn reclaim(
+        id: u64,
+        caller: Principal,
+        from: AccountInternal,
+        to: AccountInternal,
+        amount: Tokens128,
+    ) -> Self {
+        Self {
+            caller,
+            index: id,
+            from: from.into(),
+            to: to.into(),
+            amount,
+            fee: 0.into(),
+            timestamp: ic::time(),
+            status: TransactionStatus::Succeeded,
+            operation: Operation::Reclaim,
+            memo: None,
+            tags: None,
+            redemption_ref: None,
+            tip: None,
+            batch_id: None,
+            owner_fee: None,
+            auction_fee: None,
+            royalty: None,
         }
     }
 }
@@ -18,7 +18,9 @@ use token_api::{
     canister::{TokenCanisterAPI, DEFAULT_AUCTION_PERIOD_SECONDS},
     state::{
         balances::{Balances, StableBalances},
+        certification::certify_state,
         config::{Metadata, TokenConfig},
+        layout::{StableLayout, STABLE_LAYOUT_VERSION},
         ledger::LedgerData,
     },
 };
@@ -35,6 +37,12 @@ impl TokenCanister {
     pub fn init(&self, metadata: Metadata, amount: Tokens128) {
         let owner = metadata.owner;
         let owner_account = AccountInternal::new(owner, None);
+        if let Some(max_supply) = metadata.max_supply {
+            assert!(
+                amount <= max_supply,
+                "initial supply exceeds the maximum supply"
+            );
+        }
 
         StableBalances.clear();
         StableBalances.insert(owner_account, amount);
@@ -43,9 +51,11 @@ impl TokenCanister {
             AccountInternal::from(owner),
             AccountInternal::from(owner),
             amount,
+            None,
         );
 
         TokenConfig::set_stable(metadata.into());
+        StableLayout::set_stored_version(STABLE_LAYOUT_VERSION);
 
         let auction_state = self.auction_state();
         auction_state.replace(AuctionState::new(
@@ -63,7 +73,14 @@ impl TokenCanister {
 
     #[post_upgrade]
     fn post_upgrade(&self) {
-        // All required canister state stored in stable memory, so no need to save/load anything.
+        // All required canister state stored in stable memory, so no need to save/load anything,
+        // but the stored data must be readable by the new code.
+        if let Err(msg) = StableLayout::upgrade() {
+            canister_sdk::ic_kit::ic::trap(&msg);
+        }
+
+        // Certified data is not preserved over upgrades.
+        certify_state();
     }
 }
 
@@ -104,6 +121,14 @@ impl Auction for TokenCanister {
     fn disburse_rewards(&self) -> Result<AuctionInfo, AuctionError> {
         token_api::canister::is20_auction::disburse_rewards(&self.auction_state().borrow())
     }
+
+    fn set_auction_period(&self, interval: Interval) -> Result<(), AuctionError> {
+        token_api::canister::is20_auction::set_auction_period(&self.auction_state(), interval)
+    }
+
+    fn bid_cycles(&self, bidder: Principal) -> Result<u64, AuctionError> {
+        token_api::canister::is20_auction::bid_cycles(&self.auction_state(), bidder)
+    }
 }
 
 impl Metrics for TokenCanister {
@@ -116,6 +141,7 @@ impl Metrics for TokenCanister {
 mod test {
     use super::*;
     use canister_sdk::ic_kit::MockContext;
+    use token_api::state::layout::MIN_COMPATIBLE_LAYOUT_VERSION;
 
     #[test]
     #[cfg_attr(coverage_nightly, no_coverage)]
@@ -138,4 +164,45 @@ mod test {
             "To Kill a Mockingbird".to_string()
         );
     }
+
+    #[test]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn test_upgrade_from_compatible_layout() {
+        MockContext::new().inject();
+
+        let canister = TokenCanister::init_instance();
+        assert_eq!(StableLayout::stored_version(), STABLE_LAYOUT_VERSION);
+
+        StableLayout::set_stored_version(MIN_COMPATIBLE_LAYOUT_VERSION);
+        canister.pre_upgrade();
+        canister.post_upgrade();
+
+        assert_eq!(StableLayout::stored_version(), STABLE_LAYOUT_VERSION);
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn test_downgrade_is_refused() {
+        MockContext::new().inject();
+
+        let canister = TokenCanister::init_instance();
+        StableLayout::set_stored_version(STABLE_LAYOUT_VERSION + 1);
+
+        canister.pre_upgrade();
+        canister.post_upgrade();
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg_attr(coverage_nightly, no_coverage)]
+    fn test_upgrade_from_incompatible_layout_is_refused() {
+        MockContext::new().inject();
+
+        let canister = TokenCanister::init_instance();
+        StableLayout::set_stored_version(MIN_COMPATIBLE_LAYOUT_VERSION - 1);
+
+        canister.pre_upgrade();
+        canister.post_upgrade();
+    }
 }
@@ -40,6 +40,7 @@ fn init() -> (Metadata, TokenCanister, &'static mut MockContext) {
         symbol: "TST".into(),
         owner: alice(),
         is_test_token: None,
+        max_supply: None,
     };
     canister.init(meta.clone(), 1_000_000_000.into());
     (meta, canister, context)
@@ -97,8 +98,12 @@ fn transfer(canister: &TokenCanister, to: Principal, amount: u128) {
             to: Account::new(to, None),
             amount: amount.into(),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: None,
+            valid_until: None,
+            tags: None,
+            tip: None,
         })
         .unwrap();
 }
@@ -138,8 +143,12 @@ fn bad_fee_transfer() {
         to: Account::new(john(), None),
         amount: 1000.into(),
         fee: Some(126.into()),
+        max_fee: None,
         memo: None,
         created_at_time: None,
+        valid_until: None,
+        tags: None,
+        tip: None,
     });
 
     assert_eq!(
@@ -165,8 +174,12 @@ fn too_old_transfer() {
         to: Account::new(john(), None),
         amount: 1000.into(),
         fee: None,
+        max_fee: None,
         memo: None,
         created_at_time: Some(curr_ts - 10 * 60 * 1_000_000_000),
+        valid_until: None,
+        tags: None,
+        tip: None,
     });
 
     assert_eq!(result, Err(TransferError::TooOld))
@@ -187,8 +200,12 @@ fn created_in_future() {
         to: Account::new(john(), None),
         amount: 1000.into(),
         fee: None,
+        max_fee: None,
         memo: None,
         created_at_time: Some(curr_ts + 3 * 60 * 1_000_000_000),
+        valid_until: None,
+        tags: None,
+        tip: None,
     });
 
     assert_eq!(
@@ -215,8 +232,12 @@ fn duplicate_check() {
             to: Account::new(john(), None),
             amount: 1000.into(),
             fee: None,
+            max_fee: None,
             memo: None,
             created_at_time: Some(curr_ts),
+            valid_until: None,
+            tags: None,
+            tip: None,
         })
         .unwrap();
 
@@ -225,8 +246,12 @@ fn duplicate_check() {
         to: Account::new(john(), None),
         amount: 1000.into(),
         fee: None,
+        max_fee: None,
         memo: None,
         created_at_time: Some(curr_ts),
+        valid_until: None,
+        tags: None,
+        tip: None,
     });
 
     assert_eq!(